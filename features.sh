@@ -0,0 +1,19 @@
+#!/bin/bash
+# builds rust-kms-local with combinations of its features. uses cargo-hack
+# when installed, otherwise each feature is checked on its own.
+
+if cargo hack --version > /dev/null 2>&1; then
+	echo "checking feature powerset"
+	cargo hack check -p rust-kms-local --all-targets --feature-powerset --depth 2
+else
+	echo "cargo-hack not installed, checking each feature"
+
+	features=$(sed -n '/^\[features\]/,/^\[/p' rust-kms-local/Cargo.toml | grep -oE '^[a-z0-9_-]+ =' | cut -d ' ' -f 1)
+
+	cargo check -p rust-kms-local --all-targets --no-default-features || exit 1
+
+	for feature in $features; do
+		echo "checking $feature"
+		cargo check -p rust-kms-local --all-targets --no-default-features --features $feature || exit 1
+	done
+fi
@@ -0,0 +1,132 @@
+#ifndef RKMS_H
+#define RKMS_H
+
+/* generated by cbindgen, do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * the call succeeded
+ */
+#define RKMS_OK 0
+
+/**
+ * the version, or for latest any version, is not in the store
+ */
+#define RKMS_NOT_FOUND 1
+
+/**
+ * a pointer that is required was null
+ */
+#define RKMS_NULL_ARGUMENT -1
+
+/**
+ * the path is not valid utf-8
+ */
+#define RKMS_INVALID_PATH -2
+
+/**
+ * there is no file at the path, or the detached header is missing
+ */
+#define RKMS_FILE_NOT_FOUND -3
+
+/**
+ * reading the file failed
+ */
+#define RKMS_IO -4
+
+/**
+ * the file is not a store that can be opened with the key, e.g. it was
+ * truncated or saved with another key
+ */
+#define RKMS_CORRUPT -5
+
+/**
+ * `out_buf` is too small for the key, `out_len` holds the length needed
+ */
+#define RKMS_BUFFER_TOO_SMALL -6
+
+/**
+ * the store was poisoned by a panic while it was locked
+ */
+#define RKMS_POISONED -7
+
+/**
+ * the call panicked, the store should not be used again
+ */
+#define RKMS_PANIC -8
+
+/**
+ * any other error
+ */
+#define RKMS_OTHER -9
+
+/**
+ * an open store. only ever handled through a pointer.
+ */
+typedef struct RkmsStore RkmsStore;
+
+/**
+ * opens the encrypted store at `path` with the 32 byte `key`, reading the
+ * detached header from `header_path` when it is not null. returns null on
+ * failure with the reason in `out_error` when it is not null. the store is
+ * freed with [`rkms_store_close`].
+ *
+ * only stores of `Key<Vec<u8>>` can be opened. a store saved with fixed
+ * size keys such as `Key<[u8; 32]>` fails with [`RKMS_CORRUPT`].
+ *
+ * # Safety
+ *
+ * `path` must be a nul terminated string, `header_path` must be null or a
+ * nul terminated string, `key` must point to 32 readable bytes and
+ * `out_error` must be null or valid for writes
+ */
+struct RkmsStore *rkms_store_open_encrypted(const char *path,
+                                            const char *header_path,
+                                            const uint8_t *key,
+                                            int32_t *out_error);
+
+/**
+ * copies the data of the newest key into `out_buf` and its version into
+ * `out_version` when it is not null
+ *
+ * # Safety
+ *
+ * `store` must come from [`rkms_store_open_encrypted`] and not be closed,
+ * `out_len` must be valid, `out_buf` must be null or valid for `*out_len`
+ * bytes and `out_version` must be null or valid for writes
+ */
+int32_t rkms_store_latest(const struct RkmsStore *store,
+                          uint8_t *out_buf,
+                          size_t *out_len,
+                          uint64_t *out_version);
+
+/**
+ * copies the data of the key at `version` into `out_buf`
+ *
+ * # Safety
+ *
+ * `store` must come from [`rkms_store_open_encrypted`] and not be closed,
+ * `out_len` must be valid and `out_buf` must be null or valid for
+ * `*out_len` bytes
+ */
+int32_t rkms_store_get(const struct RkmsStore *store,
+                       uint64_t version,
+                       uint8_t *out_buf,
+                       size_t *out_len);
+
+/**
+ * frees a store. null is ignored.
+ *
+ * # Safety
+ *
+ * `store` must be null or come from [`rkms_store_open_encrypted`] and not
+ * already be closed
+ */
+void rkms_store_close(struct RkmsStore *store);
+
+#endif /* RKMS_H */
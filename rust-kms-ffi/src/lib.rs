@@ -0,0 +1,251 @@
+//! a small C interface for reading keys from an encrypted store written by
+//! rust-kms-local. stores are opened read only, there is no way to change
+//! them from C.
+//!
+//! keys are `Key<Vec<u8>>` and only their data is given out. buffers are
+//! always allocated by the caller: `out_len` is the capacity of `out_buf`
+//! when called and the length of the key when the call returns
+//! [`RKMS_OK`] or [`RKMS_BUFFER_TOO_SMALL`]. a null `out_buf` with a
+//! capacity of 0 asks for the length only.
+//!
+//! the header for C is `include/rkms.h`, generated with cbindgen by the
+//! ignored `write_header` test.
+
+use std::ffi::{c_char, CStr};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use rust_kms_local::{crypto, fs, local, Key, Local};
+use rust_kms_local::fs::Wrapper;
+use rust_kms_local::fs::encrypted::{Encrypted, Options};
+
+/// the call succeeded
+pub const RKMS_OK: i32 = 0;
+/// the version, or for latest any version, is not in the store
+pub const RKMS_NOT_FOUND: i32 = 1;
+/// a pointer that is required was null
+pub const RKMS_NULL_ARGUMENT: i32 = -1;
+/// the path is not valid utf-8
+pub const RKMS_INVALID_PATH: i32 = -2;
+/// there is no file at the path, or the detached header is missing
+pub const RKMS_FILE_NOT_FOUND: i32 = -3;
+/// reading the file failed
+pub const RKMS_IO: i32 = -4;
+/// the file is not a store that can be opened with the key, e.g. it was
+/// truncated or saved with another key
+pub const RKMS_CORRUPT: i32 = -5;
+/// `out_buf` is too small for the key, `out_len` holds the length needed
+pub const RKMS_BUFFER_TOO_SMALL: i32 = -6;
+/// the store was poisoned by a panic while it was locked
+pub const RKMS_POISONED: i32 = -7;
+/// the call panicked, the store should not be used again
+pub const RKMS_PANIC: i32 = -8;
+/// any other error
+pub const RKMS_OTHER: i32 = -9;
+
+/// an open store. only ever handled through a pointer.
+pub struct RkmsStore {
+    local: Local<Key<Vec<u8>>>,
+}
+
+fn local_code(error: &local::Error) -> i32 {
+    match error {
+        local::Error::Poisoned => RKMS_POISONED,
+        _ => RKMS_OTHER,
+    }
+}
+
+fn fs_code(error: &fs::Error) -> i32 {
+    if error.is_not_found() {
+        return RKMS_FILE_NOT_FOUND;
+    }
+
+    if error.is_corrupt() {
+        return RKMS_CORRUPT;
+    }
+
+    match error {
+        fs::Error::MissingHeader => RKMS_FILE_NOT_FOUND,
+        fs::Error::Io(_) | fs::Error::Retries { .. } => RKMS_IO,
+        fs::Error::Local(e) => local_code(e),
+        _ => RKMS_OTHER,
+    }
+}
+
+/// copies `data` into the caller's buffer following the `out_len` protocol
+///
+/// # Safety
+///
+/// `out_len` must be valid and `out_buf` valid for `*out_len` bytes unless
+/// it is null
+unsafe fn copy_out(data: &[u8], out_buf: *mut u8, out_len: *mut usize) -> i32 {
+    let capacity = *out_len;
+
+    *out_len = data.len();
+
+    if capacity < data.len() {
+        return RKMS_BUFFER_TOO_SMALL;
+    }
+
+    if out_buf.is_null() {
+        return if data.is_empty() { RKMS_OK } else { RKMS_NULL_ARGUMENT };
+    }
+
+    std::ptr::copy_nonoverlapping(data.as_ptr(), out_buf, data.len());
+
+    RKMS_OK
+}
+
+/// overwrites the bytes with zeros in a way the compiler cannot skip
+fn zero(bytes: &mut [u8]) {
+    for b in bytes.iter_mut() {
+        // SAFETY: the pointer comes from a valid mutable reference
+        unsafe { std::ptr::write_volatile(b, 0) };
+    }
+
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
+
+/// opens the encrypted store at `path` with the 32 byte `key`, reading the
+/// detached header from `header_path` when it is not null. returns null on
+/// failure with the reason in `out_error` when it is not null. the store is
+/// freed with [`rkms_store_close`].
+///
+/// only stores of `Key<Vec<u8>>` can be opened. a store saved with fixed
+/// size keys such as `Key<[u8; 32]>` fails with [`RKMS_CORRUPT`].
+///
+/// # Safety
+///
+/// `path` must be a nul terminated string, `header_path` must be null or a
+/// nul terminated string, `key` must point to 32 readable bytes and
+/// `out_error` must be null or valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn rkms_store_open_encrypted(
+    path: *const c_char,
+    header_path: *const c_char,
+    key: *const u8,
+    out_error: *mut i32,
+) -> *mut RkmsStore {
+    let result = catch_unwind(|| {
+        if path.is_null() || key.is_null() {
+            return Err(RKMS_NULL_ARGUMENT);
+        }
+
+        let path = CStr::from_ptr(path).to_str().map_err(|_| RKMS_INVALID_PATH)?;
+        let header_path = if header_path.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(header_path).to_str().map_err(|_| RKMS_INVALID_PATH)?)
+        };
+        let mut master: crypto::Key = [0; crypto::KEY_LEN];
+
+        std::ptr::copy_nonoverlapping(key, master.as_mut_ptr(), crypto::KEY_LEN);
+
+        let mut options = Options::new(path, master);
+        options.header_path = header_path.map(Into::into);
+
+        zero(&mut master);
+
+        let store: Encrypted<Key<Vec<u8>>> = Encrypted::load(options)
+            .map_err(|e| fs_code(&e))?;
+
+        Ok(Box::new(RkmsStore {
+            local: store.into_inner(),
+        }))
+    });
+
+    let code = match result {
+        Ok(Ok(store)) => {
+            if !out_error.is_null() {
+                *out_error = RKMS_OK;
+            }
+
+            return Box::into_raw(store);
+        }
+        Ok(Err(code)) => code,
+        Err(_) => RKMS_PANIC,
+    };
+
+    if !out_error.is_null() {
+        *out_error = code;
+    }
+
+    std::ptr::null_mut()
+}
+
+/// copies the data of the newest key into `out_buf` and its version into
+/// `out_version` when it is not null
+///
+/// # Safety
+///
+/// `store` must come from [`rkms_store_open_encrypted`] and not be closed,
+/// `out_len` must be valid, `out_buf` must be null or valid for `*out_len`
+/// bytes and `out_version` must be null or valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn rkms_store_latest(
+    store: *const RkmsStore,
+    out_buf: *mut u8,
+    out_len: *mut usize,
+    out_version: *mut u64,
+) -> i32 {
+    if store.is_null() || out_len.is_null() {
+        return RKMS_NULL_ARGUMENT;
+    }
+
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        let found = match (*store).local.latest_version() {
+            Ok(Some(found)) => found,
+            Ok(None) => return RKMS_NOT_FOUND,
+            Err(e) => return local_code(&e),
+        };
+
+        if !out_version.is_null() {
+            *out_version = found.0;
+        }
+
+        copy_out(found.1.data(), out_buf, out_len)
+    }));
+
+    result.unwrap_or(RKMS_PANIC)
+}
+
+/// copies the data of the key at `version` into `out_buf`
+///
+/// # Safety
+///
+/// `store` must come from [`rkms_store_open_encrypted`] and not be closed,
+/// `out_len` must be valid and `out_buf` must be null or valid for
+/// `*out_len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn rkms_store_get(
+    store: *const RkmsStore,
+    version: u64,
+    out_buf: *mut u8,
+    out_len: *mut usize,
+) -> i32 {
+    if store.is_null() || out_len.is_null() {
+        return RKMS_NULL_ARGUMENT;
+    }
+
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        match (*store).local.get(&version) {
+            Ok(Some(key)) => copy_out(key.data(), out_buf, out_len),
+            Ok(None) => RKMS_NOT_FOUND,
+            Err(e) => local_code(&e),
+        }
+    }));
+
+    result.unwrap_or(RKMS_PANIC)
+}
+
+/// frees a store. null is ignored.
+///
+/// # Safety
+///
+/// `store` must be null or come from [`rkms_store_open_encrypted`] and not
+/// already be closed
+#[no_mangle]
+pub unsafe extern "C" fn rkms_store_close(store: *mut RkmsStore) {
+    if !store.is_null() {
+        drop(Box::from_raw(store));
+    }
+}
@@ -0,0 +1,46 @@
+//! checks that `include/rkms.h` matches what cbindgen generates from the
+//! crate. run the ignored `write_header` test after changing the interface
+//! and review the diff.
+
+use std::path::PathBuf;
+
+fn generate() -> Vec<u8> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let mut header = Vec::new();
+
+    let mut config = cbindgen::Config::default();
+
+    config.language = cbindgen::Language::C;
+    config.include_guard = Some("RKMS_H".to_owned());
+    config.autogen_warning = Some("/* generated by cbindgen, do not edit */".to_owned());
+    config.usize_is_size_t = true;
+
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(dir.join("src/lib.rs"))
+        .generate()
+        .expect("failed to generate header")
+        .write(&mut header);
+
+    header
+}
+
+fn header_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("include/rkms.h")
+}
+
+#[test]
+#[ignore]
+fn write_header() {
+    std::fs::write(header_path(), generate()).expect("failed to write header");
+}
+
+#[test]
+fn header() {
+    let current = std::fs::read(header_path()).expect("failed to read header");
+
+    assert!(
+        current == generate(),
+        "include/rkms.h is out of date. run the ignored write_header test and review the diff"
+    );
+}
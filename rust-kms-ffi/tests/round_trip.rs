@@ -0,0 +1,175 @@
+//! writes stores with rust-kms-local and reads them back only through the
+//! extern "C" functions
+
+use std::ffi::CString;
+use std::path::PathBuf;
+
+use rust_kms_ffi::*;
+use rust_kms_local::{crypto, Key, Local};
+use rust_kms_local::fs::{Encrypted, Wrapper};
+
+const MASTER: crypto::Key = [9; crypto::KEY_LEN];
+
+/// a file in the temp dir that is removed when dropped
+struct TempPath(PathBuf);
+
+impl TempPath {
+    fn new(name: &str) -> Self {
+        TempPath(std::env::temp_dir().join(format!("rkms-ffi-{}-{}.enc", name, std::process::id())))
+    }
+
+    fn c_path(&self) -> CString {
+        CString::new(self.0.to_str().unwrap()).unwrap()
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn write_store(path: &TempPath) {
+    let local: Local<Key<Vec<u8>>> = Local::new();
+
+    local.update(Key::builder(vec![1, 2, 3]).build().unwrap()).unwrap();
+    local.update(Key::builder(vec![4; 40]).build().unwrap()).unwrap();
+    local.update(Key::builder(vec![5, 6]).build().unwrap()).unwrap();
+    local.drop(&3).unwrap();
+
+    Encrypted::new(local, &path.0, MASTER).save().unwrap();
+}
+
+fn open(path: &TempPath, key: &crypto::Key) -> Result<*mut RkmsStore, i32> {
+    let mut error = RKMS_OTHER;
+    let store = unsafe { rkms_store_open_encrypted(path.c_path().as_ptr(), std::ptr::null(), key.as_ptr(), &mut error) };
+
+    if store.is_null() {
+        Err(error)
+    } else {
+        assert_eq!(error, RKMS_OK);
+
+        Ok(store)
+    }
+}
+
+#[test]
+fn read_keys() {
+    let path = TempPath::new("read");
+    write_store(&path);
+
+    let store = open(&path, &MASTER).unwrap();
+    let mut buf = [0u8; 64];
+    let mut len = buf.len();
+    let mut version = 0;
+
+    unsafe {
+        assert_eq!(rkms_store_latest(store, buf.as_mut_ptr(), &mut len, &mut version), RKMS_OK);
+        assert_eq!(version, 2);
+        assert_eq!(&buf[..len], &[4; 40]);
+
+        len = buf.len();
+        assert_eq!(rkms_store_get(store, 1, buf.as_mut_ptr(), &mut len), RKMS_OK);
+        assert_eq!(&buf[..len], &[1, 2, 3]);
+
+        len = buf.len();
+        assert_eq!(rkms_store_get(store, 3, buf.as_mut_ptr(), &mut len), RKMS_NOT_FOUND);
+
+        // asking for the length first
+        len = 0;
+        assert_eq!(rkms_store_get(store, 2, std::ptr::null_mut(), &mut len), RKMS_BUFFER_TOO_SMALL);
+        assert_eq!(len, 40);
+
+        len = 10;
+        assert_eq!(rkms_store_latest(store, buf.as_mut_ptr(), &mut len, std::ptr::null_mut()), RKMS_BUFFER_TOO_SMALL);
+        assert_eq!(len, 40);
+
+        assert_eq!(rkms_store_get(store, 1, buf.as_mut_ptr(), std::ptr::null_mut()), RKMS_NULL_ARGUMENT);
+        assert_eq!(rkms_store_get(std::ptr::null(), 1, buf.as_mut_ptr(), &mut len), RKMS_NULL_ARGUMENT);
+
+        rkms_store_close(store);
+        rkms_store_close(std::ptr::null_mut());
+    }
+}
+
+#[test]
+fn empty_store() {
+    let path = TempPath::new("empty");
+
+    Encrypted::new(Local::<Key<Vec<u8>>>::new(), &path.0, MASTER).save().unwrap();
+
+    let store = open(&path, &MASTER).unwrap();
+    let mut len = 0;
+
+    unsafe {
+        assert_eq!(rkms_store_latest(store, std::ptr::null_mut(), &mut len, std::ptr::null_mut()), RKMS_NOT_FOUND);
+
+        rkms_store_close(store);
+    }
+}
+
+#[test]
+fn open_errors() {
+    let path = TempPath::new("errors");
+
+    assert_eq!(open(&path, &MASTER).unwrap_err(), RKMS_FILE_NOT_FOUND);
+
+    write_store(&path);
+
+    assert_eq!(open(&path, &[1; crypto::KEY_LEN]).unwrap_err(), RKMS_CORRUPT);
+
+    let store = unsafe { rkms_store_open_encrypted(std::ptr::null(), std::ptr::null(), MASTER.as_ptr(), std::ptr::null_mut()) };
+
+    assert!(store.is_null());
+}
+
+#[test]
+fn detached_header() {
+    let path = TempPath::new("detached");
+    let header = TempPath::new("detached.header");
+
+    let local: Local<Key<Vec<u8>>> = Local::new();
+    local.update(Key::builder(vec![7; 16]).build().unwrap()).unwrap();
+
+    let mut store = Encrypted::new(local, &path.0, MASTER);
+    store.set_header_path(Some(&header.0));
+    store.save().unwrap();
+
+    let open_detached = |header: &TempPath| {
+        let mut error = RKMS_OTHER;
+        let store = unsafe {
+            rkms_store_open_encrypted(path.c_path().as_ptr(), header.c_path().as_ptr(), MASTER.as_ptr(), &mut error)
+        };
+
+        (store, error)
+    };
+
+    let (store, error) = open_detached(&header);
+    assert_eq!(error, RKMS_OK);
+
+    let mut buf = [0u8; 16];
+    let mut len = buf.len();
+
+    unsafe {
+        assert_eq!(rkms_store_latest(store, buf.as_mut_ptr(), &mut len, std::ptr::null_mut()), RKMS_OK);
+        assert_eq!(&buf[..len], &[7; 16]);
+
+        rkms_store_close(store);
+    }
+
+    let (store, error) = open_detached(&TempPath::new("detached.missing"));
+    assert!(store.is_null());
+    assert_eq!(error, RKMS_FILE_NOT_FOUND);
+}
+
+#[test]
+fn fixed_size_keys() {
+    let path = TempPath::new("fixed");
+    let local: Local<Key<[u8; 32]>> = Local::new();
+
+    local.update(Key::builder([3; 32]).build().unwrap()).unwrap();
+
+    Encrypted::new(local, &path.0, MASTER).save().unwrap();
+
+    assert_eq!(open(&path, &MASTER).unwrap_err(), RKMS_CORRUPT);
+}
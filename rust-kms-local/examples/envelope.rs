@@ -0,0 +1,68 @@
+//! encrypts a file under a fresh data key, rotates, and decrypts the file
+//! again with the key version recorded in it.
+//!
+//! ```text
+//! cargo run --example envelope --features crypto -- <file>
+//! ```
+//!
+//! the sealed copy is written next to the input with a `.sealed` suffix.
+
+use std::path::{Path, PathBuf};
+
+use rust_kms_local::{crypto, envelope, Key, Local};
+
+pub type BoxError = Box<dyn std::error::Error>;
+
+/// adds a random data key and returns its version
+pub fn new_data_key(keys: &Local<Key<crypto::Key>>) -> Result<u64, BoxError> {
+    let key = Key::<crypto::Key>::builder_os_rng()?.build()?;
+
+    Ok(keys.update(key)?)
+}
+
+/// encrypts `input` with the latest data key into `output`. returns the
+/// version that was used.
+pub fn encrypt_file(keys: &Local<Key<crypto::Key>>, input: &Path, output: &Path) -> Result<u64, BoxError> {
+    let blob = envelope::seal(keys, std::fs::read(input)?)?;
+    let (version, _) = crypto::split_version(&blob)?;
+
+    std::fs::write(output, blob)?;
+
+    Ok(version)
+}
+
+/// decrypts a file written by [`encrypt_file`] with whichever version it
+/// names
+pub fn decrypt_file(keys: &Local<Key<crypto::Key>>, input: &Path) -> Result<Vec<u8>, BoxError> {
+    Ok(envelope::open(keys, &std::fs::read(input)?)?)
+}
+
+fn main() -> Result<(), BoxError> {
+    let Some(input) = std::env::args().nth(1).map(PathBuf::from) else {
+        eprintln!("usage: envelope <file>");
+        std::process::exit(2);
+    };
+
+    let mut sealed = input.clone().into_os_string();
+    sealed.push(".sealed");
+
+    let sealed = PathBuf::from(sealed);
+    let keys = Local::new();
+
+    new_data_key(&keys)?;
+
+    let used = encrypt_file(&keys, &input, &sealed)?;
+    let rotated = new_data_key(&keys)?;
+    let plaintext = decrypt_file(&keys, &sealed)?;
+
+    if plaintext != std::fs::read(&input)? {
+        return Err("decrypted file does not match the input".into());
+    }
+
+    println!(
+        "sealed {} with version {} and opened it after rotating to version {}",
+        input.display(), used, rotated
+    );
+
+    Ok(())
+}
@@ -0,0 +1,57 @@
+//! converts a json store of byte keys into an encrypted store.
+//!
+//! ```text
+//! cargo run --example migrate_format --features encrypted,json -- keys.json keys.enc master.key
+//! ```
+//!
+//! the master key file holds 32 raw bytes. the json store is left in place
+//! so it can be removed once the encrypted store has been checked.
+
+use std::path::Path;
+
+use rust_kms_local::{crypto, Key};
+use rust_kms_local::fs::{self, Encrypted, Json, Wrapper};
+
+pub type BoxError = Box<dyn std::error::Error>;
+
+/// reads a master key that must already exist
+pub fn read_master_key(path: &Path) -> Result<crypto::Key, BoxError> {
+    std::fs::read(path)?
+        .try_into()
+        .map_err(|_| format!("{} is not a {} byte key", path.display(), crypto::KEY_LEN).into())
+}
+
+/// writes the json store at `from` to an encrypted store at `to` and loads
+/// it back to check that nothing was lost. returns the number of keys.
+pub fn migrate(from: &Path, to: &Path, master_key: crypto::Key) -> Result<usize, BoxError> {
+    let json: Json<Key<Vec<u8>>> = Json::load(fs::json::Options::new(from))?;
+    let encrypted = Encrypted::new(json.into_inner(), to, master_key);
+
+    encrypted.save()?;
+
+    let check: Encrypted<Key<Vec<u8>>> = Encrypted::load(fs::encrypted::Options::new(to, master_key))?;
+
+    let written = encrypted.store_reader()?;
+    let read = check.store_reader()?;
+
+    if check.count()? != encrypted.count()? || *read != *written {
+        return Err(format!("{} does not match {}", to.display(), from.display()).into());
+    }
+
+    Ok(read.len())
+}
+
+fn main() -> Result<(), BoxError> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let [from, to, key_path] = args.as_slice() else {
+        eprintln!("usage: migrate_format <json store> <encrypted store> <master key>");
+        std::process::exit(2);
+    };
+
+    let count = migrate(from.as_ref(), to.as_ref(), read_master_key(key_path.as_ref())?)?;
+
+    println!("migrated {} keys from {} to {}", count, from, to);
+
+    Ok(())
+}
@@ -0,0 +1,133 @@
+//! keeps an encrypted store of data keys rotated until ctrl-c is pressed.
+//!
+//! ```text
+//! cargo run --example rotate_daemon --features encrypted -- keys.enc master.key [max age secs]
+//! ```
+//!
+//! the master key file holds 32 raw bytes and is created with a random key
+//! if it does not exist. the store is created on the first save if it does
+//! not exist. every save is atomic so the store on disk is always either
+//! the old or the new version, including when the process is killed.
+
+use std::io::ErrorKind;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use rust_kms_local::{crypto, Key, Local};
+use rust_kms_local::fs::{Encrypted, Wrapper};
+use rust_kms_local::fs::encrypted::Options;
+use rust_kms_local::policy::PolicySet;
+
+pub type BoxError = Box<dyn std::error::Error>;
+
+pub type Store = Encrypted<Key<crypto::Key>>;
+
+/// the max age used when none is given, one day
+pub const DEFAULT_MAX_AGE: u64 = 24 * 60 * 60;
+
+/// how often the latest key is checked against the policy
+pub const CHECK_EVERY: Duration = Duration::from_secs(60);
+
+/// the longest the daemon sleeps before noticing ctrl-c
+const POLL: Duration = Duration::from_millis(200);
+
+/// reads the master key, writing a random one to `path` if there is no file
+pub fn master_key(path: &Path) -> Result<crypto::Key, BoxError> {
+    match std::fs::read(path) {
+        Ok(bytes) => bytes.try_into()
+            .map_err(|_| format!("{} is not a {} byte key", path.display(), crypto::KEY_LEN).into()),
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            let key = Key::<crypto::Key>::builder_os_rng()?.build()?;
+
+            std::fs::write(path, key.data())?;
+
+            Ok(*key.data())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// opens the store at `path` or starts an empty one
+pub fn open_store(path: &Path, master_key: crypto::Key) -> Result<Store, BoxError> {
+    Ok(Store::load_or_create(Options::new(path, master_key))?)
+}
+
+/// adds a random data key if there is none or the latest is older than the
+/// policy allows. returns the version that was added.
+pub fn rotate_if_due(
+    keys: &Local<Key<crypto::Key>>,
+    policy: &PolicySet,
+    now: SystemTime
+) -> Result<Option<u64>, BoxError> {
+    if let Some(latest) = keys.latest()? {
+        if policy.max_age.is_none() || policy.time_until_rotation(*latest.created(), now).is_some() {
+            return Ok(None);
+        }
+    }
+
+    let mut builder = Key::<crypto::Key>::builder_os_rng()?;
+    builder.set_created(now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs());
+
+    Ok(Some(keys.update(builder.build()?)?))
+}
+
+/// checks the policy every `check_every` and saves after each rotation
+/// until `stop` is set, then saves one last time
+pub fn run(
+    store: &Store,
+    policy: &PolicySet,
+    check_every: Duration,
+    stop: &AtomicBool
+) -> Result<(), BoxError> {
+    let mut next_check = Instant::now();
+
+    while !stop.load(Ordering::Relaxed) {
+        if Instant::now() >= next_check {
+            if let Some(version) = rotate_if_due(store, policy, SystemTime::now())? {
+                store.save()?;
+
+                println!("rotated to version {}", version);
+            }
+
+            next_check = Instant::now() + check_every;
+        }
+
+        std::thread::sleep(POLL.min(check_every));
+    }
+
+    store.save()?;
+
+    Ok(())
+}
+
+fn main() -> Result<(), BoxError> {
+    let mut args = std::env::args().skip(1);
+
+    let (Some(store_path), Some(key_path)) = (args.next(), args.next()) else {
+        eprintln!("usage: rotate_daemon <store> <master key> [max age secs]");
+        std::process::exit(2);
+    };
+    let max_age = match args.next() {
+        Some(secs) => secs.parse()?,
+        None => DEFAULT_MAX_AGE,
+    };
+
+    let store = open_store(store_path.as_ref(), master_key(key_path.as_ref())?)?;
+    let policy = PolicySet {
+        max_age: Some(max_age),
+        ..PolicySet::default()
+    };
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = stop.clone();
+
+    ctrlc::set_handler(move || handler_stop.store(true, Ordering::Relaxed))?;
+
+    run(&store, &policy, CHECK_EVERY, &stop)?;
+
+    println!("saved {} versions, stopping", store.store_reader()?.len());
+
+    Ok(())
+}
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use rust_kms_local::fs::{KeyCodec, SerdeCodec};
+use rust_kms_local::key::Key;
+
+// the same path Binary::load takes once the file has been read
+fuzz_target!(|data: &[u8]| {
+    let _ = <SerdeCodec as KeyCodec<Key<Vec<u8>>>>::deserialize_local(data);
+    let _ = <SerdeCodec as KeyCodec<String>>::deserialize_local(data);
+});
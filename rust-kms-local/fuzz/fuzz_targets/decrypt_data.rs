@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use rust_kms_local::crypto;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = crypto::decrypt_data(&crypto::empty_key(), data.to_vec());
+});
@@ -0,0 +1,329 @@
+//! a store for async code that waits on tokio locks instead of blocking
+//! the thread.
+//!
+//! [`AsyncLocal`] only has the core of [`Local`]: adding, getting and
+//! dropping keys. there are no hooks, tombstones, reservations or staged
+//! versions. a [`Local`] can be turned into one with
+//! [`from_local`](AsyncLocal::from_local) and back with
+//! [`into_local`](AsyncLocal::into_local) to use the rest.
+
+use std::cell::Cell;
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::ser::Error as _;
+use tokio::sync::{Mutex, RwLock};
+
+use crate::local::{self, Local, Snapshot, VersionedKey, Parts, Fields};
+
+pub struct AsyncLocal<KeyType> {
+    count: Mutex<u64>,
+    store: RwLock<BTreeMap<u64, KeyType>>,
+}
+
+impl<KeyType> AsyncLocal<KeyType> {
+    pub fn new() -> Self {
+        AsyncLocal {
+            count: Mutex::new(0),
+            store: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// the counter and keys of `local`. access times are left behind.
+    ///
+    /// a version that is staged, disabled, pending a drop, reserved,
+    /// tombstoned or has metadata fails with [`local::Error::Conflict`] as
+    /// the store has nowhere to keep it and would hand out staged and
+    /// disabled versions as the latest.
+    pub fn from_local(local: Local<KeyType>) -> Result<Self, local::Error> {
+        let kept_elsewhere = [
+            local.staged()?.first().copied(),
+            local.disabled()?.first().copied(),
+            local.pending_drops()?.keys().next().copied(),
+            local.reservations()?.keys().next().copied(),
+            local.tombstones()?.keys().next().copied(),
+            local.all_meta()?.keys().next().copied(),
+        ];
+
+        if let Some(version) = kept_elsewhere.into_iter().flatten().min() {
+            return Err(local::Error::Conflict(version));
+        }
+
+        let count = local.count()?;
+
+        Ok(AsyncLocal {
+            count: Mutex::new(count),
+            store: RwLock::new(local.into_entries().into_iter().collect()),
+        })
+    }
+
+    /// a [`Local`] with the counter and keys of the store
+    pub fn into_local(self) -> Local<KeyType> {
+        Local::from_parts(Parts::new(self.count.into_inner(), self.store.into_inner()))
+    }
+
+    /// adds the key as a new version and returns the version it was given.
+    /// fails with [`local::Error::CounterOverflow`] once the counter is at
+    /// `u64::MAX`.
+    pub async fn update(&self, key: KeyType) -> Result<u64, local::Error> {
+        let mut version_lock = self.count.lock().await;
+        let mut store_writer = self.store.write().await;
+
+        let version = version_lock.checked_add(1)
+            .ok_or(local::Error::CounterOverflow)?;
+
+        store_writer.insert(version, key);
+        *version_lock = version;
+
+        Ok(version)
+    }
+
+    /// removes the key of `version`, `None` if there was none
+    pub async fn drop(&self, version: &u64) -> Option<KeyType> {
+        self.store.write().await.remove(version)
+    }
+
+    pub async fn count(&self) -> u64 {
+        *self.count.lock().await
+    }
+
+    /// the versions in the store in ascending order
+    pub async fn versions(&self) -> Vec<u64> {
+        self.store.read().await.keys().copied().collect()
+    }
+}
+
+impl<KeyType> AsyncLocal<KeyType>
+where
+    KeyType: Clone
+{
+    pub async fn get(&self, version: &u64) -> Option<KeyType> {
+        self.store.read().await.get(version).cloned()
+    }
+
+    pub async fn latest(&self) -> Option<KeyType> {
+        self.latest_version().await.map(|found| found.1)
+    }
+
+    pub async fn latest_version(&self) -> Option<VersionedKey<KeyType>> {
+        self.store.read()
+            .await
+            .last_key_value()
+            .map(|(version, key)| VersionedKey(*version, key.clone()))
+    }
+
+    /// a copy of the counter and keys. serializes to the same json as a
+    /// [`Local`] with only keys.
+    pub async fn snapshot(&self) -> Snapshot<KeyType> {
+        let version_lock = self.count.lock().await;
+        let store_reader = self.store.read().await;
+
+        Snapshot {
+            count: *version_lock,
+            store: store_reader.clone(),
+        }
+    }
+}
+
+impl<KeyType> Default for AsyncLocal<KeyType> {
+    fn default() -> Self {
+        AsyncLocal::new()
+    }
+}
+
+/// serialized the same as a [`Local`] with only keys, in every format.
+/// serializing cannot wait for the locks so it fails if a change is
+/// running, use [`snapshot`](AsyncLocal::snapshot) while other tasks use
+/// the store.
+impl<KeyType> Serialize for AsyncLocal<KeyType>
+where
+    KeyType: Serialize
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer
+    {
+        let (Ok(version_lock), Ok(store_reader)) = (self.count.try_lock(), self.store.try_read()) else {
+            return Err(S::Error::custom("AsyncLocal is locked"));
+        };
+
+        let failed = Cell::new(None);
+
+        Fields::keys_only(*version_lock, &store_reader, &failed)
+            .serialize(serializer)
+    }
+}
+
+/// accepts everything a [`Local`] is deserialized from, only the counter
+/// and keys are kept
+impl<'de, KeyType> Deserialize<'de> for AsyncLocal<KeyType>
+where
+    KeyType: Deserialize<'de>
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>
+    {
+        let local = Local::deserialize(deserializer)?;
+
+        AsyncLocal::from_local(local).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn basics() {
+        let local = AsyncLocal::new();
+
+        assert_eq!(local.latest().await, None);
+        assert_eq!(local.update(10).await.unwrap(), 1);
+        assert_eq!(local.update(20).await.unwrap(), 2);
+        assert_eq!(local.get(&1).await, Some(10));
+        assert_eq!(local.latest().await, Some(20));
+        assert_eq!(local.drop(&2).await, Some(20));
+        assert_eq!(local.drop(&2).await, None);
+        assert_eq!(local.latest().await, Some(10));
+        assert_eq!(local.update(30).await.unwrap(), 3);
+        assert_eq!(local.versions().await, vec![1, 3]);
+    }
+
+    #[tokio::test]
+    async fn concurrent_updates() {
+        let local = Arc::new(AsyncLocal::new());
+
+        let tasks: Vec<_> = (0..8).map(|task| {
+            let local = local.clone();
+
+            tokio::spawn(async move {
+                let mut versions = Vec::new();
+
+                for n in 0..25 {
+                    versions.push(local.update(task * 100 + n).await.unwrap());
+
+                    tokio::task::yield_now().await;
+                }
+
+                versions
+            })
+        }).collect();
+
+        let mut versions = Vec::new();
+
+        for task in tasks {
+            versions.extend(task.await.unwrap());
+        }
+
+        versions.sort_unstable();
+
+        assert_eq!(versions, (1..=200).collect::<Vec<_>>());
+        assert_eq!(local.count().await, 200);
+    }
+
+    #[tokio::test]
+    async fn serde() {
+        let local = AsyncLocal::new();
+
+        local.update(10).await.unwrap();
+        local.update(20).await.unwrap();
+        local.drop(&1).await;
+
+        let json = serde_json::to_string(&local).unwrap();
+
+        assert_eq!(json, serde_json::to_string(&local.snapshot().await).unwrap());
+
+        let loaded: Local<u64> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(loaded.count().unwrap(), 2);
+        assert_eq!(loaded.versions().unwrap(), vec![2]);
+
+        let back: AsyncLocal<u64> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(back.update(30).await.unwrap(), 3);
+
+        let local = back.into_local();
+
+        assert_eq!(local.versions().unwrap(), vec![2, 3]);
+
+        let again = AsyncLocal::from_local(local).unwrap();
+
+        assert_eq!(again.latest().await, Some(30));
+
+        let _writer = again.store.write().await;
+
+        assert!(serde_json::to_string(&again).is_err());
+    }
+
+    #[cfg(feature = "binary")]
+    #[tokio::test]
+    async fn bincode() {
+        let local: AsyncLocal<u64> = AsyncLocal::new();
+
+        local.update(10).await.unwrap();
+        local.update(20).await.unwrap();
+        local.drop(&1).await;
+
+        let bytes = bincode::serialize(&local).unwrap();
+
+        assert_eq!(bytes, bincode::serialize(&Local::from_parts(Parts::new(2, [(2, 20u64)].into()))).unwrap());
+
+        let back: AsyncLocal<u64> = bincode::deserialize(&bytes).unwrap();
+
+        assert_eq!(back.count().await, 2);
+        assert_eq!(back.versions().await, vec![2]);
+        assert_eq!(back.latest().await, Some(20));
+
+        let loaded: Local<u64> = bincode::deserialize(&bytes).unwrap();
+
+        assert_eq!(loaded.count().unwrap(), 2);
+        assert_eq!(loaded.versions().unwrap(), vec![2]);
+    }
+
+    #[tokio::test]
+    async fn from_local_with_state() {
+        let staged = Local::new();
+
+        staged.update(10).unwrap();
+        staged.stage(20).unwrap();
+
+        assert!(matches!(AsyncLocal::from_local(staged), Err(local::Error::Conflict(2))));
+
+        let disabled = Local::new();
+
+        disabled.update(10).unwrap();
+        disabled.update(20).unwrap();
+        disabled.disable(&2).unwrap();
+
+        assert!(matches!(AsyncLocal::from_local(disabled), Err(local::Error::Conflict(2))));
+
+        let dropped = Local::new();
+
+        dropped.update(10).unwrap();
+        dropped.update(20).unwrap();
+        dropped.drop(&1).unwrap();
+
+        assert!(matches!(AsyncLocal::from_local(dropped), Err(local::Error::Conflict(1))));
+
+        let plain = Local::new();
+
+        plain.update(10).unwrap();
+        plain.get(&1).unwrap();
+
+        assert_eq!(AsyncLocal::from_local(plain).unwrap().latest().await, Some(10));
+    }
+
+    #[tokio::test]
+    async fn counter_overflow() {
+        let local = AsyncLocal {
+            count: Mutex::new(u64::MAX),
+            store: RwLock::new(BTreeMap::new()),
+        };
+
+        assert!(matches!(local.update(10).await, Err(local::Error::CounterOverflow)));
+        assert_eq!(local.count().await, u64::MAX);
+        assert!(local.versions().await.is_empty());
+    }
+}
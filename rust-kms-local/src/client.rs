@@ -0,0 +1,250 @@
+//! a [`Manager`] for keys served by the sidecar `server` feature or
+//! anything that speaks the same protocol.
+//!
+//! every call is a blocking http request, nothing is cached.
+
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use rust_kms_core::traits::Manager;
+
+use crate::key::Key;
+use crate::wire::KeyBody;
+
+/// the timeout used when none is given to the builder
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum Error {
+    /// the version does not exist or the store is empty
+    NotFound,
+    /// the token was missing or rejected
+    Unauthorized,
+    /// any other unsuccessful status code
+    Status(u16),
+    /// the request could not be sent or the response could not be read
+    Transport(Box<ureq::Transport>),
+    /// the response was not a key
+    Body(io::Error),
+    Base64(base64::DecodeError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound => f.write_str("NotFound"),
+            Error::Unauthorized => f.write_str("Unauthorized"),
+            Error::Status(code) => write!(f, "Status {}", code),
+            Error::Transport(_) => f.write_str("Transport"),
+            Error::Body(_) => f.write_str("Body"),
+            Error::Base64(_) => f.write_str("Base64"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Transport(e) => Some(e.as_ref()),
+            Error::Body(e) => Some(e),
+            Error::Base64(e) => Some(e),
+            Error::NotFound |
+            Error::Unauthorized |
+            Error::Status(_) => None,
+        }
+    }
+}
+
+impl From<ureq::Error> for Error {
+    fn from(e: ureq::Error) -> Self {
+        match e {
+            ureq::Error::Status(404, _) => Error::NotFound,
+            ureq::Error::Status(401, _) => Error::Unauthorized,
+            ureq::Error::Status(code, _) => Error::Status(code),
+            ureq::Error::Transport(transport) => Error::Transport(Box::new(transport)),
+        }
+    }
+}
+
+pub struct RemoteManagerBuilder {
+    base_url: String,
+    token: Option<String>,
+    timeout: Duration,
+}
+
+impl RemoteManagerBuilder {
+    /// the bearer token sent with every request
+    pub fn token<T>(mut self, token: T) -> Self
+    where
+        T: Into<String>
+    {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// the limit for a whole request, including connecting
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn build(self) -> RemoteManager {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(self.timeout)
+            .build();
+
+        RemoteManager {
+            agent,
+            base_url: self.base_url.trim_end_matches('/').to_owned(),
+            token: self.token,
+        }
+    }
+}
+
+/// reads keys from a sidecar over http
+pub struct RemoteManager {
+    agent: ureq::Agent,
+    base_url: String,
+    token: Option<String>,
+}
+
+impl RemoteManager {
+    /// `base_url` is the address of the server without the `/v1` path,
+    /// e.g. `http://127.0.0.1:8200`
+    pub fn builder<U>(base_url: U) -> RemoteManagerBuilder
+    where
+        U: Into<String>
+    {
+        RemoteManagerBuilder {
+            base_url: base_url.into(),
+            token: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    fn fetch(&self, path: &str) -> Result<Key<Vec<u8>>, Error> {
+        let mut request = self.agent.get(&format!("{}{}", self.base_url, path));
+
+        if let Some(token) = &self.token {
+            request = request.set("Authorization", &format!("Bearer {}", token));
+        }
+
+        let body: KeyBody = request.call()?
+            .into_json()
+            .map_err(Error::Body)?;
+
+        body.to_key().map_err(Error::Base64)
+    }
+}
+
+impl Manager for RemoteManager {
+    type Key = Key<Vec<u8>>;
+    type Version = u64;
+    type Error = Error;
+
+    fn get(&self, version: u64) -> Result<Self::Key, Self::Error> {
+        self.fetch(&format!("/v1/keys/{}", version))
+    }
+
+    fn latest(&self) -> Result<Self::Key, Self::Error> {
+        self.fetch("/v1/keys/latest")
+    }
+}
+
+impl fmt::Debug for RemoteManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteManager")
+            .field("base_url", &self.base_url)
+            .field("token", &self.token.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::net::SocketAddr;
+
+    use crate::local::Local;
+
+    const TOKEN: &str = "client-token";
+
+    /// runs the sidecar on an ephemeral port for the rest of the test run
+    fn start_server() -> (SocketAddr, Arc<Local<Key<Vec<u8>>>>) {
+        let store = Arc::new(Local::new());
+
+        for byte in 1..=3u8 {
+            let mut builder = Key::builder(vec![byte; 16]);
+            builder.set_created(byte as u64 * 10);
+
+            store.update(builder.build().unwrap()).unwrap();
+        }
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")
+            .expect("failed to bind ephemeral port");
+        let addr = listener.local_addr().unwrap();
+        let app = crate::server::router(store.clone(), TOKEN).unwrap();
+
+        listener.set_nonblocking(true).unwrap();
+
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_io()
+                .build()
+                .expect("failed to build runtime");
+
+            runtime.block_on(async move {
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+
+                axum::serve(listener, app).await
+            })
+        });
+
+        (addr, store)
+    }
+
+    #[test]
+    fn remote() {
+        let (addr, store) = start_server();
+        let remote = RemoteManager::builder(format!("http://{}/", addr))
+            .token(TOKEN)
+            .timeout(Duration::from_secs(5))
+            .build();
+
+        assert_eq!(remote.latest().unwrap(), store.latest().unwrap().unwrap());
+        assert_eq!(remote.get(2).unwrap(), store.get(&2).unwrap().unwrap());
+        assert!(matches!(remote.get(99), Err(Error::NotFound)));
+
+        let anonymous = RemoteManager::builder(format!("http://{}", addr)).build();
+
+        assert!(matches!(anonymous.latest(), Err(Error::Unauthorized)));
+
+        let wrong = RemoteManager::builder(format!("http://{}", addr))
+            .token("wrong-token")
+            .build();
+
+        assert!(matches!(wrong.get(1), Err(Error::Unauthorized)));
+    }
+
+    #[test]
+    fn transport() {
+        // nothing listens on a port that was just released
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let remote = RemoteManager::builder(format!("http://{}", addr))
+            .token(TOKEN)
+            .timeout(Duration::from_secs(1))
+            .build();
+
+        assert!(matches!(remote.latest(), Err(Error::Transport(_))));
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::key::Key;
+use crate::local::{Local, Parts, Reserved, Tombstone};
+
+/// the representative values that have golden fixtures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fixture {
+    KeyVecJson,
+    KeyVecBincode,
+    KeyArrayJson,
+    KeyArrayBincode,
+    LocalJson,
+    LocalBincode,
+    /// a store whose versions sort differently as numbers and as strings,
+    /// with every serialized part that is keyed by version filled in. locks in that
+    /// versions are always written in ascending numeric order.
+    OrderJson,
+    OrderBincode,
+}
+
+impl Fixture {
+    pub const ALL: [Fixture; 8] = [
+        Fixture::KeyVecJson,
+        Fixture::KeyVecBincode,
+        Fixture::KeyArrayJson,
+        Fixture::KeyArrayBincode,
+        Fixture::LocalJson,
+        Fixture::LocalBincode,
+        Fixture::OrderJson,
+        Fixture::OrderBincode,
+    ];
+}
+
+impl fmt::Display for Fixture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fixture::KeyVecJson => f.write_str("Key<Vec<u8>> json"),
+            Fixture::KeyVecBincode => f.write_str("Key<Vec<u8>> bincode"),
+            Fixture::KeyArrayJson => f.write_str("Key<[u8; 32]> json"),
+            Fixture::KeyArrayBincode => f.write_str("Key<[u8; 32]> bincode"),
+            Fixture::LocalJson => f.write_str("Local<Key<Vec<u8>>> json"),
+            Fixture::LocalBincode => f.write_str("Local<Key<Vec<u8>>> bincode"),
+            Fixture::OrderJson => f.write_str("Local<u64> order json"),
+            Fixture::OrderBincode => f.write_str("Local<u64> order bincode"),
+        }
+    }
+}
+
+/// the golden bytes of a fixture for this version of the crate. with the
+/// `rfc3339-timestamps` feature the json fixtures are the ones in
+/// `fixtures/compat/rfc3339` with created written as a timestamp string.
+pub fn current_fixture(kind: Fixture) -> &'static [u8] {
+    match kind {
+        #[cfg(not(feature = "rfc3339-timestamps"))]
+        Fixture::KeyVecJson => include_bytes!("../fixtures/compat/key_vec.json"),
+        #[cfg(feature = "rfc3339-timestamps")]
+        Fixture::KeyVecJson => include_bytes!("../fixtures/compat/rfc3339/key_vec.json"),
+        Fixture::KeyVecBincode => include_bytes!("../fixtures/compat/key_vec.bin"),
+        #[cfg(not(feature = "rfc3339-timestamps"))]
+        Fixture::KeyArrayJson => include_bytes!("../fixtures/compat/key_array.json"),
+        #[cfg(feature = "rfc3339-timestamps")]
+        Fixture::KeyArrayJson => include_bytes!("../fixtures/compat/rfc3339/key_array.json"),
+        Fixture::KeyArrayBincode => include_bytes!("../fixtures/compat/key_array.bin"),
+        #[cfg(not(feature = "rfc3339-timestamps"))]
+        Fixture::LocalJson => include_bytes!("../fixtures/compat/local.json"),
+        #[cfg(feature = "rfc3339-timestamps")]
+        Fixture::LocalJson => include_bytes!("../fixtures/compat/rfc3339/local.json"),
+        Fixture::LocalBincode => include_bytes!("../fixtures/compat/local.bin"),
+        Fixture::OrderJson => include_bytes!("../fixtures/compat/order.json"),
+        Fixture::OrderBincode => include_bytes!("../fixtures/compat/order.bin"),
+    }
+}
+
+/// why bytes are not compatible with the current representation
+#[derive(Debug)]
+pub struct Report {
+    pub kind: Fixture,
+    pub reason: Reason,
+}
+
+#[derive(Debug)]
+pub enum Reason {
+    /// the bytes could not be deserialized
+    Deserialize(String),
+    /// the bytes deserialized but serialize to something other than the
+    /// current fixture
+    Mismatch {
+        expected: Vec<u8>,
+        found: Vec<u8>,
+    },
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.reason {
+            Reason::Deserialize(msg) => write!(f, "{} failed to deserialize: {}", self.kind, msg),
+            Reason::Mismatch { .. } => write!(f, "{} does not match the current fixture", self.kind),
+        }
+    }
+}
+
+impl std::error::Error for Report {}
+
+fn key_vec() -> Key<Vec<u8>> {
+    Key::from_parts(vec![1, 2, 3, 4], 1_700_000_000)
+}
+
+fn key_array() -> Key<[u8; 32]> {
+    let mut data = [0; 32];
+
+    for (i, b) in data.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+
+    Key::from_parts(data, 1_700_000_001)
+}
+
+/// three versions with the second missing. the gap is built directly
+/// instead of with `drop` so no tombstone, which holds the current time, ends
+/// up in the fixture.
+fn local() -> Local<Key<Vec<u8>>> {
+    let store = BTreeMap::from([
+        (1, Key::from_parts(vec![1], 1_700_000_010)),
+        (3, Key::from_parts(vec![3, 3, 3], 1_700_000_030)),
+    ]);
+
+    Local::from_parts(Parts::new(3, store))
+}
+
+/// versions 2, 9, 10 and 100 in the store with 3 and 20 tombstoned and 11
+/// reserved, inserted in descending order. every timestamp is fixed.
+fn order() -> Local<u64> {
+    let versions = [100, 10, 9, 2];
+    let mut parts = Parts::new(100, versions.iter().map(|version| (*version, version * 7)).collect());
+
+    parts.pending.insert(100, 1_800_000_000);
+    parts.pending.insert(9, 1_800_000_009);
+    parts.reserved.insert(11, Reserved::Abandoned);
+    parts.tombstones.insert(20, Tombstone { at: 1_700_000_020, note: None, evicted: true });
+    parts.tombstones.insert(3, Tombstone { at: 1_700_000_003, note: Some("three".into()), evicted: false });
+    parts.staged.insert(10);
+    parts.staged.insert(2);
+
+    Local::from_parts(parts)
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<Vec<u8>, String> {
+    serde_json::to_vec(value).map_err(|e| e.to_string())
+}
+
+fn to_bincode<T: Serialize>(value: &T) -> Result<Vec<u8>, String> {
+    bincode::serialize(value).map_err(|e| e.to_string())
+}
+
+fn json_round_trip<T>(bytes: &[u8]) -> Result<Vec<u8>, String>
+where
+    T: Serialize + DeserializeOwned
+{
+    to_json(&serde_json::from_slice::<T>(bytes).map_err(|e| e.to_string())?)
+}
+
+fn bincode_round_trip<T>(bytes: &[u8]) -> Result<Vec<u8>, String>
+where
+    T: Serialize + DeserializeOwned
+{
+    to_bincode(&bincode::deserialize::<T>(bytes).map_err(|e| e.to_string())?)
+}
+
+/// serializes the representative value of a fixture with the current
+/// version of the crate
+pub fn serialize_current(kind: Fixture) -> Vec<u8> {
+    let result = match kind {
+        Fixture::KeyVecJson => to_json(&key_vec()),
+        Fixture::KeyVecBincode => to_bincode(&key_vec()),
+        Fixture::KeyArrayJson => to_json(&key_array()),
+        Fixture::KeyArrayBincode => to_bincode(&key_array()),
+        Fixture::LocalJson => to_json(&local()),
+        Fixture::LocalBincode => to_bincode(&local()),
+        Fixture::OrderJson => to_json(&order()),
+        Fixture::OrderBincode => to_bincode(&order()),
+    };
+
+    result.expect("failed to serialize fixture value")
+}
+
+/// checks that bytes saved from the representative value of a fixture,
+/// possibly by another version of the crate, deserialize with this version
+/// and serialize back to the current fixture.
+pub fn check_deserialize_current(kind: Fixture, bytes: &[u8]) -> Result<(), Report> {
+    let result = match kind {
+        Fixture::KeyVecJson => json_round_trip::<Key<Vec<u8>>>(bytes),
+        Fixture::KeyVecBincode => bincode_round_trip::<Key<Vec<u8>>>(bytes),
+        Fixture::KeyArrayJson => json_round_trip::<Key<[u8; 32]>>(bytes),
+        Fixture::KeyArrayBincode => bincode_round_trip::<Key<[u8; 32]>>(bytes),
+        Fixture::LocalJson => json_round_trip::<Local<Key<Vec<u8>>>>(bytes),
+        Fixture::LocalBincode => bincode_round_trip::<Local<Key<Vec<u8>>>>(bytes),
+        Fixture::OrderJson => json_round_trip::<Local<u64>>(bytes),
+        Fixture::OrderBincode => bincode_round_trip::<Local<u64>>(bytes),
+    };
+
+    let found = result.map_err(|msg| Report {
+        kind,
+        reason: Reason::Deserialize(msg),
+    })?;
+
+    let expected = current_fixture(kind);
+
+    if found != expected {
+        return Err(Report {
+            kind,
+            reason: Reason::Mismatch {
+                expected: expected.to_vec(),
+                found,
+            }
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    #[ignore]
+    fn write_fixtures() {
+        let files = [
+            (Fixture::KeyVecJson, "key_vec.json"),
+            (Fixture::KeyVecBincode, "key_vec.bin"),
+            (Fixture::KeyArrayJson, "key_array.json"),
+            (Fixture::KeyArrayBincode, "key_array.bin"),
+            (Fixture::LocalJson, "local.json"),
+            (Fixture::LocalBincode, "local.bin"),
+            (Fixture::OrderJson, "order.json"),
+            (Fixture::OrderBincode, "order.bin"),
+        ];
+
+        let json_dir = if cfg!(feature = "rfc3339-timestamps") {
+            "fixtures/compat/rfc3339"
+        } else {
+            "fixtures/compat"
+        };
+
+        for (kind, file) in files {
+            let dir = if file.ends_with(".json") && !file.starts_with("order") {
+                json_dir
+            } else {
+                "fixtures/compat"
+            };
+
+            std::fs::write(format!("{}/{}", dir, file), serialize_current(kind))
+                .expect("failed to write fixture");
+        }
+    }
+
+    #[test]
+    fn fixtures() {
+        for kind in Fixture::ALL {
+            assert_eq!(
+                serialize_current(kind),
+                current_fixture(kind),
+                "{} changed. if this is intended run the ignored write_fixtures test and review the diff",
+                kind
+            );
+
+            if let Err(report) = check_deserialize_current(kind, current_fixture(kind)) {
+                panic!("{}", report);
+            }
+        }
+    }
+
+    /// json saved before the feature was enabled still loads
+    #[cfg(feature = "rfc3339-timestamps")]
+    #[test]
+    fn legacy_json() {
+        let legacy: [(Fixture, &[u8]); 3] = [
+            (Fixture::KeyVecJson, include_bytes!("../fixtures/compat/key_vec.json")),
+            (Fixture::KeyArrayJson, include_bytes!("../fixtures/compat/key_array.json")),
+            (Fixture::LocalJson, include_bytes!("../fixtures/compat/local.json")),
+        ];
+
+        for (kind, bytes) in legacy {
+            if let Err(report) = check_deserialize_current(kind, bytes) {
+                panic!("{}", report);
+            }
+        }
+    }
+}
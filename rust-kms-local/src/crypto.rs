@@ -1,10 +1,15 @@
 use chacha20poly1305::{
     XChaCha20Poly1305,
-    aead::Aead,
+    aead::{Aead, Payload},
     KeyInit,
     Error as ChaChaError
 };
-use rand::RngCore;
+use rand::{CryptoRng, RngCore};
+#[cfg(feature = "mlock")]
+use chacha20poly1305::aead::AeadInPlace;
+
+#[cfg(feature = "mlock")]
+use crate::memory::{LockedBuffer, LockMode};
 
 pub const KEY_LEN: usize = 32;
 pub const NONCE_LEN: usize = 24;
@@ -12,11 +17,25 @@ pub const NONCE_LEN: usize = 24;
 pub type Key = [u8; KEY_LEN];
 pub type Nonce = [u8; NONCE_LEN];
 
+/// the bytes that start every blob produced by [`tag_version`]
+pub const VERSION_MAGIC: [u8; 4] = *b"rkv\x01";
+
+/// the largest number of bytes a varint encoded u64 can take
+const MAX_VARINT_LEN: usize = 10;
+
 #[derive(Debug)]
 pub enum Error {
     InvalidEncoding,
+    InvalidMagic,
+    TruncatedVersion,
+    VersionOverflow,
+    /// the version is encoded with more bytes than it needs, so the same
+    /// version could be written more than one way
+    NonMinimalVersion,
     ChaCha,
     Rand(rand::Error),
+    #[cfg(feature = "mlock")]
+    Memory(crate::memory::Error),
 }
 
 impl std::fmt::Display for Error {
@@ -24,7 +43,13 @@ impl std::fmt::Display for Error {
         match self {
             Error::Rand(e) => write!(f, "Rand {}", e),
             Error::ChaCha => write!(f, "ChaCha"),
-            Error::InvalidEncoding => write!(f, "InvalidEncoding")
+            Error::InvalidEncoding => write!(f, "InvalidEncoding"),
+            Error::InvalidMagic => write!(f, "InvalidMagic"),
+            Error::TruncatedVersion => write!(f, "TruncatedVersion"),
+            Error::VersionOverflow => write!(f, "VersionOverflow"),
+            Error::NonMinimalVersion => write!(f, "NonMinimalVersion"),
+            #[cfg(feature = "mlock")]
+            Error::Memory(e) => write!(f, "Memory {}", e),
         }
     }
 }
@@ -33,8 +58,14 @@ impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Error::Rand(e) => Some(e),
+            #[cfg(feature = "mlock")]
+            Error::Memory(e) => Some(e),
             Error::ChaCha |
-            Error::InvalidEncoding => None
+            Error::InvalidEncoding |
+            Error::InvalidMagic |
+            Error::TruncatedVersion |
+            Error::VersionOverflow |
+            Error::NonMinimalVersion => None
         }
     }
 }
@@ -56,31 +87,36 @@ pub fn empty_key() -> Key {
     [0; KEY_LEN]
 }
 
+/// a random nonce from the [`rand_source`](crate::rand_source)
 pub fn make_nonce() -> Result<Nonce, Error> {
     let mut nonce: Nonce = [0; NONCE_LEN];
 
-    rand::rngs::OsRng.try_fill_bytes(&mut nonce)?;
+    crate::rand_source::fill_bytes(&mut nonce)?;
 
     Ok(nonce)
 }
 
-fn decode_data(data: Vec<u8>) -> Result<(Nonce, Vec<u8>), Error> {
+/// a random nonce from the given rng
+pub fn make_nonce_with_rng<R>(rng: &mut R) -> Result<Nonce, Error>
+where
+    R: RngCore + CryptoRng
+{
     let mut nonce: Nonce = [0; NONCE_LEN];
-    let mut encrypted: Vec<u8> = Vec::with_capacity(data.len() - nonce.len());
-    let mut iter = data.into_iter();
-
-    for i in 0..nonce.len() {
-        if let Some(b) = iter.next() {
-            nonce[i] = b;
-        } else {
-            return Err(Error::InvalidEncoding);
-        }
-    }
 
-    while let Some(b) = iter.next() {
-        encrypted.push(b);
+    rng.try_fill_bytes(&mut nonce)?;
+
+    Ok(nonce)
+}
+
+fn decode_data(mut data: Vec<u8>) -> Result<(Nonce, Vec<u8>), Error> {
+    if data.len() < NONCE_LEN {
+        return Err(Error::InvalidEncoding);
     }
 
+    let encrypted = data.split_off(NONCE_LEN);
+    let mut nonce: Nonce = [0; NONCE_LEN];
+    nonce.copy_from_slice(&data);
+
     Ok((nonce, encrypted))
 }
 
@@ -99,24 +135,262 @@ fn encode_data(nonce: Nonce, data: Vec<u8>) -> Result<Vec<u8>, Error> {
 }
 
 pub fn decrypt_data(key: &Key, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    decrypt_data_aad(key, data, &[])
+}
+
+pub fn encrypt_data(key: &Key, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    encrypt_data_aad(key, data, &[])
+}
+
+/// decrypts data that was encrypted with [`encrypt_data_aad`]. the provided
+/// associated data must match what was given during encryption otherwise
+/// authentication will fail.
+pub fn decrypt_data_aad(key: &Key, data: Vec<u8>, aad: &[u8]) -> Result<Vec<u8>, Error> {
     let (nonce, encrypted) = decode_data(data)?;
 
     let cipher = XChaCha20Poly1305::new_from_slice(key)
         .expect("invalid key provided to chacha cipher");
 
-    Ok(cipher.decrypt((&nonce).into(), encrypted.as_slice())?)
+    Ok(cipher.decrypt((&nonce).into(), Payload {
+        msg: encrypted.as_slice(),
+        aad
+    })?)
 }
 
-pub fn encrypt_data(key: &Key, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+/// [`decrypt_data_aad`] into a [`LockedBuffer`] so the plaintext never
+/// leaves locked memory. the ciphertext is decrypted in place.
+#[cfg(feature = "mlock")]
+pub fn decrypt_data_aad_locked(key: &Key, data: &[u8], aad: &[u8], mode: LockMode) -> Result<LockedBuffer, Error> {
+    if data.len() < NONCE_LEN {
+        return Err(Error::InvalidEncoding);
+    }
+
+    let (nonce, encrypted) = data.split_at(NONCE_LEN);
+    let mut buffer = LockedBuffer::with_mode(encrypted.len(), mode)
+        .map_err(Error::Memory)?;
+
+    buffer.extend_from_slice(encrypted)
+        .map_err(Error::Memory)?;
+
+    let cipher = XChaCha20Poly1305::new_from_slice(key)
+        .expect("invalid key provided to chacha cipher");
+
+    cipher.decrypt_in_place(nonce.into(), aad, &mut buffer)?;
+
+    Ok(buffer)
+}
+
+/// a master key held in locked memory that is zeroed when dropped
+#[cfg(feature = "mlock")]
+#[derive(Clone)]
+pub struct MasterKey(LockedBuffer);
+
+#[cfg(feature = "mlock")]
+impl MasterKey {
+    /// copies the key into locked memory, falling back to unlocked memory
+    /// if it cannot be locked
+    pub fn new(key: Key) -> Self {
+        MasterKey(LockedBuffer::from_slice(&key))
+    }
+
+    /// copies the key into locked memory, failing if it cannot be locked
+    pub fn strict(key: Key) -> Result<Self, Error> {
+        let mut buffer = LockedBuffer::strict(KEY_LEN)
+            .map_err(Error::Memory)?;
+
+        buffer.extend_from_slice(&key)
+            .map_err(Error::Memory)?;
+
+        Ok(MasterKey(buffer))
+    }
+
+    /// a random key from the [`rand_source`](crate::rand_source) generated
+    /// directly into locked memory
+    pub fn generate(mode: LockMode) -> Result<Self, Error> {
+        let mut buffer = LockedBuffer::with_mode(KEY_LEN, mode)
+            .map_err(Error::Memory)?;
+
+        buffer.extend_from_slice(&empty_key())
+            .map_err(Error::Memory)?;
+
+        crate::rand_source::fill_bytes(buffer.as_mut_slice())?;
+
+        Ok(MasterKey(buffer))
+    }
+
+    pub fn key(&self) -> &Key {
+        self.0.as_slice()
+            .try_into()
+            .expect("master key is always KEY_LEN bytes")
+    }
+
+    /// if the key is held in locked memory
+    pub fn is_locked(&self) -> bool {
+        self.0.is_locked()
+    }
+
+    /// why the key could not be locked
+    pub fn lock_error(&self) -> Option<&std::io::Error> {
+        self.0.lock_error()
+    }
+}
+
+#[cfg(feature = "mlock")]
+impl std::fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MasterKey")
+            .field("locked", &self.is_locked())
+            .finish_non_exhaustive()
+    }
+}
+
+/// encrypts data with additional associated data that is authenticated but
+/// not stored in the output. the same associated data is required to
+/// decrypt.
+pub fn encrypt_data_aad(key: &Key, data: Vec<u8>, aad: &[u8]) -> Result<Vec<u8>, Error> {
     let nonce = make_nonce()?;
     let cipher = XChaCha20Poly1305::new_from_slice(key)
         .expect("invalid key provded to chacha cipher");
 
-    let encrypted = cipher.encrypt((&nonce).into(), data.as_slice())?;
+    let encrypted = cipher.encrypt((&nonce).into(), Payload {
+        msg: data.as_slice(),
+        aad
+    })?;
 
     encode_data(nonce, encrypted)
 }
 
+/// encrypts data with a nonce derived from the key and the data instead of
+/// a random one so the same key and data always give the same output.
+///
+/// the nonce is an HMAC-SHA256 of the data, keyed by a subkey derived from
+/// the key, truncated to [`NONCE_LEN`]. a nonce is only repeated for the same
+/// data so the cipher is never reused across different plaintexts, but
+/// anyone holding two ciphertexts can tell whether they contain the same
+/// data. only use this when that is acceptable, e.g. for reproducible store
+/// files. the output decrypts with [`decrypt_data`].
+#[cfg(feature = "canonical")]
+pub fn encrypt_data_deterministic(key: &Key, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    encrypt_data_deterministic_aad(key, data, &[])
+}
+
+/// [`encrypt_data_deterministic`] with associated data. the associated data
+/// is also used when deriving the nonce.
+#[cfg(feature = "canonical")]
+pub fn encrypt_data_deterministic_aad(key: &Key, data: Vec<u8>, aad: &[u8]) -> Result<Vec<u8>, Error> {
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    let mut subkey = <Hmac<Sha256> as Mac>::new_from_slice(key)
+        .expect("hmac accepts keys of any length");
+    subkey.update(b"rust-kms deterministic nonce");
+
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&subkey.finalize().into_bytes())
+        .expect("hmac accepts keys of any length");
+    mac.update(&(aad.len() as u64).to_be_bytes());
+    mac.update(aad);
+    mac.update(&data);
+
+    let mut nonce: Nonce = [0; NONCE_LEN];
+    nonce.copy_from_slice(&mac.finalize().into_bytes()[..NONCE_LEN]);
+
+    let cipher = XChaCha20Poly1305::new_from_slice(key)
+        .expect("invalid key provded to chacha cipher");
+
+    let encrypted = cipher.encrypt((&nonce).into(), Payload {
+        msg: data.as_slice(),
+        aad
+    })?;
+
+    encode_data(nonce, encrypted)
+}
+
+/// the length of the value returned by [`key_hint`]
+pub const KEY_HINT_LEN: usize = 16;
+
+/// a short value that identifies a key without revealing it.
+///
+/// the hint is the authentication tag of an empty message encrypted under
+/// a fixed nonce, so it can only be produced by someone holding the key.
+pub fn key_hint(key: &Key) -> [u8; KEY_HINT_LEN] {
+    let cipher = XChaCha20Poly1305::new_from_slice(key)
+        .expect("invalid key provided to chacha cipher");
+    let nonce: Nonce = [0; NONCE_LEN];
+
+    let tag = cipher.encrypt((&nonce).into(), Payload {
+        msg: &[],
+        aad: b"rust-kms key hint",
+    }).expect("failed to encrypt empty message");
+
+    let mut rtn = [0; KEY_HINT_LEN];
+    rtn.copy_from_slice(&tag);
+    rtn
+}
+
+/// prefixes ciphertext with the key version that produced it.
+///
+/// the layout is [`VERSION_MAGIC`] followed by the version as an unsigned
+/// LEB128 varint and then the ciphertext unchanged. this is the single
+/// framing used for any ciphertext that needs to record its key version.
+pub fn tag_version(version: u64, ciphertext: Vec<u8>) -> Vec<u8> {
+    let mut rtn = Vec::with_capacity(VERSION_MAGIC.len() + MAX_VARINT_LEN + ciphertext.len());
+    let mut remaining = version;
+
+    rtn.extend_from_slice(&VERSION_MAGIC);
+
+    loop {
+        let byte = (remaining & 0x7f) as u8;
+        remaining >>= 7;
+
+        if remaining == 0 {
+            rtn.push(byte);
+            break;
+        }
+
+        rtn.push(byte | 0x80);
+    }
+
+    rtn.extend(ciphertext);
+    rtn
+}
+
+/// splits a blob created by [`tag_version`] into the key version and the
+/// ciphertext. a version with trailing zero groups, e.g. `0x80 0x00` for 0,
+/// fails with [`Error::NonMinimalVersion`] as [`tag_version`] never writes
+/// it.
+pub fn split_version(blob: &[u8]) -> Result<(u64, &[u8]), Error> {
+    let Some(rest) = blob.strip_prefix(&VERSION_MAGIC) else {
+        return Err(Error::InvalidMagic);
+    };
+
+    let mut version: u64 = 0;
+
+    for (index, byte) in rest.iter().enumerate() {
+        if index == MAX_VARINT_LEN {
+            return Err(Error::VersionOverflow);
+        }
+
+        let value = (byte & 0x7f) as u64;
+        let shift = index * 7;
+
+        if shift == 63 && value > 1 {
+            return Err(Error::VersionOverflow);
+        }
+
+        version |= value << shift;
+
+        if byte & 0x80 == 0 {
+            if index > 0 && *byte == 0 {
+                return Err(Error::NonMinimalVersion);
+            }
+
+            return Ok((version, &rest[index + 1..]));
+        }
+    }
+
+    Err(Error::TruncatedVersion)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -146,4 +420,137 @@ mod test {
 
         assert_eq!(bytes, decrypted.as_slice());
     }
+
+    #[test]
+    fn encrypt_decrypt_aad() {
+        let bytes = b"i am test data with associated data";
+        let empty_key = empty_key();
+
+        let encrypted = encrypt_data_aad(&empty_key, bytes.to_vec(), b"aad")
+            .expect("failed to encrypt data");
+
+        let decrypted = decrypt_data_aad(&empty_key, encrypted.clone(), b"aad")
+            .expect("failed to decrypt data");
+
+        assert_eq!(bytes, decrypted.as_slice());
+
+        assert!(
+            decrypt_data_aad(&empty_key, encrypted, b"other").is_err(),
+            "decrypted data with mismatched associated data"
+        );
+    }
+
+    #[test]
+    fn version_framing() {
+        for version in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let blob = tag_version(version, b"ciphertext".to_vec());
+            let (found, ciphertext) = split_version(&blob)
+                .expect("failed to split tagged blob");
+
+            assert_eq!(found, version);
+            assert_eq!(ciphertext, b"ciphertext");
+        }
+
+        let blob = tag_version(5, Vec::new());
+        let (found, ciphertext) = split_version(&blob)
+            .expect("failed to split empty ciphertext");
+
+        assert_eq!(found, 5);
+        assert!(ciphertext.is_empty());
+    }
+
+    #[test]
+    fn version_framing_missing_magic() {
+        assert!(matches!(split_version(b""), Err(Error::InvalidMagic)));
+        assert!(matches!(split_version(b"rkv"), Err(Error::InvalidMagic)));
+        assert!(matches!(split_version(b"xyz\x01\x05data"), Err(Error::InvalidMagic)));
+    }
+
+    #[cfg(feature = "mlock")]
+    #[test]
+    fn master_key() {
+        let master = MasterKey::generate(LockMode::BestEffort)
+            .expect("failed to generate master key");
+
+        assert_ne!(master.key(), &empty_key());
+        assert_eq!(master.is_locked(), master.lock_error().is_none());
+
+        let encrypted = encrypt_data_aad(master.key(), b"locked".to_vec(), b"aad").unwrap();
+        let decrypted = decrypt_data_aad_locked(master.key(), &encrypted, b"aad", LockMode::BestEffort)
+            .expect("failed to decrypt into locked memory");
+
+        assert_eq!(decrypted.as_slice(), b"locked");
+        assert!(matches!(
+            decrypt_data_aad_locked(master.key(), &encrypted, b"other", LockMode::BestEffort),
+            Err(Error::ChaCha)
+        ));
+
+        let copy = MasterKey::new(*master.key());
+
+        assert_eq!(copy.key(), master.key());
+    }
+
+    #[test]
+    fn decrypt_short_data() {
+        let empty_key = [0u8; KEY_LEN];
+
+        for len in 0..NONCE_LEN {
+            assert!(
+                matches!(decrypt_data(&empty_key, vec![0; len]), Err(Error::InvalidEncoding)),
+                "data of length {} was accepted",
+                len
+            );
+        }
+
+        assert!(matches!(decrypt_data(&empty_key, vec![0; NONCE_LEN]), Err(Error::ChaCha)));
+    }
+
+    #[test]
+    fn version_framing_truncated() {
+        let blob = tag_version(u64::MAX, Vec::new());
+
+        for len in VERSION_MAGIC.len()..blob.len() {
+            assert!(
+                matches!(split_version(&blob[..len]), Err(Error::TruncatedVersion)),
+                "truncated blob of length {} was accepted",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn version_framing_oversized() {
+        let mut blob = VERSION_MAGIC.to_vec();
+        blob.extend([0xff; 9]);
+        blob.push(0x02);
+
+        assert!(matches!(split_version(&blob), Err(Error::VersionOverflow)));
+
+        let mut blob = VERSION_MAGIC.to_vec();
+        blob.extend([0x80; 10]);
+        blob.push(0x00);
+
+        assert!(matches!(split_version(&blob), Err(Error::VersionOverflow)));
+    }
+
+    #[test]
+    fn version_framing_non_minimal() {
+        for encoded in [&[0x80, 0x00][..], &[0x81, 0x00], &[0xff, 0x80, 0x00]] {
+            let mut blob = VERSION_MAGIC.to_vec();
+            blob.extend_from_slice(encoded);
+            blob.extend_from_slice(b"data");
+
+            assert!(
+                matches!(split_version(&blob), Err(Error::NonMinimalVersion)),
+                "non minimal encoding {:x?} was accepted",
+                encoded
+            );
+        }
+
+        for version in [0, 1, 127, 128, 16_384, u64::MAX] {
+            let blob = tag_version(version, b"data".to_vec());
+
+            assert_eq!(split_version(&blob).unwrap(), (version, &b"data"[..]));
+        }
+    }
 }
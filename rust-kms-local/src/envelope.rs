@@ -0,0 +1,218 @@
+use std::fmt;
+
+use crate::crypto;
+use crate::key::Key;
+use crate::key_ref::{self, KeyRef};
+use crate::local::{self, Local};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug)]
+pub enum Error {
+    Local(local::Error),
+    Crypto(crypto::Error),
+    Empty,
+    /// the blob names a version the store does not have. `refreshed` is set
+    /// if the store was refreshed before giving up.
+    UnknownVersion {
+        version: u64,
+        refreshed: bool,
+    },
+    /// the refresh callback failed
+    Refresh(BoxError),
+    /// the blob is tagged with a version no key can have
+    KeyRef(key_ref::ParseError),
+}
+
+impl From<local::Error> for Error {
+    fn from(e: local::Error) -> Self {
+        Error::Local(e)
+    }
+}
+
+impl From<crypto::Error> for Error {
+    fn from(e: crypto::Error) -> Self {
+        Error::Crypto(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Local(_) => f.write_str("Local"),
+            Error::Crypto(_) => f.write_str("Crypto"),
+            Error::Empty => f.write_str("Empty"),
+            Error::UnknownVersion { version, refreshed: false } => write!(f, "UnknownVersion {}", version),
+            Error::UnknownVersion { version, refreshed: true } => write!(f, "UnknownVersion {} after refresh", version),
+            Error::Refresh(_) => f.write_str("Refresh"),
+            Error::KeyRef(_) => f.write_str("KeyRef"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Local(e) => Some(e),
+            Error::Crypto(e) => Some(e),
+            Error::Refresh(e) => Some(e.as_ref()),
+            Error::KeyRef(e) => Some(e),
+            Error::Empty |
+            Error::UnknownVersion { .. } => None,
+        }
+    }
+}
+
+/// encrypts data with the latest key and tags it with the key version
+/// using [`crypto::tag_version`]
+pub fn seal(manager: &Local<Key<crypto::Key>>, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    let Some(latest) = manager.latest_version()? else {
+        return Err(Error::Empty);
+    };
+
+    let ciphertext = crypto::encrypt_data(latest.data(), data)?;
+
+    Ok(crypto::tag_version(*latest.version(), ciphertext))
+}
+
+/// decrypts a blob from [`seal`] with the version named in its tag
+pub fn open(manager: &Local<Key<crypto::Key>>, blob: &[u8]) -> Result<Vec<u8>, Error> {
+    let (version, ciphertext) = crypto::split_version(blob)?;
+
+    let Some(key) = manager.get(&version)? else {
+        return Err(Error::UnknownVersion { version, refreshed: false });
+    };
+
+    Ok(crypto::decrypt_data(key.data(), ciphertext.to_vec())?)
+}
+
+/// the key a blob from [`seal`] was sealed with, without decrypting it
+pub fn key_ref(blob: &[u8]) -> Result<KeyRef, Error> {
+    let (version, _) = crypto::split_version(blob)?;
+
+    KeyRef::try_from(version).map_err(Error::KeyRef)
+}
+
+/// [`open`] that calls `refresh` once and tries again if the version is
+/// unknown, e.g. when another process rotated and saved the store after it
+/// was last loaded here.
+///
+/// `refresh` is only called for an unknown version and at most once per
+/// call. it is expected to bring the missing version into `manager`, such
+/// as by [`Local::reconcile`] with a freshly loaded store.
+pub fn open_with_refresh<F, E>(
+    manager: &Local<Key<crypto::Key>>,
+    blob: &[u8],
+    refresh: F
+) -> Result<Vec<u8>, Error>
+where
+    F: FnOnce() -> Result<(), E>,
+    E: Into<BoxError>,
+{
+    match open(manager, blob) {
+        Err(Error::UnknownVersion { .. }) => {
+            refresh().map_err(|e| Error::Refresh(e.into()))?;
+
+            open(manager, blob).map_err(|e| match e {
+                Error::UnknownVersion { version, .. } => Error::UnknownVersion { version, refreshed: true },
+                e => e
+            })
+        }
+        result => result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+
+    use crate::local::MergeStrategy;
+
+    fn key(byte: u8) -> Key<crypto::Key> {
+        Key::builder([byte; crypto::KEY_LEN]).build().unwrap()
+    }
+
+    #[test]
+    fn refresh() {
+        let reader = Local::new();
+        let writer = Local::new();
+
+        for byte in 1..=2 {
+            reader.update(key(byte)).unwrap();
+            writer.update(key(byte)).unwrap();
+        }
+
+        // the writer rotates before the reader has picked up the new key
+        writer.update(key(3)).unwrap();
+
+        let blob = seal(&writer, b"rolling".to_vec()).unwrap();
+
+        assert!(matches!(
+            open(&reader, &blob),
+            Err(Error::UnknownVersion { version: 3, refreshed: false })
+        ));
+
+        let calls = Cell::new(0);
+        let opened = open_with_refresh(&reader, &blob, || {
+            calls.set(calls.get() + 1);
+
+            let snapshot = writer.snapshot()?;
+
+            reader.reconcile(snapshot, MergeStrategy::Error).map(|_| ())
+        }).expect("failed to open after refresh");
+
+        assert_eq!(opened, b"rolling");
+        assert_eq!(calls.get(), 1);
+
+        // known versions never refresh
+        let opened = open_with_refresh(&reader, &blob, || -> Result<(), local::Error> {
+            panic!("refreshed for a known version")
+        }).unwrap();
+
+        assert_eq!(opened, b"rolling");
+    }
+
+    #[test]
+    fn blob_key_ref() {
+        let manager = Local::new();
+
+        manager.update(key(1)).unwrap();
+        manager.update(key(2)).unwrap();
+
+        let blob = seal(&manager, b"tagged".to_vec()).unwrap();
+        let key_ref = key_ref(&blob).unwrap();
+
+        assert_eq!(key_ref, manager.latest_version().unwrap().unwrap().key_ref());
+        assert_eq!(key_ref.to_string(), "00000000000000000002");
+
+        let untagged = crypto::tag_version(0, b"ciphertext".to_vec());
+
+        assert!(matches!(super::key_ref(&untagged), Err(Error::KeyRef(key_ref::ParseError::Zero(_)))));
+    }
+
+    #[test]
+    fn unknown_after_refresh() {
+        let reader = Local::new();
+        let writer = Local::new();
+
+        reader.update(key(1)).unwrap();
+        writer.update(key(1)).unwrap();
+        writer.update(key(2)).unwrap();
+
+        let blob = seal(&writer, b"lost".to_vec()).unwrap();
+        let calls = Cell::new(0);
+
+        let result = open_with_refresh(&reader, &blob, || -> Result<(), local::Error> {
+            calls.set(calls.get() + 1);
+            Ok(())
+        });
+
+        assert!(matches!(result, Err(Error::UnknownVersion { version: 2, refreshed: true })));
+        assert_eq!(calls.get(), 1);
+
+        let result = open_with_refresh(&reader, &blob, || Err(local::Error::Poisoned));
+
+        assert!(matches!(result, Err(Error::Refresh(_))));
+    }
+}
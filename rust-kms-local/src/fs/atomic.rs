@@ -0,0 +1,213 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+
+use crate::fs::error::Error;
+use crate::fs::retry::{self, RetryPolicy};
+
+#[cfg(feature = "harness")]
+thread_local! {
+    static CRASH_BEFORE_RENAME: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// makes the next atomic write on this thread stop after the temporary file
+/// is written, as if the process died before the rename
+#[cfg(feature = "harness")]
+pub(crate) fn crash_before_next_rename() {
+    CRASH_BEFORE_RENAME.with(|c| c.set(true));
+}
+
+/// the most times a rename is retried on windows while the destination is
+/// held open by another handle
+#[cfg(windows)]
+const SHARING_ATTEMPTS: u32 = 10;
+
+#[cfg(windows)]
+const SHARING_BACKOFF: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// windows refuses to replace a file that another handle has open without
+/// `FILE_SHARE_DELETE`, failing with a sharing violation or access denied
+#[cfg(windows)]
+fn is_sharing_violation(err: &std::io::Error) -> bool {
+    // ERROR_ACCESS_DENIED, ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION
+    matches!(err.raw_os_error(), Some(5) | Some(32) | Some(33))
+}
+
+/// renames `from` over `to`. on windows this is retried with a short
+/// backoff while the destination is held open by someone else, since that
+/// is usually a reader that is about to finish.
+fn replace(from: &Path, to: &Path) -> std::io::Result<()> {
+    #[cfg(windows)]
+    {
+        let mut attempts = 0;
+
+        loop {
+            match std::fs::rename(from, to) {
+                Err(err) if attempts < SHARING_ATTEMPTS && is_sharing_violation(&err) => {
+                    attempts += 1;
+
+                    std::thread::sleep(SHARING_BACKOFF * attempts);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    #[cfg(not(windows))]
+    std::fs::rename(from, to)
+}
+
+/// the file a store is written to before it is renamed over `path`
+pub fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name()
+        .map(|n| n.to_owned())
+        .unwrap_or_default();
+    name.push(".tmp");
+
+    path.with_file_name(name)
+}
+
+/// writes `bytes` to [`temp_path`] and renames it over `path` so that
+/// readers only ever see the old or the new contents. the temporary file is
+/// synced before the rename. the flag is checked before the temporary file
+/// is opened.
+pub fn write_with_cancel(
+    path: &Path,
+    bytes: &[u8],
+    retry: Option<&RetryPolicy>,
+    cancel: &AtomicBool
+) -> Result<(), Error> {
+    let temp = write_temp(path, bytes, retry, cancel)?;
+
+    rename_temp(&temp, path, retry)
+}
+
+/// the first half of [`write_with_cancel`], writes and syncs the temporary
+/// file of `path` and returns where it is. the flag is checked before the
+/// file is opened.
+pub(crate) fn write_temp(
+    path: &Path,
+    bytes: &[u8],
+    retry: Option<&RetryPolicy>,
+    cancel: &AtomicBool
+) -> Result<PathBuf, Error> {
+    let temp = temp_path(path);
+
+    let write = || {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(&temp)?;
+
+        file.write_all(bytes)?;
+        file.sync_all()
+    };
+
+    if let Some(policy) = retry {
+        policy.run_with_cancel(write, cancel)?;
+    } else {
+        retry::check_cancel(cancel)?;
+
+        write().map_err(Error::Io)?;
+    }
+
+    Ok(temp)
+}
+
+/// the second half of [`write_with_cancel`], renames a temporary file from
+/// [`write_temp`] over `path`. it cannot be cancelled.
+pub(crate) fn rename_temp(
+    temp: &Path,
+    path: &Path,
+    retry: Option<&RetryPolicy>
+) -> Result<(), Error> {
+    #[cfg(feature = "harness")]
+    if CRASH_BEFORE_RENAME.with(|c| c.replace(false)) {
+        return Err(Error::Io(std::io::Error::other("simulated crash before rename")));
+    }
+
+    let rename = || replace(temp, path);
+
+    if let Some(policy) = retry {
+        policy.run(rename)
+    } else {
+        rename().map_err(Error::Io)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempStore;
+
+    #[test]
+    fn temp_name() {
+        assert_eq!(temp_path(Path::new("dir/store.json")), Path::new("dir/store.json.tmp"));
+        assert_eq!(temp_path(Path::new("store")), Path::new("store.tmp"));
+    }
+
+    #[test]
+    fn replaces() {
+        let temp = TempStore::new("atomic");
+        let file_name = temp.path();
+
+        std::fs::write(file_name, b"old").unwrap();
+
+        write_with_cancel(Path::new(file_name), b"new", None, &AtomicBool::new(false))
+            .expect("failed to write file");
+
+        assert_eq!(std::fs::read(file_name).unwrap(), b"new");
+        assert!(!temp_path(Path::new(file_name)).exists(), "temp file was left behind");
+    }
+
+    #[test]
+    fn unicode_path() {
+        let temp = TempStore::new("atomic.ключи-🔑");
+        let file_name = temp.path();
+
+        write_with_cancel(Path::new(file_name), b"data", None, &AtomicBool::new(false))
+            .expect("failed to write file");
+
+        assert_eq!(std::fs::read(file_name).unwrap(), b"data");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn unc_path() {
+        let temp = TempStore::new("atomic.unc");
+        let absolute = std::env::current_dir().unwrap().join(temp.path());
+        let path = PathBuf::from(format!(r"\\?\{}", absolute.display()));
+
+        write_with_cancel(&path, b"data", None, &AtomicBool::new(false))
+            .expect("failed to write file");
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"data");
+        assert_eq!(temp_path(&path).parent(), path.parent());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn rename_while_open() {
+        let temp = TempStore::new("atomic.open");
+        let file_name = temp.path();
+
+        std::fs::write(file_name, b"old").unwrap();
+
+        let handle = std::fs::File::open(file_name).unwrap();
+
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+
+                std::mem::drop(handle);
+            });
+
+            write_with_cancel(Path::new(file_name), b"new", None, &AtomicBool::new(false))
+                .expect("failed to replace open file");
+        });
+
+        assert_eq!(std::fs::read(file_name).unwrap(), b"new");
+    }
+}
@@ -1,25 +1,230 @@
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::marker::PhantomData;
 use std::path::{PathBuf, Path};
 use std::fs::OpenOptions;
-use std::io::{BufReader, BufWriter};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::io::Read;
 
-use serde::Serialize;
+use bincode::Options as _;
+
+use serde::{Serialize, Deserialize};
 use serde::de::DeserializeOwned;
 
-use crate::fs::error::Error;
+use crate::fs::error::{Error, Phase};
 use crate::fs::traits::Wrapper;
-use crate::local::Local;
+use crate::fs::codec::{KeyCodec, SerdeCodec};
+use crate::fs::atomic;
+use crate::fs::retry::{self, RetryPolicy};
+#[cfg(feature = "integrity")]
+use crate::fs::integrity::{self, Integrity};
+use crate::local::{Local, Parts, SerializeOptions, AccessTimes, Reserved, Tombstone, Meta, BINARY_TAG};
+use crate::hooks::{Hooks, Op, Timer};
 
 pub struct Options {
     pub path: PathBuf,
+    pub retry: Option<RetryPolicy>,
+    pub persist_accessed: bool,
+    pub hooks: Option<Arc<dyn Hooks>>,
+    /// raises a counter below the highest version in the file to it
+    /// instead of failing with
+    /// [`CountTooLow`](crate::local::Error::CountTooLow)
+    pub heal_count: bool,
+    #[cfg(feature = "integrity")]
+    pub integrity: Option<Integrity>,
+}
+
+impl Options {
+    pub fn new<P>(path: P) -> Self
+    where
+        P: Into<PathBuf>
+    {
+        Options {
+            path: path.into(),
+            retry: None,
+            persist_accessed: false,
+            hooks: None,
+            heal_count: false,
+            #[cfg(feature = "integrity")]
+            integrity: None,
+        }
+    }
+}
+
+struct OffsetReader<'a, R> {
+    inner: R,
+    offset: &'a Cell<u64>,
+}
+
+impl<R> Read for OffsetReader<'_, R>
+where
+    R: Read
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let amount = self.inner.read(buf)?;
+
+        self.offset.set(self.offset.get() + amount as u64);
+
+        Ok(amount)
+    }
 }
 
-pub struct Binary<KeyType> {
+/// deserializes a bincode encoded [`Local`] one field at a time so that a
+/// failure can report which field was being read and at what byte offset.
+///
+/// reads are limited to the length of `bytes` so a crafted length prefix
+/// fails instead of allocating more than the file could hold. a counter
+/// below the highest version fails with
+/// [`CountTooLow`](crate::local::Error::CountTooLow) unless `heal_count` is
+/// set.
+pub(crate) fn deserialize_local<KeyType>(bytes: &[u8], heal_count: bool) -> Result<Local<KeyType>, Error>
+where
+    KeyType: DeserializeOwned
+{
+    let offset = Cell::new(0);
+    let size = bytes.len() as u64;
+    let reader = OffsetReader {
+        inner: bytes,
+        offset: &offset,
+    };
+    let mut deserializer = bincode::Deserializer::with_reader(
+        reader,
+        bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(size)
+    );
+
+    let context = |phase| {
+        let offset = &offset;
+
+        move |error| Error::BincodeAt {
+            phase,
+            offset: offset.get(),
+            size,
+            error
+        }
+    };
+
+    let tag = u64::deserialize(&mut deserializer)
+        .map_err(context(Phase::Header))?;
+
+    if tag != BINARY_TAG {
+        // saved before the layout was tagged, the tag is the counter and
+        // the store is all that follows it
+        let store = BTreeMap::<u64, KeyType>::deserialize(&mut deserializer)
+            .map_err(context(Phase::Store))?;
+
+        return Local::load_parts(Parts::new(tag, store), heal_count)
+            .map_err(Error::Local);
+    }
+
+    let fields = u64::deserialize(&mut deserializer)
+        .map_err(context(Phase::Header))?;
+
+    if fields < 2 {
+        return Err(Error::BincodeAt {
+            phase: Phase::Header,
+            offset: offset.get(),
+            size,
+            error: Box::new(bincode::ErrorKind::Custom(format!(
+                "expected at least 2 fields, found {}", fields
+            )))
+        });
+    }
+
+    let count = u64::deserialize(&mut deserializer)
+        .map_err(context(Phase::Counter))?;
+    let store = BTreeMap::<u64, KeyType>::deserialize(&mut deserializer)
+        .map_err(context(Phase::Store))?;
+
+    let mut parts = Parts::new(count, store);
+
+    if fields > 2 {
+        parts.accessed = BTreeMap::<u64, AccessTimes>::deserialize(&mut deserializer)
+            .map_err(context(Phase::Accessed))?;
+    }
+
+    if fields > 3 {
+        parts.pending = BTreeMap::<u64, u64>::deserialize(&mut deserializer)
+            .map_err(context(Phase::Pending))?;
+    }
+
+    if fields > 4 {
+        parts.reserved = BTreeMap::<u64, Reserved>::deserialize(&mut deserializer)
+            .map_err(context(Phase::Reserved))?;
+    }
+
+    if fields > 5 {
+        parts.tombstones = BTreeMap::<u64, Tombstone>::deserialize(&mut deserializer)
+            .map_err(context(Phase::Tombstones))?;
+    }
+
+    if fields > 6 {
+        parts.staged = BTreeSet::<u64>::deserialize(&mut deserializer)
+            .map_err(context(Phase::Staged))?;
+    }
+
+    if fields > 7 {
+        parts.disabled = BTreeSet::<u64>::deserialize(&mut deserializer)
+            .map_err(context(Phase::Disabled))?;
+    }
+
+    if fields > 8 {
+        parts.meta = BTreeMap::<u64, Meta>::deserialize(&mut deserializer)
+            .map_err(context(Phase::Meta))?;
+    }
+
+    Local::load_parts(parts, heal_count)
+        .map_err(Error::Local)
+}
+
+/// serializes a [`Local`] into the layout read by [`deserialize_local`]
+pub(crate) fn serialize_local<KeyType>(local: &Local<KeyType>, options: SerializeOptions) -> Result<Vec<u8>, Error>
+where
+    KeyType: Serialize
+{
+    let local = local.serialize_with(options);
+
+    bincode::serialize(&local)
+        .map_err(|e| match (*e, local.failed_version()) {
+            (bincode::ErrorKind::Io(io), _) => Error::Io(io),
+            (e, Some(version)) => Error::KeySerialization {
+                version,
+                source: Box::new(e),
+            },
+            (e, None) => Error::Bincode(Box::new(e))
+        })
+}
+
+/// a store saved as a single bincode blob.
+///
+/// the whole store is one bincode value so loading and saving are always
+/// done on a single thread, even with the `rayon` feature enabled.
+pub struct Binary<KeyType, C = SerdeCodec> {
     manager: Local<KeyType>,
+    codec: PhantomData<fn() -> C>,
     path: Box<Path>,
+    retry: Option<RetryPolicy>,
+    persist_accessed: bool,
+    hooks: Option<Arc<dyn Hooks>>,
+    #[cfg(feature = "integrity")]
+    integrity: Option<Integrity>,
 }
 
 impl<KeyType> Binary<KeyType> {
     pub fn new<P>(manager: Local<KeyType>, path: P) -> Self
+    where
+        P: Into<PathBuf>
+    {
+        Binary::with_codec(manager, path)
+    }
+}
+
+impl<KeyType, C> Binary<KeyType, C> {
+    /// a store whose keys are saved with the codec `C`
+    pub fn with_codec<P>(manager: Local<KeyType>, path: P) -> Self
     where
         P: Into<PathBuf>
     {
@@ -27,16 +232,65 @@ impl<KeyType> Binary<KeyType> {
 
         Binary {
             manager,
+            codec: PhantomData,
             path: buf.into(),
+            retry: None,
+            persist_accessed: false,
+            hooks: None,
+            #[cfg(feature = "integrity")]
+            integrity: None,
         }
     }
 
+    /// the store without the file it is saved to
+    pub fn into_inner(self) -> Local<KeyType> {
+        self.manager
+    }
+
     pub fn path(&self) -> &Path {
         &self.path
     }
+
+    pub fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.retry.as_ref()
+    }
+
+    pub fn set_retry_policy(&mut self, retry: Option<RetryPolicy>) {
+        self.retry = retry;
+    }
+
+    pub fn persist_accessed(&self) -> bool {
+        self.persist_accessed
+    }
+
+    /// when set, the access times of each version are written on save.
+    /// access times are otherwise kept in memory only.
+    pub fn set_persist_accessed(&mut self, persist: bool) {
+        self.persist_accessed = persist;
+    }
+
+    pub fn hooks(&self) -> Option<&Arc<dyn Hooks>> {
+        self.hooks.as_ref()
+    }
+
+    /// when set, `load` and `save` are timed with the given hooks
+    pub fn set_hooks(&mut self, hooks: Option<Arc<dyn Hooks>>) {
+        self.hooks = hooks;
+    }
+
+    #[cfg(feature = "integrity")]
+    pub fn integrity(&self) -> Option<&Integrity> {
+        self.integrity.as_ref()
+    }
+
+    /// when set, an integrity tag is written on save and checked on load
+    #[cfg(feature = "integrity")]
+    pub fn set_integrity(&mut self, integrity: Option<Integrity>) {
+        self.integrity = integrity;
+    }
 }
 
-impl<KeyType> std::ops::Deref for Binary<KeyType> {
+impl<KeyType, C> std::ops::Deref for Binary<KeyType, C> {
     type Target = Local<KeyType>;
 
     fn deref(&self) -> &Self::Target {
@@ -44,7 +298,7 @@ impl<KeyType> std::ops::Deref for Binary<KeyType> {
     }
 }
 
-impl<KeyType> std::fmt::Debug for Binary<KeyType>
+impl<KeyType, C> std::fmt::Debug for Binary<KeyType, C>
 where
     KeyType: std::fmt::Debug
 {
@@ -56,72 +310,464 @@ where
     }
 }
 
-impl<KeyType> Wrapper for Binary<KeyType>
+impl<KeyType, C> Wrapper for Binary<KeyType, C>
 where
-    KeyType: Serialize + DeserializeOwned
+    C: KeyCodec<KeyType>
 {
     type Error = Error;
     type Args = Options;
 
-    fn load(options: Self::Args) -> Result<Self, Self::Error> {
-        let path = options.path.into();
-        let file = OpenOptions::new()
-            .read(true)
-            .open(&path)
-            .map_err(|e| Error::Io(e))?;
-        let reader = BufReader::new(file);
+    fn canonical_bytes(&self) -> Result<Vec<u8>, Self::Error> {
+        C::serialize_local(&self.manager, false)
+    }
+
+    fn load_with_cancel(options: Self::Args, cancel: &AtomicBool) -> Result<Self, Self::Error> {
+        let path: Box<Path> = options.path.into();
+        let retry = options.retry;
+        let persist_accessed = options.persist_accessed;
+        let hooks = options.hooks;
+        let _timer = Timer::start(hooks.as_ref(), Op::Load);
+        #[cfg(feature = "integrity")]
+        let integrity = options.integrity;
+
+        let buffer = retry::read_with_cancel(
+            || OpenOptions::new().read(true).open(&path),
+            retry.as_ref(),
+            cancel
+        )?;
+
+        #[cfg(feature = "integrity")]
+        let bytes = integrity::strip_footer(integrity.as_ref(), buffer.as_slice())?;
+        #[cfg(not(feature = "integrity"))]
+        let bytes = buffer.as_slice();
+
+        retry::check_cancel(cancel)?;
 
-        let manager = bincode::deserialize_from(reader)
-            .map_err(|e| match *e {
-                bincode::ErrorKind::Io(io) => Error::Io(io),
-                _ => Error::Bincode(e)
-            })?;
+        let manager = C::deserialize_local(bytes, options.heal_count)?;
 
         Ok(Binary {
             manager,
-            path
+            codec: PhantomData,
+            path,
+            retry,
+            persist_accessed,
+            hooks,
+            #[cfg(feature = "integrity")]
+            integrity,
         })
     }
 
-    fn save(&self) -> Result<(), Self::Error> {
-        let file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(&self.path)
-            .map_err(|e| Error::Io(e))?;
-        let writer = BufWriter::new(file);
+    fn save_with_cancel(&self, cancel: &AtomicBool) -> Result<(), Self::Error> {
+        let _timer = Timer::start(self.hooks.as_ref(), Op::Save);
 
-        bincode::serialize_into(writer, &self.manager)
-            .map_err(|e| match *e {
-                bincode::ErrorKind::Io(io) => Error::Io(io),
-                _ => Error::Bincode(e)
-            })?;
+        #[cfg_attr(not(feature = "integrity"), allow(unused_mut))]
+        let mut serialize = C::serialize_local(&self.manager, self.persist_accessed)?;
 
-        Ok(())
+        #[cfg(feature = "integrity")]
+        if let Some(integrity) = &self.integrity {
+            integrity::append_footer(integrity, &mut serialize);
+        }
+
+        retry::check_cancel(cancel)?;
+
+        atomic::write_with_cancel(
+            &self.path,
+            serialize.as_slice(),
+            self.retry.as_ref(),
+            cancel
+        )
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::local;
+    use crate::test_util::{self, TempStore};
     use crate::fs;
 
+    #[test]
+    fn conformance() {
+        fs::conformance::run(
+            |path| Binary::new(Local::new(), path),
+            |path| Binary::load(Options::new(path)),
+        );
+    }
+
     #[test]
     fn base() {
-        let file_name = "test.binary";
-        let manager = local::test::create_store();
+        let temp = TempStore::new("binary");
+        let file_name = temp.path();
+        let manager = test_util::sample_local();
+
+        let wrapper = Binary::new(manager, file_name);
+
+        wrapper.save().expect("failed to save to binary file");
+
+        let and_back: Binary<u64> = Binary::load(Options::new(file_name))
+            .expect("failed to load binary file");
+
+        test_util::assert_local_eq(&wrapper.manager, &and_back.manager);
+    }
+
+    #[test]
+    fn unicode_path() {
+        let temp = TempStore::new("binary.ключи-🔑");
+        let file_name = temp.path();
+        let manager = test_util::sample_local();
+
+        let wrapper = Binary::new(manager, file_name);
+
+        wrapper.save().expect("failed to save to binary file");
+
+        let and_back: Binary<u64> = Binary::load(Options::new(file_name))
+            .expect("failed to load binary file");
+
+        assert_eq!(and_back.path(), Path::new(file_name));
+        test_util::assert_local_eq(&wrapper.manager, &and_back.manager);
+    }
+
+    #[test]
+    fn persist_accessed() {
+        let temp = TempStore::new("binary.accessed");
+        let file_name = temp.path();
+        let manager = test_util::sample_local();
+
+        manager.get(&2).unwrap();
+
+        let mut wrapper = Binary::new(manager, file_name);
+
+        wrapper.save().expect("failed to save to binary file");
+
+        let and_back: Binary<u64> = Binary::load(Options::new(file_name))
+            .expect("failed to load binary file");
+
+        assert!(and_back.access_times().unwrap().is_empty(), "access times were persisted");
+
+        wrapper.set_persist_accessed(true);
+        wrapper.save().expect("failed to save to binary file");
+
+        let and_back: Binary<u64> = Binary::load(Options::new(file_name))
+            .expect("failed to load binary file");
+
+        test_util::assert_local_eq(&wrapper.manager, &and_back.manager);
+        assert_eq!(and_back.access_times().unwrap(), wrapper.access_times().unwrap());
+    }
+
+    #[test]
+    fn truncated() {
+        let temp = TempStore::new("binary.truncated");
+        let file_name = temp.path();
+        let manager = test_util::sample_local();
+
+        let wrapper = Binary::new(manager, file_name);
+
+        wrapper.save().expect("failed to save to binary file");
 
-        fs::test::create_test_file(file_name);
+        let bytes = std::fs::read(file_name)
+            .expect("failed to read binary file");
+        let total = bytes.len();
+
+        // 8 bytes of tag, 8 bytes of field count, 8 bytes of counter, 8
+        // bytes of store length followed by 16 bytes per entry
+        let cases = [
+            (4, Phase::Header),
+            (12, Phase::Header),
+            (20, Phase::Counter),
+            (28, Phase::Store),
+            (32 + 16 * 3 + 4, Phase::Store),
+            (total - 10, Phase::Accessed),
+            (total - 2, Phase::Pending),
+        ];
+
+        for (len, expected) in cases {
+            match deserialize_local::<u64>(&bytes[..len], false) {
+                Err(Error::BincodeAt { phase, offset, size, .. }) => {
+                    assert_eq!(phase, expected, "unexpected phase for length {}", len);
+                    assert_eq!(size, len as u64, "unexpected size for length {}", len);
+                    assert!(offset <= size, "offset {} is past the end of {} bytes", offset, size);
+                }
+                Err(err) => panic!("unexpected error for length {}: {:?}", len, err),
+                Ok(_) => panic!("truncated file of length {} loaded", len),
+            }
+        }
+
+        std::fs::write(file_name, &bytes[..30])
+            .expect("failed to write truncated binary file");
+
+        let result = Binary::<u64>::load(Options::new(file_name));
+
+        assert!(
+            matches!(result, Err(Error::BincodeAt { phase: Phase::Store, size: 30, .. })),
+            "unexpected result: {:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn reservations() {
+        let temp = TempStore::new("binary.reserved");
+        let file_name = temp.path();
+        let manager = test_util::sample_local();
+        let plain = serialize_local(&manager, SerializeOptions::default())
+            .expect("failed to serialize store");
+
+        let reservation = manager.reserve().unwrap();
 
         let wrapper = Binary::new(manager, file_name);
 
         wrapper.save().expect("failed to save to binary file");
 
-        let and_back: Binary<u64> = Binary::load(Options{
-            path: PathBuf::from(file_name),
-        }).expect("failed to load binary file");
+        let and_back: Binary<u64> = Binary::load(Options::new(file_name))
+            .expect("failed to load binary file");
+
+        assert_eq!(and_back.outstanding().unwrap(), vec![reservation]);
+
+        and_back.fulfill(reservation, 30).unwrap();
+
+        // without reservations the layout is unchanged
+        let fulfilled = serialize_local(&and_back, SerializeOptions::default()).unwrap();
+
+        assert_eq!(fulfilled.len(), plain.len() + 16);
+        assert_eq!(deserialize_local::<u64>(&plain, false).unwrap().count().unwrap(), 12);
+    }
+
+    #[test]
+    fn tombstones() {
+        let manager = test_util::sample_local();
+
+        manager.drop_with_note(&3, "rotated early").unwrap();
+
+        let bytes = serialize_local(&manager, SerializeOptions::default())
+            .expect("failed to serialize store");
+        let and_back = deserialize_local::<u64>(&bytes, false)
+            .expect("failed to deserialize store");
+
+        assert!(and_back.reservations().unwrap().is_empty());
+        assert_eq!(and_back.tombstones().unwrap(), manager.tombstones().unwrap());
+        assert_eq!(and_back.gap_report().unwrap(), manager.gap_report().unwrap());
+
+        let and_back = <SerdeCodec as KeyCodec<u64>>::deserialize_local(
+            &<SerdeCodec as KeyCodec<u64>>::serialize_local(&manager, false).unwrap(),
+            false
+        ).expect("failed to round trip with the codec");
+
+        assert_eq!(and_back.tombstones().unwrap(), manager.tombstones().unwrap());
+    }
+
+    #[test]
+    fn staged() {
+        let manager = test_util::sample_local();
+        let version = manager.stage(30).unwrap();
+
+        let bytes = serialize_local(&manager, SerializeOptions::default())
+            .expect("failed to serialize store");
+        let and_back = deserialize_local::<u64>(&bytes, false)
+            .expect("failed to deserialize store");
+
+        assert!(and_back.is_staged(&version).unwrap());
+        assert_eq!(and_back.latest().unwrap(), manager.latest().unwrap());
+
+        let and_back = <SerdeCodec as KeyCodec<u64>>::deserialize_local(
+            &<SerdeCodec as KeyCodec<u64>>::serialize_local(&manager, false).unwrap(),
+            false
+        ).expect("failed to round trip with the codec");
+
+        assert_eq!(and_back.staged().unwrap(), manager.staged().unwrap());
+
+        // promoting leaves the layout as it was before staging existed
+        manager.promote(&version).unwrap();
+
+        let promoted = serialize_local(&manager, SerializeOptions::default()).unwrap();
+        let and_back = deserialize_local::<u64>(&promoted, false).unwrap();
+
+        assert!(promoted.len() < bytes.len());
+        assert_eq!(and_back.latest().unwrap(), Some(30));
+    }
+
+    #[test]
+    fn disabled() {
+        let manager = test_util::sample_local();
+        let version = manager.update(30).unwrap();
+
+        manager.disable(&version).unwrap();
+
+        let bytes = serialize_local(&manager, SerializeOptions::default())
+            .expect("failed to serialize store");
+        let and_back = deserialize_local::<u64>(&bytes, false)
+            .expect("failed to deserialize store");
+
+        assert!(and_back.is_disabled(&version).unwrap());
+        assert!(and_back.staged().unwrap().is_empty());
+        assert_eq!(and_back.latest().unwrap(), manager.latest().unwrap());
+
+        let and_back = <SerdeCodec as KeyCodec<u64>>::deserialize_local(
+            &<SerdeCodec as KeyCodec<u64>>::serialize_local(&manager, false).unwrap(),
+            false
+        ).expect("failed to round trip with the codec");
+
+        assert_eq!(and_back.disabled().unwrap(), manager.disabled().unwrap());
+        assert_eq!(and_back.latest().unwrap(), manager.latest().unwrap());
+    }
+
+    #[test]
+    fn meta() {
+        let manager = test_util::sample_local();
+        let version = manager.update_with_meta(30, [("env".to_owned(), "prod".to_owned())].into()).unwrap();
+
+        let bytes = serialize_local(&manager, SerializeOptions::default())
+            .expect("failed to serialize store");
+        let and_back = deserialize_local::<u64>(&bytes, false)
+            .expect("failed to deserialize store");
+
+        assert_eq!(and_back.meta(&version).unwrap(), manager.meta(&version).unwrap());
+        assert!(and_back.disabled().unwrap().is_empty());
+
+        let and_back = <SerdeCodec as KeyCodec<u64>>::deserialize_local(
+            &<SerdeCodec as KeyCodec<u64>>::serialize_local(&manager, false).unwrap(),
+            false
+        ).expect("failed to round trip with the codec");
+
+        assert_eq!(and_back.all_meta().unwrap(), manager.all_meta().unwrap());
+    }
+
+    #[test]
+    fn oversized_length() {
+        // a store with one string key that claims to be far larger than the
+        // bytes that follow it
+        let mut bytes = Vec::new();
+        bytes.extend(BINARY_TAG.to_le_bytes());
+        bytes.extend(2u64.to_le_bytes());
+        bytes.extend(1u64.to_le_bytes());
+        bytes.extend(1u64.to_le_bytes());
+        bytes.extend(1u64.to_le_bytes());
+        bytes.extend((u64::MAX >> 4).to_le_bytes());
+        bytes.extend(b"short");
+
+        let result = deserialize_local::<String>(&bytes, false);
+
+        assert!(
+            matches!(result, Err(Error::BincodeAt { phase: Phase::Store, .. })),
+            "unexpected result: {:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn count_too_low() {
+        let temp = TempStore::new("binary.count_too_low");
+        let file_name = temp.path();
+        let bytes = bincode::serialize(&(1u64, BTreeMap::from([(1u64, 10u64), (4, 40)]))).unwrap();
+
+        assert!(matches!(
+            deserialize_local::<u64>(&bytes, false),
+            Err(Error::Local(crate::local::Error::CountTooLow { count: 1, highest: 4 }))
+        ));
+
+        std::fs::write(file_name, &bytes)
+            .expect("failed to write binary file");
+
+        let result = Binary::<u64>::load(Options::new(file_name));
+
+        assert!(
+            matches!(result, Err(Error::Local(crate::local::Error::CountTooLow { count: 1, highest: 4 }))),
+            "unexpected result: {:?}",
+            result
+        );
+
+        let mut options = Options::new(file_name);
+        options.heal_count = true;
+
+        let healed: Binary<u64> = Binary::load(options)
+            .expect("failed to load binary file with heal_count");
+
+        assert_eq!(healed.count().unwrap(), 4);
+        assert_eq!(healed.update(50).unwrap(), 5);
+    }
+
+    #[test]
+    fn untagged_layout() {
+        // saved before the layout was tagged, a counter of 2 and keys 10
+        // and 20
+        let bytes = include_bytes!("../../fixtures/legacy/local.bin");
+
+        let and_back = deserialize_local::<u64>(bytes, false)
+            .expect("failed to deserialize untagged store");
+
+        assert_eq!(and_back.count().unwrap(), 2);
+        assert_eq!(*and_back.store_reader().unwrap(), BTreeMap::from([(1, 10), (2, 20)]));
+
+        let and_back: Local<u64> = bincode::deserialize(bytes)
+            .expect("failed to deserialize untagged store with serde");
+
+        assert_eq!(and_back.count().unwrap(), 2);
+        assert_eq!(*and_back.store_reader().unwrap(), BTreeMap::from([(1, 10), (2, 20)]));
+
+        // saving it again writes the tagged layout
+        let bytes = serialize_local(&and_back, SerializeOptions::default()).unwrap();
+
+        assert_eq!(bytes[..8], BINARY_TAG.to_le_bytes());
+        test_util::assert_local_eq(&deserialize_local::<u64>(&bytes, false).unwrap(), &and_back);
+    }
+
+    #[cfg(feature = "integrity")]
+    #[test]
+    fn integrity() {
+        let temp = TempStore::new("binary.integrity");
+        let file_name = temp.path();
+        let manager = test_util::sample_local();
+
+        let mut wrapper = Binary::new(manager, file_name);
+        wrapper.set_integrity(Some(Integrity::new(b"integrity key".to_vec())));
+        wrapper.save().expect("failed to save to binary file");
+
+        let mut options = Options::new(file_name);
+        options.integrity = Some(Integrity::required(b"integrity key".to_vec()));
+
+        let and_back: Binary<u64> = Binary::load(options)
+            .expect("failed to load binary file");
+
+        test_util::assert_local_eq(&wrapper.manager, &and_back.manager);
+
+        let mut bytes = std::fs::read(file_name)
+            .expect("failed to read binary file");
+
+        // the value of the first store entry
+        bytes[32] ^= 1;
+
+        std::fs::write(file_name, &bytes)
+            .expect("failed to write tampered binary file");
+
+        let mut options = Options::new(file_name);
+        options.integrity = Some(Integrity::new(b"integrity key".to_vec()));
+
+        let result = Binary::<u64>::load(options);
+
+        assert!(matches!(result, Err(Error::IntegrityFailure)), "unexpected result: {:?}", result);
+    }
+
+    #[cfg(feature = "integrity")]
+    #[test]
+    fn integrity_legacy() {
+        let temp = TempStore::new("binary.legacy");
+        let file_name = temp.path();
+        let manager = test_util::sample_local();
+
+        let wrapper = Binary::new(manager, file_name);
+        wrapper.save().expect("failed to save to binary file");
+
+        let mut options = Options::new(file_name);
+        options.integrity = Some(Integrity::new(b"integrity key".to_vec()));
+
+        let and_back: Binary<u64> = Binary::load(options)
+            .expect("failed to load binary file");
+
+        test_util::assert_local_eq(&wrapper.manager, &and_back.manager);
+
+        let mut options = Options::new(file_name);
+        options.integrity = Some(Integrity::required(b"integrity key".to_vec()));
+
+        let result = Binary::<u64>::load(options);
 
-        local::test::assert_local_eq(&wrapper.manager, &and_back.manager);
+        assert!(matches!(result, Err(Error::IntegrityMissing)), "unexpected result: {:?}", result);
     }
 }
@@ -0,0 +1,331 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error as StdError;
+
+use bincode::Options as _;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::fs::binary;
+use crate::fs::error::Error;
+use crate::local::{Local, Parts, SerializeOptions, AccessTimes, Reserved, Tombstone, Meta};
+
+/// encodes key values for the binary and encrypted wrappers so that key
+/// types do not need to implement serde.
+///
+/// stores using a codec are saved as `(count, entries, accessed, pending)`
+/// where each entry is the version and the bytes from [`encode`]. the
+/// counter, access times, and pending drops are still written with bincode.
+/// reservations, tombstones, staged and disabled versions and metadata are
+/// appended after the pending drops when there are any.
+///
+/// [`encode`]: KeyCodec::encode
+pub trait KeyCodec<KeyType> {
+    type Error: StdError + Send + Sync + 'static;
+
+    fn encode(key: &KeyType) -> Result<Vec<u8>, Self::Error>;
+
+    fn decode(bytes: &[u8]) -> Result<KeyType, Self::Error>;
+
+    /// serializes the whole store, including access times if `accessed` is
+    /// set
+    fn serialize_local(local: &Local<KeyType>, accessed: bool) -> Result<Vec<u8>, Error> {
+        let accessed = if accessed {
+            local.access_times().map_err(Error::Local)?
+        } else {
+            BTreeMap::new()
+        };
+        let pending = local.pending_drops().map_err(Error::Local)?;
+        let reserved = local.reservations().map_err(Error::Local)?;
+        let tombstones = local.tombstones().map_err(Error::Local)?;
+        let staged = local.staged().map_err(Error::Local)?;
+        let disabled = local.disabled().map_err(Error::Local)?;
+        let meta = local.all_meta().map_err(Error::Local)?;
+
+        // read last so the counter covers every version read above
+        let (count, entries) = {
+            let (count, reader) = local.counted_store().map_err(Error::Local)?;
+            let mut entries = Vec::with_capacity(reader.len());
+
+            for (version, key) in reader.iter() {
+                let bytes = Self::encode(key)
+                    .map_err(|e| Error::KeySerialization {
+                        version: *version,
+                        source: Box::new(e),
+                    })?;
+
+                entries.push((*version, bytes));
+            }
+
+            (count, entries)
+        };
+
+        let mut rtn = bincode::serialize(&(count, entries, accessed, pending))
+            .map_err(Error::Bincode)?;
+
+        // each part is written when it or any part after it is set
+        let fields = if !meta.is_empty() {
+            5
+        } else if !disabled.is_empty() {
+            4
+        } else if !staged.is_empty() {
+            3
+        } else if !tombstones.is_empty() {
+            2
+        } else if !reserved.is_empty() {
+            1
+        } else {
+            0
+        };
+
+        if fields > 0 {
+            bincode::serialize_into(&mut rtn, &reserved)
+                .map_err(Error::Bincode)?;
+        }
+
+        if fields > 1 {
+            bincode::serialize_into(&mut rtn, &tombstones)
+                .map_err(Error::Bincode)?;
+        }
+
+        if fields > 2 {
+            bincode::serialize_into(&mut rtn, &staged)
+                .map_err(Error::Bincode)?;
+        }
+
+        if fields > 3 {
+            bincode::serialize_into(&mut rtn, &disabled)
+                .map_err(Error::Bincode)?;
+        }
+
+        if fields > 4 {
+            bincode::serialize_into(&mut rtn, &meta)
+                .map_err(Error::Bincode)?;
+        }
+
+        Ok(rtn)
+    }
+
+    /// deserializes the whole store. a counter below the highest version
+    /// fails with [`CountTooLow`](crate::local::Error::CountTooLow) unless
+    /// `heal_count` is set, which raises it.
+    fn deserialize_local(bytes: &[u8], heal_count: bool) -> Result<Local<KeyType>, Error> {
+        type Encoded = (u64, Vec<(u64, Vec<u8>)>, BTreeMap<u64, AccessTimes>, BTreeMap<u64, u64>);
+
+        let options = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .with_limit(bytes.len() as u64);
+        let mut reader = bytes;
+
+        let (count, entries, accessed, pending): Encoded = options
+            .deserialize_from(&mut reader)
+            .map_err(Error::Bincode)?;
+        let reserved: BTreeMap<u64, Reserved> = if reader.is_empty() {
+            BTreeMap::new()
+        } else {
+            options.deserialize_from(&mut reader)
+                .map_err(Error::Bincode)?
+        };
+        let tombstones: BTreeMap<u64, Tombstone> = if reader.is_empty() {
+            BTreeMap::new()
+        } else {
+            options.deserialize_from(&mut reader)
+                .map_err(Error::Bincode)?
+        };
+        let staged: BTreeSet<u64> = if reader.is_empty() {
+            BTreeSet::new()
+        } else {
+            options.deserialize_from(&mut reader)
+                .map_err(Error::Bincode)?
+        };
+        let disabled: BTreeSet<u64> = if reader.is_empty() {
+            BTreeSet::new()
+        } else {
+            options.deserialize_from(&mut reader)
+                .map_err(Error::Bincode)?
+        };
+        let meta: BTreeMap<u64, Meta> = if reader.is_empty() {
+            BTreeMap::new()
+        } else {
+            options.deserialize(reader)
+                .map_err(Error::Bincode)?
+        };
+
+        let mut store = BTreeMap::new();
+
+        for (version, bytes) in entries {
+            let key = Self::decode(&bytes)
+                .map_err(|e| Error::Codec(Box::new(e)))?;
+
+            store.insert(version, key);
+        }
+
+        let mut parts = Parts::new(count, store);
+        parts.accessed = accessed;
+        parts.pending = pending;
+        parts.reserved = reserved;
+        parts.tombstones = tombstones;
+        parts.staged = staged;
+        parts.disabled = disabled;
+        parts.meta = meta;
+
+        Local::load_parts(parts, heal_count)
+            .map_err(Error::Local)
+    }
+}
+
+/// the default codec, encoding keys with bincode through serde.
+///
+/// whole stores are saved in the same layout the wrappers have always used
+/// rather than the codec layout so existing files keep loading.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SerdeCodec;
+
+impl<KeyType> KeyCodec<KeyType> for SerdeCodec
+where
+    KeyType: Serialize + DeserializeOwned
+{
+    type Error = bincode::Error;
+
+    fn encode(key: &KeyType) -> Result<Vec<u8>, Self::Error> {
+        bincode::serialize(key)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<KeyType, Self::Error> {
+        bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(bytes.len() as u64)
+            .deserialize(bytes)
+    }
+
+    fn serialize_local(local: &Local<KeyType>, accessed: bool) -> Result<Vec<u8>, Error> {
+        binary::serialize_local(local, SerializeOptions { accessed })
+    }
+
+    fn deserialize_local(bytes: &[u8], heal_count: bool) -> Result<Local<KeyType>, Error> {
+        binary::deserialize_local(bytes, heal_count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempStore;
+    use crate::fs::Wrapper;
+    use crate::fs::binary::{Binary, Options};
+
+    #[derive(Debug, PartialEq)]
+    struct Raw {
+        id: u32,
+        secret: Vec<u8>,
+    }
+
+    #[derive(Debug)]
+    struct RawError;
+
+    impl std::fmt::Display for RawError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("RawError")
+        }
+    }
+
+    impl StdError for RawError {}
+
+    /// length prefixed `id` followed by the secret bytes
+    struct RawCodec;
+
+    impl KeyCodec<Raw> for RawCodec {
+        type Error = RawError;
+
+        fn encode(key: &Raw) -> Result<Vec<u8>, Self::Error> {
+            let mut rtn = Vec::with_capacity(4 + key.secret.len());
+            rtn.extend_from_slice(&key.id.to_le_bytes());
+            rtn.extend_from_slice(&key.secret);
+            Ok(rtn)
+        }
+
+        fn decode(bytes: &[u8]) -> Result<Raw, Self::Error> {
+            if bytes.len() < 4 {
+                return Err(RawError);
+            }
+
+            let (id, secret) = bytes.split_at(4);
+
+            Ok(Raw {
+                id: u32::from_le_bytes(id.try_into().unwrap()),
+                secret: secret.to_vec(),
+            })
+        }
+    }
+
+    #[test]
+    fn custom_codec() {
+        let temp = TempStore::new("codec.binary");
+        let file_name = temp.path();
+        let manager = Local::new();
+
+        for id in 0..4u32 {
+            manager.update(Raw { id, secret: vec![id as u8; 8] })
+                .expect("failed to add key");
+        }
+
+        let reservation = manager.reserve().unwrap();
+
+        let wrapper: Binary<Raw, RawCodec> = Binary::with_codec(manager, file_name);
+
+        wrapper.save().expect("failed to save to binary file");
+
+        let and_back: Binary<Raw, RawCodec> = Binary::load(Options::new(file_name))
+            .expect("failed to load binary file");
+
+        assert_eq!(wrapper.count().unwrap(), and_back.count().unwrap());
+        assert_eq!(and_back.outstanding().unwrap(), vec![reservation]);
+
+        let expected = wrapper.store_reader().unwrap();
+        let actual = and_back.store_reader().unwrap();
+
+        assert_eq!(*expected, *actual);
+    }
+
+    #[test]
+    fn serialize_while_updating() {
+        let manager = Local::new();
+        let done = std::sync::atomic::AtomicBool::new(false);
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for id in 0..200u32 {
+                    manager.update(Raw { id, secret: vec![id as u8; 8] })
+                        .expect("failed to add key");
+                    manager.reserve()
+                        .expect("failed to reserve version");
+                }
+
+                done.store(true, std::sync::atomic::Ordering::Release);
+            });
+
+            while !done.load(std::sync::atomic::Ordering::Acquire) {
+                let bytes = RawCodec::serialize_local(&manager, false)
+                    .expect("failed to serialize store");
+
+                RawCodec::deserialize_local(&bytes, false)
+                    .expect("failed to deserialize store serialized during updates");
+            }
+        });
+    }
+
+    #[test]
+    fn codec_error() {
+        let bytes = bincode::serialize(&(
+            1u64,
+            vec![(1u64, vec![0u8; 2])],
+            BTreeMap::<u64, AccessTimes>::new(),
+            BTreeMap::<u64, u64>::new(),
+        )).unwrap();
+
+        assert!(matches!(
+            RawCodec::deserialize_local(&bytes, false),
+            Err(Error::Codec(_))
+        ));
+    }
+}
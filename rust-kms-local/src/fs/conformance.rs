@@ -0,0 +1,216 @@
+//! the load and save contract every [`Wrapper`] is expected to follow.
+//!
+//! [`run`] checks a wrapper of `u64` keys against it:
+//!
+//! - a store loads back with the same counter, keys, pending drops,
+//!   reservations, tombstones, staged and disabled versions and metadata
+//!   it was saved with
+//! - loading a missing file fails with [`Error::is_not_found`]
+//! - saving creates the file if it does not exist
+//! - empty and large stores round trip
+//! - saving twice loads the same as saving once and leaves no temporary
+//!   file behind
+//! - loading garbage, truncated or empty files fails with
+//!   [`Error::is_corrupt`]
+
+use std::ops::Deref;
+use std::path::Path;
+
+use crate::fs::{atomic, Error, Wrapper};
+use crate::local::Local;
+use crate::test_util::TempStore;
+
+/// the number of versions used for the large store
+pub const LARGE_STORE: u64 = 10_000;
+
+/// removes the file and its temporary file so the next check starts over
+fn clear(path: &Path) {
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(atomic::temp_path(path));
+}
+
+/// asserts that everything a wrapper saves is the same in both stores
+pub fn assert_same(expected: &Local<u64>, actual: &Local<u64>) {
+    assert_eq!(expected.count().unwrap(), actual.count().unwrap(), "counters differ");
+    assert_eq!(*expected.store_reader().unwrap(), *actual.store_reader().unwrap(), "keys differ");
+    assert_eq!(expected.pending_drops().unwrap(), actual.pending_drops().unwrap(), "pending drops differ");
+    assert_eq!(expected.reservations().unwrap(), actual.reservations().unwrap(), "reservations differ");
+    assert_eq!(expected.tombstones().unwrap(), actual.tombstones().unwrap(), "tombstones differ");
+    assert_eq!(expected.staged().unwrap(), actual.staged().unwrap(), "staged versions differ");
+    assert_eq!(expected.disabled().unwrap(), actual.disabled().unwrap(), "disabled versions differ");
+    assert_eq!(expected.all_meta().unwrap(), actual.all_meta().unwrap(), "metadata differs");
+}
+
+/// runs the whole contract. `factory` makes an empty wrapper that saves to
+/// the path and `loader` loads one from it. files are created in the temp
+/// directory, named after the wrapper type, and removed afterwards.
+pub fn run<W, F, L>(factory: F, loader: L)
+where
+    W: Wrapper<Error = Error> + Deref<Target = Local<u64>>,
+    F: Fn(&Path) -> W,
+    L: Fn(&Path) -> Result<W, Error>,
+{
+    let name: String = std::any::type_name::<W>()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let temp = TempStore::new(&format!("conformance.{}", name));
+    let path = temp.path();
+
+    missing_file(path, &loader);
+    clear(path);
+
+    round_trip(path, &factory, &loader);
+    clear(path);
+
+    empty_store(path, &factory, &loader);
+    clear(path);
+
+    large_store(path, &factory, &loader);
+    clear(path);
+
+    save_twice(path, &factory, &loader);
+    clear(path);
+
+    corrupt_file(path, &factory, &loader);
+}
+
+fn missing_file<W, L>(path: &Path, loader: &L)
+where
+    L: Fn(&Path) -> Result<W, Error>,
+{
+    match loader(path) {
+        Ok(_) => panic!("loaded a store from a missing file"),
+        Err(e) => assert!(e.is_not_found(), "missing file gave {:?}", e),
+    }
+}
+
+fn round_trip<W, F, L>(path: &Path, factory: &F, loader: &L)
+where
+    W: Wrapper<Error = Error> + Deref<Target = Local<u64>>,
+    F: Fn(&Path) -> W,
+    L: Fn(&Path) -> Result<W, Error>,
+{
+    let wrapper = factory(path);
+
+    for value in 0..12 {
+        wrapper.update(value * 3).unwrap();
+    }
+
+    wrapper.drop_with_note(&4, "conformance").unwrap();
+    wrapper.schedule_drop_at(&6, 1_000).unwrap();
+
+    let reservation = wrapper.reserve().unwrap();
+
+    wrapper.abandon(reservation).unwrap();
+    wrapper.reserve().unwrap();
+    wrapper.stage(99).unwrap();
+    wrapper.disable(&8).unwrap();
+    wrapper.set_meta(&9, [("env".to_owned(), "conformance".to_owned())].into()).unwrap();
+
+    assert!(!path.exists(), "file exists before the first save");
+
+    wrapper.save().unwrap();
+
+    assert!(path.exists(), "save did not create the file");
+
+    let loaded = loader(path).unwrap();
+
+    assert_same(&wrapper, &loaded);
+}
+
+fn empty_store<W, F, L>(path: &Path, factory: &F, loader: &L)
+where
+    W: Wrapper<Error = Error> + Deref<Target = Local<u64>>,
+    F: Fn(&Path) -> W,
+    L: Fn(&Path) -> Result<W, Error>,
+{
+    let wrapper = factory(path);
+
+    wrapper.save().unwrap();
+
+    let loaded = loader(path).unwrap();
+
+    assert_eq!(loaded.count().unwrap(), 0);
+    assert!(loaded.store_reader().unwrap().is_empty());
+    assert_same(&wrapper, &loaded);
+}
+
+fn large_store<W, F, L>(path: &Path, factory: &F, loader: &L)
+where
+    W: Wrapper<Error = Error> + Deref<Target = Local<u64>>,
+    F: Fn(&Path) -> W,
+    L: Fn(&Path) -> Result<W, Error>,
+{
+    let wrapper = factory(path);
+
+    for value in 0..LARGE_STORE {
+        wrapper.update(value.wrapping_mul(0x9e37_79b9_7f4a_7c15)).unwrap();
+    }
+
+    for version in (1..=LARGE_STORE).step_by(97) {
+        wrapper.drop(&version).unwrap();
+    }
+
+    wrapper.save().unwrap();
+
+    let loaded = loader(path).unwrap();
+
+    assert_same(&wrapper, &loaded);
+}
+
+fn save_twice<W, F, L>(path: &Path, factory: &F, loader: &L)
+where
+    W: Wrapper<Error = Error> + Deref<Target = Local<u64>>,
+    F: Fn(&Path) -> W,
+    L: Fn(&Path) -> Result<W, Error>,
+{
+    let wrapper = factory(path);
+
+    for value in 0..5 {
+        wrapper.update(value).unwrap();
+    }
+
+    wrapper.save().unwrap();
+
+    let once = loader(path).unwrap();
+
+    wrapper.save().unwrap();
+
+    let twice = loader(path).unwrap();
+
+    assert_same(&once, &twice);
+    assert_eq!(once.canonical_bytes().unwrap(), twice.canonical_bytes().unwrap());
+    assert!(!atomic::temp_path(path).exists(), "save left its temporary file");
+}
+
+fn corrupt_file<W, F, L>(path: &Path, factory: &F, loader: &L)
+where
+    W: Wrapper<Error = Error> + Deref<Target = Local<u64>>,
+    F: Fn(&Path) -> W,
+    L: Fn(&Path) -> Result<W, Error>,
+{
+    let wrapper = factory(path);
+
+    for value in 0..5 {
+        wrapper.update(value).unwrap();
+    }
+
+    wrapper.save().unwrap();
+
+    let saved = std::fs::read(path).unwrap();
+    let cases: [(&str, Vec<u8>); 3] = [
+        ("garbage", vec![0xa5; saved.len()]),
+        ("truncated", saved[..saved.len() / 2].to_vec()),
+        ("empty", Vec::new()),
+    ];
+
+    for (case, bytes) in cases {
+        std::fs::write(path, bytes).unwrap();
+
+        match loader(path) {
+            Ok(_) => panic!("loaded a store from a {} file", case),
+            Err(e) => assert!(e.is_corrupt(), "{} file gave {:?}", case, e),
+        }
+    }
+}
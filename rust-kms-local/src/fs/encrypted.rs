@@ -1,28 +1,527 @@
+use std::marker::PhantomData;
 use std::path::{PathBuf, Path};
 use std::fs::OpenOptions;
-use std::io::{Read, Write, BufReader, BufWriter};
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
 
-use serde::Serialize;
-use serde::de::DeserializeOwned;
+use bincode::Options as _;
+use serde::{Serialize, Deserialize};
 
+use crate::fs::Annotations;
 use crate::fs::error::Error;
 use crate::fs::traits::Wrapper;
+use crate::fs::codec::{KeyCodec, SerdeCodec};
+use crate::fs::atomic;
+use crate::fs::retry::{self, RetryPolicy};
 use crate::local::Local;
+use crate::hooks::{Hooks, Op, Timer};
 use crate::crypto;
+use crate::zero::zero;
+#[cfg(feature = "mlock")]
+use crate::memory::LockMode;
+
+/// gives the key of an encrypted store when it is needed
+pub type KeyProvider = Box<dyn Fn() -> Result<crypto::Key, crypto::Error> + Send + Sync>;
+
+/// where an encrypted store gets its key from
+pub enum KeySource {
+    /// a key held by the store for as long as it is alive
+    Key(crypto::Key),
+    /// a provider called each time a key is needed. with a ttl the key is
+    /// kept for that long after it was given and zeroed once it is stale.
+    Provider {
+        provider: KeyProvider,
+        ttl: Option<Duration>,
+    },
+}
+
+impl From<crypto::Key> for KeySource {
+    fn from(key: crypto::Key) -> Self {
+        KeySource::Key(key)
+    }
+}
+
+impl From<KeyProvider> for KeySource {
+    fn from(provider: KeyProvider) -> Self {
+        KeySource::Provider { provider, ttl: None }
+    }
+}
+
+/// a key source and the key last given by its provider
+struct Keys {
+    source: KeySource,
+    cached: Mutex<Option<(crypto::Key, Instant)>>,
+}
+
+impl Keys {
+    fn new(source: KeySource) -> Self {
+        Keys {
+            source,
+            cached: Mutex::new(None),
+        }
+    }
+
+    fn fixed(&self) -> Option<&crypto::Key> {
+        match &self.source {
+            KeySource::Key(key) => Some(key),
+            KeySource::Provider { .. } => None,
+        }
+    }
+
+    /// the key to use now, calling the provider when nothing fresh is
+    /// cached
+    fn get(&self) -> Result<crypto::Key, Error> {
+        let (provider, ttl) = match &self.source {
+            KeySource::Key(key) => return Ok(*key),
+            KeySource::Provider { provider, ttl } => (provider, ttl),
+        };
+
+        let Some(ttl) = ttl else {
+            return provider().map_err(Error::KeyUnavailable);
+        };
+
+        let mut cached = self.cached.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if let Some((key, given)) = cached.as_mut() {
+            if given.elapsed() < *ttl {
+                return Ok(*key);
+            }
+
+            zero(key);
+        }
+
+        *cached = None;
+
+        let key = provider().map_err(Error::KeyUnavailable)?;
+
+        *cached = Some((key, Instant::now()));
+
+        Ok(key)
+    }
+
+    fn forget(&self) {
+        let mut cached = self.cached.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if let Some((key, _)) = cached.as_mut() {
+            zero(key);
+        }
+
+        *cached = None;
+    }
+}
+
+impl Drop for Keys {
+    fn drop(&mut self) {
+        self.forget();
+    }
+}
 
 pub struct Options {
     pub path: PathBuf,
-    pub key: crypto::Key,
+    pub key: KeySource,
+    pub header_path: Option<PathBuf>,
+    pub retry: Option<RetryPolicy>,
+    pub persist_accessed: bool,
+    pub hooks: Option<Arc<dyn Hooks>>,
+    /// added to the annotations read from the file, replacing any with the
+    /// same name
+    pub annotations: Annotations,
+    /// raises a counter below the highest version in the file to it
+    /// instead of failing with
+    /// [`CountTooLow`](crate::local::Error::CountTooLow)
+    pub heal_count: bool,
+    #[cfg(feature = "canonical")]
+    pub deterministic_nonce: bool,
+    #[cfg(feature = "mlock")]
+    pub lock_plaintext: Option<LockMode>,
+}
+
+impl Options {
+    pub fn new<P>(path: P, key: crypto::Key) -> Self
+    where
+        P: Into<PathBuf>
+    {
+        Options {
+            path: path.into(),
+            key: key.into(),
+            header_path: None,
+            retry: None,
+            persist_accessed: false,
+            hooks: None,
+            annotations: Annotations::new(),
+            heal_count: false,
+            #[cfg(feature = "canonical")]
+            deterministic_nonce: false,
+            #[cfg(feature = "mlock")]
+            lock_plaintext: None,
+        }
+    }
+
+    /// options that load with a key from `provider`
+    pub fn with_provider<P>(path: P, provider: KeyProvider) -> Self
+    where
+        P: Into<PathBuf>
+    {
+        let mut options = Options::new(path, crypto::empty_key());
+        options.key = provider.into();
+        options
+    }
+}
+
+/// the newest header format this version can read
+pub const HEADER_VERSION: u32 = 3;
+
+/// the first header format followed by a [`Bridge`]. headers without a
+/// bridge are still written as version 1.
+pub const BRIDGE_HEADER_VERSION: u32 = 2;
+
+/// the first header format followed by an optional [`Bridge`] and the
+/// annotations of the store. headers without annotations are still written
+/// as version 1 or 2.
+pub const ANNOTATED_HEADER_VERSION: u32 = 3;
+
+/// the start of an inline store saved with
+/// [`save_dual`](Encrypted::save_dual)
+pub const BRIDGE_MAGIC: [u8; 8] = *b"rkmsdual";
+
+/// the start of the annotations of an inline store. they come before the
+/// bridge if there is one.
+pub const ANNOTATIONS_MAGIC: [u8; 8] = *b"rkmsnote";
+
+/// the length of the magic and length in front of an inline section
+const SECTION_PREFIX_LEN: usize = 12;
+
+/// the cipher used for the body of an encrypted store
+pub const CIPHER_ID: &str = "xchacha20poly1305";
+
+/// the detached header of an encrypted store.
+///
+/// the header is used as associated data for the body so the body cannot
+/// be decrypted without the exact header it was saved with. keys are given
+/// directly rather than derived so there is no salt or kdf parameters.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    pub format_version: u32,
+    pub cipher: String,
+    pub key_hint: [u8; crypto::KEY_HINT_LEN],
 }
 
-pub struct Encrypted<KeyType> {
+impl Header {
+    fn new(key: &crypto::Key) -> Self {
+        Header {
+            format_version: 1,
+            cipher: CIPHER_ID.to_owned(),
+            key_hint: crypto::key_hint(key),
+        }
+    }
+
+    fn to_bytes(&self, bridge: Option<&Bridge>, annotations: &Annotations) -> Result<Vec<u8>, Error> {
+        if !annotations.is_empty() {
+            let header = Header {
+                format_version: ANNOTATED_HEADER_VERSION,
+                ..self.clone()
+            };
+
+            let mut rtn = bincode::serialize(&header).map_err(Error::Bincode)?;
+            rtn.extend(bincode::serialize(&(bridge, annotations)).map_err(Error::Bincode)?);
+
+            return Ok(rtn);
+        }
+
+        let Some(bridge) = bridge else {
+            return bincode::serialize(self).map_err(Error::Bincode);
+        };
+
+        let header = Header {
+            format_version: BRIDGE_HEADER_VERSION,
+            ..self.clone()
+        };
+
+        let mut rtn = bincode::serialize(&header).map_err(Error::Bincode)?;
+        rtn.extend(bridge.to_bytes()?);
+
+        Ok(rtn)
+    }
+
+    /// parses a header along with the bridge and annotations that follow
+    /// it without checking it against a key
+    fn parse(bytes: &[u8]) -> Result<(Header, Option<Bridge>, Annotations), Error> {
+        let options = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .allow_trailing_bytes()
+            .with_limit(bytes.len() as u64);
+        let mut reader = bytes;
+
+        let header: Header = options.deserialize_from(&mut reader)
+            .map_err(Error::Bincode)?;
+
+        if header.format_version > HEADER_VERSION {
+            return Err(Error::UnsupportedHeader(header.format_version));
+        }
+
+        if header.cipher != CIPHER_ID {
+            return Err(Error::HeaderMismatch);
+        }
+
+        let (bridge, annotations) = if header.format_version >= ANNOTATED_HEADER_VERSION {
+            decode(reader)?
+        } else if header.format_version >= BRIDGE_HEADER_VERSION {
+            (Some(Bridge::from_bytes(reader)?), Annotations::new())
+        } else {
+            (None, Annotations::new())
+        };
+
+        Ok((header, bridge, annotations))
+    }
+
+    /// parses and checks a header against the key it is expected to be
+    /// used with, returning the key of the body and the annotations. the
+    /// key is the given key unless it was bridged to a newer one.
+    fn open(bytes: &[u8], key: &crypto::Key) -> Result<(crypto::Key, Annotations), Error> {
+        let (header, bridge, annotations) = Header::parse(bytes)?;
+
+        let key = match bridge {
+            Some(bridge) => bridge.resolve(key)?,
+            None => *key,
+        };
+
+        if header.key_hint != crypto::key_hint(&key) {
+            return Err(Error::HeaderMismatch);
+        }
+
+        Ok((key, annotations))
+    }
+}
+
+/// a copy of the current key encrypted with the previous key, written by
+/// [`save_dual`](Encrypted::save_dual) so that readers that only have the
+/// previous key can still load the store.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bridge {
+    /// the hint of the previous key
+    pub from_hint: [u8; crypto::KEY_HINT_LEN],
+    /// the hint of the current key, used as associated data for `wrapped`
+    pub to_hint: [u8; crypto::KEY_HINT_LEN],
+    /// the current key encrypted with the previous key
+    pub wrapped: Vec<u8>,
+}
+
+impl Bridge {
+    fn new(from: &crypto::Key, to: &crypto::Key) -> Result<Self, Error> {
+        let to_hint = crypto::key_hint(to);
+        let wrapped = crypto::encrypt_data_aad(from, to.to_vec(), &to_hint)
+            .map_err(Error::Crypto)?;
+
+        Ok(Bridge {
+            from_hint: crypto::key_hint(from),
+            to_hint,
+            wrapped,
+        })
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        bincode::serialize(self).map_err(Error::Bincode)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        decode(bytes)
+    }
+
+    /// the current key if `key` is the previous key, otherwise `key`
+    fn resolve(&self, key: &crypto::Key) -> Result<crypto::Key, Error> {
+        let hint = crypto::key_hint(key);
+
+        if hint == self.to_hint || hint != self.from_hint {
+            return Ok(*key);
+        }
+
+        let unwrapped = crypto::decrypt_data_aad(key, self.wrapped.clone(), &self.to_hint)
+            .map_err(Error::Crypto)?;
+
+        unwrapped.try_into().map_err(|_| Error::HeaderMismatch)
+    }
+}
+
+fn decode<T>(bytes: &[u8]) -> Result<T, Error>
+where
+    T: serde::de::DeserializeOwned
+{
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(bytes.len() as u64)
+        .deserialize(bytes)
+        .map_err(Error::Bincode)
+}
+
+/// the magic and length of an inline section followed by its contents
+fn section(magic: &[u8; 8], contents: Vec<u8>) -> Vec<u8> {
+    let mut rtn = Vec::with_capacity(SECTION_PREFIX_LEN + contents.len());
+    rtn.extend(magic);
+    rtn.extend((contents.len() as u32).to_le_bytes());
+    rtn.extend(contents);
+    rtn
+}
+
+/// takes the section starting with `magic` off the front of `buffer` if
+/// there is one, returning the whole section
+fn split_section(buffer: &mut Vec<u8>, magic: &[u8; 8]) -> Result<Option<Vec<u8>>, Error> {
+    if buffer.len() < SECTION_PREFIX_LEN || buffer[..magic.len()] != *magic {
+        return Ok(None);
+    }
+
+    let mut len = [0; 4];
+    len.copy_from_slice(&buffer[magic.len()..SECTION_PREFIX_LEN]);
+
+    let end = SECTION_PREFIX_LEN.checked_add(u32::from_le_bytes(len) as usize)
+        .filter(|end| *end <= buffer.len())
+        .ok_or(Error::HeaderMismatch)?;
+
+    let body = buffer.split_off(end);
+
+    Ok(Some(std::mem::replace(buffer, body)))
+}
+
+/// the sections in front of the body of an inline store
+#[derive(Default)]
+struct Inline {
+    annotations: Annotations,
+    bridge: Option<Bridge>,
+    /// every section as written, used as associated data for the body
+    aad: Vec<u8>,
+}
+
+/// takes the annotations and bridge off the front of an inline store,
+/// leaving the body in `buffer`
+fn split_inline(buffer: &mut Vec<u8>) -> Result<Inline, Error> {
+    let mut inline = Inline::default();
+
+    if let Some(section) = split_section(buffer, &ANNOTATIONS_MAGIC)? {
+        inline.annotations = decode(&section[SECTION_PREFIX_LEN..])?;
+        inline.aad.extend(section);
+    }
+
+    if let Some(section) = split_section(buffer, &BRIDGE_MAGIC)? {
+        inline.bridge = Some(Bridge::from_bytes(&section[SECTION_PREFIX_LEN..])?);
+        inline.aad.extend(section);
+    }
+
+    Ok(inline)
+}
+
+/// where the detached header being replaced is kept while a save renames
+/// the new files into place, `header_path` with `.prev` added to the file
+/// name.
+///
+/// the header is renamed before the body. if the process stops between the
+/// two, the new header sits next to the old body and load falls back to
+/// this copy. it is removed once both files are in place.
+pub fn previous_header_path(header_path: &Path) -> PathBuf {
+    let mut name = header_path.file_name()
+        .map(|n| n.to_owned())
+        .unwrap_or_default();
+    name.push(".prev");
+
+    header_path.with_file_name(name)
+}
+
+/// copies the current detached header, if there is one, to
+/// [`previous_header_path`]
+fn keep_previous_header(header_path: &Path, retry: Option<&RetryPolicy>) -> Result<(), Error> {
+    let bytes = match std::fs::read(header_path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(Error::Io(e)),
+    };
+
+    atomic::write_with_cancel(
+        &previous_header_path(header_path),
+        bytes.as_slice(),
+        retry,
+        &AtomicBool::new(false)
+    )
+}
+
+/// the annotations of an encrypted store, read without its key. give the
+/// header path if the store was saved with a detached header.
+///
+/// nothing is authenticated until the store is loaded with its key, so the
+/// annotations can only be trusted as far as the file itself is.
+pub fn read_annotations<P>(path: P, header_path: Option<&Path>) -> Result<Annotations, Error>
+where
+    P: AsRef<Path>
+{
+    match header_path {
+        Some(header_path) => {
+            let bytes = std::fs::read(header_path).map_err(|e| match e.kind() {
+                ErrorKind::NotFound => Error::MissingHeader,
+                _ => Error::Io(e)
+            })?;
+
+            Ok(Header::parse(&bytes)?.2)
+        }
+        None => {
+            let mut buffer = std::fs::read(path).map_err(Error::Io)?;
+
+            Ok(split_inline(&mut buffer)?.annotations)
+        }
+    }
+}
+
+/// a store saved as a single bincode blob encrypted with one nonce.
+///
+/// the whole file is a single AEAD message so decryption and deserializing
+/// are always done on a single thread, even with the `rayon` feature
+/// enabled. use [`SealedValues`](crate::fs::SealedValues) when per entry
+/// parallelism matters.
+///
+/// when a header path is set a [`Header`] is written to it on save and the
+/// main file only holds the encrypted body. both files are required to
+/// load. the header being replaced is kept at [`previous_header_path`]
+/// until the body is in place, so a save that stops part way can still be
+/// loaded.
+pub struct Encrypted<KeyType, C = SerdeCodec> {
     manager: Local<KeyType>,
+    codec: PhantomData<fn() -> C>,
     path: Box<Path>,
-    key: crypto::Key,
+    keys: Keys,
+    header_path: Option<Box<Path>>,
+    retry: Option<RetryPolicy>,
+    persist_accessed: bool,
+    hooks: Option<Arc<dyn Hooks>>,
+    annotations: Annotations,
+    #[cfg(feature = "canonical")]
+    deterministic_nonce: bool,
+    #[cfg(feature = "mlock")]
+    lock_plaintext: Option<LockMode>,
 }
 
 impl<KeyType> Encrypted<KeyType> {
     pub fn new<P>(manager: Local<KeyType>, path: P, key: crypto::Key) -> Self
+    where
+        P: Into<PathBuf>
+    {
+        Encrypted::with_codec(manager, path, key)
+    }
+
+    /// a store that asks `provider` for its key on each load and save
+    /// instead of holding it. failures of the provider are
+    /// [`Error::KeyUnavailable`].
+    pub fn with_provider<P>(manager: Local<KeyType>, path: P, provider: KeyProvider) -> Self
+    where
+        P: Into<PathBuf>
+    {
+        let mut store = Encrypted::with_codec(manager, path, crypto::empty_key());
+        store.set_key_provider(provider, None);
+        store
+    }
+}
+
+impl<KeyType, C> Encrypted<KeyType, C> {
+    /// a store whose keys are saved with the codec `C`
+    pub fn with_codec<P>(manager: Local<KeyType>, path: P, key: crypto::Key) -> Self
     where
         P: Into<PathBuf>
     {
@@ -30,21 +529,138 @@ impl<KeyType> Encrypted<KeyType> {
 
         Encrypted {
             manager,
+            codec: PhantomData,
             path: buf.into(),
-            key,
+            keys: Keys::new(key.into()),
+            header_path: None,
+            retry: None,
+            persist_accessed: false,
+            hooks: None,
+            annotations: Annotations::new(),
+            #[cfg(feature = "canonical")]
+            deterministic_nonce: false,
+            #[cfg(feature = "mlock")]
+            lock_plaintext: None,
         }
     }
 
+    /// the store without the file it is saved to
+    pub fn into_inner(self) -> Local<KeyType> {
+        self.manager
+    }
+
     pub fn path(&self) -> &Path {
         &self.path
     }
 
-    pub fn key(&self) -> &crypto::Key {
-        &self.key
+    /// the key held by the store, `None` when it comes from a provider
+    pub fn key(&self) -> Option<&crypto::Key> {
+        self.keys.fixed()
+    }
+
+    /// the key used by the next `save`, replacing any provider
+    pub fn set_key(&mut self, key: crypto::Key) {
+        self.keys = Keys::new(key.into());
+    }
+
+    /// asks `provider` for the key of each following save, replacing any
+    /// key held by the store. with a ttl the given key is reused until it
+    /// is that old.
+    pub fn set_key_provider(&mut self, provider: KeyProvider, ttl: Option<Duration>) {
+        self.keys = Keys::new(KeySource::Provider { provider, ttl });
+    }
+
+    /// zeroes the key cached from the provider, the next save asks for it
+    /// again
+    pub fn forget_key(&self) {
+        self.keys.forget();
+    }
+
+    pub fn header_path(&self) -> Option<&Path> {
+        self.header_path.as_deref()
+    }
+
+    pub fn set_header_path<P>(&mut self, header_path: Option<P>)
+    where
+        P: Into<PathBuf>
+    {
+        self.header_path = header_path.map(|p| p.into().into());
+    }
+
+    pub fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.retry.as_ref()
+    }
+
+    pub fn set_retry_policy(&mut self, retry: Option<RetryPolicy>) {
+        self.retry = retry;
+    }
+
+    pub fn persist_accessed(&self) -> bool {
+        self.persist_accessed
+    }
+
+    /// when set, the access times of each version are written on save.
+    /// access times are otherwise kept in memory only.
+    pub fn set_persist_accessed(&mut self, persist: bool) {
+        self.persist_accessed = persist;
+    }
+
+    pub fn hooks(&self) -> Option<&Arc<dyn Hooks>> {
+        self.hooks.as_ref()
+    }
+
+    /// when set, `load` and `save` are timed with the given hooks
+    pub fn set_hooks(&mut self, hooks: Option<Arc<dyn Hooks>>) {
+        self.hooks = hooks;
+    }
+
+    pub fn annotations(&self) -> &Annotations {
+        &self.annotations
+    }
+
+    /// sets an annotation that is written on the next save, returning the
+    /// previous value. annotations are kept in the clear, in the header if
+    /// there is one and in front of the body otherwise.
+    pub fn set_annotation<K, V>(&mut self, name: K, value: V) -> Option<String>
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.annotations.insert(name.into(), value.into())
+    }
+
+    pub fn remove_annotation(&mut self, name: &str) -> Option<String> {
+        self.annotations.remove(name)
+    }
+
+    #[cfg(feature = "canonical")]
+    pub fn deterministic_nonce(&self) -> bool {
+        self.deterministic_nonce
+    }
+
+    /// when set, the nonce is derived from the key and the serialized store
+    /// so saving the same store twice gives the same file. see
+    /// [`crypto::encrypt_data_deterministic`] for what this gives up.
+    #[cfg(feature = "canonical")]
+    pub fn set_deterministic_nonce(&mut self, deterministic: bool) {
+        self.deterministic_nonce = deterministic;
+    }
+
+    #[cfg(feature = "mlock")]
+    pub fn lock_plaintext(&self) -> Option<LockMode> {
+        self.lock_plaintext
+    }
+
+    /// when set, the store is decrypted in place into locked memory on load
+    /// instead of a plain allocation. with [`LockMode::Strict`] the load
+    /// fails if the memory cannot be locked.
+    #[cfg(feature = "mlock")]
+    pub fn set_lock_plaintext(&mut self, mode: Option<LockMode>) {
+        self.lock_plaintext = mode;
     }
 }
 
-impl<KeyType> std::ops::Deref for Encrypted<KeyType> {
+impl<KeyType, C> std::ops::Deref for Encrypted<KeyType, C> {
     type Target = Local<KeyType>;
 
     fn deref(&self) -> &Self::Target {
@@ -52,7 +668,7 @@ impl<KeyType> std::ops::Deref for Encrypted<KeyType> {
     }
 }
 
-impl<KeyType> std::fmt::Debug for Encrypted<KeyType>
+impl<KeyType, C> std::fmt::Debug for Encrypted<KeyType, C>
 where
     KeyType: std::fmt::Debug
 {
@@ -64,89 +680,835 @@ where
     }
 }
 
-impl<KeyType> Wrapper for Encrypted<KeyType>
+impl<KeyType, C> Wrapper for Encrypted<KeyType, C>
 where
-    KeyType: Serialize + DeserializeOwned
+    C: KeyCodec<KeyType>
 {
     type Error = Error;
     type Args = Options;
 
-    fn load(options: Self::Args) -> Result<Self, Self::Error> {
-        let path = options.path.into();
-        let key = options.key;
+    fn canonical_bytes(&self) -> Result<Vec<u8>, Self::Error> {
+        C::serialize_local(&self.manager, false)
+    }
 
-        let file = OpenOptions::new()
-            .read(true)
-            .open(&path)
-            .map_err(|e| Error::Io(e))?;
-        let mut reader = BufReader::new(file);
-        let mut buffer = Vec::new();
+    fn load_with_cancel(options: Self::Args, cancel: &AtomicBool) -> Result<Self, Self::Error> {
+        let path: Box<Path> = options.path.into();
+        let keys = Keys::new(options.key);
+        let key = keys.get()?;
+        let header_path: Option<Box<Path>> = options.header_path.map(Into::into);
+        let retry = options.retry;
+        let persist_accessed = options.persist_accessed;
+        let heal_count = options.heal_count;
+        let hooks = options.hooks;
+        let _timer = Timer::start(hooks.as_ref(), Op::Load);
+        #[cfg(feature = "canonical")]
+        let deterministic_nonce = options.deterministic_nonce;
+        #[cfg(feature = "mlock")]
+        let lock_plaintext = options.lock_plaintext;
 
-        reader.read_to_end(&mut buffer)
-            .map_err(|e| Error::Io(e))?;
+        let buffer = retry::read_with_cancel(
+            || OpenOptions::new().read(true).open(&path),
+            retry.as_ref(),
+            cancel
+        )?;
 
-        let decrypted = crypto::decrypt_data(&key, buffer)
-            .map_err(|e| Error::Crypto(e))?;
+        retry::check_cancel(cancel)?;
 
-        let manager = bincode::deserialize(decrypted.as_slice())
-            .map_err(|e| match *e {
-                bincode::ErrorKind::Io(io) => Error::Io(io),
-                _ => Error::Bincode(e),
-            })?;
+        let open_body = |key: &crypto::Key, body: Vec<u8>, aad: &[u8]| -> Result<Local<KeyType>, Error> {
+            #[cfg(feature = "mlock")]
+            if let Some(mode) = lock_plaintext {
+                let decrypted = crypto::decrypt_data_aad_locked(key, &body, aad, mode)
+                    .map_err(Error::Crypto)?;
+
+                retry::check_cancel(cancel)?;
+
+                return C::deserialize_local(decrypted.as_slice(), heal_count);
+            }
+
+            let decrypted = crypto::decrypt_data_aad(key, body, aad)
+                .map_err(Error::Crypto)?;
+
+            retry::check_cancel(cancel)?;
+
+            C::deserialize_local(decrypted.as_slice(), heal_count)
+        };
+
+        let (key, mut annotations, manager) = match &header_path {
+            Some(header_path) => {
+                let bytes = retry::read_with_cancel(
+                    || OpenOptions::new().read(true).open(header_path),
+                    retry.as_ref(),
+                    cancel
+                ).map_err(|e| match e {
+                    Error::Io(ref io) | Error::Retries { error: ref io, .. } if io.kind() == ErrorKind::NotFound => Error::MissingHeader,
+                    e => e
+                })?;
+
+                let open_with = |header: &[u8], body: Vec<u8>| {
+                    let (key, annotations) = Header::open(header, &key)?;
+                    let manager = open_body(&key, body, header)?;
+
+                    Ok::<_, Error>((key, annotations, manager))
+                };
+
+                match open_with(&bytes, buffer.clone()) {
+                    // a save that stopped between renaming the header and
+                    // the body leaves the new header next to the old body
+                    Err(err @ (Error::HeaderMismatch | Error::Crypto(_))) => {
+                        match std::fs::read(previous_header_path(header_path)) {
+                            Ok(previous) => open_with(&previous, buffer).map_err(|_| err)?,
+                            Err(_) => return Err(err),
+                        }
+                    }
+                    opened => opened?,
+                }
+            }
+            None => {
+                let mut body = buffer;
+                let inline = split_inline(&mut body)?;
+
+                let key = match &inline.bridge {
+                    Some(bridge) => bridge.resolve(&key)?,
+                    None => key,
+                };
+
+                let manager = open_body(&key, body, &inline.aad)?;
+
+                (key, inline.annotations, manager)
+            }
+        };
+
+        annotations.extend(options.annotations);
+
+        // a bridged key replaces a held key, a provider stays the source
+        let keys = match keys.fixed() {
+            Some(_) => Keys::new(key.into()),
+            None => keys,
+        };
 
         Ok(Encrypted {
             manager,
+            codec: PhantomData,
             path,
-            key
+            keys,
+            header_path,
+            retry,
+            persist_accessed,
+            hooks,
+            annotations,
+            #[cfg(feature = "canonical")]
+            deterministic_nonce,
+            #[cfg(feature = "mlock")]
+            lock_plaintext,
         })
     }
 
-    fn save(&self) -> Result<(), Self::Error> {
-        let serialize = bincode::serialize(&self.manager)
-            .map_err(|e| match *e {
-                bincode::ErrorKind::Io(io) => Error::Io(io),
-                _ => Error::Bincode(e)
-            })?;
+    fn save_with_cancel(&self, cancel: &AtomicBool) -> Result<(), Self::Error> {
+        self.write(&self.keys.get()?, None, cancel)
+    }
+}
 
-        let encrypted = crypto::encrypt_data(&self.key, serialize)
-            .map_err(|e| Error::Crypto(e))?;
+impl<KeyType, C> Encrypted<KeyType, C>
+where
+    C: KeyCodec<KeyType>
+{
+    /// loads the store or, when there is nothing at `options.path`, starts
+    /// an empty store with the same options that is written there on the
+    /// first save. a missing header for an existing store is still an
+    /// error.
+    pub fn load_or_create(options: Options) -> Result<Self, Error> {
+        if options.path.try_exists().map_err(Error::Io)? {
+            return Self::load(options);
+        }
 
-        let file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(&self.path)
-            .map_err(|e| Error::Io(e))?;
-        let mut writer = BufWriter::new(file);
+        Ok(Encrypted {
+            manager: Local::new(),
+            codec: PhantomData,
+            path: options.path.into(),
+            keys: Keys::new(options.key),
+            header_path: options.header_path.map(Into::into),
+            retry: options.retry,
+            persist_accessed: options.persist_accessed,
+            hooks: options.hooks,
+            annotations: options.annotations,
+            #[cfg(feature = "canonical")]
+            deterministic_nonce: options.deterministic_nonce,
+            #[cfg(feature = "mlock")]
+            lock_plaintext: options.lock_plaintext,
+        })
+    }
+
+    /// saves the store encrypted with `new_key` along with a copy of
+    /// `new_key` encrypted with `old_key`, so that loading with either key
+    /// works while readers move to the new key. the copy is kept in the
+    /// detached header if there is one, otherwise in front of the body.
+    ///
+    /// a store loaded with either key keeps `new_key`, so the next `save`
+    /// is under `new_key` alone and old key readers stop working. the key
+    /// of this store is not changed, see [`set_key`](Encrypted::set_key).
+    pub fn save_dual(&self, old_key: &crypto::Key, new_key: &crypto::Key) -> Result<(), Error> {
+        let bridge = Bridge::new(old_key, new_key)?;
+
+        self.write(new_key, Some(&bridge), &AtomicBool::new(false))
+    }
+
+    fn write(&self, key: &crypto::Key, bridge: Option<&Bridge>, cancel: &AtomicBool) -> Result<(), Error> {
+        let _timer = Timer::start(self.hooks.as_ref(), Op::Save);
+
+        let serialize = C::serialize_local(&self.manager, self.persist_accessed)?;
+
+        retry::check_cancel(cancel)?;
+
+        let header = match &self.header_path {
+            Some(_) => Header::new(key).to_bytes(bridge, &self.annotations)?,
+            None => {
+                let mut prefix = Vec::new();
+
+                if !self.annotations.is_empty() {
+                    let bytes = bincode::serialize(&self.annotations).map_err(Error::Bincode)?;
+
+                    prefix.extend(section(&ANNOTATIONS_MAGIC, bytes));
+                }
+
+                if let Some(bridge) = bridge {
+                    prefix.extend(section(&BRIDGE_MAGIC, bridge.to_bytes()?));
+                }
+
+                prefix
+            }
+        };
+
+        #[cfg(feature = "canonical")]
+        let encrypted = if self.deterministic_nonce {
+            crypto::encrypt_data_deterministic_aad(key, serialize, &header)
+        } else {
+            crypto::encrypt_data_aad(key, serialize, &header)
+        }.map_err(Error::Crypto)?;
+
+        #[cfg(not(feature = "canonical"))]
+        let encrypted = crypto::encrypt_data_aad(key, serialize, &header)
+            .map_err(Error::Crypto)?;
 
-        writer.write_all(encrypted.as_slice())
-            .map_err(|e| Error::Io(e))?;
+        retry::check_cancel(cancel)?;
 
-        Ok(())
+        if let Some(header_path) = &self.header_path {
+            // both temporary files are written before either is renamed so
+            // a cancelled or failed write changes neither. nothing is
+            // checked between the renames.
+            let header_temp = atomic::write_temp(
+                header_path,
+                header.as_slice(),
+                self.retry.as_ref(),
+                cancel
+            )?;
+            let body_temp = atomic::write_temp(
+                &self.path,
+                encrypted.as_slice(),
+                self.retry.as_ref(),
+                cancel
+            )?;
+
+            keep_previous_header(header_path, self.retry.as_ref())?;
+
+            atomic::rename_temp(&header_temp, header_path, self.retry.as_ref())?;
+            atomic::rename_temp(&body_temp, &self.path, self.retry.as_ref())?;
+
+            // the copy may hold a bridge from the old key, it is only kept
+            // for as long as the two files can disagree
+            match std::fs::remove_file(previous_header_path(header_path)) {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(Error::Io(e)),
+                _ => Ok(()),
+            }
+        } else {
+            // inline annotations and bridge are written in front of the
+            // body they cover
+            let mut file = header;
+            file.extend(encrypted);
+
+            atomic::write_with_cancel(
+                &self.path,
+                file.as_slice(),
+                self.retry.as_ref(),
+                cancel
+            )
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::local;
+    use crate::test_util::{self, TempStore};
     use crate::fs;
+    use std::time::Duration;
+
+    #[test]
+    fn conformance() {
+        let key = [3; crypto::KEY_LEN];
+
+        fs::conformance::run(
+            |path| Encrypted::new(Local::new(), path, key),
+            |path| Encrypted::load(Options::new(path, key)),
+        );
+    }
 
     #[test]
     fn base() {
-        let file_name = "test.encrypted";
-        let manager = local::test::create_store();
+        let temp = TempStore::new("encrypted");
+        let file_name = temp.path();
+        let manager = test_util::sample_local();
+
+        let wrapper = Encrypted::new(manager, file_name, crypto::empty_key());
+
+        wrapper.save().expect("failed to save to encrypted file");
+
+        let and_back: Encrypted<u64> = Encrypted::load(Options::new(file_name, crypto::empty_key()))
+            .expect("failed to load encrypted file");
+
+        test_util::assert_local_eq(&wrapper.manager, &and_back.manager);
+    }
+
+    #[test]
+    fn untagged_layout() {
+        // saved before the binary layout was tagged with a key of all 7s, a
+        // counter of 2 and keys 10 and 20
+        let temp = TempStore::new("encrypted.untagged");
+        let file_name = temp.path();
+
+        std::fs::write(file_name, include_bytes!("../../fixtures/legacy/local.enc"))
+            .expect("failed to write fixture");
+
+        let and_back: Encrypted<u64> = Encrypted::load(Options::new(file_name, [7; crypto::KEY_LEN]))
+            .expect("failed to load untagged encrypted file");
+
+        assert_eq!(and_back.count().unwrap(), 2);
+        assert_eq!(and_back.get(&1).unwrap(), Some(10));
+        assert_eq!(and_back.get(&2).unwrap(), Some(20));
+        assert_eq!(and_back.latest().unwrap(), Some(20));
+    }
+
+    #[test]
+    fn hooks() {
+        use crate::hooks::AtomicHistogramHooks;
+
+        let temp = TempStore::new("encrypted.hooks");
+        let file_name = temp.path();
+        let hooks = Arc::new(AtomicHistogramHooks::new());
+        let manager = test_util::sample_local();
+
+        let mut wrapper = Encrypted::new(manager, file_name, crypto::empty_key());
+        wrapper.set_hooks(Some(hooks.clone()));
+
+        wrapper.save().expect("failed to save to encrypted file");
+        wrapper.save().expect("failed to save to encrypted file");
+
+        let mut options = Options::new(file_name, crypto::empty_key());
+        options.hooks = Some(hooks.clone());
+
+        let and_back: Encrypted<u64> = Encrypted::load(options)
+            .expect("failed to load encrypted file");
+
+        assert!(and_back.hooks().is_some());
+
+        let snapshot = hooks.snapshot();
+
+        assert_eq!(snapshot.get(Op::Save).total(), 2);
+        assert_eq!(snapshot.get(Op::Load).total(), 1);
+        // the store itself was built without hooks
+        assert_eq!(snapshot.get(Op::Get).total(), 0);
+    }
+
+    #[cfg(feature = "mlock")]
+    #[test]
+    fn lock_plaintext() {
+        let temp = TempStore::new("encrypted.locked");
+        let file_name = temp.path();
+        let manager = test_util::sample_local();
+
+        let mut wrapper = Encrypted::new(manager, file_name, crypto::empty_key());
+        wrapper.set_lock_plaintext(Some(LockMode::BestEffort));
+
+        wrapper.save().expect("failed to save to encrypted file");
+
+        let mut options = Options::new(file_name, crypto::empty_key());
+        options.lock_plaintext = Some(LockMode::BestEffort);
 
-        fs::test::create_test_file(file_name);
+        let and_back: Encrypted<u64> = Encrypted::load(options)
+            .expect("failed to load encrypted file");
+
+        test_util::assert_local_eq(&wrapper.manager, &and_back.manager);
+
+        let mut options = Options::new(file_name, [1; crypto::KEY_LEN]);
+        options.lock_plaintext = Some(LockMode::BestEffort);
+
+        assert!(matches!(
+            Encrypted::<u64>::load(options),
+            Err(Error::Crypto(crypto::Error::ChaCha))
+        ));
+    }
+
+    #[test]
+    fn cancelled() {
+        let temp = TempStore::new("encrypted.cancelled");
+        let file_name = temp.path();
+        let manager = test_util::sample_local();
 
         let wrapper = Encrypted::new(manager, file_name, crypto::empty_key());
 
         wrapper.save().expect("failed to save to encrypted file");
 
-        let and_back: Encrypted<u64> = Encrypted::load(Options {
-            path: PathBuf::from(file_name),
-            key: crypto::empty_key(),
-        }).expect("failed to load encrypted file");
+        let before = std::fs::read(file_name)
+            .expect("failed to read encrypted file");
+        let cancel = AtomicBool::new(true);
+
+        let result = wrapper.save_with_cancel(&cancel);
+
+        assert!(matches!(result, Err(Error::Cancelled)), "unexpected result: {:?}", result);
+        assert_eq!(std::fs::read(file_name).unwrap(), before, "cancelled save changed the file");
+
+        let result = Encrypted::<u64>::load_with_cancel(
+            Options::new(file_name, crypto::empty_key()),
+            &cancel
+        );
+
+        assert!(matches!(result, Err(Error::Cancelled)), "unexpected result: {:?}", result);
+
+        let and_back: Encrypted<u64> = Encrypted::load_with_timeout(
+            Options::new(file_name, crypto::empty_key()),
+            Duration::from_secs(10)
+        ).expect("failed to load encrypted file");
+
+        test_util::assert_local_eq(&wrapper.manager, &and_back.manager);
+
+        let mut temp = TempStore::new("encrypted.cancelled_detached");
+        let header = temp.sibling(".header");
+        let file_name = temp.path();
+
+        let mut wrapper = Encrypted::new(test_util::sample_local(), file_name, crypto::empty_key());
+        wrapper.set_header_path(Some(&header));
+        wrapper.save().expect("failed to save to encrypted file");
+
+        let before = std::fs::read(file_name)
+            .expect("failed to read encrypted file");
+        let header_before = std::fs::read(&header)
+            .expect("failed to read header");
+
+        // a new key changes the header
+        wrapper.set_key([4; crypto::KEY_LEN]);
+
+        let result = wrapper.save_with_cancel(&cancel);
+
+        assert!(matches!(result, Err(Error::Cancelled)), "unexpected result: {:?}", result);
+        assert_eq!(std::fs::read(file_name).unwrap(), before, "cancelled save changed the body");
+        assert_eq!(std::fs::read(&header).unwrap(), header_before, "cancelled save changed the header");
+
+        let mut options = Options::new(file_name, crypto::empty_key());
+        options.header_path = Some(header.clone());
+
+        let and_back: Encrypted<u64> = Encrypted::load(options)
+            .expect("failed to load encrypted file");
+
+        test_util::assert_local_eq(&wrapper.manager, &and_back.manager);
+    }
+
+    #[test]
+    fn previous_header() {
+        let old_key = [1; crypto::KEY_LEN];
+        let new_key = [2; crypto::KEY_LEN];
+        let mut temp = TempStore::new("encrypted.previous_header");
+        let header = temp.sibling(".header");
+        let previous = temp.sibling(".header.prev");
+        let file_name = temp.path();
+
+        assert_eq!(previous_header_path(&header), previous);
+
+        let mut wrapper = Encrypted::new(test_util::sample_local(), file_name, old_key);
+        wrapper.set_header_path(Some(&header));
+        wrapper.save().expect("failed to save to encrypted file");
+
+        let old_body = std::fs::read(file_name).unwrap();
+        let old_header = std::fs::read(&header).unwrap();
+
+        wrapper.set_key(new_key);
+        wrapper.save().expect("failed to save with the new key");
+
+        assert!(!previous.exists(), "previous header was left after the save");
+
+        // as if the save stopped after the header was renamed
+        std::fs::write(file_name, &old_body).unwrap();
+        std::fs::write(&previous, &old_header).unwrap();
+
+        let mut options = Options::new(file_name, old_key);
+        options.header_path = Some(header.clone());
+
+        let and_back: Encrypted<u64> = Encrypted::load(options)
+            .expect("failed to load with the previous header");
+
+        test_util::assert_local_eq(&wrapper.manager, &and_back.manager);
+
+        std::fs::remove_file(&previous).unwrap();
+
+        let mut options = Options::new(file_name, old_key);
+        options.header_path = Some(header.clone());
+
+        let result = Encrypted::<u64>::load(options);
+
+        assert!(matches!(result, Err(Error::HeaderMismatch)), "unexpected result: {:?}", result);
+    }
+
+    #[cfg(feature = "canonical")]
+    #[test]
+    fn deterministic_nonce() {
+        let temp_a = TempStore::new("encrypted.deterministic_a");
+        let file_a = temp_a.path();
+        let temp_b = TempStore::new("encrypted.deterministic_b");
+        let file_b = temp_b.path();
+
+        let mut a = Encrypted::new(test_util::sample_local(), file_a, crypto::empty_key());
+        let mut b = Encrypted::new(test_util::sample_local(), file_b, crypto::empty_key());
+
+        a.set_deterministic_nonce(true);
+        b.set_deterministic_nonce(true);
+        a.save().expect("failed to save to encrypted file");
+        b.save().expect("failed to save to encrypted file");
+
+        assert_eq!(std::fs::read(file_a).unwrap(), std::fs::read(file_b).unwrap());
+        assert_eq!(a.content_hash().unwrap(), b.content_hash().unwrap());
+
+        let and_back: Encrypted<u64> = Encrypted::load(Options::new(file_a, crypto::empty_key()))
+            .expect("failed to load encrypted file");
+
+        test_util::assert_local_eq(&a.manager, &and_back.manager);
+
+        b.set_deterministic_nonce(false);
+        b.save().expect("failed to save to encrypted file");
+
+        assert_ne!(std::fs::read(file_a).unwrap(), std::fs::read(file_b).unwrap());
+        assert_eq!(a.content_hash().unwrap(), b.content_hash().unwrap());
+    }
+
+    #[test]
+    fn save_dual() {
+        let old_key = [1; crypto::KEY_LEN];
+        let new_key = [2; crypto::KEY_LEN];
+
+        for detached in [false, true] {
+            let mut temp = TempStore::new(if detached {
+                "encrypted.dual_detached"
+            } else {
+                "encrypted.dual_inline"
+            });
+            let header = detached.then(|| temp.sibling(".header"));
+            let file_name = temp.path();
+
+            let options = |key| {
+                let mut options = Options::new(file_name, key);
+                options.header_path = header.clone();
+                options
+            };
+
+            let mut wrapper = Encrypted::new(test_util::sample_local(), file_name, old_key);
+            wrapper.set_header_path(header.clone());
+            wrapper.save().expect("failed to save to encrypted file");
+            wrapper.save_dual(&old_key, &new_key).expect("failed to save with both keys");
+
+            let with_old: Encrypted<u64> = Encrypted::load(options(old_key))
+                .expect("failed to load with the old key");
+            let with_new: Encrypted<u64> = Encrypted::load(options(new_key))
+                .expect("failed to load with the new key");
+
+            test_util::assert_local_eq(&wrapper.manager, &with_old.manager);
+            test_util::assert_local_eq(&wrapper.manager, &with_new.manager);
+            assert_eq!(with_old.key(), Some(&new_key));
+
+            let wrong = Encrypted::<u64>::load(options([3; crypto::KEY_LEN]));
+
+            assert!(wrong.is_err());
+
+            // saving without the bridge leaves only the new key
+            with_old.save().expect("failed to save with the new key");
+
+            let result = Encrypted::<u64>::load(options(old_key));
+
+            assert!(
+                matches!(result, Err(Error::HeaderMismatch) | Err(Error::Crypto(_))),
+                "unexpected result: {:?}",
+                result
+            );
+
+            let and_back: Encrypted<u64> = Encrypted::load(options(new_key))
+                .expect("failed to load with the new key");
+
+            test_util::assert_local_eq(&wrapper.manager, &and_back.manager);
+        }
+    }
+
+    #[test]
+    fn detached_header() {
+        let temp_a = TempStore::new("encrypted.detached_a");
+        let file_a = temp_a.path();
+        let temp_header_a = TempStore::new("encrypted.detached_a.header");
+        let header_a = temp_header_a.path();
+        let temp_b = TempStore::new("encrypted.detached_b");
+        let file_b = temp_b.path();
+        let temp_header_b = TempStore::new("encrypted.detached_b.header");
+        let header_b = temp_header_b.path();
+        let key_b = [1; crypto::KEY_LEN];
+
+        let mut a = Encrypted::new(test_util::sample_local(), file_a, crypto::empty_key());
+        a.set_header_path(Some(header_a));
+        a.save().expect("failed to save to encrypted file");
+
+        let mut b = Encrypted::new(test_util::sample_local(), file_b, key_b);
+        b.set_header_path(Some(header_b));
+        b.save().expect("failed to save to encrypted file");
+
+        let detached = |file, header, key| {
+            let mut options = Options::new(file, key);
+            options.header_path = Some(PathBuf::from(header));
+
+            Encrypted::<u64>::load(options)
+        };
+
+        let and_back = detached(file_a, header_a, crypto::empty_key())
+            .expect("failed to load encrypted file");
+
+        test_util::assert_local_eq(&a.manager, &and_back.manager);
+
+        // the body alone cannot be loaded without its header
+        let result = Encrypted::<u64>::load(Options::new(file_a, crypto::empty_key()));
+
+        assert!(matches!(result, Err(Error::Crypto(_))), "unexpected result: {:?}", result);
+
+        let result = detached(file_a, header_b, crypto::empty_key());
+
+        assert!(matches!(result, Err(Error::HeaderMismatch)), "unexpected result: {:?}", result);
+
+        std::fs::remove_file(header_a).expect("failed to remove header");
+
+        let result = detached(file_a, header_a, crypto::empty_key());
+
+        assert!(matches!(result, Err(Error::MissingHeader)), "unexpected result: {:?}", result);
+
+        let mut newer = Header::new(&key_b);
+        newer.format_version = HEADER_VERSION + 1;
+
+        std::fs::write(header_b, newer.to_bytes(None, &Annotations::new()).unwrap())
+            .expect("failed to write header");
+
+        let result = detached(file_b, header_b, key_b);
+
+        assert!(matches!(result, Err(Error::UnsupportedHeader(4))), "unexpected result: {:?}", result);
+    }
+
+    #[test]
+    fn annotations() {
+        let old_key = [1; crypto::KEY_LEN];
+        let key = [2; crypto::KEY_LEN];
+
+        for detached in [false, true] {
+            let mut temp = TempStore::new(if detached {
+                "encrypted.annotations_detached"
+            } else {
+                "encrypted.annotations_inline"
+            });
+            let header = detached.then(|| temp.sibling(".header"));
+            let file_name = temp.path();
+
+            let options = |key| {
+                let mut options = Options::new(file_name, key);
+                options.header_path = header.clone();
+                options
+            };
+
+            let mut wrapper = Encrypted::new(test_util::sample_local(), file_name, key);
+            wrapper.set_header_path(header.clone());
+            wrapper.set_annotation("name", "payments-db-envelope-keys");
+            wrapper.set_annotation("owner", "payments");
+            wrapper.save().expect("failed to save to encrypted file");
+
+            // readable without the key
+            let annotations = read_annotations(file_name, header.as_deref())
+                .expect("failed to read annotations");
+
+            assert_eq!(&annotations, wrapper.annotations());
+            assert_eq!(annotations["name"], "payments-db-envelope-keys");
+
+            let mut with_env = options(key);
+            with_env.annotations.insert("owner".into(), "platform".into());
+            with_env.annotations.insert("environment".into(), "prod".into());
+
+            let mut and_back: Encrypted<u64> = Encrypted::load(with_env)
+                .expect("failed to load encrypted file");
+
+            test_util::assert_local_eq(&wrapper.manager, &and_back.manager);
+            assert_eq!(and_back.annotations().len(), 3);
+            assert_eq!(and_back.annotations()["owner"], "platform");
+            assert_eq!(and_back.remove_annotation("environment").as_deref(), Some("prod"));
+
+            // both the annotations and a bridge in front of the body
+            wrapper.save_dual(&old_key, &key).expect("failed to save with both keys");
+
+            let with_old: Encrypted<u64> = Encrypted::load(options(old_key))
+                .expect("failed to load with the old key");
+
+            assert_eq!(with_old.annotations(), wrapper.annotations());
+            assert_eq!(read_annotations(file_name, header.as_deref()).unwrap(), annotations);
+
+            // saving without annotations goes back to the previous format
+            wrapper.remove_annotation("name");
+            wrapper.remove_annotation("owner");
+            wrapper.save().expect("failed to save to encrypted file");
+
+            assert!(read_annotations(file_name, header.as_deref()).unwrap().is_empty());
+            assert!(Encrypted::<u64>::load(options(key)).unwrap().annotations().is_empty());
+        }
+    }
+
+    #[test]
+    fn tampered_annotations() {
+        let temp = TempStore::new("encrypted.annotations_tampered");
+        let file_name = temp.path();
+
+        let mut wrapper = Encrypted::new(test_util::sample_local(), file_name, crypto::empty_key());
+        wrapper.set_annotation("environment", "prod");
+        wrapper.save().expect("failed to save to encrypted file");
+
+        let contents = std::fs::read(file_name).unwrap();
+        let at = contents.windows(4).position(|w| w == b"prod").unwrap();
+        let mut tampered = contents.clone();
+        tampered[at..at + 4].copy_from_slice(b"test");
+
+        std::fs::write(file_name, tampered).unwrap();
+
+        assert_eq!(read_annotations(file_name, None).unwrap()["environment"], "test");
+
+        let result = Encrypted::<u64>::load(Options::new(file_name, crypto::empty_key()));
+
+        assert!(matches!(result, Err(Error::Crypto(_))), "unexpected result: {:?}", result);
+    }
+
+    /// a provider of `key` that counts its calls and fails once `fail` is
+    /// set
+    fn counting(key: crypto::Key) -> (KeyProvider, Arc<std::sync::atomic::AtomicUsize>, Arc<AtomicBool>) {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let fail = Arc::new(AtomicBool::new(false));
+        let provider: KeyProvider = {
+            let calls = calls.clone();
+            let fail = fail.clone();
+
+            Box::new(move || {
+                calls.fetch_add(1, Ordering::SeqCst);
+
+                if fail.load(Ordering::SeqCst) {
+                    Err(crypto::Error::ChaCha)
+                } else {
+                    Ok(key)
+                }
+            })
+        };
+
+        (provider, calls, fail)
+    }
+
+    #[test]
+    fn key_provider() {
+        use std::sync::atomic::Ordering;
+
+        let temp = TempStore::new("encrypted.provider");
+        let file_name = temp.path();
+        let key = [5; crypto::KEY_LEN];
+        let (provider, calls, _) = counting(key);
+
+        let wrapper = Encrypted::with_provider(test_util::sample_local(), file_name, provider);
+
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        assert!(wrapper.key().is_none());
+
+        wrapper.save().expect("failed to save to encrypted file");
+        wrapper.save().expect("failed to save to encrypted file");
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let and_back: Encrypted<u64> = Encrypted::load(Options::new(file_name, key))
+            .expect("failed to load with the provided key");
+
+        test_util::assert_local_eq(&wrapper.manager, &and_back.manager);
+
+        let (provider, calls, _) = counting(key);
+        let and_back: Encrypted<u64> = Encrypted::load(Options::with_provider(file_name, provider))
+            .expect("failed to load with a provider");
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(and_back.key().is_none());
+
+        and_back.save().expect("failed to save to encrypted file");
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn key_provider_ttl() {
+        use std::sync::atomic::Ordering;
+
+        let temp = TempStore::new("encrypted.provider_ttl");
+        let file_name = temp.path();
+        let (provider, calls, _) = counting([5; crypto::KEY_LEN]);
+
+        let mut wrapper = Encrypted::new(test_util::sample_local(), file_name, crypto::empty_key());
+        wrapper.set_key_provider(provider, Some(Duration::from_secs(3600)));
+
+        wrapper.save().expect("failed to save to encrypted file");
+        wrapper.save().expect("failed to save to encrypted file");
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        wrapper.forget_key();
+        wrapper.save().expect("failed to save to encrypted file");
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let (provider, calls, _) = counting([5; crypto::KEY_LEN]);
+        wrapper.set_key_provider(provider, Some(Duration::from_millis(20)));
+
+        wrapper.save().expect("failed to save to encrypted file");
+        std::thread::sleep(Duration::from_millis(40));
+        wrapper.save().expect("failed to save to encrypted file");
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn key_provider_failure() {
+        let temp = TempStore::new("encrypted.provider_failure");
+        let file_name = temp.path();
+        let key = [5; crypto::KEY_LEN];
+        let (provider, _, fail) = counting(key);
+
+        let wrapper = Encrypted::with_provider(test_util::sample_local(), file_name, provider);
+        wrapper.save().expect("failed to save to encrypted file");
+
+        let contents = std::fs::read(file_name).unwrap();
+
+        wrapper.drop(&1).unwrap();
+        fail.store(true, std::sync::atomic::Ordering::SeqCst);
+
+        let result = wrapper.save();
+
+        assert!(matches!(result, Err(Error::KeyUnavailable(crypto::Error::ChaCha))), "unexpected result: {:?}", result);
+        assert!(!result.unwrap_err().is_corrupt());
+        assert_eq!(std::fs::read(file_name).unwrap(), contents);
+
+        let (provider, _, fail) = counting(key);
+        fail.store(true, std::sync::atomic::Ordering::SeqCst);
+
+        let result = Encrypted::<u64>::load(Options::with_provider(file_name, provider));
+
+        assert!(matches!(result, Err(Error::KeyUnavailable(_))), "unexpected result: {:?}", result);
+        let and_back = Encrypted::<u64>::load(Options::new(file_name, key)).unwrap();
 
-        local::test::assert_local_eq(&wrapper.manager, &and_back.manager);
+        test_util::assert_local_eq(&test_util::sample_local(), &and_back.manager);
     }
 }
@@ -1,18 +1,150 @@
 use std::io::Error as IoError;
 use std::fmt;
 
+/// the part of a binary store that was being read when decoding failed
+#[cfg(feature = "binary")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Header,
+    Counter,
+    Store,
+    Accessed,
+    Pending,
+    Reserved,
+    Tombstones,
+    Staged,
+    Disabled,
+    Meta,
+}
+
+#[cfg(feature = "binary")]
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Phase::Header => f.write_str("reading header"),
+            Phase::Counter => f.write_str("reading counter"),
+            Phase::Store => f.write_str("reading store entry"),
+            Phase::Accessed => f.write_str("reading access times"),
+            Phase::Pending => f.write_str("reading pending drops"),
+            Phase::Reserved => f.write_str("reading reservations"),
+            Phase::Tombstones => f.write_str("reading tombstones"),
+            Phase::Staged => f.write_str("reading staged versions"),
+            Phase::Disabled => f.write_str("reading disabled versions"),
+            Phase::Meta => f.write_str("reading metadata"),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
     Io(IoError),
 
+    Retries {
+        attempts: u32,
+        error: IoError,
+    },
+
+    Local(crate::local::Error),
+
+    /// the operation was stopped by its cancel flag
+    Cancelled,
+
+    /// the key of `version` failed to serialize while saving. nothing was
+    /// written.
+    KeySerialization {
+        version: u64,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
     #[cfg(feature = "binary")]
     Bincode(bincode::Error),
 
+    /// a [`KeyCodec`](crate::fs::codec::KeyCodec) failed to decode a key
+    #[cfg(feature = "binary")]
+    Codec(Box<dyn std::error::Error + Send + Sync>),
+
+    #[cfg(feature = "binary")]
+    BincodeAt {
+        phase: Phase,
+        offset: u64,
+        size: u64,
+        error: bincode::Error,
+    },
+
     #[cfg(feature = "json")]
     Json(serde_json::Error),
 
-    #[cfg(feature = "crypto")]
+    #[cfg(any(feature = "encrypted", feature = "sealed"))]
     Crypto(crate::crypto::Error),
+
+    /// the detached header of an encrypted store could not be found
+    #[cfg(feature = "encrypted")]
+    MissingHeader,
+
+    /// the detached header was made with a different key or cipher
+    #[cfg(feature = "encrypted")]
+    HeaderMismatch,
+
+    /// the detached header is from a newer format version
+    #[cfg(feature = "encrypted")]
+    UnsupportedHeader(u32),
+
+    /// the key provider of an encrypted store failed to give a key. nothing
+    /// was read or written.
+    #[cfg(feature = "encrypted")]
+    KeyUnavailable(crate::crypto::Error),
+
+    #[cfg(feature = "sealed")]
+    Base64(base64::DecodeError),
+
+    /// the integrity tag of the store did not match its contents
+    #[cfg(feature = "integrity")]
+    IntegrityFailure,
+
+    /// the store has no integrity tag and one is required
+    #[cfg(feature = "integrity")]
+    IntegrityMissing,
+}
+
+impl Error {
+    /// the file of the store does not exist
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            Error::Io(e) |
+            Error::Retries { error: e, .. } => e.kind() == std::io::ErrorKind::NotFound,
+            _ => false,
+        }
+    }
+
+    /// the file was read but its contents are not a store that can be
+    /// loaded, e.g. it was truncated, overwritten, or saved with another key
+    pub fn is_corrupt(&self) -> bool {
+        match self {
+            #[cfg(feature = "binary")]
+            Error::Bincode(_) |
+            Error::Codec(_) |
+            Error::BincodeAt { .. } => true,
+
+            #[cfg(feature = "json")]
+            Error::Json(_) => true,
+
+            #[cfg(any(feature = "encrypted", feature = "sealed"))]
+            Error::Crypto(_) => true,
+
+            #[cfg(feature = "encrypted")]
+            Error::HeaderMismatch |
+            Error::UnsupportedHeader(_) => true,
+
+            #[cfg(feature = "sealed")]
+            Error::Base64(_) => true,
+
+            #[cfg(feature = "integrity")]
+            Error::IntegrityFailure |
+            Error::IntegrityMissing => true,
+
+            _ => false,
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -20,14 +152,51 @@ impl fmt::Display for Error {
         match self {
             Error::Io(_) => f.write_str("Io"),
 
+            Error::Retries { attempts, .. } => write!(f, "Io after {} attempts", attempts),
+
+            Error::Local(_) => f.write_str("Local"),
+
+            Error::Cancelled => f.write_str("Cancelled"),
+
+            Error::KeySerialization { version, .. } => write!(f, "KeySerialization version {}", version),
+
             #[cfg(feature = "binary")]
             Error::Bincode(_) => f.write_str("Bincode"),
 
+            #[cfg(feature = "binary")]
+            Error::Codec(_) => f.write_str("Codec"),
+
+            #[cfg(feature = "binary")]
+            Error::BincodeAt { phase, offset, size, .. } => write!(
+                f, "Bincode {} at byte {} of {}", phase, offset, size
+            ),
+
             #[cfg(feature = "json")]
             Error::Json(_) => f.write_str("Json"),
 
-            #[cfg(feature = "crypto")]
+            #[cfg(any(feature = "encrypted", feature = "sealed"))]
             Error::Crypto(_) => f.write_str("Crypto"),
+
+            #[cfg(feature = "encrypted")]
+            Error::MissingHeader => f.write_str("MissingHeader"),
+
+            #[cfg(feature = "encrypted")]
+            Error::HeaderMismatch => f.write_str("HeaderMismatch"),
+
+            #[cfg(feature = "encrypted")]
+            Error::UnsupportedHeader(version) => write!(f, "UnsupportedHeader {}", version),
+
+            #[cfg(feature = "encrypted")]
+            Error::KeyUnavailable(_) => f.write_str("KeyUnavailable"),
+
+            #[cfg(feature = "sealed")]
+            Error::Base64(_) => f.write_str("Base64"),
+
+            #[cfg(feature = "integrity")]
+            Error::IntegrityFailure => f.write_str("IntegrityFailure"),
+
+            #[cfg(feature = "integrity")]
+            Error::IntegrityMissing => f.write_str("IntegrityMissing"),
         }
     }
 }
@@ -37,14 +206,43 @@ impl std::error::Error for Error {
         match self {
             Error::Io(e) => Some(e),
 
+            Error::Retries { error, .. } => Some(error),
+
+            Error::Local(e) => Some(e),
+
+            Error::Cancelled => None,
+
+            Error::KeySerialization { source, .. } => Some(source.as_ref()),
+
             #[cfg(feature = "binary")]
             Error::Bincode(e) => Some(e),
 
+            #[cfg(feature = "binary")]
+            Error::Codec(e) => Some(e.as_ref()),
+
+            #[cfg(feature = "binary")]
+            Error::BincodeAt { error, .. } => Some(error),
+
             #[cfg(feature = "json")]
             Error::Json(e) => Some(e),
 
-            #[cfg(feature = "crypto")]
+            #[cfg(any(feature = "encrypted", feature = "sealed"))]
             Error::Crypto(e) => Some(e),
+
+            #[cfg(feature = "encrypted")]
+            Error::MissingHeader |
+            Error::HeaderMismatch |
+            Error::UnsupportedHeader(_) => None,
+
+            #[cfg(feature = "encrypted")]
+            Error::KeyUnavailable(e) => Some(e),
+
+            #[cfg(feature = "sealed")]
+            Error::Base64(e) => Some(e),
+
+            #[cfg(feature = "integrity")]
+            Error::IntegrityFailure |
+            Error::IntegrityMissing => None,
         }
     }
 }
@@ -0,0 +1,204 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::fs::error::Error;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// the length of the tag produced by [`Integrity::compute`]
+pub const TAG_LEN: usize = 32;
+
+/// the bytes placed before the tag at the end of a binary store
+pub const FOOTER_MAGIC: [u8; 8] = *b"rkms-mac";
+
+/// tamper evidence for unencrypted stores.
+///
+/// when set on a wrapper an HMAC-SHA256 of the serialized store is written
+/// on save and verified on load before anything is deserialized. stores
+/// saved without a tag still load unless `require` is set.
+#[derive(Clone)]
+pub struct Integrity {
+    pub key: Vec<u8>,
+    pub require: bool,
+}
+
+impl Integrity {
+    pub fn new(key: Vec<u8>) -> Self {
+        Integrity {
+            key,
+            require: false,
+        }
+    }
+
+    pub fn required(key: Vec<u8>) -> Self {
+        Integrity {
+            key,
+            require: true,
+        }
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.key)
+            .expect("hmac accepts keys of any length")
+    }
+
+    pub fn compute(&self, bytes: &[u8]) -> [u8; TAG_LEN] {
+        let mut mac = self.mac();
+        mac.update(bytes);
+        mac.finalize().into_bytes().into()
+    }
+
+    pub fn verify(&self, bytes: &[u8], tag: &[u8]) -> Result<(), Error> {
+        let mut mac = self.mac();
+        mac.update(bytes);
+        mac.verify_slice(tag)
+            .map_err(|_| Error::IntegrityFailure)
+    }
+}
+
+impl std::fmt::Debug for Integrity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Integrity")
+            .field("require", &self.require)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "json")]
+fn to_hex(bytes: &[u8]) -> String {
+    let mut rtn = String::with_capacity(bytes.len() * 2);
+
+    for b in bytes {
+        rtn.push_str(&format!("{:02x}", b));
+    }
+
+    rtn
+}
+
+#[cfg(feature = "json")]
+fn from_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(feature = "json")]
+#[derive(serde::Deserialize)]
+struct JsonEnvelope<'a> {
+    #[serde(borrow)]
+    payload: &'a serde_json::value::RawValue,
+    mac: String,
+}
+
+/// wraps a serialized json store as `{ "payload": ..., "mac": "..." }`
+/// where the mac is the hex encoded tag of the exact payload bytes.
+#[cfg(feature = "json")]
+pub(crate) fn wrap_json(integrity: &Integrity, payload: Vec<u8>) -> Vec<u8> {
+    let tag = to_hex(&integrity.compute(&payload));
+    let mut rtn = Vec::with_capacity(payload.len() + tag.len() + 24);
+
+    rtn.extend_from_slice(b"{\"payload\":");
+    rtn.extend(payload);
+    rtn.extend_from_slice(b",\"mac\":\"");
+    rtn.extend_from_slice(tag.as_bytes());
+    rtn.extend_from_slice(b"\"}");
+    rtn
+}
+
+/// returns the bytes of the store from a json file, verifying the mac if
+/// the file has one and an integrity key is given.
+#[cfg(feature = "json")]
+pub(crate) fn unwrap_json<'a>(integrity: Option<&Integrity>, bytes: &'a [u8]) -> Result<&'a [u8], Error> {
+    let Ok(envelope) = serde_json::from_slice::<JsonEnvelope<'a>>(bytes) else {
+        if integrity.is_some_and(|i| i.require) {
+            return Err(Error::IntegrityMissing);
+        }
+
+        return Ok(bytes);
+    };
+
+    let payload = envelope.payload.get().as_bytes();
+
+    if let Some(integrity) = integrity {
+        let Some(tag) = from_hex(&envelope.mac) else {
+            return Err(Error::IntegrityFailure);
+        };
+
+        integrity.verify(payload, &tag)?;
+    }
+
+    Ok(payload)
+}
+
+/// appends the footer magic and tag to a serialized binary store
+#[cfg(feature = "binary")]
+pub(crate) fn append_footer(integrity: &Integrity, bytes: &mut Vec<u8>) {
+    let tag = integrity.compute(bytes);
+
+    bytes.extend_from_slice(&FOOTER_MAGIC);
+    bytes.extend_from_slice(&tag);
+}
+
+/// returns the bytes of the store from a binary file, verifying the footer
+/// if the file has one and an integrity key is given.
+#[cfg(feature = "binary")]
+pub(crate) fn strip_footer<'a>(integrity: Option<&Integrity>, bytes: &'a [u8]) -> Result<&'a [u8], Error> {
+    let footer_len = FOOTER_MAGIC.len() + TAG_LEN;
+
+    if bytes.len() >= footer_len {
+        let (payload, footer) = bytes.split_at(bytes.len() - footer_len);
+        let (magic, tag) = footer.split_at(FOOTER_MAGIC.len());
+
+        if magic == FOOTER_MAGIC {
+            if let Some(integrity) = integrity {
+                integrity.verify(payload, tag)?;
+            }
+
+            return Ok(payload);
+        }
+    }
+
+    if integrity.is_some_and(|i| i.require) {
+        return Err(Error::IntegrityMissing);
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn hex() {
+        let bytes = [0x00, 0x01, 0xab, 0xff];
+
+        assert_eq!(to_hex(&bytes), "0001abff");
+        assert_eq!(from_hex("0001abff").unwrap(), bytes);
+        assert!(from_hex("abc").is_none());
+        assert!(from_hex("zz").is_none());
+    }
+
+    #[test]
+    fn verify() {
+        let integrity = Integrity::new(b"integrity key".to_vec());
+        let tag = integrity.compute(b"payload");
+
+        integrity.verify(b"payload", &tag).expect("failed to verify tag");
+
+        assert!(matches!(
+            integrity.verify(b"pAyload", &tag),
+            Err(Error::IntegrityFailure)
+        ));
+        assert!(matches!(
+            Integrity::new(b"other key".to_vec()).verify(b"payload", &tag),
+            Err(Error::IntegrityFailure)
+        ));
+    }
+}
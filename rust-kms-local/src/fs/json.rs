@@ -1,21 +1,104 @@
 use std::path::{PathBuf, Path};
 use std::fs::OpenOptions;
-use std::io::{BufReader, BufWriter};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 
-use serde::Serialize;
+use serde::{Serialize, Deserialize};
 use serde::de::DeserializeOwned;
+use serde_json::value::RawValue;
 
+use crate::fs::Annotations;
 use crate::fs::error::Error;
 use crate::fs::traits::Wrapper;
-use crate::local::Local;
+use crate::fs::atomic;
+use crate::fs::retry::{self, RetryPolicy};
+#[cfg(feature = "integrity")]
+use crate::fs::integrity::{self, Integrity};
+use crate::local::{Local, Parts, SerializeOptions};
+use crate::hooks::{Hooks, Op, Timer};
 
 pub struct Options {
     pub path: PathBuf,
+    pub retry: Option<RetryPolicy>,
+    pub persist_accessed: bool,
+    pub hooks: Option<Arc<dyn Hooks>>,
+    /// added to the annotations read from the file, replacing any with the
+    /// same name
+    pub annotations: Annotations,
+    /// raises a counter below the highest version in the file to it
+    /// instead of failing with
+    /// [`CountTooLow`](crate::local::Error::CountTooLow)
+    pub heal_count: bool,
+    #[cfg(feature = "integrity")]
+    pub integrity: Option<Integrity>,
+}
+
+impl Options {
+    pub fn new<P>(path: P) -> Self
+    where
+        P: Into<PathBuf>
+    {
+        Options {
+            path: path.into(),
+            retry: None,
+            persist_accessed: false,
+            hooks: None,
+            annotations: Annotations::new(),
+            heal_count: false,
+            #[cfg(feature = "integrity")]
+            integrity: None,
+        }
+    }
+}
+
+/// a store saved with annotations, `{ "annotations": ..., "local": ... }`.
+/// stores without annotations are saved as the store alone.
+#[derive(Serialize)]
+struct Annotated<'a, L> {
+    annotations: &'a Annotations,
+    local: L,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AnnotatedRaw<'a> {
+    annotations: Annotations,
+    #[serde(borrow)]
+    local: &'a RawValue,
+}
+
+/// splits the annotations from the bytes of the store
+fn split_annotations(bytes: &[u8]) -> (Annotations, &[u8]) {
+    match serde_json::from_slice::<AnnotatedRaw<'_>>(bytes) {
+        Ok(annotated) => (annotated.annotations, annotated.local.get().as_bytes()),
+        Err(_) => (Annotations::new(), bytes),
+    }
+}
+
+/// the annotations of a json store without loading its keys
+pub fn read_annotations<P>(path: P) -> Result<Annotations, Error>
+where
+    P: AsRef<Path>
+{
+    let buffer = std::fs::read(path).map_err(Error::Io)?;
+
+    #[cfg(feature = "integrity")]
+    let bytes = integrity::unwrap_json(None, buffer.as_slice())?;
+    #[cfg(not(feature = "integrity"))]
+    let bytes = buffer.as_slice();
+
+    Ok(split_annotations(bytes).0)
 }
 
 pub struct Json<KeyType> {
     manager: Local<KeyType>,
     path: Box<Path>,
+    retry: Option<RetryPolicy>,
+    persist_accessed: bool,
+    hooks: Option<Arc<dyn Hooks>>,
+    annotations: Annotations,
+    #[cfg(feature = "integrity")]
+    integrity: Option<Integrity>,
 }
 
 impl<KeyType> Json<KeyType> {
@@ -28,12 +111,141 @@ impl<KeyType> Json<KeyType> {
         Json {
             manager,
             path: buf.into(),
+            retry: None,
+            persist_accessed: false,
+            hooks: None,
+            annotations: Annotations::new(),
+            #[cfg(feature = "integrity")]
+            integrity: None,
         }
     }
 
+    /// the store without the file it is saved to
+    pub fn into_inner(self) -> Local<KeyType> {
+        self.manager
+    }
+
     pub fn path(&self) -> &Path {
         &self.path
     }
+
+    pub fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.retry.as_ref()
+    }
+
+    pub fn set_retry_policy(&mut self, retry: Option<RetryPolicy>) {
+        self.retry = retry;
+    }
+
+    pub fn persist_accessed(&self) -> bool {
+        self.persist_accessed
+    }
+
+    /// when set, the access times of each version are written on save.
+    /// access times are otherwise kept in memory only.
+    pub fn set_persist_accessed(&mut self, persist: bool) {
+        self.persist_accessed = persist;
+    }
+
+    pub fn hooks(&self) -> Option<&Arc<dyn Hooks>> {
+        self.hooks.as_ref()
+    }
+
+    /// when set, `load` and `save` are timed with the given hooks
+    pub fn set_hooks(&mut self, hooks: Option<Arc<dyn Hooks>>) {
+        self.hooks = hooks;
+    }
+
+    pub fn annotations(&self) -> &Annotations {
+        &self.annotations
+    }
+
+    /// sets an annotation that is written on the next save, returning the
+    /// previous value
+    pub fn set_annotation<K, V>(&mut self, name: K, value: V) -> Option<String>
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.annotations.insert(name.into(), value.into())
+    }
+
+    pub fn remove_annotation(&mut self, name: &str) -> Option<String> {
+        self.annotations.remove(name)
+    }
+
+    fn to_json(&self, options: SerializeOptions, annotated: bool) -> Result<Vec<u8>, Error>
+    where
+        KeyType: Serialize
+    {
+        use serde_json::error::Category;
+
+        let local = self.manager.serialize_with(options);
+
+        let result = if !annotated || self.annotations.is_empty() {
+            serde_json::to_vec(&local)
+        } else {
+            serde_json::to_vec(&Annotated {
+                annotations: &self.annotations,
+                local: &local,
+            })
+        };
+        let local_failed = local.failed_version();
+
+        result.map_err(|e| match (e.classify(), local_failed) {
+            (Category::Io, _) => Error::Io(e.into()),
+            (_, Some(version)) => Error::KeySerialization {
+                version,
+                source: Box::new(e),
+            },
+            _ => Error::Json(e)
+        })
+    }
+
+    /// saves the store in its canonical form so that equal stores with equal
+    /// annotations produce identical files. access times are never written,
+    /// even if `persist_accessed` is set.
+    ///
+    /// without annotations the file is exactly
+    /// [`canonical_bytes`](Wrapper::canonical_bytes). annotations are kept
+    /// around it, they are not part of `canonical_bytes` or the content
+    /// hash. an integrity tag is still added if configured.
+    pub fn save_canonical(&self) -> Result<(), Error>
+    where
+        KeyType: Serialize
+    {
+        let serialize = self.to_json(SerializeOptions::default(), true)?;
+
+        self.write(serialize, &AtomicBool::new(false))
+    }
+
+    fn write(&self, serialize: Vec<u8>, cancel: &AtomicBool) -> Result<(), Error> {
+        #[cfg(feature = "integrity")]
+        let serialize = match &self.integrity {
+            Some(integrity) => integrity::wrap_json(integrity, serialize),
+            None => serialize,
+        };
+
+        retry::check_cancel(cancel)?;
+
+        atomic::write_with_cancel(
+            &self.path,
+            serialize.as_slice(),
+            self.retry.as_ref(),
+            cancel
+        )
+    }
+
+    #[cfg(feature = "integrity")]
+    pub fn integrity(&self) -> Option<&Integrity> {
+        self.integrity.as_ref()
+    }
+
+    /// when set, an integrity tag is written on save and checked on load
+    #[cfg(feature = "integrity")]
+    pub fn set_integrity(&mut self, integrity: Option<Integrity>) {
+        self.integrity = integrity;
+    }
 }
 
 impl<KeyType> std::ops::Deref for Json<KeyType> {
@@ -63,70 +275,332 @@ where
     type Error = Error;
     type Args = Options;
 
-    fn load(options: Self::Args) -> Result<Self, Self::Error> {
+    fn load_with_cancel(options: Self::Args, cancel: &AtomicBool) -> Result<Self, Self::Error> {
         use serde_json::error::Category;
 
-        let path = options.path.into();
+        let path: Box<Path> = options.path.into();
+        let retry = options.retry;
+        let persist_accessed = options.persist_accessed;
+        let hooks = options.hooks;
+        let _timer = Timer::start(hooks.as_ref(), Op::Load);
+        #[cfg(feature = "integrity")]
+        let integrity = options.integrity;
+
+        let buffer = retry::read_with_cancel(
+            || OpenOptions::new().read(true).open(&path),
+            retry.as_ref(),
+            cancel
+        )?;
+
+        #[cfg(feature = "integrity")]
+        let bytes = integrity::unwrap_json(integrity.as_ref(), buffer.as_slice())?;
+        #[cfg(not(feature = "integrity"))]
+        let bytes = buffer.as_slice();
+
+        retry::check_cancel(cancel)?;
 
-        let file = OpenOptions::new()
-            .read(true)
-            .open(&path)
-            .map_err(|e| Error::Io(e))?;
-        let reader = BufReader::new(file);
+        let (mut annotations, bytes) = split_annotations(bytes);
+        annotations.extend(options.annotations);
 
-        let manager = serde_json::from_reader(reader)
+        let parts: Parts<KeyType> = serde_json::from_slice(bytes)
             .map_err(|e| match e.classify() {
                 Category::Io => Error::Io(e.into()),
                 _ => Error::Json(e)
             })?;
+        let manager = Local::load_parts(parts, options.heal_count)
+            .map_err(Error::Local)?;
 
         Ok(Json {
             manager,
-            path
+            path,
+            retry,
+            persist_accessed,
+            hooks,
+            annotations,
+            #[cfg(feature = "integrity")]
+            integrity,
         })
     }
 
-    fn save(&self) -> Result<(), Self::Error> {
-        use serde_json::error::Category;
+    fn canonical_bytes(&self) -> Result<Vec<u8>, Self::Error> {
+        self.to_json(SerializeOptions::default(), false)
+    }
 
-        let file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(&self.path)
-            .map_err(|e| Error::Io(e))?;
-        let writer = BufWriter::new(file);
+    fn save_with_cancel(&self, cancel: &AtomicBool) -> Result<(), Self::Error> {
+        let _timer = Timer::start(self.hooks.as_ref(), Op::Save);
 
-        serde_json::to_writer(writer, &self.manager)
-            .map_err(|e| match e.classify() {
-                Category::Io => Error::Io(e.into()),
-                _ => Error::Json(e)
-            })?;
+        let options = SerializeOptions {
+            accessed: self.persist_accessed,
+        };
+
+        let serialize = self.to_json(options, true)?;
+
+        retry::check_cancel(cancel)?;
 
-        Ok(())
+        self.write(serialize, cancel)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::local;
+    use crate::test_util::{self, TempStore};
     use crate::fs;
 
     #[test]
-    fn base() {
-        let file_name = "test.json";
-        let manager = local::test::create_store();
+    fn conformance() {
+        fs::conformance::run(
+            |path| Json::new(Local::new(), path),
+            |path| Json::load(Options::new(path)),
+        );
+    }
+
+    #[test]
+    fn annotations() {
+        let temp = TempStore::new("json.annotations");
+        let file_name = temp.path();
+        let manager = test_util::sample_local();
+
+        let mut wrapper = Json::new(manager, file_name);
+        let plain = wrapper.canonical_bytes().unwrap();
+
+        wrapper.set_annotation("name", "payments-db-envelope-keys");
+        wrapper.set_annotation("notes", "rotated by cron");
+        wrapper.save().expect("failed to save to json file");
 
-        fs::test::create_test_file(file_name);
+        assert_eq!(wrapper.canonical_bytes().unwrap(), plain);
+        assert_eq!(&read_annotations(file_name).unwrap(), wrapper.annotations());
+
+        let mut options = Options::new(file_name);
+        options.annotations.insert("environment".into(), "staging".into());
+
+        let and_back: Json<u64> = Json::load(options)
+            .expect("failed to load json file");
+
+        test_util::assert_local_eq(&wrapper.manager, &and_back.manager);
+        assert_eq!(and_back.annotations().len(), 3);
+        assert_eq!(and_back.annotations()["notes"], "rotated by cron");
+
+        // stores saved before annotations still load
+        std::fs::write(file_name, plain).unwrap();
+
+        let legacy: Json<u64> = Json::load(Options::new(file_name))
+            .expect("failed to load json file");
+
+        test_util::assert_local_eq(&wrapper.manager, &legacy.manager);
+        assert!(legacy.annotations().is_empty());
+        assert!(read_annotations(file_name).unwrap().is_empty());
+    }
+
+    /// a key that fails to serialize when it is 0
+    #[derive(Debug, Deserialize)]
+    struct Sentinel(u64);
+
+    impl Serialize for Sentinel {
+        fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: serde::Serializer
+        {
+            if self.0 == 0 {
+                return Err(serde::ser::Error::custom("sentinel key"));
+            }
+
+            serializer.serialize_u64(self.0)
+        }
+    }
+
+    #[test]
+    fn key_serialization() {
+        let temp = TempStore::new("json.key_serialization");
+        let file_name = temp.path();
+
+        let wrapper = Json::new(Local::new(), file_name);
+        wrapper.update(Sentinel(1)).unwrap();
+        wrapper.update(Sentinel(2)).unwrap();
+        wrapper.save().expect("failed to save to json file");
+
+        let saved = std::fs::read(file_name).unwrap();
+
+        wrapper.update(Sentinel(0)).unwrap();
+        wrapper.update(Sentinel(4)).unwrap();
+
+        match wrapper.save() {
+            Err(Error::KeySerialization { version, .. }) => assert_eq!(version, 3),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        assert_eq!(std::fs::read(file_name).unwrap(), saved, "file changed by failed save");
+    }
+
+    #[test]
+    fn base() {
+        let temp = TempStore::new("json");
+        let file_name = temp.path();
+        let manager = test_util::sample_local();
 
         let wrapper = Json::new(manager, file_name);
 
         wrapper.save().expect("failed to save to json file");
 
-        let and_back: Json<u64> = Json::load(Options {
-            path: PathBuf::from(file_name),
-        }).expect("failed to load json file");
+        let and_back: Json<u64> = Json::load(Options::new(file_name))
+            .expect("failed to load json file");
+
+        test_util::assert_local_eq(&wrapper.manager, &and_back.manager);
+    }
+
+    #[test]
+    fn deeply_nested() {
+        let temp = TempStore::new("json.nested");
+        let file_name = temp.path();
+        let depth = 100_000;
+        let contents = format!(
+            "{{\"count\":1,\"store\":{{\"1\":{}{}}}}}",
+            "[".repeat(depth),
+            "]".repeat(depth),
+        );
+
+        std::fs::write(file_name, contents)
+            .expect("failed to write json file");
+
+        let result = Json::<serde_json::Value>::load(Options::new(file_name));
+
+        assert!(
+            matches!(&result, Err(Error::Json(e)) if e.to_string().starts_with("recursion limit exceeded")),
+            "unexpected result: {:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn count_too_low() {
+        let temp = TempStore::new("json.count_too_low");
+        let file_name = temp.path();
+
+        std::fs::write(file_name, r#"{"count":1,"store":{"1":10,"4":40}}"#)
+            .expect("failed to write json file");
+
+        let result = Json::<u64>::load(Options::new(file_name));
+
+        assert!(
+            matches!(result, Err(Error::Local(crate::local::Error::CountTooLow { count: 1, highest: 4 }))),
+            "unexpected result: {:?}",
+            result
+        );
+
+        let mut options = Options::new(file_name);
+        options.heal_count = true;
+
+        let healed: Json<u64> = Json::load(options)
+            .expect("failed to load json file with heal_count");
+
+        assert_eq!(healed.count().unwrap(), 4);
+    }
+
+    #[test]
+    fn canonical() {
+        let temp_a = TempStore::new("json.canonical_a");
+        let file_a = temp_a.path();
+        let temp_b = TempStore::new("json.canonical_b");
+        let file_b = temp_b.path();
+
+        let mut a = Json::new(test_util::sample_local(), file_a);
+        let b = Json::new(test_util::sample_local(), file_b);
+
+        // access times are not part of the canonical form
+        a.get(&3).unwrap();
+        a.set_persist_accessed(true);
+
+        a.save_canonical().expect("failed to save canonical json");
+        b.save_canonical().expect("failed to save canonical json");
+
+        assert_eq!(std::fs::read(file_a).unwrap(), std::fs::read(file_b).unwrap());
+        assert_eq!(a.canonical_bytes().unwrap(), std::fs::read(file_a).unwrap());
+
+        // annotations are saved around the canonical bytes
+        a.set_annotation("owner", "payments");
+        a.save_canonical().expect("failed to save canonical json");
+
+        assert_ne!(a.canonical_bytes().unwrap(), std::fs::read(file_a).unwrap());
+
+        let and_back: Json<u64> = Json::load(Options::new(file_a))
+            .expect("failed to load canonical json");
+
+        assert_eq!(and_back.annotations(), a.annotations());
+        assert_eq!(and_back.canonical_bytes().unwrap(), a.canonical_bytes().unwrap());
+        assert_eq!(and_back.canonical_bytes().unwrap(), std::fs::read(file_b).unwrap());
+
+        #[cfg(feature = "canonical")]
+        {
+            assert_eq!(a.content_hash().unwrap(), b.content_hash().unwrap());
+
+            b.update(100).unwrap();
+
+            assert_ne!(a.content_hash().unwrap(), b.content_hash().unwrap());
+        }
+    }
+
+    #[cfg(feature = "integrity")]
+    #[test]
+    fn integrity() {
+        let temp = TempStore::new("json.integrity");
+        let file_name = temp.path();
+        let manager = test_util::sample_local();
+
+        let mut wrapper = Json::new(manager, file_name);
+        wrapper.set_integrity(Some(Integrity::new(b"integrity key".to_vec())));
+        wrapper.save().expect("failed to save to json file");
+
+        let mut options = Options::new(file_name);
+        options.integrity = Some(Integrity::required(b"integrity key".to_vec()));
+
+        let and_back: Json<u64> = Json::load(options)
+            .expect("failed to load json file");
+
+        test_util::assert_local_eq(&wrapper.manager, &and_back.manager);
+
+        let mut bytes = std::fs::read(file_name)
+            .expect("failed to read json file");
+        let index = bytes.windows(3)
+            .position(|w| w == b"\"4\"")
+            .expect("failed to find store entry");
+
+        // changes the key of version 4 from 4 to 3
+        bytes[index + 4] = b'3';
+
+        std::fs::write(file_name, &bytes)
+            .expect("failed to write tampered json file");
+
+        let mut options = Options::new(file_name);
+        options.integrity = Some(Integrity::new(b"integrity key".to_vec()));
+
+        let result = Json::<u64>::load(options);
+
+        assert!(matches!(result, Err(Error::IntegrityFailure)), "unexpected result: {:?}", result);
+    }
+
+    #[cfg(feature = "integrity")]
+    #[test]
+    fn integrity_legacy() {
+        let temp = TempStore::new("json.legacy");
+        let file_name = temp.path();
+        let manager = test_util::sample_local();
+
+        let wrapper = Json::new(manager, file_name);
+        wrapper.save().expect("failed to save to json file");
+
+        let mut options = Options::new(file_name);
+        options.integrity = Some(Integrity::new(b"integrity key".to_vec()));
+
+        let and_back: Json<u64> = Json::load(options)
+            .expect("failed to load json file");
+
+        test_util::assert_local_eq(&wrapper.manager, &and_back.manager);
+
+        let mut options = Options::new(file_name);
+        options.integrity = Some(Integrity::required(b"integrity key".to_vec()));
+
+        let result = Json::<u64>::load(options);
 
-        local::test::assert_local_eq(&wrapper.manager, &and_back.manager);
+        assert!(matches!(result, Err(Error::IntegrityMissing)), "unexpected result: {:?}", result);
     }
 }
@@ -0,0 +1,479 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::fs::OpenOptions;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::AtomicBool;
+
+use crate::fs::error::Error;
+use crate::fs::traits::Wrapper;
+use crate::fs::atomic;
+use crate::fs::retry::{self, RetryPolicy};
+use crate::fs::sealed::{self, Options, SealedData, SealedStore, SealedEntry};
+use crate::local::{self, Local, Parts, AccessTimes, Reserved, Tombstone, Meta, SerializeOptions, DEFAULT_TOMBSTONE_RETENTION};
+use crate::hooks::{Hooks, Op, Timer};
+use crate::key::Key;
+use crate::crypto;
+
+/// the parts of a sealed file that are kept as read
+struct Index {
+    sealed: BTreeMap<u64, SealedEntry>,
+    accessed: Option<BTreeMap<u64, AccessTimes>>,
+    pending: BTreeMap<u64, u64>,
+    reserved: BTreeMap<u64, Reserved>,
+    tombstones: BTreeMap<u64, Tombstone>,
+    staged: BTreeSet<u64>,
+    disabled: BTreeSet<u64>,
+    meta: BTreeMap<u64, Meta>,
+}
+
+/// a [`SealedValues`](crate::fs::SealedValues) file that is decrypted one
+/// entry at a time.
+///
+/// only the index of the file is read on load. an entry is decrypted the
+/// first time it is requested and the key is cached so it is only decrypted
+/// once. entries that were never requested are written back exactly as they
+/// were read, as are entries that were only read.
+///
+/// pending drops, reservations, tombstones, staged and disabled versions,
+/// metadata and access times are kept as they were read from the file, except for versions removed with
+/// [`drop`](EncryptedLazy::drop) which leave a tombstone.
+pub struct EncryptedLazy<Data> {
+    cache: Local<Key<Data>>,
+    index: RwLock<Index>,
+    path: Box<Path>,
+    key: crypto::Key,
+    retry: Option<RetryPolicy>,
+    persist_accessed: bool,
+    hooks: Option<Arc<dyn Hooks>>,
+}
+
+fn poisoned<T>(_e: std::sync::PoisonError<T>) -> Error {
+    Error::Local(local::Error::Poisoned)
+}
+
+impl<Data> EncryptedLazy<Data> {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn key(&self) -> &crypto::Key {
+        &self.key
+    }
+
+    pub fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.retry.as_ref()
+    }
+
+    pub fn set_retry_policy(&mut self, retry: Option<RetryPolicy>) {
+        self.retry = retry;
+    }
+
+    pub fn persist_accessed(&self) -> bool {
+        self.persist_accessed
+    }
+
+    /// when set, the access times read from the file are written back on
+    /// save. reads through this store do not update them.
+    pub fn set_persist_accessed(&mut self, persist: bool) {
+        self.persist_accessed = persist;
+    }
+
+    pub fn hooks(&self) -> Option<&Arc<dyn Hooks>> {
+        self.hooks.as_ref()
+    }
+
+    /// when set, `load`, `save` and the decryption of each entry are timed
+    /// with the given hooks
+    pub fn set_hooks(&mut self, hooks: Option<Arc<dyn Hooks>>) {
+        self.hooks = hooks;
+    }
+
+    pub fn count(&self) -> Result<u64, Error> {
+        self.cache.count().map_err(Error::Local)
+    }
+
+    /// every version in the store, decrypted or not, in order
+    pub fn versions(&self) -> Result<Vec<u64>, Error> {
+        let index = self.index.read().map_err(poisoned)?;
+        let cached = self.cache.store_reader().map_err(Error::Local)?;
+
+        let mut rtn: Vec<u64> = index.sealed.keys()
+            .chain(cached.keys())
+            .copied()
+            .collect();
+
+        rtn.sort_unstable();
+        rtn.dedup();
+
+        Ok(rtn)
+    }
+
+    /// the versions that have been decrypted or added since load
+    pub fn cached(&self) -> Result<Vec<u64>, Error> {
+        let cached = self.cache.store_reader().map_err(Error::Local)?;
+
+        Ok(cached.keys().copied().collect())
+    }
+
+    /// adds a key at the next version. the key is sealed on save.
+    pub fn update(&self, key: Key<Data>) -> Result<u64, Error> {
+        self.cache.insert(key).map_err(Error::Local)
+    }
+
+    /// removes a version whether it was decrypted or not. returns true if
+    /// the version existed.
+    pub fn drop(&self, version: &u64) -> Result<bool, Error> {
+        let mut index = self.index.write().map_err(poisoned)?;
+
+        let sealed = index.sealed.remove(version).is_some();
+        let cached = self.cache.drop(version).map_err(Error::Local)?.is_some();
+
+        if let Some(accessed) = index.accessed.as_mut() {
+            accessed.remove(version);
+        }
+
+        index.pending.remove(version);
+        index.staged.remove(version);
+        index.disabled.remove(version);
+        index.meta.remove(version);
+
+        if sealed || cached {
+            index.tombstones.insert(*version, Tombstone {
+                at: local::unix_now(),
+                note: None,
+                evicted: false,
+            });
+
+            while index.tombstones.len() > DEFAULT_TOMBSTONE_RETENTION {
+                index.tombstones.pop_first();
+            }
+        }
+
+        Ok(sealed || cached)
+    }
+}
+
+impl<Data> EncryptedLazy<Data>
+where
+    Data: SealedData + Clone
+{
+    /// decrypts the entry into the cache if it is not already there
+    fn open(&self, version: &u64) -> Result<bool, Error> {
+        if self.cache.store_reader().map_err(Error::Local)?.contains_key(version) {
+            return Ok(true);
+        }
+
+        let entry = {
+            let index = self.index.read().map_err(poisoned)?;
+
+            let Some(entry) = index.sealed.get(version) else {
+                return Ok(false);
+            };
+
+            entry.clone()
+        };
+
+        let key = {
+            let _timer = Timer::start(self.hooks.as_ref(), Op::Decrypt);
+
+            sealed::open_entry(&self.key, *version, entry)?
+        };
+
+        self.cache.store.write()
+            .map_err(poisoned)?
+            .entry(*version)
+            .or_insert(key);
+
+        Ok(true)
+    }
+
+    pub fn get(&self, version: &u64) -> Result<Option<Key<Data>>, Error> {
+        if !self.open(version)? {
+            return Ok(None);
+        }
+
+        self.cache.get(version).map_err(Error::Local)
+    }
+
+    /// the highest version that is not pending a drop, staged or disabled
+    pub fn latest(&self) -> Result<Option<Key<Data>>, Error> {
+        let versions = self.versions()?;
+        let latest = {
+            let index = self.index.read().map_err(poisoned)?;
+
+            versions.into_iter()
+                .rev()
+                .find(|version| {
+                    !index.pending.contains_key(version) &&
+                        !index.staged.contains(version) &&
+                        !index.disabled.contains(version)
+                })
+        };
+
+        match latest {
+            Some(version) => self.get(&version),
+            None => Ok(None),
+        }
+    }
+
+    /// decrypts the given versions ahead of time. versions that do not
+    /// exist are ignored.
+    pub fn prefetch(&self, versions: &[u64]) -> Result<(), Error> {
+        for version in versions {
+            self.open(version)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<Data> std::fmt::Debug for EncryptedLazy<Data>
+where
+    Data: std::fmt::Debug
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedLazy")
+            .field("cache", &self.cache)
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Data> Wrapper for EncryptedLazy<Data>
+where
+    Data: SealedData + Clone
+{
+    type Error = Error;
+    type Args = Options;
+
+    /// decrypts every entry that has not been decrypted yet
+    fn canonical_bytes(&self) -> Result<Vec<u8>, Self::Error> {
+        self.prefetch(&self.versions()?)?;
+
+        let index = self.index.read().map_err(poisoned)?;
+        let local = Local::from_parts(Parts {
+            count: self.count()?,
+            store: self.cache.store_reader().map_err(Error::Local)?.clone(),
+            accessed: BTreeMap::new(),
+            pending: index.pending.clone(),
+            reserved: index.reserved.clone(),
+            tombstones: index.tombstones.clone(),
+            staged: index.staged.clone(),
+            disabled: index.disabled.clone(),
+            meta: index.meta.clone(),
+        });
+
+        crate::fs::binary::serialize_local(&local, SerializeOptions::default())
+    }
+
+    fn load_with_cancel(options: Self::Args, cancel: &AtomicBool) -> Result<Self, Self::Error> {
+        use serde_json::error::Category;
+
+        let path: Box<Path> = options.path.into();
+        let key = options.key;
+        let retry = options.retry;
+        let persist_accessed = options.persist_accessed;
+        let hooks = options.hooks;
+        let _timer = Timer::start(hooks.as_ref(), Op::Load);
+
+        let buffer = retry::read_with_cancel(
+            || OpenOptions::new().read(true).open(&path),
+            retry.as_ref(),
+            cancel
+        )?;
+
+        retry::check_cancel(cancel)?;
+
+        let sealed: SealedStore = serde_json::from_slice(buffer.as_slice())
+            .map_err(|e| match e.classify() {
+                Category::Io => Error::Io(e.into()),
+                _ => Error::Json(e)
+            })?;
+
+        // the cache starts empty so the counter is checked against the
+        // versions of the index
+        let highest = sealed.store.keys()
+            .chain(sealed.reserved.keys())
+            .chain(sealed.tombstones.keys())
+            .max()
+            .copied()
+            .unwrap_or(0);
+
+        if highest > sealed.count && !options.heal_count {
+            return Err(Error::Local(local::Error::CountTooLow {
+                count: sealed.count,
+                highest,
+            }));
+        }
+
+        Ok(EncryptedLazy {
+            cache: Local::from_parts(Parts::new(sealed.count.max(highest), BTreeMap::new())),
+            index: RwLock::new(Index {
+                sealed: sealed.store,
+                accessed: sealed.accessed,
+                pending: sealed.pending,
+                reserved: sealed.reserved,
+                tombstones: sealed.tombstones,
+                staged: sealed.staged,
+                disabled: sealed.disabled,
+                meta: sealed.meta,
+            }),
+            path,
+            key,
+            retry,
+            persist_accessed,
+            hooks,
+        })
+    }
+
+    fn save_with_cancel(&self, cancel: &AtomicBool) -> Result<(), Self::Error> {
+        use serde_json::error::Category;
+
+        let _timer = Timer::start(self.hooks.as_ref(), Op::Save);
+
+        let mut index = self.index.write().map_err(poisoned)?;
+
+        // keys added since load are sealed once and kept with the entries
+        // read from the file so later saves write the same bytes
+        {
+            let cached = self.cache.store_reader().map_err(Error::Local)?;
+
+            for (version, key) in cached.iter() {
+                if !index.sealed.contains_key(version) {
+                    let entry = sealed::seal_entry(&self.key, *version, key)?;
+
+                    index.sealed.insert(*version, entry);
+                }
+            }
+        }
+
+        retry::check_cancel(cancel)?;
+
+        let store = SealedStore {
+            count: self.count()?,
+            store: index.sealed.clone(),
+            accessed: if self.persist_accessed {
+                index.accessed.clone()
+            } else {
+                None
+            },
+            pending: index.pending.clone(),
+            reserved: index.reserved.clone(),
+            tombstones: index.tombstones.clone(),
+            staged: index.staged.clone(),
+            disabled: index.disabled.clone(),
+            meta: index.meta.clone(),
+        };
+
+        let serialize = serde_json::to_vec(&store)
+            .map_err(|e| match e.classify() {
+                Category::Io => Error::Io(e.into()),
+                _ => Error::Json(e)
+            })?;
+
+        retry::check_cancel(cancel)?;
+
+        atomic::write_with_cancel(
+            &self.path,
+            serialize.as_slice(),
+            self.retry.as_ref(),
+            cancel
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempStore;
+    use crate::fs::SealedValues;
+    use crate::hooks::AtomicHistogramHooks;
+
+    fn create_store(count: u64) -> Local<Key<Vec<u8>>> {
+        let local = Local::new();
+
+        for version in 1..=count {
+            let mut builder = Key::builder(version.to_be_bytes().to_vec());
+            builder.set_created(version * 10);
+
+            local.update(builder.build().unwrap())
+                .expect("failed to add value");
+        }
+
+        local
+    }
+
+    fn entries(file_name: &Path) -> serde_json::Map<String, serde_json::Value> {
+        let contents = std::fs::read_to_string(file_name)
+            .expect("failed to read sealed file");
+        let mut value: serde_json::Value = serde_json::from_str(&contents)
+            .expect("failed to parse sealed file as json");
+
+        match value["store"].take() {
+            serde_json::Value::Object(map) => map,
+            _ => panic!("store is not a json object"),
+        }
+    }
+
+    #[test]
+    fn lazy() {
+        let temp = TempStore::new("lazy");
+        let file_name = temp.path();
+
+        SealedValues::new(create_store(300), file_name, crypto::empty_key())
+            .save()
+            .expect("failed to save to sealed file");
+
+        let before = entries(file_name);
+        let hooks = Arc::new(AtomicHistogramHooks::new());
+        let mut options = Options::new(file_name, crypto::empty_key());
+        options.hooks = Some(hooks.clone());
+
+        let lazy: EncryptedLazy<Vec<u8>> = EncryptedLazy::load(options)
+            .expect("failed to load sealed file");
+
+        assert_eq!(lazy.versions().unwrap().len(), 300);
+        assert_eq!(hooks.snapshot().get(Op::Decrypt).total(), 0);
+
+        let latest = lazy.latest().unwrap().unwrap();
+
+        assert_eq!(latest.data(), &300u64.to_be_bytes());
+        assert_eq!(lazy.get(&42).unwrap().unwrap().data(), &42u64.to_be_bytes());
+        assert_eq!(lazy.get(&42).unwrap().unwrap().created(), &420);
+        assert_eq!(lazy.get(&1000).unwrap(), None);
+
+        lazy.prefetch(&[7, 42, 299]).unwrap();
+
+        assert_eq!(lazy.cached().unwrap(), vec![7, 42, 299, 300]);
+        assert_eq!(hooks.snapshot().get(Op::Decrypt).total(), 4);
+
+        let version = lazy.update(Key::builder(b"new".to_vec()).build().unwrap()).unwrap();
+
+        assert_eq!(version, 301);
+        assert!(lazy.drop(&5).unwrap());
+        assert!(!lazy.drop(&5).unwrap());
+
+        lazy.save().expect("failed to save lazy store");
+
+        let after = entries(file_name);
+
+        assert_eq!(after.len(), 300);
+        assert!(!after.contains_key("5"));
+
+        for (version, entry) in &before {
+            if version != "5" {
+                assert_eq!(after.get(version), Some(entry), "entry {} changed", version);
+            }
+        }
+
+        // only the requested versions were ever decrypted
+        assert_eq!(hooks.snapshot().get(Op::Decrypt).total(), 4);
+
+        let and_back: SealedValues<Vec<u8>> = SealedValues::load(Options::new(file_name, crypto::empty_key()))
+            .expect("failed to load sealed file");
+
+        assert_eq!(and_back.count().unwrap(), 301);
+        assert_eq!(and_back.get(&301).unwrap().unwrap().data(), b"new");
+        assert_eq!(and_back.get(&5).unwrap(), None);
+    }
+}
@@ -1,8 +1,41 @@
 mod traits;
 pub use traits::Wrapper;
 
+/// free form notes about a store, such as its name, owner or environment,
+/// saved next to the store by wrappers that support them
+pub type Annotations = std::collections::BTreeMap<String, String>;
+
 mod error;
 pub use error::Error;
+#[cfg(feature = "binary")]
+pub use error::Phase;
+
+pub mod retry;
+pub use retry::RetryPolicy;
+
+pub mod atomic;
+
+pub mod sniff;
+pub use sniff::{sniff, Detected};
+#[cfg(feature = "json")]
+pub use sniff::EXT_JSON;
+#[cfg(feature = "binary")]
+pub use sniff::EXT_BINARY;
+#[cfg(feature = "encrypted")]
+pub use sniff::{EXT_ENCRYPTED, EXT_HEADER};
+
+#[cfg(any(test, feature = "harness"))]
+pub mod conformance;
+
+#[cfg(feature = "integrity")]
+pub mod integrity;
+#[cfg(feature = "integrity")]
+pub use integrity::Integrity;
+
+#[cfg(feature = "binary")]
+pub mod codec;
+#[cfg(feature = "binary")]
+pub use codec::{KeyCodec, SerdeCodec};
 
 #[cfg(feature = "binary")]
 pub mod binary;
@@ -14,22 +47,17 @@ pub mod json;
 #[cfg(feature = "json")]
 pub use json::Json;
 
-#[cfg(feature = "crypto")]
+#[cfg(feature = "encrypted")]
 pub mod encrypted;
-#[cfg(feature = "crypto")]
+#[cfg(feature = "encrypted")]
 pub use encrypted::Encrypted;
 
-#[cfg(test)]
-pub(crate) mod test {
-    pub fn create_test_file<P>(path: P) -> std::fs::File
-    where
-        P: AsRef<std::path::Path>
-    {
-        std::fs::OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .create(true)
-            .open(path)
-            .expect("failed to create test file")
-    }
-}
+#[cfg(feature = "sealed")]
+pub mod sealed;
+#[cfg(feature = "sealed")]
+pub use sealed::SealedValues;
+
+#[cfg(feature = "sealed")]
+pub mod lazy;
+#[cfg(feature = "sealed")]
+pub use lazy::EncryptedLazy;
@@ -0,0 +1,363 @@
+use std::io::{self, Read, Write, ErrorKind};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use crate::fs::error::Error;
+
+/// controls how io failures are retried when loading and saving.
+///
+/// only failures from opening, reading, and writing files are retried.
+/// parsing and crypto failures are never transient and are returned
+/// immediately. before each retry the policy sleeps for `backoff` multiplied
+/// by the number of attempts already made.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub backoff: Duration,
+    pub retry_on: fn(&io::Error) -> bool,
+}
+
+impl RetryPolicy {
+    pub fn new(attempts: u32, backoff: Duration) -> Self {
+        RetryPolicy {
+            attempts,
+            backoff,
+            retry_on: is_transient,
+        }
+    }
+
+    pub fn run<T, F>(&self, op: F) -> Result<T, Error>
+    where
+        F: FnMut() -> io::Result<T>
+    {
+        self.run_with_cancel(op, &AtomicBool::new(false))
+    }
+
+    /// same as [`run`](RetryPolicy::run) but gives up with
+    /// [`Error::Cancelled`] once `cancel` is set. the flag is checked before
+    /// every attempt and after every failure.
+    pub fn run_with_cancel<T, F>(&self, mut op: F, cancel: &AtomicBool) -> Result<T, Error>
+    where
+        F: FnMut() -> io::Result<T>
+    {
+        let mut attempts = 0;
+
+        loop {
+            attempts += 1;
+
+            check_cancel(cancel)?;
+
+            match op() {
+                Ok(v) => return Ok(v),
+                Err(err) => {
+                    check_cancel(cancel)?;
+
+                    if attempts >= self.attempts || !(self.retry_on)(&err) {
+                        return if attempts == 1 {
+                            Err(Error::Io(err))
+                        } else {
+                            Err(Error::Retries { attempts, error: err })
+                        };
+                    }
+                }
+            }
+
+            std::thread::sleep(self.backoff * attempts);
+        }
+    }
+}
+
+/// the default check for [`RetryPolicy::retry_on`]
+pub fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::WouldBlock |
+        ErrorKind::Interrupted |
+        ErrorKind::TimedOut |
+        ErrorKind::StaleNetworkFileHandle
+    )
+}
+
+/// the size of each read done by [`read_with_cancel`]
+pub const READ_CHUNK: usize = 64 * 1024;
+
+/// returns [`Error::Cancelled`] if the flag has been set
+pub fn check_cancel(cancel: &AtomicBool) -> Result<(), Error> {
+    if cancel.load(Ordering::Relaxed) {
+        Err(Error::Cancelled)
+    } else {
+        Ok(())
+    }
+}
+
+/// reads everything from the reader returned by `open`, retrying the open
+/// and read according to the policy if one is given.
+pub fn read_with<R, F>(open: F, retry: Option<&RetryPolicy>) -> Result<Vec<u8>, Error>
+where
+    R: Read,
+    F: FnMut() -> io::Result<R>,
+{
+    read_with_cancel(open, retry, &AtomicBool::new(false))
+}
+
+/// same as [`read_with`] but reads in chunks of [`READ_CHUNK`] bytes and
+/// stops with [`Error::Cancelled`] between chunks once `cancel` is set.
+pub fn read_with_cancel<R, F>(
+    mut open: F,
+    retry: Option<&RetryPolicy>,
+    cancel: &AtomicBool
+) -> Result<Vec<u8>, Error>
+where
+    R: Read,
+    F: FnMut() -> io::Result<R>,
+{
+    let mut op = || {
+        let mut reader = open()?;
+        let mut buffer = Vec::new();
+        let mut chunk = vec![0; READ_CHUNK];
+
+        loop {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(io::Error::other("cancelled"));
+            }
+
+            let amount = match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(amount) => amount,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+
+            buffer.extend_from_slice(&chunk[..amount]);
+        }
+
+        Ok(buffer)
+    };
+
+    if let Some(policy) = retry {
+        policy.run_with_cancel(op, cancel)
+    } else {
+        check_cancel(cancel)?;
+
+        op().map_err(|err| match check_cancel(cancel) {
+            Ok(()) => Error::Io(err),
+            Err(cancelled) => cancelled,
+        })
+    }
+}
+
+/// writes all of `bytes` to the writer returned by `open`, retrying the
+/// open and write according to the policy if one is given. the writer is
+/// expected to start from the beginning on every call to `open`.
+pub fn write_with<W, F>(open: F, bytes: &[u8], retry: Option<&RetryPolicy>) -> Result<(), Error>
+where
+    W: Write,
+    F: FnMut() -> io::Result<W>,
+{
+    write_with_cancel(open, bytes, retry, &AtomicBool::new(false))
+}
+
+/// same as [`write_with`] but stops with [`Error::Cancelled`] if `cancel`
+/// is set before the writer is opened. once writing has started it is
+/// allowed to finish so that a cancelled save never leaves a partially
+/// written file behind.
+pub fn write_with_cancel<W, F>(
+    mut open: F,
+    bytes: &[u8],
+    retry: Option<&RetryPolicy>,
+    cancel: &AtomicBool
+) -> Result<(), Error>
+where
+    W: Write,
+    F: FnMut() -> io::Result<W>,
+{
+    let mut op = || {
+        let mut writer = open()?;
+
+        writer.write_all(bytes)?;
+        writer.flush()
+    };
+
+    if let Some(policy) = retry {
+        policy.run_with_cancel(op, cancel)
+    } else {
+        check_cancel(cancel)?;
+
+        op().map_err(Error::Io)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+    use std::time::Instant;
+
+    struct Flaky<'a> {
+        failures: &'a Cell<u32>,
+        data: &'a [u8],
+    }
+
+    impl<'a> Read for Flaky<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.failures.get() > 0 {
+                self.failures.set(self.failures.get() - 1);
+
+                return Err(io::Error::from(ErrorKind::WouldBlock));
+            }
+
+            self.data.read(buf)
+        }
+    }
+
+    impl<'a> Write for Flaky<'a> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.failures.get() > 0 {
+                self.failures.set(self.failures.get() - 1);
+
+                return Err(io::Error::from(ErrorKind::TimedOut));
+            }
+
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn read_after_retry() {
+        let failures = Cell::new(2);
+        let policy = RetryPolicy::new(3, Duration::from_millis(1));
+
+        let result = read_with(|| Ok(Flaky {
+            failures: &failures,
+            data: b"data",
+        }), Some(&policy)).expect("failed to read after retry");
+
+        assert_eq!(result, b"data");
+    }
+
+    #[test]
+    fn write_after_retry() {
+        let failures = Cell::new(2);
+        let opened = Cell::new(0);
+        let policy = RetryPolicy::new(3, Duration::from_millis(1));
+
+        write_with(|| {
+            opened.set(opened.get() + 1);
+
+            Ok(Flaky {
+                failures: &failures,
+                data: &[],
+            })
+        }, b"data", Some(&policy)).expect("failed to write after retry");
+
+        assert_eq!(opened.get(), 3);
+    }
+
+    #[test]
+    fn exhausted() {
+        let failures = Cell::new(10);
+        let policy = RetryPolicy::new(3, Duration::from_millis(1));
+
+        let result = read_with(|| Ok(Flaky {
+            failures: &failures,
+            data: b"data",
+        }), Some(&policy));
+
+        match result {
+            Err(Error::Retries { attempts, error }) => {
+                assert_eq!(attempts, 3);
+                assert_eq!(error.kind(), ErrorKind::WouldBlock);
+            }
+            _ => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn not_retryable() {
+        let opened = Cell::new(0);
+        let policy = RetryPolicy::new(3, Duration::from_millis(1));
+
+        let result = read_with(|| -> io::Result<&[u8]> {
+            opened.set(opened.get() + 1);
+
+            Err(io::Error::from(ErrorKind::NotFound))
+        }, Some(&policy));
+
+        assert!(matches!(result, Err(Error::Io(_))), "unexpected result: {:?}", result);
+        assert_eq!(opened.get(), 1);
+    }
+
+    #[test]
+    fn no_policy() {
+        let failures = Cell::new(1);
+
+        let result = read_with(|| Ok(Flaky {
+            failures: &failures,
+            data: b"data",
+        }), None);
+
+        assert!(matches!(result, Err(Error::Io(_))), "unexpected result: {:?}", result);
+    }
+
+    struct Slow {
+        remaining: usize,
+    }
+
+    impl Read for Slow {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.remaining == 0 {
+                return Ok(0);
+            }
+
+            std::thread::sleep(Duration::from_millis(1));
+
+            let amount = buf.len().min(self.remaining);
+            self.remaining -= amount;
+
+            Ok(amount)
+        }
+    }
+
+    #[test]
+    fn cancelled_read() {
+        let cancel = AtomicBool::new(false);
+        let policy = RetryPolicy::new(3, Duration::from_millis(1));
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(Duration::from_millis(20));
+
+                cancel.store(true, Ordering::Relaxed);
+            });
+
+            let start = Instant::now();
+
+            // roughly 10 seconds worth of chunks if never cancelled
+            let result = read_with_cancel(|| Ok(Slow {
+                remaining: READ_CHUNK * 10_000,
+            }), Some(&policy), &cancel);
+
+            assert!(matches!(result, Err(Error::Cancelled)), "unexpected result: {:?}", result);
+            assert!(start.elapsed() < Duration::from_secs(2), "cancel was not prompt");
+        });
+    }
+
+    #[test]
+    fn cancelled_write() {
+        let cancel = AtomicBool::new(true);
+        let opened = Cell::new(0);
+
+        let result = write_with_cancel(|| {
+            opened.set(opened.get() + 1);
+
+            Ok(io::sink())
+        }, b"data", None, &cancel);
+
+        assert!(matches!(result, Err(Error::Cancelled)), "unexpected result: {:?}", result);
+        assert_eq!(opened.get(), 0);
+    }
+}
@@ -0,0 +1,652 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{PathBuf, Path};
+use std::fs::OpenOptions;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use base64::Engine;
+use bincode::Options as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::fs::error::Error;
+use crate::fs::traits::Wrapper;
+use crate::fs::atomic;
+use crate::fs::retry::{self, RetryPolicy};
+use crate::local::{Local, Parts, AccessTimes, Reserved, Tombstone, Meta, SerializeOptions};
+use crate::hooks::{Hooks, Op, Timer};
+use crate::key::Key;
+use crate::crypto;
+
+pub struct Options {
+    pub path: PathBuf,
+    pub key: crypto::Key,
+    pub retry: Option<RetryPolicy>,
+    pub persist_accessed: bool,
+    pub hooks: Option<Arc<dyn Hooks>>,
+    /// raises a counter below the highest version in the file to it
+    /// instead of failing with
+    /// [`CountTooLow`](crate::local::Error::CountTooLow)
+    pub heal_count: bool,
+}
+
+impl Options {
+    pub fn new<P>(path: P, key: crypto::Key) -> Self
+    where
+        P: Into<PathBuf>
+    {
+        Options {
+            path: path.into(),
+            key,
+            retry: None,
+            persist_accessed: false,
+            hooks: None,
+            heal_count: false,
+        }
+    }
+}
+
+/// a json store where only the data of each key is encrypted.
+///
+/// the version and created timestamp of every key are left in the clear so
+/// that the file can be inspected without the master key. each entry is
+/// encrypted with its own nonce and the version and created timestamp are
+/// used as associated data so an entry cannot be moved to another version
+/// without failing authentication.
+///
+/// every entry is decrypted when the file is loaded. to decrypt entries
+/// only when they are requested, load the same file and [`Options`] with
+/// [`EncryptedLazy`](crate::fs::EncryptedLazy) instead.
+///
+/// with the `rayon` feature enabled entries are encrypted and decrypted
+/// across threads.
+pub struct SealedValues<Data> {
+    manager: Local<Key<Data>>,
+    path: Box<Path>,
+    key: crypto::Key,
+    retry: Option<RetryPolicy>,
+    persist_accessed: bool,
+    hooks: Option<Arc<dyn Hooks>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(super) struct SealedStore {
+    pub(super) count: u64,
+    pub(super) store: BTreeMap<u64, SealedEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) accessed: Option<BTreeMap<u64, AccessTimes>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(super) pending: BTreeMap<u64, u64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(super) reserved: BTreeMap<u64, Reserved>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(super) tombstones: BTreeMap<u64, Tombstone>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub(super) staged: BTreeSet<u64>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub(super) disabled: BTreeSet<u64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(super) meta: BTreeMap<u64, Meta>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(super) struct SealedEntry {
+    created: u64,
+    data: String,
+}
+
+fn entry_aad(version: u64, created: u64) -> [u8; 16] {
+    let mut aad = [0; 16];
+    aad[..8].copy_from_slice(&version.to_be_bytes());
+    aad[8..].copy_from_slice(&created.to_be_bytes());
+    aad
+}
+
+pub(super) fn seal_entry<Data>(key: &crypto::Key, version: u64, entry: &Key<Data>) -> Result<SealedEntry, Error>
+where
+    Data: Serialize
+{
+    let serialize = bincode::serialize(entry.data())
+        .map_err(|e| match *e {
+            bincode::ErrorKind::Io(io) => Error::Io(io),
+            _ => Error::Bincode(e)
+        })?;
+
+    let encrypted = crypto::encrypt_data_aad(key, serialize, &entry_aad(version, *entry.created()))
+        .map_err(Error::Crypto)?;
+
+    Ok(SealedEntry {
+        created: *entry.created(),
+        data: BASE64.encode(encrypted),
+    })
+}
+
+pub(super) fn open_entry<Data>(key: &crypto::Key, version: u64, entry: SealedEntry) -> Result<Key<Data>, Error>
+where
+    Data: DeserializeOwned
+{
+    let encrypted = BASE64.decode(entry.data)
+        .map_err(Error::Base64)?;
+
+    let decrypted = crypto::decrypt_data_aad(key, encrypted, &entry_aad(version, entry.created))
+        .map_err(Error::Crypto)?;
+
+    let data = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(decrypted.len() as u64)
+        .deserialize(decrypted.as_slice())
+        .map_err(|e| match *e {
+            bincode::ErrorKind::Io(io) => Error::Io(io),
+            _ => Error::Bincode(e)
+        })?;
+
+    Ok(Key::from_parts(data, entry.created))
+}
+
+/// the bounds required of key data stored in [`SealedValues`]. `Send` and
+/// `Sync` are only required when the `rayon` feature is enabled.
+#[cfg(not(feature = "rayon"))]
+pub trait SealedData: Serialize + DeserializeOwned {}
+
+#[cfg(not(feature = "rayon"))]
+impl<T> SealedData for T
+where
+    T: Serialize + DeserializeOwned
+{}
+
+/// the bounds required of key data stored in [`SealedValues`]. `Send` and
+/// `Sync` are only required when the `rayon` feature is enabled.
+#[cfg(feature = "rayon")]
+pub trait SealedData: Serialize + DeserializeOwned + Send + Sync {}
+
+#[cfg(feature = "rayon")]
+impl<T> SealedData for T
+where
+    T: Serialize + DeserializeOwned + Send + Sync
+{}
+
+#[cfg_attr(feature = "rayon", allow(dead_code))]
+fn seal_entries_serial<Data>(
+    key: &crypto::Key,
+    store: &BTreeMap<u64, Key<Data>>
+) -> Result<BTreeMap<u64, SealedEntry>, Error>
+where
+    Data: Serialize
+{
+    let mut rtn = BTreeMap::new();
+
+    for (version, entry) in store {
+        rtn.insert(*version, seal_entry(key, *version, entry)?);
+    }
+
+    Ok(rtn)
+}
+
+#[cfg_attr(feature = "rayon", allow(dead_code))]
+fn open_entries_serial<Data>(
+    key: &crypto::Key,
+    store: BTreeMap<u64, SealedEntry>
+) -> Result<BTreeMap<u64, Key<Data>>, Error>
+where
+    Data: DeserializeOwned
+{
+    let mut rtn = BTreeMap::new();
+
+    for (version, entry) in store {
+        rtn.insert(version, open_entry(key, version, entry)?);
+    }
+
+    Ok(rtn)
+}
+
+#[cfg(feature = "rayon")]
+fn seal_entries_parallel<Data>(
+    key: &crypto::Key,
+    store: &BTreeMap<u64, Key<Data>>
+) -> Result<BTreeMap<u64, SealedEntry>, Error>
+where
+    Data: Serialize + Sync
+{
+    store.par_iter()
+        .map(|(version, entry)| Ok((*version, seal_entry(key, *version, entry)?)))
+        .collect()
+}
+
+#[cfg(feature = "rayon")]
+fn open_entries_parallel<Data>(
+    key: &crypto::Key,
+    store: BTreeMap<u64, SealedEntry>
+) -> Result<BTreeMap<u64, Key<Data>>, Error>
+where
+    Data: DeserializeOwned + Send
+{
+    store.into_par_iter()
+        .map(|(version, entry)| Ok((version, open_entry(key, version, entry)?)))
+        .collect()
+}
+
+fn seal_entries<Data>(
+    key: &crypto::Key,
+    store: &BTreeMap<u64, Key<Data>>
+) -> Result<BTreeMap<u64, SealedEntry>, Error>
+where
+    Data: SealedData
+{
+    #[cfg(feature = "rayon")]
+    return seal_entries_parallel(key, store);
+
+    #[cfg(not(feature = "rayon"))]
+    return seal_entries_serial(key, store);
+}
+
+fn open_entries<Data>(
+    key: &crypto::Key,
+    store: BTreeMap<u64, SealedEntry>
+) -> Result<BTreeMap<u64, Key<Data>>, Error>
+where
+    Data: SealedData
+{
+    #[cfg(feature = "rayon")]
+    return open_entries_parallel(key, store);
+
+    #[cfg(not(feature = "rayon"))]
+    return open_entries_serial(key, store);
+}
+
+impl<Data> SealedValues<Data> {
+    pub fn new<P>(manager: Local<Key<Data>>, path: P, key: crypto::Key) -> Self
+    where
+        P: Into<PathBuf>
+    {
+        let buf = path.into();
+
+        SealedValues {
+            manager,
+            path: buf.into(),
+            key,
+            retry: None,
+            persist_accessed: false,
+            hooks: None,
+        }
+    }
+
+    /// the store without the file it is saved to
+    pub fn into_inner(self) -> Local<Key<Data>> {
+        self.manager
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn key(&self) -> &crypto::Key {
+        &self.key
+    }
+
+    pub fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.retry.as_ref()
+    }
+
+    pub fn set_retry_policy(&mut self, retry: Option<RetryPolicy>) {
+        self.retry = retry;
+    }
+
+    pub fn persist_accessed(&self) -> bool {
+        self.persist_accessed
+    }
+
+    /// when set, the access times of each version are written on save.
+    /// access times are otherwise kept in memory only.
+    pub fn set_persist_accessed(&mut self, persist: bool) {
+        self.persist_accessed = persist;
+    }
+
+    pub fn hooks(&self) -> Option<&Arc<dyn Hooks>> {
+        self.hooks.as_ref()
+    }
+
+    /// when set, `load` and `save` are timed with the given hooks
+    pub fn set_hooks(&mut self, hooks: Option<Arc<dyn Hooks>>) {
+        self.hooks = hooks;
+    }
+}
+
+impl<Data> std::ops::Deref for SealedValues<Data> {
+    type Target = Local<Key<Data>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.manager
+    }
+}
+
+impl<Data> std::fmt::Debug for SealedValues<Data>
+where
+    Data: std::fmt::Debug
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SealedValues")
+            .field("manager", &self.manager)
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Data> Wrapper for SealedValues<Data>
+where
+    Data: SealedData
+{
+    type Error = Error;
+    type Args = Options;
+
+    fn canonical_bytes(&self) -> Result<Vec<u8>, Self::Error> {
+        crate::fs::binary::serialize_local(&self.manager, SerializeOptions::default())
+    }
+
+    fn load_with_cancel(options: Self::Args, cancel: &AtomicBool) -> Result<Self, Self::Error> {
+        use serde_json::error::Category;
+
+        let path: Box<Path> = options.path.into();
+        let key = options.key;
+        let retry = options.retry;
+        let persist_accessed = options.persist_accessed;
+        let hooks = options.hooks;
+        let _timer = Timer::start(hooks.as_ref(), Op::Load);
+
+        let buffer = retry::read_with_cancel(
+            || OpenOptions::new().read(true).open(&path),
+            retry.as_ref(),
+            cancel
+        )?;
+
+        retry::check_cancel(cancel)?;
+
+        let sealed: SealedStore = serde_json::from_slice(buffer.as_slice())
+            .map_err(|e| match e.classify() {
+                Category::Io => Error::Io(e.into()),
+                _ => Error::Json(e)
+            })?;
+
+        retry::check_cancel(cancel)?;
+
+        let store = open_entries(&key, sealed.store)?;
+
+        let manager = Local::load_parts(Parts {
+            count: sealed.count,
+            store,
+            accessed: sealed.accessed.unwrap_or_default(),
+            pending: sealed.pending,
+            reserved: sealed.reserved,
+            tombstones: sealed.tombstones,
+            staged: sealed.staged,
+            disabled: sealed.disabled,
+            meta: sealed.meta,
+        }, options.heal_count).map_err(Error::Local)?;
+
+        Ok(SealedValues {
+            manager,
+            path,
+            key,
+            retry,
+            persist_accessed,
+            hooks,
+        })
+    }
+
+    fn save_with_cancel(&self, cancel: &AtomicBool) -> Result<(), Self::Error> {
+        use serde_json::error::Category;
+
+        let _timer = Timer::start(self.hooks.as_ref(), Op::Save);
+
+        let accessed = if self.persist_accessed {
+            Some(self.manager.access_times().map_err(Error::Local)?)
+        } else {
+            None
+        };
+
+        let pending = self.manager.pending_drops()
+            .map_err(Error::Local)?;
+        let reserved = self.manager.reservations()
+            .map_err(Error::Local)?;
+        let tombstones = self.manager.tombstones()
+            .map_err(Error::Local)?;
+        let staged = self.manager.staged()
+            .map_err(Error::Local)?;
+        let disabled = self.manager.disabled()
+            .map_err(Error::Local)?;
+        let meta = self.manager.all_meta()
+            .map_err(Error::Local)?;
+
+        // read last so the counter covers every version read above
+        let (count, store) = {
+            let (count, reader) = self.manager.counted_store()
+                .map_err(Error::Local)?;
+
+            (count, seal_entries(&self.key, &reader)?)
+        };
+
+        retry::check_cancel(cancel)?;
+
+        let serialize = serde_json::to_vec(&SealedStore { count, store, accessed, pending, reserved, tombstones, staged, disabled, meta })
+            .map_err(|e| match e.classify() {
+                Category::Io => Error::Io(e.into()),
+                _ => Error::Json(e)
+            })?;
+
+        retry::check_cancel(cancel)?;
+
+        atomic::write_with_cancel(
+            &self.path,
+            serialize.as_slice(),
+            self.retry.as_ref(),
+            cancel
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{self, TempStore};
+
+    #[test]
+    fn base() {
+        let temp = TempStore::new("sealed.base");
+        let file_name = temp.path();
+        let manager = test_util::sample_key_store();
+
+        let wrapper = SealedValues::new(manager, file_name, crypto::empty_key());
+
+        wrapper.save().expect("failed to save to sealed file");
+
+        let and_back: SealedValues<Vec<u8>> = SealedValues::load(Options::new(file_name, crypto::empty_key()))
+            .expect("failed to load sealed file");
+
+        test_util::assert_local_eq(&wrapper.manager, &and_back.manager);
+    }
+
+    #[test]
+    fn count_too_low() {
+        let temp = TempStore::new("sealed.count_too_low");
+        let file_name = temp.path();
+
+        SealedValues::new(test_util::sample_key_store(), file_name, crypto::empty_key())
+            .save()
+            .expect("failed to save to sealed file");
+
+        let contents = std::fs::read_to_string(file_name)
+            .expect("failed to read sealed file");
+        let mut value: serde_json::Value = serde_json::from_str(&contents)
+            .expect("failed to parse sealed file as json");
+
+        value["count"] = 1.into();
+
+        std::fs::write(file_name, serde_json::to_vec(&value).unwrap())
+            .expect("failed to write sealed file");
+
+        let result = SealedValues::<Vec<u8>>::load(Options::new(file_name, crypto::empty_key()));
+
+        assert!(matches!(result, Err(Error::Local(crate::local::Error::CountTooLow { count: 1, highest: 4 }))));
+
+        let result = crate::fs::EncryptedLazy::<Vec<u8>>::load(Options::new(file_name, crypto::empty_key()));
+
+        assert!(matches!(result, Err(Error::Local(crate::local::Error::CountTooLow { count: 1, highest: 4 }))));
+
+        let mut options = Options::new(file_name, crypto::empty_key());
+        options.heal_count = true;
+
+        let healed: SealedValues<Vec<u8>> = SealedValues::load(options)
+            .expect("failed to load sealed file with heal_count");
+
+        assert_eq!(healed.manager.count().unwrap(), 4);
+
+        let mut options = Options::new(file_name, crypto::empty_key());
+        options.heal_count = true;
+
+        let lazy: crate::fs::EncryptedLazy<Vec<u8>> = crate::fs::EncryptedLazy::load(options)
+            .expect("failed to load lazy file with heal_count");
+
+        assert_eq!(lazy.count().unwrap(), 4);
+    }
+
+    #[test]
+    fn save_while_updating() {
+        let temp = TempStore::new("sealed.save_while_updating");
+        let file_name = temp.path();
+        let wrapper = SealedValues::new(Local::new(), file_name, crypto::empty_key());
+        let done = AtomicBool::new(false);
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for index in 0..200u64 {
+                    wrapper.manager.update(Key::from_parts(vec![index as u8; 32], index))
+                        .expect("failed to add key");
+                    wrapper.manager.reserve()
+                        .expect("failed to reserve version");
+                }
+
+                done.store(true, std::sync::atomic::Ordering::Release);
+            });
+
+            while !done.load(std::sync::atomic::Ordering::Acquire) {
+                wrapper.save().expect("failed to save to sealed file");
+
+                SealedValues::<Vec<u8>>::load(Options::new(file_name, crypto::empty_key()))
+                    .expect("failed to load sealed file saved during updates");
+            }
+        });
+    }
+
+    #[test]
+    fn inspect_without_key() {
+        let temp = TempStore::new("sealed.inspect");
+        let file_name = temp.path();
+        let manager = test_util::sample_key_store();
+
+        let wrapper = SealedValues::new(manager, file_name, crypto::empty_key());
+
+        wrapper.save().expect("failed to save to sealed file");
+
+        let contents = std::fs::read_to_string(file_name)
+            .expect("failed to read sealed file");
+        let value: serde_json::Value = serde_json::from_str(&contents)
+            .expect("failed to parse sealed file as json");
+
+        assert_eq!(value["count"], 4);
+
+        let store = value["store"].as_object()
+            .expect("store is not a json object");
+        let versions: Vec<&str> = store.keys()
+            .map(|k| k.as_str())
+            .collect();
+
+        assert_eq!(versions, ["1", "2", "3", "4"]);
+        assert_eq!(store["2"]["created"], 20);
+        assert!(store["2"]["data"].is_string(), "data is not a string");
+        assert!(!contents.contains("super secret"), "plaintext found in sealed file");
+        assert!(
+            !contents.contains(&BASE64.encode(b"super secret key data")),
+            "encoded plaintext found in sealed file"
+        );
+    }
+
+    #[test]
+    fn swapped_entries() {
+        let temp = TempStore::new("sealed.swapped");
+        let file_name = temp.path();
+        let manager = test_util::sample_key_store();
+
+        let wrapper = SealedValues::new(manager, file_name, crypto::empty_key());
+
+        wrapper.save().expect("failed to save to sealed file");
+
+        let contents = std::fs::read_to_string(file_name)
+            .expect("failed to read sealed file");
+        let mut value: serde_json::Value = serde_json::from_str(&contents)
+            .expect("failed to parse sealed file as json");
+
+        let first = value["store"]["1"]["data"].take();
+        let second = value["store"]["2"]["data"].take();
+        value["store"]["1"]["data"] = second;
+        value["store"]["2"]["data"] = first;
+
+        std::fs::write(file_name, serde_json::to_vec(&value).unwrap())
+            .expect("failed to write tampered sealed file");
+
+        let result: Result<SealedValues<Vec<u8>>, _> = SealedValues::load(Options::new(file_name, crypto::empty_key()));
+
+        assert!(
+            matches!(result, Err(Error::Crypto(_))),
+            "swapped entries did not fail authentication: {:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn serial_entries() {
+        let local = test_util::sample_key_store();
+        let reader = local.store_reader().unwrap();
+
+        let sealed = seal_entries_serial(&crypto::empty_key(), &reader)
+            .expect("failed to seal entries");
+        let opened = open_entries_serial(&crypto::empty_key(), sealed)
+            .expect("failed to open entries");
+
+        assert_eq!(*reader, opened);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_entries() {
+        let mut store = BTreeMap::new();
+
+        for version in 1..=3000u64 {
+            let data = version.to_be_bytes().repeat(4);
+
+            store.insert(version, Key::from_parts(data, version * 10));
+        }
+
+        let key = crypto::empty_key();
+
+        let sealed = seal_entries_parallel(&key, &store)
+            .expect("failed to seal entries in parallel");
+
+        let serial: BTreeMap<u64, Key<Vec<u8>>> = open_entries_serial(&key, sealed.clone())
+            .expect("failed to open entries serially");
+        let parallel: BTreeMap<u64, Key<Vec<u8>>> = open_entries_parallel(&key, sealed)
+            .expect("failed to open entries in parallel");
+
+        assert_eq!(serial, store);
+        assert_eq!(parallel, store);
+
+        let sealed = seal_entries_serial(&key, &store)
+            .expect("failed to seal entries serially");
+        let parallel: BTreeMap<u64, Key<Vec<u8>>> = open_entries_parallel(&key, sealed)
+            .expect("failed to open serially sealed entries in parallel");
+
+        assert_eq!(parallel, store);
+    }
+}
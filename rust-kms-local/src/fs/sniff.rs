@@ -0,0 +1,278 @@
+//! telling which kind of store a file holds from the first bytes of it.
+//!
+//! none of the formats start with a magic number so the checks look for
+//! the structure each format always starts with. an encrypted store saved
+//! without a detached header and without annotations or a bridge is
+//! indistinguishable from random bytes and is reported as
+//! [`Detected::Unknown`], its detached header is detected instead.
+
+use std::io::Read;
+
+use crate::fs::error::Error;
+#[cfg(feature = "binary")]
+use crate::local::BINARY_TAG;
+#[cfg(feature = "encrypted")]
+use crate::fs::encrypted::{
+    ANNOTATED_HEADER_VERSION,
+    ANNOTATIONS_MAGIC,
+    BRIDGE_HEADER_VERSION,
+    BRIDGE_MAGIC,
+    CIPHER_ID,
+    HEADER_VERSION,
+};
+
+/// the extension of json stores, including sealed stores
+#[cfg(feature = "json")]
+pub const EXT_JSON: &str = "json";
+
+/// the extension of binary stores
+#[cfg(feature = "binary")]
+pub const EXT_BINARY: &str = "bin";
+
+/// the extension of encrypted stores
+#[cfg(feature = "encrypted")]
+pub const EXT_ENCRYPTED: &str = "enc";
+
+/// the extension of the detached header of an encrypted store, added after
+/// the extension of the store e.g. `keys.enc.header`
+#[cfg(feature = "encrypted")]
+pub const EXT_HEADER: &str = "header";
+
+/// the most bytes read by [`sniff`]
+pub const SNIFF_LEN: u64 = 256;
+
+/// the kind of store found by [`sniff`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Detected {
+    /// a json or sealed store, with or without annotations or an integrity
+    /// tag
+    #[cfg(feature = "json")]
+    Json,
+    /// a binary store. `version` is the number of fields in the layout,
+    /// which started at 2 and grows as fields are added. a store saved
+    /// before the layout was tagged is version 2.
+    #[cfg(feature = "binary")]
+    Binary {
+        version: u64,
+    },
+    /// an encrypted store with inline annotations or a bridge, or the
+    /// detached header of one
+    #[cfg(feature = "encrypted")]
+    Encrypted {
+        format_version: u32,
+        cipher: String,
+    },
+    Unknown,
+}
+
+/// reads up to [`SNIFF_LEN`] bytes and reports which kind of store they
+/// start. a prefix too short to tell is [`Detected::Unknown`].
+pub fn sniff<R>(reader: R) -> Result<Detected, Error>
+where
+    R: Read
+{
+    let mut prefix = Vec::with_capacity(SNIFF_LEN as usize);
+
+    reader.take(SNIFF_LEN)
+        .read_to_end(&mut prefix)
+        .map_err(Error::Io)?;
+
+    #[cfg(feature = "encrypted")]
+    if let Some(detected) = encrypted(&prefix) {
+        return Ok(detected);
+    }
+
+    #[cfg(feature = "binary")]
+    if let Some(detected) = binary(&prefix) {
+        return Ok(detected);
+    }
+
+    #[cfg(feature = "json")]
+    if json(&prefix) {
+        return Ok(Detected::Json);
+    }
+
+    #[cfg(feature = "binary")]
+    if let Some(detected) = untagged_binary(&prefix) {
+        return Ok(detected);
+    }
+
+    Ok(Detected::Unknown)
+}
+
+#[cfg(any(feature = "binary", feature = "encrypted"))]
+fn u64_at(prefix: &[u8], offset: usize) -> Option<u64> {
+    let bytes = prefix.get(offset..offset + 8)?;
+
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// a detached header starts with the format version and the cipher name,
+/// an inline store with the magic of its first section
+#[cfg(feature = "encrypted")]
+fn encrypted(prefix: &[u8]) -> Option<Detected> {
+    let detected = |format_version| Some(Detected::Encrypted {
+        format_version,
+        cipher: CIPHER_ID.to_owned(),
+    });
+
+    if prefix.starts_with(&ANNOTATIONS_MAGIC) {
+        return detected(ANNOTATED_HEADER_VERSION);
+    }
+
+    if prefix.starts_with(&BRIDGE_MAGIC) {
+        return detected(BRIDGE_HEADER_VERSION);
+    }
+
+    let format_version = u32::from_le_bytes(prefix.get(..4)?.try_into().ok()?);
+    let cipher_len = u64_at(prefix, 4)?;
+
+    if !(1..=HEADER_VERSION).contains(&format_version) || cipher_len != CIPHER_ID.len() as u64 {
+        return None;
+    }
+
+    if prefix.get(12..12 + CIPHER_ID.len())? != CIPHER_ID.as_bytes() {
+        return None;
+    }
+
+    detected(format_version)
+}
+
+/// a binary store starts with its tag, the number of fields, the counter
+/// and the number of keys, which can never be more than the counter
+#[cfg(feature = "binary")]
+fn binary(prefix: &[u8]) -> Option<Detected> {
+    if u64_at(prefix, 0)? != BINARY_TAG {
+        return None;
+    }
+
+    let fields = u64_at(prefix, 8)?;
+    let count = u64_at(prefix, 16)?;
+    let len = u64_at(prefix, 24)?;
+
+    if !(2..=9).contains(&fields) || len > count {
+        return None;
+    }
+
+    Some(Detected::Binary { version: fields })
+}
+
+/// a binary store saved before the layout was tagged is the counter
+/// followed by the number of keys, which is never more than the counter.
+/// this is checked last as it is the weakest of the checks.
+#[cfg(feature = "binary")]
+fn untagged_binary(prefix: &[u8]) -> Option<Detected> {
+    let count = u64_at(prefix, 0)?;
+    let len = u64_at(prefix, 8)?;
+
+    if count == BINARY_TAG || len > count {
+        return None;
+    }
+
+    Some(Detected::Binary { version: 2 })
+}
+
+/// a json store is an object whose first field is one of the fields every
+/// json layout starts with
+#[cfg(feature = "json")]
+fn json(prefix: &[u8]) -> bool {
+    const FIRST_FIELDS: [&[u8]; 4] = [b"\"count\"", b"\"annotations\"", b"\"payload\"", b"\"local\""];
+
+    let mut rest = prefix.trim_ascii_start();
+
+    let Some(after) = rest.strip_prefix(b"{") else {
+        return false;
+    };
+
+    rest = after.trim_ascii_start();
+
+    FIRST_FIELDS.iter().any(|field| rest.starts_with(field))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json() {
+        use crate::fs::{Json, Wrapper};
+        use crate::test_util::{sample_local, TempStore};
+
+        let temp = TempStore::new(EXT_JSON);
+        let mut wrapper = Json::new(sample_local(), temp.path());
+
+        wrapper.save().unwrap();
+
+        assert_eq!(sniff(std::fs::File::open(temp.path()).unwrap()).unwrap(), Detected::Json);
+
+        wrapper.set_annotation("name", "sniffed");
+        wrapper.save().unwrap();
+
+        assert_eq!(sniff(std::fs::File::open(temp.path()).unwrap()).unwrap(), Detected::Json);
+        assert_eq!(sniff(&b" {\n  \"count\": 0"[..]).unwrap(), Detected::Json);
+        assert_eq!(sniff(&b"{\"name\": \"package\"}"[..]).unwrap(), Detected::Unknown);
+    }
+
+    #[cfg(feature = "binary")]
+    #[test]
+    fn binary() {
+        use crate::fs::{Binary, Wrapper};
+        use crate::test_util::{sample_local, TempStore};
+
+        let temp = TempStore::new(EXT_BINARY);
+        let local = sample_local();
+
+        local.drop(&1).unwrap();
+
+        Binary::new(local, temp.path()).save().unwrap();
+
+        let bytes = std::fs::read(temp.path()).unwrap();
+
+        assert_eq!(sniff(bytes.as_slice()).unwrap(), Detected::Binary { version: 6 });
+
+        // too short to hold the number of keys
+        assert_eq!(sniff(&bytes[..28]).unwrap(), Detected::Unknown);
+
+        let untagged = include_bytes!("../../fixtures/legacy/local.bin");
+
+        assert_eq!(sniff(untagged.as_slice()).unwrap(), Detected::Binary { version: 2 });
+    }
+
+    #[cfg(feature = "encrypted")]
+    #[test]
+    fn encrypted() {
+        use crate::crypto;
+        use crate::fs::{Encrypted, Wrapper};
+        use crate::test_util::{sample_local, TempStore};
+
+        let key: crypto::Key = [7; crypto::KEY_LEN];
+        let mut temp = TempStore::new(EXT_ENCRYPTED);
+        let header = temp.sibling(&format!(".{}", EXT_HEADER));
+
+        let mut wrapper = Encrypted::new(sample_local(), temp.path(), key);
+        wrapper.set_header_path(Some(header.clone()));
+        wrapper.save().unwrap();
+
+        assert_eq!(sniff(std::fs::File::open(&header).unwrap()).unwrap(), Detected::Encrypted {
+            format_version: 1,
+            cipher: CIPHER_ID.to_owned(),
+        });
+
+        wrapper.set_header_path(None::<std::path::PathBuf>);
+        wrapper.set_annotation("name", "sniffed");
+        wrapper.save().unwrap();
+
+        assert_eq!(sniff(std::fs::File::open(temp.path()).unwrap()).unwrap(), Detected::Encrypted {
+            format_version: ANNOTATED_HEADER_VERSION,
+            cipher: CIPHER_ID.to_owned(),
+        });
+    }
+
+    #[test]
+    fn unrelated() {
+        assert_eq!(sniff(&b""[..]).unwrap(), Detected::Unknown);
+        assert_eq!(sniff(&b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\0\0\0\x10\0\0\0\x10\x08\x06\0\0\0"[..]).unwrap(), Detected::Unknown);
+        assert_eq!(sniff(&b"[1, 2, 3]"[..]).unwrap(), Detected::Unknown);
+    }
+}
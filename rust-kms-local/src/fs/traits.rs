@@ -1,8 +1,61 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
 pub trait Wrapper: Sized {
     type Error;
     type Args;
 
-    fn load(options: Self::Args) -> Result<Self, Self::Error>;
+    fn load(options: Self::Args) -> Result<Self, Self::Error> {
+        Self::load_with_cancel(options, &AtomicBool::new(false))
+    }
+
+    fn save(&self) -> Result<(), Self::Error> {
+        self.save_with_cancel(&AtomicBool::new(false))
+    }
+
+    /// loads the store, stopping early once `cancel` is set. the flag is
+    /// checked between reads of the file and between each stage of decoding.
+    fn load_with_cancel(options: Self::Args, cancel: &AtomicBool) -> Result<Self, Self::Error>;
+
+    /// saves the store, stopping early once `cancel` is set. the flag is only
+    /// checked before the file is opened so a cancelled save leaves the
+    /// previous contents in place.
+    fn save_with_cancel(&self, cancel: &AtomicBool) -> Result<(), Self::Error>;
+
+    /// the bytes of the store in a form that only depends on its keys,
+    /// counter, and pending drops. access times are left out and encrypted
+    /// formats return their plaintext so two logically equal stores always
+    /// give the same bytes.
+    fn canonical_bytes(&self) -> Result<Vec<u8>, Self::Error>;
+
+    /// the SHA-256 of [`canonical_bytes`](Wrapper::canonical_bytes)
+    #[cfg(feature = "canonical")]
+    fn content_hash(&self) -> Result<[u8; 32], Self::Error> {
+        use sha2::{Sha256, Digest};
+
+        Ok(Sha256::digest(self.canonical_bytes()?).into())
+    }
+
+    /// loads the store, cancelling the load if it takes longer than `timeout`
+    fn load_with_timeout(options: Self::Args, timeout: Duration) -> Result<Self, Self::Error> {
+        let cancel = AtomicBool::new(false);
+        let (done, wait) = mpsc::channel::<()>();
+
+        std::thread::scope(|s| {
+            let cancel = &cancel;
+
+            s.spawn(move || {
+                if let Err(RecvTimeoutError::Timeout) = wait.recv_timeout(timeout) {
+                    cancel.store(true, Ordering::Relaxed);
+                }
+            });
+
+            let result = Self::load_with_cancel(options, cancel);
+
+            drop(done);
 
-    fn save(&self) -> Result<(), Self::Error>;
+            result
+        })
+    }
 }
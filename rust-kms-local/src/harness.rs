@@ -0,0 +1,309 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Deref;
+
+use crate::fs::{self, Wrapper};
+use crate::local::Local;
+
+/// a small deterministic random number generator (xorshift64*) so that a
+/// scenario gives the same keys for the same seed
+#[derive(Debug, Clone)]
+pub struct SimRng(u64);
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        SimRng(seed.max(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+/// a clock that only moves when told to
+#[derive(Debug, Clone, Copy)]
+pub struct SimClock(u64);
+
+impl SimClock {
+    pub fn new(start: u64) -> Self {
+        SimClock(start)
+    }
+
+    pub fn now(&self) -> u64 {
+        self.0
+    }
+
+    pub fn advance(&mut self, secs: u64) {
+        self.0 += secs;
+    }
+}
+
+/// a single operation of a [`Scenario`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// adds a new key made from the rng and clock
+    Rotate,
+    /// fetches a version, which may not exist
+    Get(u64),
+    /// drops a version, which may not exist
+    Drop(u64),
+    /// moves the clock forward
+    Advance(u64),
+    Save,
+    /// saves but stops after writing the temporary file, leaving the last
+    /// saved file in place
+    CrashBeforeRename,
+    /// replaces the wrapper with one loaded from disk
+    Reload,
+    /// runs the rekey hook then saves and reloads
+    Rekey,
+}
+
+/// the step that broke an invariant
+#[derive(Debug)]
+pub struct Failure {
+    pub index: usize,
+    pub step: Step,
+    pub message: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "step {} ({:?}): {}", self.index, self.step, self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+type Snapshot<K> = (u64, BTreeMap<u64, K>);
+
+type RekeyHook<W> = Box<dyn FnMut(&mut W)>;
+
+/// drives a sequence of [`Step`]s against a wrapper and checks the
+/// following after every step:
+///
+/// - the counter is never lower than the highest stored version
+/// - the counter never goes down
+/// - a rotation never reuses a version
+/// - a reload gives back exactly what was last saved
+pub struct Scenario<W, K, A, M>
+where
+    W: Wrapper,
+{
+    wrapper: W,
+    args: A,
+    make_key: M,
+    rekey: Option<RekeyHook<W>>,
+    rng: SimRng,
+    clock: SimClock,
+    issued: BTreeSet<u64>,
+    last_count: u64,
+    saved: Option<Snapshot<K>>,
+}
+
+impl<W, K, A, M> Scenario<W, K, A, M>
+where
+    W: Wrapper<Error = fs::Error> + Deref<Target = Local<K>>,
+    K: Clone + PartialEq + fmt::Debug,
+    A: FnMut() -> W::Args,
+    M: FnMut(&mut SimRng, &SimClock) -> K,
+{
+    /// `args` gives the options used to reload the wrapper and `make_key`
+    /// creates the key for every rotation
+    pub fn new(wrapper: W, args: A, make_key: M, rng: SimRng, clock: SimClock) -> Self {
+        Scenario {
+            wrapper,
+            args,
+            make_key,
+            rekey: None,
+            rng,
+            clock,
+            issued: BTreeSet::new(),
+            last_count: 0,
+            saved: None,
+        }
+    }
+
+    /// called by [`Step::Rekey`] before saving, e.g. to change the master
+    /// key used by the wrapper
+    pub fn set_rekey<F>(&mut self, rekey: F)
+    where
+        F: FnMut(&mut W) + 'static
+    {
+        self.rekey = Some(Box::new(rekey));
+    }
+
+    pub fn wrapper(&self) -> &W {
+        &self.wrapper
+    }
+
+    pub fn run(&mut self, steps: &[Step]) -> Result<(), Failure> {
+        for (index, step) in steps.iter().enumerate() {
+            self.step(*step)
+                .and_then(|_| self.check())
+                .map_err(|message| Failure { index, step: *step, message })?;
+        }
+
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Result<Snapshot<K>, String> {
+        let count = self.wrapper.count().map_err(|e| e.to_string())?;
+        let store = self.wrapper.store_reader().map_err(|e| e.to_string())?.clone();
+
+        Ok((count, store))
+    }
+
+    fn save(&mut self) -> Result<(), String> {
+        self.wrapper.save().map_err(|e| format!("save failed: {}", e))?;
+        self.saved = Some(self.snapshot()?);
+
+        Ok(())
+    }
+
+    fn reload(&mut self) -> Result<(), String> {
+        let Some(saved) = &self.saved else {
+            return Ok(());
+        };
+
+        self.wrapper = W::load((self.args)()).map_err(|e| format!("reload failed: {}", e))?;
+
+        let loaded = self.snapshot()?;
+
+        if loaded != *saved {
+            return Err(format!("reload gave {:?} but {:?} was saved", loaded, saved));
+        }
+
+        // the counter in memory may have been ahead of what was saved
+        self.last_count = loaded.0;
+
+        Ok(())
+    }
+
+    fn step(&mut self, step: Step) -> Result<(), String> {
+        match step {
+            Step::Rotate => {
+                let key = (self.make_key)(&mut self.rng, &self.clock);
+                let version = self.wrapper.insert(key).map_err(|e| e.to_string())?;
+
+                if !self.issued.insert(version) {
+                    return Err(format!("version {} was reused", version));
+                }
+            }
+            Step::Get(version) => {
+                self.wrapper.get(&version).map_err(|e| e.to_string())?;
+            }
+            Step::Drop(version) => {
+                self.wrapper.drop(&version).map_err(|e| e.to_string())?;
+            }
+            Step::Advance(secs) => self.clock.advance(secs),
+            Step::Save => self.save()?,
+            Step::CrashBeforeRename => {
+                fs::atomic::crash_before_next_rename();
+
+                if self.wrapper.save().is_ok() {
+                    return Err("save did not stop before the rename".into());
+                }
+            }
+            Step::Reload => self.reload()?,
+            Step::Rekey => {
+                if let Some(rekey) = &mut self.rekey {
+                    rekey(&mut self.wrapper);
+                }
+
+                self.save()?;
+                self.reload()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check(&mut self) -> Result<(), String> {
+        let (count, store) = self.snapshot()?;
+
+        if let Some(max) = store.keys().next_back() {
+            if *max > count {
+                return Err(format!("version {} is above the counter {}", max, count));
+            }
+        }
+
+        if count < self.last_count {
+            return Err(format!("counter went from {} to {}", self.last_count, count));
+        }
+
+        self.last_count = count;
+
+        // versions issued after the last save are lost on reload and will be
+        // issued again so only versions that are still reachable count
+        self.issued.retain(|v| *v <= count);
+
+        Ok(())
+    }
+}
+
+/// the built in scenario. it rotates, drops, saves, crashes part way
+/// through a save, and reloads, covering every step.
+pub fn lifecycle() -> Vec<Step> {
+    vec![
+        Step::Rotate,
+        Step::Rotate,
+        Step::Rotate,
+        Step::Get(2),
+        Step::Save,
+        Step::Reload,
+        Step::Advance(60),
+        Step::Rotate,
+        Step::Drop(1),
+        Step::CrashBeforeRename,
+        Step::Reload,
+        Step::Rotate,
+        Step::Rotate,
+        Step::Drop(5),
+        Step::Save,
+        Step::Advance(3600),
+        Step::Rekey,
+        Step::Rotate,
+        Step::Get(6),
+        Step::Save,
+        Step::Reload,
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::TempStore;
+    use crate::fs::binary::{Binary, Options};
+    use crate::key::Key;
+
+    #[test]
+    fn builtin_lifecycle() {
+        let temp = TempStore::new("harness");
+        let file_name = temp.path();
+
+        let wrapper = Binary::new(Local::new(), file_name);
+        let mut scenario = Scenario::new(
+            wrapper,
+            || Options::new(file_name),
+            |rng: &mut SimRng, clock: &SimClock| {
+                let mut builder = Key::builder(rng.next_u64().to_be_bytes());
+                builder.set_created(clock.now());
+                builder.build().unwrap()
+            },
+            SimRng::new(42),
+            SimClock::new(1_700_000_000),
+        );
+
+        scenario.run(&lifecycle()).unwrap_or_else(|failure| panic!("{}", failure));
+
+        let versions: Vec<u64> = scenario.wrapper().store_reader().unwrap().keys().copied().collect();
+
+        // the rotation and drop before the crash were never saved
+        assert_eq!(versions, vec![1, 2, 3, 4, 6]);
+        assert_eq!(scenario.wrapper().count().unwrap(), 6);
+    }
+}
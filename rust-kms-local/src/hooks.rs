@@ -0,0 +1,230 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// the operations reported to [`Hooks`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Op {
+    /// [`Local::get`](crate::Local::get) and `get_version`
+    Get,
+    /// [`Local::latest`](crate::Local::latest) and `latest_version`
+    Latest,
+    /// [`Local::update`](crate::Local::update)
+    Update,
+    /// [`Local::drop`](crate::Local::drop)
+    Drop,
+    /// loading a store from a file
+    Load,
+    /// saving a store to a file
+    Save,
+    /// decrypting a single entry of an
+    /// [`EncryptedLazy`](crate::fs::EncryptedLazy) store
+    Decrypt,
+    /// [`Local::compact`](crate::Local::compact)
+    Compact,
+}
+
+impl Op {
+    pub const ALL: [Op; 8] = [
+        Op::Get,
+        Op::Latest,
+        Op::Update,
+        Op::Drop,
+        Op::Load,
+        Op::Save,
+        Op::Decrypt,
+        Op::Compact,
+    ];
+
+    fn index(self) -> usize {
+        match self {
+            Op::Get => 0,
+            Op::Latest => 1,
+            Op::Update => 2,
+            Op::Drop => 3,
+            Op::Load => 4,
+            Op::Save => 5,
+            Op::Decrypt => 6,
+            Op::Compact => 7,
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Op::Get => f.write_str("Get"),
+            Op::Latest => f.write_str("Latest"),
+            Op::Update => f.write_str("Update"),
+            Op::Drop => f.write_str("Drop"),
+            Op::Load => f.write_str("Load"),
+            Op::Save => f.write_str("Save"),
+            Op::Decrypt => f.write_str("Decrypt"),
+            Op::Compact => f.write_str("Compact"),
+        }
+    }
+}
+
+/// receives the duration of every instrumented operation. called on the
+/// thread that ran the operation, after it finished, so it should be cheap.
+pub trait Hooks: Send + Sync {
+    fn observe(&self, op: Op, dur: Duration);
+}
+
+/// times an operation and reports it when dropped. nothing is timed when no
+/// hooks are set.
+pub(crate) struct Timer(Option<(Arc<dyn Hooks>, Op, Instant)>);
+
+impl Timer {
+    pub(crate) fn start(hooks: Option<&Arc<dyn Hooks>>, op: Op) -> Self {
+        Timer(hooks.map(|hooks| (hooks.clone(), op, Instant::now())))
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some((hooks, op, start)) = self.0.take() {
+            hooks.observe(op, start.elapsed());
+        }
+    }
+}
+
+/// the upper bounds of the histogram buckets in microseconds. durations
+/// above the last bound go into a final overflow bucket.
+pub const BUCKET_BOUNDS_MICROS: [u64; 6] = [10, 100, 1_000, 10_000, 100_000, 1_000_000];
+
+/// the number of buckets in a [`Histogram`], including the overflow bucket
+pub const BUCKET_COUNT: usize = BUCKET_BOUNDS_MICROS.len() + 1;
+
+/// the counts of a single operation per bucket
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    pub counts: [u64; BUCKET_COUNT],
+}
+
+impl Histogram {
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+/// the histograms of every operation at the time of
+/// [`AtomicHistogramHooks::snapshot`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    histograms: [Histogram; Op::ALL.len()],
+}
+
+impl HistogramSnapshot {
+    pub fn get(&self, op: Op) -> &Histogram {
+        &self.histograms[op.index()]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Op, &Histogram)> + '_ {
+        Op::ALL.into_iter().map(|op| (op, self.get(op)))
+    }
+}
+
+/// [`Hooks`] that count durations into fixed buckets per operation
+#[derive(Debug, Default)]
+pub struct AtomicHistogramHooks {
+    counts: [[AtomicU64; BUCKET_COUNT]; Op::ALL.len()],
+}
+
+impl AtomicHistogramHooks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// copies the current counts. counts taken while operations are running
+    /// may be off by the operations in flight.
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let mut rtn = HistogramSnapshot::default();
+
+        for (histogram, counts) in rtn.histograms.iter_mut().zip(&self.counts) {
+            for (count, atomic) in histogram.counts.iter_mut().zip(counts) {
+                *count = atomic.load(Ordering::Relaxed);
+            }
+        }
+
+        rtn
+    }
+}
+
+impl Hooks for AtomicHistogramHooks {
+    fn observe(&self, op: Op, dur: Duration) {
+        let micros = dur.as_micros();
+        let bucket = BUCKET_BOUNDS_MICROS.iter()
+            .position(|bound| micros <= *bound as u128)
+            .unwrap_or(BUCKET_BOUNDS_MICROS.len());
+
+        self.counts[op.index()][bucket].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::local::Local;
+
+    #[test]
+    fn buckets() {
+        let hooks = AtomicHistogramHooks::new();
+
+        hooks.observe(Op::Get, Duration::from_micros(5));
+        hooks.observe(Op::Get, Duration::from_micros(10));
+        hooks.observe(Op::Get, Duration::from_micros(11));
+        hooks.observe(Op::Save, Duration::from_secs(5));
+
+        let snapshot = hooks.snapshot();
+
+        assert_eq!(snapshot.get(Op::Get).counts, [2, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(snapshot.get(Op::Save).counts, [0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(snapshot.get(Op::Latest).total(), 0);
+    }
+
+    #[test]
+    fn workload() {
+        let hooks = Arc::new(AtomicHistogramHooks::new());
+        let local = Local::builder()
+            .hooks(hooks.clone())
+            .build()
+            .unwrap();
+
+        for v in 0..10u64 {
+            local.update(v).unwrap();
+        }
+
+        for v in 1..=10 {
+            local.get(&v).unwrap();
+        }
+
+        local.latest().unwrap();
+        local.latest_version().unwrap();
+        local.drop(&1).unwrap();
+
+        let snapshot = hooks.snapshot();
+
+        assert_eq!(snapshot.get(Op::Update).total(), 10);
+        assert_eq!(snapshot.get(Op::Get).total(), 10);
+        assert_eq!(snapshot.get(Op::Latest).total(), 2);
+        assert_eq!(snapshot.get(Op::Drop).total(), 1);
+        assert_eq!(snapshot.get(Op::Load).total(), 0);
+
+        // in memory operations finish well under a second
+        for (op, histogram) in snapshot.iter() {
+            assert_eq!(histogram.counts[BUCKET_COUNT - 1], 0, "{} overflowed", op);
+        }
+    }
+
+    #[test]
+    fn unset() {
+        assert!(Timer::start(None, Op::Get).0.is_none());
+
+        let local: Local<u64> = Local::new();
+
+        assert!(local.config().hooks().is_none());
+    }
+}
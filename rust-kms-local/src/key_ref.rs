@@ -0,0 +1,192 @@
+//! a stable way to refer to a key version outside of the store.
+//!
+//! versions are written as 20 zero padded digits so they sort the same as
+//! text and as numbers and never change width. version 0 is never given to
+//! a key so it is rejected everywhere a [`KeyRef`] is made.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de;
+
+/// the number of digits in a formatted [`KeyRef`], enough for `u64::MAX`
+pub const KEY_REF_LEN: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// not 1 to 20 ascii digits
+    Malformed(String),
+    /// more than `u64::MAX`
+    Overflow(String),
+    /// version 0
+    Zero(String),
+}
+
+impl ParseError {
+    /// the input that failed to parse
+    pub fn input(&self) -> &str {
+        match self {
+            ParseError::Malformed(input) |
+            ParseError::Overflow(input) |
+            ParseError::Zero(input) => input,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Malformed(input) => write!(f, "Malformed {:?}", input),
+            ParseError::Overflow(input) => write!(f, "Overflow {:?}", input),
+            ParseError::Zero(input) => write!(f, "Zero {:?}", input),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// a key version as it is given to other systems
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KeyRef(u64);
+
+impl KeyRef {
+    /// for versions that came from a store, which never hands out 0
+    pub(crate) fn from_version(version: u64) -> Self {
+        debug_assert!(version != 0, "version 0 is never given to a key");
+
+        KeyRef(version)
+    }
+
+    pub fn version(&self) -> u64 {
+        self.0
+    }
+}
+
+impl TryFrom<u64> for KeyRef {
+    type Error = ParseError;
+
+    fn try_from(version: u64) -> Result<Self, Self::Error> {
+        if version == 0 {
+            Err(ParseError::Zero(version.to_string()))
+        } else {
+            Ok(KeyRef(version))
+        }
+    }
+}
+
+impl From<KeyRef> for u64 {
+    fn from(key_ref: KeyRef) -> Self {
+        key_ref.0
+    }
+}
+
+impl fmt::Display for KeyRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:0width$}", self.0, width = KEY_REF_LEN)
+    }
+}
+
+/// accepts 1 to 20 ascii digits so unpadded versions also parse. signs and
+/// whitespace are rejected.
+impl FromStr for KeyRef {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() || s.len() > KEY_REF_LEN || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseError::Malformed(s.to_owned()));
+        }
+
+        let version: u64 = s.parse()
+            .map_err(|_| ParseError::Overflow(s.to_owned()))?;
+
+        KeyRef::try_from(version)
+            .map_err(|_| ParseError::Zero(s.to_owned()))
+    }
+}
+
+impl TryFrom<&str> for KeyRef {
+    type Error = ParseError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// a string in human readable formats and a number otherwise
+impl Serialize for KeyRef {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer
+    {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_u64(self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyRef {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>
+    {
+        if deserializer.is_human_readable() {
+            let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+
+            s.parse().map_err(de::Error::custom)
+        } else {
+            KeyRef::try_from(u64::deserialize(deserializer)?)
+                .map_err(de::Error::custom)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn format() {
+        assert_eq!(KeyRef::from_version(1).to_string(), "00000000000000000001");
+        assert_eq!(KeyRef::from_version(42).to_string(), "00000000000000000042");
+        assert_eq!(KeyRef::from_version(u64::MAX).to_string(), "18446744073709551615");
+
+        for version in [1, 9, 10, 1234, u64::MAX] {
+            let key_ref = KeyRef::from_version(version);
+            let s = key_ref.to_string();
+
+            assert_eq!(s.len(), KEY_REF_LEN);
+            assert_eq!(s.parse::<KeyRef>().unwrap(), key_ref);
+            assert_eq!(KeyRef::try_from(s.as_str()).unwrap().version(), version);
+        }
+
+        assert_eq!("7".parse::<KeyRef>().unwrap(), KeyRef::from_version(7));
+    }
+
+    #[test]
+    fn malformed() {
+        for input in ["", " 1", "1 ", "+1", "-1", "1.0", "0x10", "abc", "000000000000000000001"] {
+            assert_eq!(input.parse::<KeyRef>(), Err(ParseError::Malformed(input.to_owned())));
+        }
+
+        assert_eq!(
+            "18446744073709551616".parse::<KeyRef>(),
+            Err(ParseError::Overflow("18446744073709551616".to_owned()))
+        );
+        assert_eq!("00000000000000000000".parse::<KeyRef>(), Err(ParseError::Zero("00000000000000000000".to_owned())));
+        assert_eq!(KeyRef::try_from(0u64), Err(ParseError::Zero("0".to_owned())));
+        assert_eq!(ParseError::Malformed("kid-1".to_owned()).input(), "kid-1");
+    }
+
+    #[test]
+    fn serde() {
+        let key_ref = KeyRef::from_version(3);
+
+        assert_eq!(serde_json::to_string(&key_ref).unwrap(), "\"00000000000000000003\"");
+        assert_eq!(serde_json::from_str::<KeyRef>("\"3\"").unwrap(), key_ref);
+        assert!(serde_json::from_str::<KeyRef>("\"0\"").is_err());
+        assert!(serde_json::from_str::<KeyRef>("3").is_err());
+    }
+}
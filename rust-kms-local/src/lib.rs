@@ -1,10 +1,61 @@
 #[cfg(feature = "crypto")]
 pub mod crypto;
-#[cfg(any(feature = "binary", feature = "json"))]
+#[cfg(feature = "fs")]
 pub mod fs;
 
+pub mod hooks;
+
+#[cfg(feature = "rand")]
+pub mod rand_source;
+
+#[cfg(feature = "mlock")]
+pub mod memory;
+
+#[cfg(any(feature = "mlock", feature = "encrypted"))]
+mod zero;
+
 pub mod key;
 pub use key::Key;
 
+pub mod rfc3339;
+
+pub mod key_ref;
+pub use key_ref::KeyRef;
+
 pub mod local;
 pub use local::Local;
+
+#[cfg(feature = "async")]
+pub mod async_local;
+#[cfg(feature = "async")]
+pub use async_local::AsyncLocal;
+
+pub mod policy;
+pub use policy::Enforced;
+
+#[cfg(feature = "crypto")]
+pub mod validate;
+
+#[cfg(feature = "crypto")]
+pub mod envelope;
+
+#[cfg(feature = "compat")]
+pub mod compat;
+
+#[cfg(feature = "registry")]
+pub mod registry;
+
+#[cfg(feature = "harness")]
+pub mod harness;
+
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+
+#[cfg(any(feature = "server", feature = "client"))]
+pub mod wire;
+
+#[cfg(feature = "server")]
+pub mod server;
+
+#[cfg(feature = "client")]
+pub mod client;
@@ -1,12 +1,92 @@
-use std::collections::BTreeMap;
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::marker::PhantomData;
-use std::sync::{Mutex, RwLock, PoisonError};
-use std::sync::RwLockReadGuard;
+use std::sync::{mpsc, LockResult, PoisonError};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
 use std::fmt;
 
+use crate::key::Key;
+use crate::key_ref::KeyRef;
+use crate::hooks::{Op, Timer};
+
+mod sync;
+mod builder;
+mod reconcile;
+mod reserve;
+mod gaps;
+mod stage;
+mod disable;
+mod meta;
+mod observe;
+mod freeze;
+mod iter;
+mod confirm;
+mod diff;
+mod view;
+mod timed;
+mod import;
+mod recent;
+mod recover;
+#[cfg(test)]
+mod model;
+pub use builder::{LocalBuilder, Config, Change};
+use builder::{EvictHook, ChangeHook};
+pub use reconcile::{Snapshot, MergeStrategy, ReconcileReport};
+pub use reserve::{Reservation, Reserved};
+pub use gaps::{Gap, GapReason, Tombstone, DEFAULT_TOMBSTONE_RETENTION};
+pub use freeze::FreezeGuard;
+pub use iter::{Iter, IterVersioned, Versions, VersionedRef, KeyGuard, StoreReader};
+pub use confirm::{PendingDrop, DEFAULT_CONFIRM_WINDOW};
+pub use diff::Diff;
+pub use view::View;
+pub use meta::Meta;
+pub use import::{ImportPolicy, ImportReport};
+pub use recent::{OpRecord, Outcome};
+use recent::RecentOps;
+use sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
 #[derive(Debug)]
 pub enum Error {
+    /// a thread panicked while changing the store, see [`Local::recover`]
     Poisoned,
+    VersionNotFound(u64),
+    Conflict(u64),
+    NotReserved(u64),
+    Occupied(u64),
+    /// the store was changed while a [`FreezeGuard`] was alive
+    Frozen,
+    /// the store has no key to return
+    Empty,
+    /// a lock was held elsewhere and the call was not allowed to wait
+    WouldBlock,
+    /// a lock was still held elsewhere after waiting this long
+    LockTimeout {
+        waited: Duration,
+    },
+    /// the store requires confirmed drops, give the token to
+    /// [`Local::confirm_drop`] to drop the version
+    ConfirmDrop(PendingDrop),
+    /// the token was confirmed after the confirm window of the store
+    DropTokenExpired(u64),
+    /// the token was for another version
+    DropTokenMismatch(u64),
+    /// the latest version was not the one expected by
+    /// [`Local::update_if_latest`]
+    VersionConflict {
+        expected: Option<u64>,
+        actual: Option<u64>,
+    },
+    /// version 0 is never handed out so a store cannot have it
+    InvalidVersion(u64),
+    /// the counter is below a version in the store and would hand it out
+    /// again
+    CountTooLow {
+        count: u64,
+        highest: u64,
+    },
+    /// the counter is at `u64::MAX` and cannot hand out another version
+    CounterOverflow,
 }
 
 impl<T> From<PoisonError<T>> for Error {
@@ -18,13 +98,34 @@ impl<T> From<PoisonError<T>> for Error {
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Error::Poisoned => f.write_str("StorePoisoned")
+            Error::Poisoned => f.write_str("StorePoisoned"),
+            Error::VersionNotFound(version) => write!(f, "VersionNotFound {}", version),
+            Error::Conflict(version) => write!(f, "Conflict {}", version),
+            Error::NotReserved(version) => write!(f, "NotReserved {}", version),
+            Error::Occupied(version) => write!(f, "Occupied {}", version),
+            Error::Frozen => f.write_str("Frozen"),
+            Error::Empty => f.write_str("Empty"),
+            Error::WouldBlock => f.write_str("WouldBlock"),
+            Error::LockTimeout { waited } => write!(f, "LockTimeout after {}ms", waited.as_millis()),
+            Error::ConfirmDrop(token) => write!(f, "ConfirmDrop {}", token.version()),
+            Error::DropTokenExpired(version) => write!(f, "DropTokenExpired {}", version),
+            Error::DropTokenMismatch(version) => write!(f, "DropTokenMismatch {}", version),
+            Error::VersionConflict { expected, actual } => write!(
+                f, "VersionConflict expected {:?} found {:?}", expected, actual
+            ),
+            Error::InvalidVersion(version) => write!(f, "InvalidVersion {}", version),
+            Error::CountTooLow { count, highest } => write!(
+                f, "CountTooLow {} below version {}", count, highest
+            ),
+            Error::CounterOverflow => f.write_str("CounterOverflow"),
         }
     }
 }
 
 impl std::error::Error for Error {}
 
+/// a key with its version. serialized as `{"version": .., "key": ..}`.
+#[derive(Clone, PartialEq, Eq)]
 pub struct VersionedKey<T>(
     pub u64,
     pub T
@@ -34,6 +135,23 @@ impl<T> VersionedKey<T> {
     pub fn version(&self) -> &u64 {
         &self.0
     }
+
+    pub fn into_inner(self) -> (u64, T) {
+        (self.0, self.1)
+    }
+
+    /// the same version with `f` applied to the key
+    pub fn map<U, F>(self, f: F) -> VersionedKey<U>
+    where
+        F: FnOnce(T) -> U
+    {
+        VersionedKey(self.0, f(self.1))
+    }
+
+    /// the version as it is given to other systems
+    pub fn key_ref(&self) -> KeyRef {
+        KeyRef::from_version(self.0)
+    }
 }
 
 impl<T> std::ops::Deref for VersionedKey<T> {
@@ -56,286 +174,3390 @@ where
     }
 }
 
-pub struct Local<KeyType> {
-    store: RwLock<BTreeMap<u64, KeyType>>,
-    count: Mutex<u64>,
+/// the fields of a serialized [`VersionedKey`]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct VersionedKeyFields<T> {
+    version: u64,
+    key: T,
 }
 
-impl<KeyType> Local<KeyType> {
-    pub fn new() -> Self {
-        Local {
-            store: RwLock::new(BTreeMap::new()),
-            count: Mutex::new(0),
-        }
+impl<T> serde::Serialize for VersionedKey<T>
+where
+    T: serde::Serialize
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer
+    {
+        VersionedKeyFields {
+            version: self.0,
+            key: &self.1,
+        }.serialize(serializer)
     }
+}
 
-    pub fn store_reader<'a>(&'a self) -> Result<RwLockReadGuard<'a, BTreeMap<u64, KeyType>>, Error> {
-        Ok(self.store.read()?)
+impl<'de, T> serde::Deserialize<'de> for VersionedKey<T>
+where
+    T: serde::Deserialize<'de>
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>
+    {
+        let fields = VersionedKeyFields::deserialize(deserializer)?;
+
+        Ok(VersionedKey(fields.version, fields.key))
     }
+}
 
-    pub fn count(&self) -> Result<u64, Error> {
-        let count_lock = self.count.lock()?;
+/// the first and last time a version was fetched, in seconds since the unix
+/// epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AccessTimes {
+    pub first: u64,
+    pub last: u64,
+}
 
-        Ok(*count_lock)
+#[derive(Debug)]
+struct Access {
+    first: AtomicU64,
+    last: AtomicU64,
+}
+
+impl Access {
+    fn new(times: AccessTimes) -> Self {
+        Access {
+            first: AtomicU64::new(times.first),
+            last: AtomicU64::new(times.last),
+        }
     }
 
-    pub fn update(&self, key: KeyType) -> Result<(), Error> {
-        let mut version_lock = self.count.lock()?;
-        let new_version = *version_lock + 1;
+    fn times(&self) -> AccessTimes {
+        AccessTimes {
+            first: self.first.load(Ordering::Relaxed),
+            last: self.last.load(Ordering::Relaxed),
+        }
+    }
+}
 
-        {
-            let mut store_writer = self.store.write()?;
+pub(crate) fn unix_now() -> u64 {
+    unix_secs(SystemTime::now())
+}
 
-            store_writer.insert(new_version, key);
-        }
+/// seconds since the unix epoch, zero for times before it
+pub(crate) fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
 
-        *version_lock = new_version;
+/// the numbers of a [`Local`] at one point in time, from [`Local::stats`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StoreStats {
+    /// the counter, as from [`count`](Local::count). it is the highest
+    /// version handed out since the store was created or last compacted,
+    /// not the number of versions ever issued, as
+    /// [`compact`](Local::compact) lowers it to the number of keys kept.
+    pub count: u64,
+    /// the number of keys in the store, including staged and disabled
+    pub live_keys: usize,
+    /// the version [`oldest`](Local::oldest) would return
+    pub oldest_version: Option<u64>,
+    /// the version [`latest`](Local::latest) would return
+    pub latest_version: Option<u64>,
+    /// the last time each version was fetched, as from
+    /// [`last_accessed`](Local::last_accessed). versions that were never
+    /// fetched are left out.
+    pub last_accessed: BTreeMap<u64, u64>,
+}
 
-        Ok(())
+/// the old to new version mapping produced by [`Local::compact`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CompactionMap(BTreeMap<u64, u64>);
+
+impl CompactionMap {
+    /// the new version of an old version, `None` if it was not kept
+    pub fn get(&self, old: &u64) -> Option<u64> {
+        self.0.get(old).copied()
     }
 
-    pub fn drop(&self, version: &u64) -> Result<Option<KeyType>, Error> {
-        let mut store_writer = self.store.write()?;
+    /// the pairs of old and new versions ordered by old version
+    pub fn iter(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.0.iter().map(|(old, new)| (*old, *new))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 
-        Ok(store_writer.remove(version))
+    pub fn into_inner(self) -> BTreeMap<u64, u64> {
+        self.0
     }
 }
 
-impl<KeyType> Local<KeyType>
-where
-    KeyType: Clone
-{
-    pub fn get(&self, version: &u64) -> Result<Option<KeyType>, Error> {
-        let store_reader = self.store.read()?;
+/// controls what optional state is included when a [`Local`] is serialized.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct SerializeOptions {
+    pub(crate) accessed: bool,
+}
 
-        let Some(key) = store_reader.get(version) else {
-            return Ok(None);
-        };
+/// the contents of a [`Local`] as read from a serialized store
+pub(crate) struct Parts<KeyType> {
+    pub(crate) count: u64,
+    pub(crate) store: BTreeMap<u64, KeyType>,
+    pub(crate) accessed: BTreeMap<u64, AccessTimes>,
+    pub(crate) pending: BTreeMap<u64, u64>,
+    pub(crate) reserved: BTreeMap<u64, Reserved>,
+    pub(crate) tombstones: BTreeMap<u64, Tombstone>,
+    pub(crate) staged: BTreeSet<u64>,
+    pub(crate) disabled: BTreeSet<u64>,
+    pub(crate) meta: BTreeMap<u64, Meta>,
+}
 
-        Ok(Some(key.clone()))
+impl<KeyType> Parts<KeyType> {
+    pub(crate) fn new(count: u64, store: BTreeMap<u64, KeyType>) -> Self {
+        Parts {
+            count,
+            store,
+            accessed: BTreeMap::new(),
+            pending: BTreeMap::new(),
+            reserved: BTreeMap::new(),
+            tombstones: BTreeMap::new(),
+            staged: BTreeSet::new(),
+            disabled: BTreeSet::new(),
+            meta: BTreeMap::new(),
+        }
     }
+}
 
-    pub fn get_version(&self, version: &u64) -> Result<Option<VersionedKey<KeyType>>, Error> {
-        let store_reader = self.store.read()?;
-
-        let Some((ver, key)) = store_reader.get_key_value(version) else {
-            return Ok(None);
-        };
+pub(crate) struct SerializeWith<'a, KeyType> {
+    local: &'a Local<KeyType>,
+    options: SerializeOptions,
+    failed: Cell<Option<u64>>,
+}
 
-        Ok(Some(VersionedKey(*ver, key.clone())))
+impl<KeyType> SerializeWith<'_, KeyType> {
+    /// the version of the key that failed to serialize, if serializing
+    /// failed because of a key
+    #[cfg(any(feature = "binary", feature = "json"))]
+    pub(crate) fn failed_version(&self) -> Option<u64> {
+        self.failed.get()
     }
+}
 
-    pub fn latest(&self) -> Result<Option<KeyType>, Error> {
-        let store_reader = self.store.read()?;
+/// the keys of a store written one entry at a time so that the version of a
+/// key that fails to serialize can be recorded
+struct Entries<'a, KeyType> {
+    store: &'a BTreeMap<u64, KeyType>,
+    failed: &'a Cell<Option<u64>>,
+}
 
-        let Some((_, key)) = store_reader.last_key_value() else {
-            return Ok(None);
-        };
+struct Entry<'a, KeyType> {
+    version: u64,
+    key: &'a KeyType,
+    failed: &'a Cell<Option<u64>>,
+}
 
-        Ok(Some(key.clone()))
+/// the first value of the binary layout. stores saved before the layout
+/// could grow were a bare `(count, store)` pair that starts with the counter
+/// instead, which never gets this high, so loading can tell the two apart.
+pub(crate) const BINARY_TAG: u64 = u64::from_le_bytes(*b"RKMS\xff\xff\xff\xff");
+
+/// everything written for a store, read out of a [`Local`] by
+/// [`SerializeWith`] or given by a store that only has keys
+pub(crate) struct Fields<'a, KeyType> {
+    count: u64,
+    entries: Entries<'a, KeyType>,
+    accessed: Option<BTreeMap<u64, AccessTimes>>,
+    pending: BTreeMap<u64, u64>,
+    reserved: BTreeMap<u64, Reserved>,
+    tombstones: BTreeMap<u64, Tombstone>,
+    staged: BTreeSet<u64>,
+    disabled: BTreeSet<u64>,
+    meta: BTreeMap<u64, Meta>,
+}
+
+impl<'a, KeyType> Fields<'a, KeyType> {
+    /// the fields of a store with only a counter and keys. a key that fails
+    /// to serialize records its version in `failed`.
+    #[cfg(feature = "async")]
+    pub(crate) fn keys_only(
+        count: u64,
+        store: &'a BTreeMap<u64, KeyType>,
+        failed: &'a Cell<Option<u64>>
+    ) -> Self {
+        Fields {
+            count,
+            entries: Entries {
+                store,
+                failed,
+            },
+            accessed: None,
+            pending: BTreeMap::new(),
+            reserved: BTreeMap::new(),
+            tombstones: BTreeMap::new(),
+            staged: BTreeSet::new(),
+            disabled: BTreeSet::new(),
+            meta: BTreeMap::new(),
+        }
     }
+}
 
-    pub fn latest_version(&self) -> Result<Option<VersionedKey<KeyType>>, Error> {
-        let store_reader = self.store.read()?;
+/// the length prefixed fields of the binary layout that follow
+/// [`BINARY_TAG`]
+struct BinaryFields<'b, 'a, KeyType>(&'b Fields<'a, KeyType>);
+
+/// a versioned store of keys.
+///
+/// - `update` gives each key the version after the counter and moves the
+///   counter to it. versions are never handed out twice, dropping the
+///   highest version does not lower the counter. the one exception is
+///   `compact`, which renumbers the kept keys from 1 and resets the counter
+///   to the number kept, so versions above it are handed out again.
+/// - `drop` of a version that is not in the store is `Ok(None)` and leaves
+///   no tombstone.
+/// - `latest` is the highest version in the store that is not pending a
+///   drop, staged or disabled, so dropping it falls back to the next
+///   highest.
+///   an empty store has no latest.
+/// - `count` is the counter, not the number of keys. it is always at least
+///   the highest version in the store, reserved or tombstoned. a file with
+///   a counter that is too low fails to load with [`Error::CountTooLow`]
+///   unless it is loaded as [`Healed`] or with `heal_count` set, and a
+///   reconcile raises it, so versions are never handed out again.
+pub struct Local<KeyType> {
+    pub(crate) store: RwLock<BTreeMap<u64, KeyType>>,
+    count: Mutex<u64>,
+    accessed: RwLock<BTreeMap<u64, Access>>,
+    pending: RwLock<BTreeMap<u64, u64>>,
+    reserved: RwLock<BTreeMap<u64, Reserved>>,
+    tombstones: RwLock<BTreeMap<u64, Tombstone>>,
+    staged: RwLock<BTreeSet<u64>>,
+    disabled: RwLock<BTreeSet<u64>>,
+    meta: RwLock<BTreeMap<u64, Meta>>,
+    frozen: AtomicUsize,
+    generation: AtomicU64,
+    config: Config,
+    on_evict: Option<EvictHook<KeyType>>,
+    recent: Option<RecentOps>,
+    observers: RwLock<Vec<ChangeHook>>,
+    subscribers: Mutex<Vec<mpsc::Sender<Change>>>,
+}
 
-        let Some((version, key)) = store_reader.last_key_value() else {
-            return Ok(None);
-        };
+/// the version after `last`, failing once the counter is at `u64::MAX`
+fn next_version(last: u64) -> Result<u64, Error> {
+    last.checked_add(1).ok_or(Error::CounterOverflow)
+}
 
-        Ok(Some(VersionedKey(*version, key.clone())))
-    }
+/// the counter raised to the highest version that was handed out. a
+/// counter below one would give that version out again, e.g. after a file
+/// was edited by hand or written by a bug.
+fn healed_count<KeyType>(
+    count: u64,
+    store: &BTreeMap<u64, KeyType>,
+    reserved: &BTreeMap<u64, Reserved>,
+    tombstones: &BTreeMap<u64, Tombstone>,
+) -> u64 {
+    [
+        store.keys().next_back(),
+        reserved.keys().next_back(),
+        tombstones.keys().next_back(),
+    ].into_iter()
+        .flatten()
+        .fold(count, |count, version| count.max(*version))
 }
 
-impl<KeyType> fmt::Debug for Local<KeyType>
-where
-    KeyType: fmt::Debug
-{
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Local")
-            .field("store", &self.store)
-            .field("count", &self.count)
-            .finish()
-    }
+/// what is set on a version as it is added
+#[derive(Default)]
+struct Added {
+    staged: bool,
+    meta: Meta,
 }
 
-use serde::ser::{Serialize, Serializer, SerializeStruct};
-use serde::de::{self, Deserialize, Deserializer, Visitor, MapAccess, SeqAccess};
+/// the versions `latest` passes over: pending a drop, staged or disabled
+struct Skipped<'a> {
+    pending: RwLockReadGuard<'a, BTreeMap<u64, u64>>,
+    staged: RwLockReadGuard<'a, BTreeSet<u64>>,
+    disabled: RwLockReadGuard<'a, BTreeSet<u64>>,
+}
 
-impl<KeyType> Serialize for Local<KeyType>
-where
-    KeyType: Serialize
-{
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut state = serializer.serialize_struct("Local", 2)?;
-        state.serialize_field("count", &self.count)?;
-        state.serialize_field("store", &self.store)?;
-        state.end()
+impl Skipped<'_> {
+    fn contains(&self, version: &u64) -> bool {
+        self.pending.contains_key(version) ||
+            self.staged.contains(version) ||
+            self.disabled.contains(version)
     }
 }
 
-impl<'de, KeyType> Deserialize<'de> for Local<KeyType>
-where
-    KeyType: Deserialize<'de>
-{
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>
-    {
-        const STRUCT_FIELDS: &'static [&'static str] = &["count", "store"];
+/// the versions removed to stay under `max_versions` and their keys
+struct Evicted<KeyType>(Vec<(u64, KeyType)>);
 
-        enum LocalField {
-            Count,
-            Store,
+impl<KeyType> Evicted<KeyType> {
+    fn contains(&self, version: &u64) -> bool {
+        self.0.iter().any(|(evicted, _)| evicted == version)
+    }
+}
+
+impl<KeyType> Local<KeyType> {
+    pub fn new() -> Self {
+        Local {
+            store: RwLock::new(BTreeMap::new()),
+            count: Mutex::new(0),
+            accessed: RwLock::new(BTreeMap::new()),
+            pending: RwLock::new(BTreeMap::new()),
+            reserved: RwLock::new(BTreeMap::new()),
+            tombstones: RwLock::new(BTreeMap::new()),
+            staged: RwLock::new(BTreeSet::new()),
+            disabled: RwLock::new(BTreeSet::new()),
+            meta: RwLock::new(BTreeMap::new()),
+            frozen: AtomicUsize::new(0),
+            generation: AtomicU64::new(0),
+            config: Config::default(),
+            on_evict: None,
+            recent: None,
+            observers: RwLock::new(Vec::new()),
+            subscribers: Mutex::new(Vec::new()),
         }
+    }
 
-        impl<'de> Deserialize<'de> for LocalField {
-            fn deserialize<D>(deserializer: D) -> Result<LocalField, D::Error>
-            where
-                D: Deserializer<'de>
-            {
-                struct LocalFieldVisitor;
+    pub fn builder() -> LocalBuilder<KeyType> {
+        LocalBuilder::new()
+    }
 
-                impl<'de> Visitor<'de> for LocalFieldVisitor {
-                    type Value = LocalField;
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
 
-                    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                        formatter.write_str("'count' for 'store'")
-                    }
+    fn timer(&self, op: Op) -> Timer {
+        Timer::start(self.config.hooks.as_ref(), op)
+    }
 
-                    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
-                    where
-                        E: de::Error
-                    {
-                        match value {
-                            "count" => Ok(LocalField::Count),
-                            "store" => Ok(LocalField::Store),
-                            _ => Err(de::Error::unknown_field(value, STRUCT_FIELDS)),
-                        }
-                    }
-                }
+    /// counts a change to what is saved with the store in
+    /// [`generation`](Local::generation). [`notify`](Local::notify) does
+    /// this for every change it is given.
+    fn changed(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
 
-                deserializer.deserialize_identifier(LocalFieldVisitor)
-            }
-        }
+    fn notify(&self, change: Change) {
+        self.changed();
 
-        struct LocalVisitor<KeyType> {
-            _key: PhantomData<KeyType>
+        if let Some(on_change) = &self.config.on_change {
+            on_change(change);
         }
 
-        impl<'de, KeyType> Visitor<'de> for LocalVisitor<KeyType>
-        where
-            KeyType: Deserialize<'de>
-        {
-            type Value = Local<KeyType>;
+        // copied out so an observer can add another without waiting on
+        // the lock it is called under
+        let observers = self.observers.read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
 
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("struct Local")
-            }
+        for observer in observers {
+            observer(change);
+        }
 
-            fn visit_seq<V>(self, mut seq: V) -> Result<Self::Value, V::Error>
-            where
-                V: SeqAccess<'de>
-            {
-                let count = seq.next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
-                let store = seq.next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        // sending never blocks and a receiver that is gone drops its sender
+        self.subscribers.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|sender| sender.send(change).is_ok());
+    }
 
-                Ok(Local { count, store })
+    /// gives each evicted key to `on_evict` and notifies the drop
+    fn notify_evicted(&self, evicted: Evicted<KeyType>) {
+        for (version, key) in evicted.0 {
+            if let Some(on_evict) = &self.on_evict {
+                on_evict(version, key);
             }
 
-            fn visit_map<V>(self, mut map: V) -> Result<Self::Value, V::Error>
-            where
-                V: MapAccess<'de>
-            {
-                let mut count = None;
-                let mut store = None;
+            self.notify(Change::Dropped(version));
+        }
+    }
 
-                while let Some(key) = map.next_key()? {
-                    match key {
-                        LocalField::Count => {
-                            if count.is_some() {
-                                return Err(de::Error::duplicate_field("count"));
-                            }
+    pub(crate) fn from_parts(parts: Parts<KeyType>) -> Self {
+        let Parts { count, store, accessed, mut pending, mut reserved, mut tombstones, mut staged, mut disabled, mut meta } = parts;
 
-                            count = Some(map.next_value()?);
-                        }
-                        LocalField::Store => {
-                            if store.is_some() {
-                                return Err(de::Error::duplicate_field("store"));
-                            }
+        let count = healed_count(count, &store, &reserved, &tombstones);
 
-                            store = Some(map.next_value()?);
-                        }
-                    }
-                }
+        let accessed = accessed.into_iter()
+            .filter(|(version, _)| store.contains_key(version))
+            .map(|(version, times)| (version, Access::new(times)))
+            .collect();
 
-                let count = count.ok_or_else(|| de::Error::missing_field("count"))?;
-                let store = store.ok_or_else(|| de::Error::missing_field("store"))?;
+        pending.retain(|version, _| store.contains_key(version));
+        reserved.retain(|version, _| !store.contains_key(version));
+        tombstones.retain(|version, _| !store.contains_key(version));
+        staged.retain(|version| store.contains_key(version));
+        disabled.retain(|version| store.contains_key(version));
+        meta.retain(|version, meta| store.contains_key(version) && !meta.is_empty());
 
-                Ok(Local { count, store })
-            }
+        Local {
+            store: RwLock::new(store),
+            count: Mutex::new(count),
+            accessed: RwLock::new(accessed),
+            pending: RwLock::new(pending),
+            reserved: RwLock::new(reserved),
+            tombstones: RwLock::new(tombstones),
+            staged: RwLock::new(staged),
+            disabled: RwLock::new(disabled),
+            meta: RwLock::new(meta),
+            frozen: AtomicUsize::new(0),
+            generation: AtomicU64::new(0),
+            config: Config::default(),
+            on_evict: None,
+            recent: None,
+            observers: RwLock::new(Vec::new()),
+            subscribers: Mutex::new(Vec::new()),
         }
+    }
 
-        deserializer.deserialize_struct(
-            "Local",
-            STRUCT_FIELDS,
-            LocalVisitor {
-                _key: PhantomData
+    /// the store of parts that were read from a file. a counter below the
+    /// highest version that was handed out fails with
+    /// [`Error::CountTooLow`] unless `heal_count` is set, which raises it.
+    pub(crate) fn load_parts(parts: Parts<KeyType>, heal_count: bool) -> Result<Self, Error> {
+        if !heal_count {
+            let highest = healed_count(0, &parts.store, &parts.reserved, &parts.tombstones);
+
+            if highest > parts.count {
+                return Err(Error::CountTooLow {
+                    count: parts.count,
+                    highest,
+                });
             }
-        )
+        }
+
+        Ok(Local::from_parts(parts))
     }
-}
 
-#[cfg(test)]
-pub(crate) mod test {
-    use super::*;
+    pub(crate) fn serialize_with(&self, options: SerializeOptions) -> SerializeWith<'_, KeyType> {
+        SerializeWith {
+            local: self,
+            options,
+            failed: Cell::new(None),
+        }
+    }
 
-    pub type TestLocal = Local<u64>;
+    pub fn store_reader(&self) -> Result<StoreReader<'_, KeyType>, Error> {
+        Ok(StoreReader {
+            guard: self.store.read()?,
+        })
+    }
 
-    pub fn assert_local_eq<K>(a: &Local<K>, b: &Local<K>)
-    where
-        K: std::cmp::PartialEq + std::fmt::Debug
-    {
-        {
-            let count_a = a.count.lock().unwrap();
-            let count_b = b.count.lock().unwrap();
+    /// the counter and a reader for the store, taken together so the
+    /// counter is at least the highest version in the store. anything else
+    /// saved with them that holds versions, e.g. reservations, must be read
+    /// before this since the counter only goes up while the store is read.
+    pub(crate) fn counted_store(&self) -> Result<(u64, StoreReader<'_, KeyType>), Error> {
+        // the counter is locked before the store, as everywhere else
+        let count_lock = self.count.lock()?;
+        let guard = self.store.read()?;
 
-            assert_eq!(*count_a, *count_b, "counts are not equal");
-        }
+        Ok((*count_lock, StoreReader { guard }))
+    }
 
-        {
-            let store_a = a.store.read().unwrap();
-            let store_b = b.store.read().unwrap();
+    /// the counter, the highest version that was ever handed out. it does
+    /// not go down when versions are dropped, use [`len`](Local::len) or
+    /// [`stats`](Local::stats) for the number of keys.
+    pub fn count(&self) -> Result<u64, Error> {
+        let count_lock = self.count.lock()?;
 
-            assert_eq!(*store_a, *store_b, "stores are not equal");
-        }
+        Ok(*count_lock)
     }
 
-    pub fn create_store() -> TestLocal {
-        let local = Local::new();
-        let values = [0, 1, 2, 4, 5, 9, 11, 12, 16, 17, 22, 26];
+    /// goes up with every change to what is saved with the store: each
+    /// change given to `on_change`, e.g. a key added or dropped, as well as
+    /// reservations, scheduled drops, metadata and a counter raised by
+    /// [`reconcile`](Local::reconcile) or [`recover`](Local::recover). it
+    /// does not go up on reads, including the access times they record.
+    /// it is not saved so a loaded store starts at 0. comparing it with the
+    /// value at the last save tells if the store changed since.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
 
-        for v in &values {
-            local.update(*v).expect("failed to add value");
-        }
+    /// the versions currently in the store in ascending order, without
+    /// copying any keys. staged versions are included and dropped versions
+    /// are not.
+    pub fn versions(&self) -> Result<Vec<u64>, Error> {
+        let store_reader = self.store.read()?;
 
-        local
+        Ok(store_reader.keys().copied().collect())
     }
 
-    #[test]
-    fn serde() {
-        let local = create_store();
+    /// if the version is in the store, without cloning its key. staged
+    /// versions are included.
+    pub fn contains_version(&self, version: &u64) -> Result<bool, Error> {
+        Ok(self.store.read()?.contains_key(version))
+    }
 
-        let to_json = serde_json::to_string(&local)
-            .expect("failed to serialize Local to json string");
+    /// the number of keys in the store. unlike [`count`](Local::count) this
+    /// goes down when versions are dropped.
+    pub fn len(&self) -> Result<usize, Error> {
+        Ok(self.store.read()?.len())
+    }
 
-        let and_back: TestLocal = serde_json::from_str(&to_json)
-            .expect("failed to deserialize Local from json string");
+    pub fn is_empty(&self) -> Result<bool, Error> {
+        Ok(self.store.read()?.is_empty())
+    }
 
-        assert_local_eq(&local, &and_back)
+    /// the counter, number of keys, oldest and latest version and last
+    /// access times read under one lock so they agree with each other
+    pub fn stats(&self) -> Result<StoreStats, Error> {
+        let version_lock = self.count.lock()?;
+        let store_reader = self.store.read()?;
+        let oldest_version = self.oldest_entry(&store_reader)?.map(|(version, _)| *version);
+        let latest_version = self.latest_entry(&store_reader)?.map(|(version, _)| *version);
+        let last_accessed = self.accessed.read()?
+            .iter()
+            .map(|(version, access)| (*version, access.last.load(Ordering::Relaxed)))
+            .collect();
+
+        Ok(StoreStats {
+            count: *version_lock,
+            live_keys: store_reader.len(),
+            oldest_version,
+            latest_version,
+            last_accessed,
+        })
+    }
+
+    /// adds the key as a new version and returns the version it was given.
+    /// the version is taken under the same lock that inserts the key, so it
+    /// can be logged without a racy call to `latest_version`.
+    pub fn update(&self, key: KeyType) -> Result<u64, Error> {
+        let _timer = self.timer(Op::Update);
+
+        let result = self.insert(key);
+
+        match &result {
+            Ok(version) => self.record(Op::Update, Some(*version), Outcome::Ok),
+            Err(_) => self.record(Op::Update, None, Outcome::Failed),
+        }
+
+        result
+    }
+
+    /// [`Local::update`] that only adds the key if the version `latest`
+    /// would return is `expected_latest`, `None` meaning there is no such
+    /// version. the check and the insert happen under the same lock so of
+    /// two callers expecting the same version only one succeeds, the other
+    /// gets [`Error::VersionConflict`].
+    pub fn update_if_latest(&self, expected_latest: Option<u64>, key: KeyType) -> Result<u64, Error> {
+        let _timer = self.timer(Op::Update);
+
+        let result = self.insert_if_latest(expected_latest, key);
+
+        match &result {
+            Ok(version) => self.record(Op::Update, Some(*version), Outcome::Ok),
+            Err(_) => self.record(Op::Update, None, Outcome::Failed),
+        }
+
+        result
+    }
+
+    fn insert_if_latest(&self, expected_latest: Option<u64>, key: KeyType) -> Result<u64, Error> {
+        let version_lock = self.count.lock()?;
+        let store_writer = self.store.write()?;
+
+        let actual = self.latest_entry(&store_writer)?.map(|(version, _)| *version);
+
+        if actual != expected_latest {
+            return Err(Error::VersionConflict {
+                expected: expected_latest,
+                actual,
+            });
+        }
+
+        self.insert_locked(version_lock, store_writer, key, Added::default())
+    }
+
+    /// adds the key returned by `f` as a new version, returning the version.
+    /// `f` is given the key `latest` would return, if any, and is called
+    /// while the counter and the store are locked so no other key can be
+    /// added in between. it must not call back into the store. `f` is not
+    /// called when the store is frozen.
+    pub fn rotate_with<F>(&self, f: F) -> Result<u64, Error>
+    where
+        F: FnOnce(Option<&KeyType>) -> KeyType
+    {
+        let _timer = self.timer(Op::Update);
+
+        let result = self.insert_rotated(f);
+
+        match &result {
+            Ok(version) => self.record(Op::Update, Some(*version), Outcome::Ok),
+            Err(_) => self.record(Op::Update, None, Outcome::Failed),
+        }
+
+        result
+    }
+
+    fn insert_rotated<F>(&self, f: F) -> Result<u64, Error>
+    where
+        F: FnOnce(Option<&KeyType>) -> KeyType
+    {
+        let version_lock = self.count.lock()?;
+        let store_writer = self.store.write()?;
+
+        self.check_frozen()?;
+
+        let key = f(self.latest_entry(&store_writer)?.map(|(_, key)| key));
+
+        self.insert_locked(version_lock, store_writer, key, Added::default())
+    }
+
+    /// adds the keys as consecutive versions under a single lock of the
+    /// counter and the store, returning the versions in the order the keys
+    /// were given. the end result is the same as calling `update` for each
+    /// key, including evictions over `max_versions`. nothing is changed
+    /// for an empty iterator.
+    pub fn update_many<I>(&self, keys: I) -> Result<Vec<u64>, Error>
+    where
+        I: IntoIterator<Item = KeyType>
+    {
+        let _timer = self.timer(Op::Update);
+
+        let mut keys = keys.into_iter().peekable();
+
+        if keys.peek().is_none() {
+            return Ok(Vec::new());
+        }
+
+        let (versions, evicted) = {
+            let mut version_lock = self.count.lock()?;
+            let mut store_writer = self.store.write()?;
+
+            if let Err(e) = self.check_frozen() {
+                self.record(Op::Update, None, Outcome::Failed);
+
+                return Err(e);
+            }
+
+            let keys: Vec<KeyType> = keys.collect();
+            let mut versions = Vec::with_capacity(keys.len());
+            let mut last = *version_lock;
+
+            for _ in &keys {
+                last = match next_version(last) {
+                    Ok(version) => version,
+                    Err(e) => {
+                        self.record(Op::Update, None, Outcome::Failed);
+
+                        return Err(e);
+                    }
+                };
+
+                versions.push(last);
+            }
+
+            store_writer.extend(versions.iter().copied().zip(keys));
+
+            let evicted = if self.config.max_versions.is_some_and(|max| store_writer.len() > max) {
+                let mut accessed_writer = self.accessed.write()?;
+                let mut pending_writer = self.pending.write()?;
+
+                self.evict_over_max(&mut store_writer, &mut accessed_writer, &mut pending_writer)?
+            } else {
+                Evicted(Vec::new())
+            };
+
+            *version_lock = last;
+
+            (versions, evicted)
+        };
+
+        for version in &versions {
+            if !evicted.contains(version) {
+                self.notify(Change::Updated(*version));
+            }
+
+            self.record(Op::Update, Some(*version), Outcome::Ok);
+        }
+
+        self.notify_evicted(evicted);
+
+        Ok(versions)
+    }
+
+    /// appends every key of `other` in version order as new versions of
+    /// this store, returning the old and new version of each key. `other`
+    /// is consumed so its keys only live on here. only the keys are moved,
+    /// staged keys of `other` are added like any other key. nothing is
+    /// changed when `other` is empty.
+    pub fn merge(&self, other: Local<KeyType>) -> Result<Vec<(u64, u64)>, Error> {
+        let store = other.store.into_inner()?;
+        let old: Vec<u64> = store.keys().copied().collect();
+        let new = self.update_many(store.into_values())?;
+
+        Ok(old.into_iter().zip(new).collect())
+    }
+
+    /// adds the key as a new version and returns the version it was given
+    pub(crate) fn insert(&self, key: KeyType) -> Result<u64, Error> {
+        self.insert_with(key, Added::default())
+    }
+
+    /// [`Local::insert`] that can add the version as staged or with
+    /// metadata
+    fn insert_with(&self, key: KeyType, added: Added) -> Result<u64, Error> {
+        let version_lock = self.count.lock()?;
+        let store_writer = self.store.write()?;
+
+        self.insert_locked(version_lock, store_writer, key, added)
+    }
+
+    /// [`Local::insert_with`] for callers that already hold the counter and
+    /// the store. both are released before `on_change` is called.
+    fn insert_locked(
+        &self,
+        mut version_lock: MutexGuard<'_, u64>,
+        mut store_writer: RwLockWriteGuard<'_, BTreeMap<u64, KeyType>>,
+        key: KeyType,
+        added: Added
+    ) -> Result<u64, Error> {
+        self.check_frozen()?;
+
+        let new_version = next_version(*version_lock)?;
+
+        store_writer.insert(new_version, key);
+
+        let evicted = if self.config.max_versions.is_some_and(|max| store_writer.len() > max) {
+            let mut accessed_writer = self.accessed.write()?;
+            let mut pending_writer = self.pending.write()?;
+
+            self.evict_over_max(&mut store_writer, &mut accessed_writer, &mut pending_writer)?
+        } else {
+            Evicted(Vec::new())
+        };
+
+        if !evicted.contains(&new_version) {
+            if added.staged {
+                self.staged.write()?.insert(new_version);
+            }
+
+            if !added.meta.is_empty() {
+                self.meta.write()?.insert(new_version, added.meta);
+            }
+        }
+
+        drop(store_writer);
+
+        *version_lock = new_version;
+
+        drop(version_lock);
+
+        self.notify(Change::Updated(new_version));
+        self.notify_evicted(evicted);
+
+        Ok(new_version)
+    }
+
+    /// removes the oldest versions while there are more than `max_versions`,
+    /// for callers that already hold the write locks
+    fn evict_over_max(
+        &self,
+        store: &mut BTreeMap<u64, KeyType>,
+        accessed: &mut BTreeMap<u64, Access>,
+        pending: &mut BTreeMap<u64, u64>,
+    ) -> Result<Evicted<KeyType>, Error> {
+        let mut evicted = Vec::new();
+
+        if let Some(max) = self.config.max_versions {
+            while store.len() > max {
+                let Some(entry) = store.pop_first() else {
+                    break;
+                };
+
+                accessed.remove(&entry.0);
+                pending.remove(&entry.0);
+                evicted.push(entry);
+            }
+        }
+
+        if !evicted.is_empty() {
+            let mut tombstones_writer = self.tombstones.write()?;
+            let mut staged_writer = self.staged.write()?;
+            let mut disabled_writer = self.disabled.write()?;
+            let mut meta_writer = self.meta.write()?;
+            let now = unix_now();
+
+            for (version, _) in &evicted {
+                staged_writer.remove(version);
+                disabled_writer.remove(version);
+                meta_writer.remove(version);
+
+                self.bury(&mut tombstones_writer, *version, Tombstone::evicted(now));
+            }
+        }
+
+        Ok(Evicted(evicted))
+    }
+
+    /// evicts the oldest versions of a store that is over `max_versions`,
+    /// e.g. one that was loaded before the cap was set
+    fn enforce_max_versions(&self) -> Result<(), Error> {
+        let evicted = {
+            let mut store_writer = self.store.write()?;
+            let mut accessed_writer = self.accessed.write()?;
+            let mut pending_writer = self.pending.write()?;
+
+            self.evict_over_max(&mut store_writer, &mut accessed_writer, &mut pending_writer)?
+        };
+
+        self.notify_evicted(evicted);
+
+        Ok(())
+    }
+
+    pub fn drop(&self, version: &u64) -> Result<Option<KeyType>, Error> {
+        let result = self.check_confirmed(version, None)
+            .and_then(|note| self.remove(version, note));
+
+        self.record_lookup(Op::Drop, Some(*version), &result);
+
+        result
+    }
+
+    /// drops the version and leaves a tombstone with the note
+    fn remove(&self, version: &u64, note: Option<String>) -> Result<Option<KeyType>, Error> {
+        let _timer = self.timer(Op::Drop);
+
+        let removed = {
+            let mut store_writer = self.store.write()?;
+
+            self.check_frozen()?;
+
+            let removed = store_writer.remove(version);
+
+            if removed.is_some() {
+                self.accessed.write()?.remove(version);
+                self.pending.write()?.remove(version);
+
+                let mut tombstones_writer = self.tombstones.write()?;
+
+                self.bury(&mut tombstones_writer, *version, Tombstone::dropped(unix_now(), note));
+                self.staged.write()?.remove(version);
+                self.disabled.write()?.remove(version);
+                self.meta.write()?.remove(version);
+            }
+
+            removed
+        };
+
+        if removed.is_some() {
+            self.notify(Change::Dropped(*version));
+        }
+
+        Ok(removed)
+    }
+
+    /// removes the keys taken out of the store by `take` under a single
+    /// lock, leaving a tombstone for each and keeping the counter as is
+    fn remove_taken<F>(&self, take: F) -> Result<BTreeMap<u64, KeyType>, Error>
+    where
+        F: FnOnce(&mut BTreeMap<u64, KeyType>) -> BTreeMap<u64, KeyType>
+    {
+        let _timer = self.timer(Op::Drop);
+
+        let removed = {
+            let mut store_writer = self.store.write()?;
+
+            self.check_frozen()?;
+
+            let removed = take(&mut store_writer);
+
+            if !removed.is_empty() {
+                self.accessed.write()?.retain(|v, _| !removed.contains_key(v));
+                self.pending.write()?.retain(|v, _| !removed.contains_key(v));
+
+                let mut tombstones_writer = self.tombstones.write()?;
+                let now = unix_now();
+
+                for version in removed.keys() {
+                    self.bury(&mut tombstones_writer, *version, Tombstone::dropped(now, None));
+                }
+
+                self.staged.write()?.retain(|v| !removed.contains_key(v));
+                self.disabled.write()?.retain(|v| !removed.contains_key(v));
+                self.meta.write()?.retain(|v, _| !removed.contains_key(v));
+            }
+
+            removed
+        };
+
+        for version in removed.keys() {
+            self.notify(Change::Dropped(*version));
+            self.record(Op::Drop, Some(*version), Outcome::Ok);
+        }
+
+        Ok(removed)
+    }
+
+    /// drops every version below `version` under a single lock and returns
+    /// the removed keys, oldest first. the counter is left alone so new
+    /// versions keep counting up from where they were.
+    ///
+    /// each removed version gets a tombstone. like
+    /// [`drop_unchecked`](Local::drop_unchecked) this does not ask for
+    /// confirmation when the store requires confirmed drops.
+    pub fn prune_before(&self, version: &u64) -> Result<Vec<VersionedKey<KeyType>>, Error> {
+        let removed = self.remove_taken(|store| {
+            let kept = store.split_off(version);
+
+            std::mem::replace(store, kept)
+        })?;
+
+        Ok(removed.into_iter()
+            .map(|(version, key)| VersionedKey(version, key))
+            .collect())
+    }
+
+    /// drops every version whose key `f` returns false for under a single
+    /// lock and returns how many were dropped. the counter is left alone.
+    ///
+    /// removed versions get tombstones and, like
+    /// [`prune_before`](Local::prune_before), no confirmation is asked for.
+    pub fn retain<F>(&self, mut f: F) -> Result<usize, Error>
+    where
+        F: FnMut(&u64, &KeyType) -> bool
+    {
+        let removed = self.remove_taken(|store| {
+            let versions: Vec<u64> = store.iter()
+                .filter(|(version, key)| !f(version, key))
+                .map(|(version, _)| *version)
+                .collect();
+
+            versions.into_iter()
+                .filter_map(|version| store.remove_entry(&version))
+                .collect()
+        })?;
+
+        Ok(removed.len())
+    }
+
+    /// drops every key under a single lock and returns them, oldest first,
+    /// so they can be wiped. the counter is not reset so versions are never
+    /// handed out again.
+    ///
+    /// removed versions get tombstones and, like
+    /// [`prune_before`](Local::prune_before), no confirmation is asked for.
+    pub fn clear(&self) -> Result<Vec<KeyType>, Error> {
+        let removed = self.remove_taken(std::mem::take)?;
+
+        Ok(removed.into_values().collect())
+    }
+
+    /// [`clear`](Local::clear) that also returns the version of each key
+    pub fn drain(&self) -> Result<Vec<(u64, KeyType)>, Error> {
+        let removed = self.remove_taken(std::mem::take)?;
+
+        Ok(removed.into_iter().collect())
+    }
+
+    /// consumes the store and returns every key with its version, oldest
+    /// first. the keys are returned even if the store was poisoned.
+    pub fn into_entries(self) -> Vec<(u64, KeyType)> {
+        self.store.into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+            .into_iter()
+            .collect()
+    }
+
+    /// marks a version for removal once `after` has elapsed.
+    ///
+    /// the version can still be retrieved with `get` until it is removed by
+    /// [`Local::process_pending`] but it will never be returned by `latest`.
+    /// scheduling a version that is already pending replaces its deadline.
+    pub fn schedule_drop(&self, version: &u64, after: Duration) -> Result<(), Error> {
+        self.schedule_drop_at(version, unix_now().saturating_add(after.as_secs()))
+    }
+
+    /// same as [`Local::schedule_drop`] but with the time of removal given
+    /// in seconds since the unix epoch.
+    pub fn schedule_drop_at(&self, version: &u64, destroy_at: u64) -> Result<(), Error> {
+        let store_reader = self.store.read()?;
+
+        self.check_frozen()?;
+
+        if !store_reader.contains_key(version) {
+            return Err(Error::VersionNotFound(*version));
+        }
+
+        self.pending.write()?.insert(*version, destroy_at);
+        self.changed();
+
+        Ok(())
+    }
+
+    /// removes the pending drop for a version. returns true if the version
+    /// was pending.
+    pub fn cancel_drop(&self, version: &u64) -> Result<bool, Error> {
+        let _store_reader = self.store.read()?;
+
+        self.check_frozen()?;
+
+        let mut pending_writer = self.pending.write()?;
+
+        let removed = pending_writer.remove(version).is_some();
+
+        if removed {
+            self.changed();
+        }
+
+        Ok(removed)
+    }
+
+    /// the versions that are pending removal along with when they will be
+    /// removed in seconds since the unix epoch
+    pub fn pending_drops(&self) -> Result<BTreeMap<u64, u64>, Error> {
+        Ok(self.pending.read()?.clone())
+    }
+
+    pub fn is_pending_drop(&self, version: &u64) -> Result<bool, Error> {
+        Ok(self.pending.read()?.contains_key(version))
+    }
+
+    /// removes every pending version whose deadline is at or before `now`,
+    /// in seconds since the unix epoch, returning them in version order so
+    /// they can be disposed of.
+    pub fn process_pending(&self, now: u64) -> Result<Vec<(u64, KeyType)>, Error> {
+        let mut removed = Vec::new();
+
+        {
+            let mut store_writer = self.store.write()?;
+
+            self.check_frozen()?;
+
+            let mut accessed_writer = self.accessed.write()?;
+            let mut pending_writer = self.pending.write()?;
+            let mut tombstones_writer = self.tombstones.write()?;
+            let mut staged_writer = self.staged.write()?;
+            let mut disabled_writer = self.disabled.write()?;
+            let mut meta_writer = self.meta.write()?;
+
+            let due: Vec<u64> = pending_writer.iter()
+                .filter(|(_, destroy_at)| **destroy_at <= now)
+                .map(|(version, _)| *version)
+                .collect();
+
+            for version in due {
+                pending_writer.remove(&version);
+                accessed_writer.remove(&version);
+                staged_writer.remove(&version);
+                disabled_writer.remove(&version);
+                meta_writer.remove(&version);
+
+                if let Some(key) = store_writer.remove(&version) {
+                    self.bury(&mut tombstones_writer, version, Tombstone::dropped(now, None));
+
+                    removed.push((version, key));
+                }
+            }
+        }
+
+        for (version, _) in &removed {
+            self.notify(Change::Dropped(*version));
+        }
+
+        Ok(removed)
+    }
+
+    /// keeps only the listed versions and renumbers them from 1 in their
+    /// current order, resetting the counter to the number kept. access times,
+    /// pending drops, staged and disabled versions and metadata move with
+    /// their versions.
+    ///
+    /// every listed version must exist otherwise nothing is changed. the
+    /// returned map is needed to rewrite anything that records old versions,
+    /// such as ciphertext tagged with [`crate::crypto::tag_version`].
+    ///
+    /// abandoned reservations and tombstones are forgotten since their gaps
+    /// are closed. an outstanding reservation fails with [`Error::Conflict`]
+    /// as its version could be reused. a successful compaction is sent to
+    /// observers as a single [`Change::Compacted`].
+    pub fn compact(&self, keep: &[u64]) -> Result<CompactionMap, Error> {
+        let _timer = self.timer(Op::Compact);
+
+        let result = self.compact_versions(keep);
+
+        match &result {
+            Ok(map) => {
+                self.record(Op::Compact, None, Outcome::Ok);
+                self.notify(Change::Compacted(map.len() as u64));
+            }
+            Err(_) => self.record(Op::Compact, None, Outcome::Failed),
+        }
+
+        result
+    }
+
+    fn compact_versions(&self, keep: &[u64]) -> Result<CompactionMap, Error> {
+        let mut version_lock = self.count.lock()?;
+        let mut store_writer = self.store.write()?;
+
+        self.check_frozen()?;
+
+        let mut accessed_writer = self.accessed.write()?;
+        let mut pending_writer = self.pending.write()?;
+        let mut reserved_writer = self.reserved.write()?;
+        let mut tombstones_writer = self.tombstones.write()?;
+        let mut staged_writer = self.staged.write()?;
+        let mut disabled_writer = self.disabled.write()?;
+        let mut meta_writer = self.meta.write()?;
+
+        let outstanding = reserved_writer.iter()
+            .find(|(_, state)| **state == Reserved::Outstanding);
+
+        if let Some((version, _)) = outstanding {
+            return Err(Error::Conflict(*version));
+        }
+
+        let mut kept: Vec<u64> = keep.to_vec();
+        kept.sort_unstable();
+        kept.dedup();
+
+        if let Some(missing) = kept.iter().find(|v| !store_writer.contains_key(v)) {
+            return Err(Error::VersionNotFound(*missing));
+        }
+
+        let map: BTreeMap<u64, u64> = kept.iter()
+            .enumerate()
+            .map(|(index, old)| (*old, index as u64 + 1))
+            .collect();
+
+        let store = std::mem::take(&mut *store_writer);
+        let accessed = std::mem::take(&mut *accessed_writer);
+        let pending = std::mem::take(&mut *pending_writer);
+        let staged = std::mem::take(&mut *staged_writer);
+        let disabled = std::mem::take(&mut *disabled_writer);
+        let meta = std::mem::take(&mut *meta_writer);
+
+        *store_writer = store.into_iter()
+            .filter_map(|(old, key)| map.get(&old).map(|new| (*new, key)))
+            .collect();
+        *accessed_writer = accessed.into_iter()
+            .filter_map(|(old, access)| map.get(&old).map(|new| (*new, access)))
+            .collect();
+        *pending_writer = pending.into_iter()
+            .filter_map(|(old, at)| map.get(&old).map(|new| (*new, at)))
+            .collect();
+        *staged_writer = staged.into_iter()
+            .filter_map(|old| map.get(&old).copied())
+            .collect();
+        *disabled_writer = disabled.into_iter()
+            .filter_map(|old| map.get(&old).copied())
+            .collect();
+        *meta_writer = meta.into_iter()
+            .filter_map(|(old, meta)| map.get(&old).map(|new| (*new, meta)))
+            .collect();
+        reserved_writer.clear();
+        tombstones_writer.clear();
+        *version_lock = map.len() as u64;
+
+        Ok(CompactionMap(map))
+    }
+
+    /// the versions `latest` passes over
+    fn skipped(&self) -> Result<Skipped<'_>, Error> {
+        Ok(Skipped {
+            pending: self.pending.read()?,
+            staged: self.staged.read()?,
+            disabled: self.disabled.read()?,
+        })
+    }
+
+    /// finds the newest key that is allowed to be returned by `latest`,
+    /// skipping versions that are pending a drop, staged or disabled
+    fn latest_entry<'a>(
+        &self,
+        store: &'a BTreeMap<u64, KeyType>
+    ) -> Result<Option<(&'a u64, &'a KeyType)>, Error> {
+        self.latest_entry_until(store, u64::MAX)
+    }
+
+    /// [`latest_entry`](Local::latest_entry) of the versions at or below
+    /// `max_version`
+    fn latest_entry_until<'a>(
+        &self,
+        store: &'a BTreeMap<u64, KeyType>,
+        max_version: u64
+    ) -> Result<Option<(&'a u64, &'a KeyType)>, Error> {
+        let skipped = self.skipped()?;
+
+        Ok(store.range(..=max_version)
+            .rev()
+            .find(|(version, _)| !skipped.contains(version)))
+    }
+
+    /// finds the oldest key that is allowed to be returned by `oldest`,
+    /// skipping the same versions as `latest_entry`
+    fn oldest_entry<'a>(
+        &self,
+        store: &'a BTreeMap<u64, KeyType>
+    ) -> Result<Option<(&'a u64, &'a KeyType)>, Error> {
+        let skipped = self.skipped()?;
+
+        Ok(store.iter().find(|(version, _)| !skipped.contains(version)))
+    }
+
+    /// calls `f` with the key of `version` while the store is read locked,
+    /// for keys that should not be copied. `None` if the version is not in
+    /// the store. recorded and tracked the same as `get`.
+    ///
+    /// `f` must not add, drop or otherwise change keys of this store, that
+    /// would wait on the read lock held for `f` and never return.
+    pub fn with_key<F, R>(&self, version: &u64, f: F) -> Result<Option<R>, Error>
+    where
+        F: FnOnce(&KeyType) -> R
+    {
+        let _timer = self.timer(Op::Get);
+
+        let result = self.store.read()
+            .map_err(Error::from)
+            .map(|store_reader| store_reader.get(version).map(f));
+        let result = match result {
+            Ok(Some(value)) => self.touch(*version).map(|_| Some(value)),
+            result => result,
+        };
+
+        self.record_lookup(Op::Get, Some(*version), &result);
+
+        result
+    }
+
+    /// [`with_key`](Local::with_key) for the key `latest` would return. `f`
+    /// is also given the version of the key.
+    pub fn with_latest<F, R>(&self, f: F) -> Result<Option<R>, Error>
+    where
+        F: FnOnce(u64, &KeyType) -> R
+    {
+        let _timer = self.timer(Op::Latest);
+
+        let result = self.store.read()
+            .map_err(Error::from)
+            .and_then(|store_reader| Ok(
+                self.latest_entry(&store_reader)?.map(|(version, key)| (*version, f(*version, key)))
+            ));
+        let version = result.as_ref().ok().and_then(|found| found.as_ref().map(|found| found.0));
+        let result = result.map(|found| found.map(|(_, value)| value));
+
+        self.record_lookup(Op::Latest, version, &result);
+
+        result
+    }
+
+    /// the last time the version was fetched with `get` or `get_version`, in
+    /// seconds since the unix epoch. `None` if it was never fetched.
+    ///
+    /// access times are only kept in memory and change on every read. they
+    /// are written out by the fs wrappers on save when their options ask for
+    /// it and are restored on load if present.
+    pub fn last_accessed(&self, version: &u64) -> Result<Option<u64>, Error> {
+        let accessed_reader = self.accessed.read()?;
+
+        Ok(accessed_reader.get(version).map(|a| a.last.load(Ordering::Relaxed)))
+    }
+
+    /// the first time the version was fetched with `get` or `get_version`,
+    /// in seconds since the unix epoch. `None` if it was never fetched.
+    pub fn first_accessed(&self, version: &u64) -> Result<Option<u64>, Error> {
+        let accessed_reader = self.accessed.read()?;
+
+        Ok(accessed_reader.get(version).map(|a| a.first.load(Ordering::Relaxed)))
+    }
+
+    /// the access times of every version that has been fetched
+    pub fn access_times(&self) -> Result<BTreeMap<u64, AccessTimes>, Error> {
+        let accessed_reader = self.accessed.read()?;
+
+        Ok(accessed_reader.iter()
+            .map(|(version, access)| (*version, access.times()))
+            .collect())
+    }
+
+    fn touch(&self, version: u64) -> Result<(), Error> {
+        if !self.config.track_usage {
+            return Ok(());
+        }
+
+        let now = unix_now();
+
+        {
+            let accessed_reader = self.accessed.read()?;
+
+            if let Some(access) = accessed_reader.get(&version) {
+                access.last.fetch_max(now, Ordering::Relaxed);
+
+                return Ok(());
+            }
+        }
+
+        let mut accessed_writer = self.accessed.write()?;
+
+        accessed_writer.entry(version)
+            .or_insert_with(|| Access::new(AccessTimes { first: now, last: now }))
+            .last
+            .fetch_max(now, Ordering::Relaxed);
+
+        Ok(())
+    }
+}
+
+impl<KeyType> Local<KeyType>
+where
+    KeyType: Clone
+{
+    /// an independent copy of everything that is saved with the store,
+    /// taken under one lock of each part. the copy can be serialized while
+    /// the original keeps changing. it is built with the same [`Config`]
+    /// and `on_evict` callback, so e.g. `max_versions` still applies, and
+    /// starts with no recorded operations. callbacks added with
+    /// [`observe`](Local::observe) and [`subscribe`](Local::subscribe)
+    /// stay with the original.
+    pub fn try_clone(&self) -> Result<Local<KeyType>, Error> {
+        self.copy(false)
+    }
+
+    /// [`try_clone`](Local::try_clone) that copies a poisoned store as it
+    /// is when `recover` is set instead of failing
+    fn copy(&self, recover: bool) -> Result<Local<KeyType>, Error> {
+        fn locked<G>(result: LockResult<G>, recover: bool) -> Result<G, Error> {
+            match result {
+                Err(err) if recover => Ok(err.into_inner()),
+                result => Ok(result?),
+            }
+        }
+
+        let version_lock = locked(self.count.lock(), recover)?;
+        let store_reader = locked(self.store.read(), recover)?;
+        let accessed = locked(self.accessed.read(), recover)?.iter()
+            .map(|(version, access)| (*version, access.times()))
+            .collect();
+
+        let mut local = Local::from_parts(Parts {
+            count: *version_lock,
+            store: store_reader.clone(),
+            accessed,
+            pending: locked(self.pending.read(), recover)?.clone(),
+            reserved: locked(self.reserved.read(), recover)?.clone(),
+            tombstones: locked(self.tombstones.read(), recover)?.clone(),
+            staged: locked(self.staged.read(), recover)?.clone(),
+            disabled: locked(self.disabled.read(), recover)?.clone(),
+            meta: locked(self.meta.read(), recover)?.clone(),
+        });
+
+        local.config = self.config.clone();
+        local.on_evict = self.on_evict.clone();
+        local.recent = (self.config.recent_ops > 0).then(|| RecentOps::new(self.config.recent_ops));
+
+        Ok(local)
+    }
+
+    pub fn get(&self, version: &u64) -> Result<Option<KeyType>, Error> {
+        Ok(self.get_version(version)?.map(|found| found.1))
+    }
+
+    /// [`get`](Local::get) that fails with [`Error::VersionNotFound`]
+    /// instead of returning `None`
+    pub fn require(&self, version: &u64) -> Result<KeyType, Error> {
+        self.get(version)?.ok_or(Error::VersionNotFound(*version))
+    }
+
+    /// [`latest_version`](Local::latest_version) that fails with
+    /// [`Error::Empty`] instead of returning `None`
+    pub fn require_latest(&self) -> Result<VersionedKey<KeyType>, Error> {
+        self.latest_version()?.ok_or(Error::Empty)
+    }
+
+    pub fn get_version(&self, version: &u64) -> Result<Option<VersionedKey<KeyType>>, Error> {
+        let _timer = self.timer(Op::Get);
+
+        let result = self.find_version(version);
+
+        self.record_lookup(Op::Get, Some(*version), &result);
+
+        result
+    }
+
+    /// the keys of `versions` in the order given, `None` for versions that
+    /// are not in the store. all of them are read under one lock, a version
+    /// given more than once gets a copy of its key each time. recorded the
+    /// same as calling `get` for each version.
+    pub fn get_many(&self, versions: &[u64]) -> Result<Vec<Option<KeyType>>, Error> {
+        let _timer = self.timer(Op::Get);
+
+        let result = self.find_many(versions);
+
+        for (index, version) in versions.iter().enumerate() {
+            let outcome = match &result {
+                Ok(found) if found[index].is_some() => Outcome::Ok,
+                Ok(_) => Outcome::NotFound,
+                Err(_) => Outcome::Failed,
+            };
+
+            self.record(Op::Get, Some(*version), outcome);
+        }
+
+        result
+    }
+
+    /// [`Local::get_many`] without the timer or the records
+    fn find_many(&self, versions: &[u64]) -> Result<Vec<Option<KeyType>>, Error> {
+        let found: Vec<_> = {
+            let store_reader = self.store.read()?;
+
+            versions.iter()
+                .map(|version| store_reader.get(version).cloned())
+                .collect()
+        };
+
+        let touched: BTreeSet<u64> = versions.iter()
+            .zip(&found)
+            .filter(|(_, key)| key.is_some())
+            .map(|(version, _)| *version)
+            .collect();
+
+        for version in touched {
+            self.touch(version)?;
+        }
+
+        Ok(found)
+    }
+
+    /// [`Local::get_version`] without the timer or the record
+    fn find_version(&self, version: &u64) -> Result<Option<VersionedKey<KeyType>>, Error> {
+        let found = {
+            let store_reader = self.store.read()?;
+
+            let Some((ver, key)) = store_reader.get_key_value(version) else {
+                return Ok(None);
+            };
+
+            VersionedKey(*ver, key.clone())
+        };
+
+        self.touch(found.0)?;
+
+        Ok(Some(found))
+    }
+
+    pub fn latest(&self) -> Result<Option<KeyType>, Error> {
+        Ok(self.latest_version()?.map(|found| found.1))
+    }
+
+    pub fn latest_version(&self) -> Result<Option<VersionedKey<KeyType>>, Error> {
+        let _timer = self.timer(Op::Latest);
+
+        let result = self.find_latest();
+        let version = result.as_ref().ok().and_then(|found| found.as_ref().map(|found| found.0));
+
+        self.record_lookup(Op::Latest, version, &result);
+
+        result
+    }
+
+    /// [`Local::latest_version`] without the timer or the record
+    fn find_latest(&self) -> Result<Option<VersionedKey<KeyType>>, Error> {
+        let store_reader = self.store.read()?;
+
+        let Some((version, key)) = self.latest_entry(&store_reader)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(VersionedKey(*version, key.clone())))
+    }
+
+    /// the latest key, or if there is none the key returned by `f` added as
+    /// a new version. the check and the insert happen under the same lock
+    /// so of two callers on an empty store only one calls `f`, the other
+    /// gets the key it added. `f` must not call back into the store.
+    pub fn latest_or_insert_with<F>(&self, f: F) -> Result<VersionedKey<KeyType>, Error>
+    where
+        F: FnOnce() -> KeyType
+    {
+        if let Some(found) = self.latest_version()? {
+            return Ok(found);
+        }
+
+        let _timer = self.timer(Op::Update);
+
+        let result = {
+            let version_lock = self.count.lock()?;
+            let store_writer = self.store.write()?;
+
+            if let Some((version, key)) = self.latest_entry(&store_writer)? {
+                return Ok(VersionedKey(*version, key.clone()));
+            }
+
+            let key = f();
+
+            self.insert_locked(version_lock, store_writer, key.clone(), Added::default())
+                .map(|version| VersionedKey(version, key))
+        };
+
+        match &result {
+            Ok(found) => self.record(Op::Update, Some(found.0), Outcome::Ok),
+            Err(_) => self.record(Op::Update, None, Outcome::Failed),
+        }
+
+        result
+    }
+
+    /// the key with the smallest version still in the store. versions
+    /// pending a drop, staged or disabled are skipped the same as with
+    /// [`latest`](Local::latest).
+    pub fn oldest(&self) -> Result<Option<KeyType>, Error> {
+        Ok(self.oldest_version()?.map(|found| found.1))
+    }
+
+    pub fn oldest_version(&self) -> Result<Option<VersionedKey<KeyType>>, Error> {
+        let store_reader = self.store.read()?;
+
+        let Some((version, key)) = self.oldest_entry(&store_reader)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(VersionedKey(*version, key.clone())))
+    }
+
+    /// up to `n` of the newest keys, newest first. versions pending a drop,
+    /// staged or disabled are skipped the same as with [`latest`](Local::latest).
+    pub fn latest_n(&self, n: usize) -> Result<Vec<VersionedKey<KeyType>>, Error> {
+        let _timer = self.timer(Op::Latest);
+
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+
+        let store_reader = self.store.read()?;
+        let skipped = self.skipped()?;
+
+        Ok(store_reader.iter()
+            .rev()
+            .filter(|(version, _)| !skipped.contains(version))
+            .take(n)
+            .map(|(version, key)| VersionedKey(*version, key.clone()))
+            .collect())
+    }
+}
+
+impl<Data> Local<Key<Data>> {
+    /// every version paired with its created timestamp, oldest first. keys
+    /// with the same timestamp are ordered by version.
+    ///
+    /// imported keys can be older than the versions around them so this can
+    /// differ from version order.
+    pub fn versions_by_created(&self) -> Result<Vec<(u64, u64)>, Error> {
+        let store_reader = self.store.read()?;
+
+        let mut rtn: Vec<(u64, u64)> = store_reader.iter()
+            .map(|(version, key)| (*version, *key.created()))
+            .collect();
+
+        // versions come out of the store in order so a stable sort keeps
+        // them ordered within the same timestamp
+        rtn.sort_by_key(|(_, created)| *created);
+
+        Ok(rtn)
+    }
+
+    /// the age of the key `latest` would return, skipping versions pending
+    /// a drop, staged or disabled
+    pub fn latest_age(&self, now: SystemTime) -> Result<Option<Duration>, Error> {
+        let store_reader = self.store.read()?;
+
+        Ok(self.latest_entry(&store_reader)?
+            .map(|(_, key)| key.age(now)))
+    }
+}
+
+impl<Data> Local<Key<Data>>
+where
+    Data: Clone
+{
+    /// the key with the newest created timestamp, which is not always the
+    /// highest version. the highest version wins when timestamps are equal
+    /// and versions pending a drop, staged or disabled are skipped the same as
+    /// `latest`.
+    pub fn latest_by_created(&self) -> Result<Option<Key<Data>>, Error> {
+        let store_reader = self.store.read()?;
+        let skipped = self.skipped()?;
+
+        let found = store_reader.iter()
+            .filter(|(version, _)| !skipped.contains(version))
+            .max_by_key(|(version, key)| (*key.created(), **version));
+
+        Ok(found.map(|(_, key)| key.clone()))
+    }
+}
+
+impl<KeyType> Default for Local<KeyType> {
+    fn default() -> Self {
+        Local::new()
+    }
+}
+
+/// a deep copy of the store, not a handle to the same one, built with the
+/// same options. the same as [`try_clone`](Local::try_clone) except that a
+/// poisoned store is copied as it is and the copy is not poisoned.
+impl<KeyType> Clone for Local<KeyType>
+where
+    KeyType: Clone
+{
+    fn clone(&self) -> Self {
+        match self.copy(true) {
+            Ok(local) => local,
+            Err(_) => unreachable!("poisoned locks are recovered"),
+        }
+    }
+}
+
+/// gives the keys versions 1 to n in the order they come, the same as
+/// adding each with `update` to a new store
+impl<KeyType> FromIterator<KeyType> for Local<KeyType> {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = KeyType>
+    {
+        let store: BTreeMap<u64, KeyType> = (1..).zip(iter).collect();
+
+        Local::from_parts(Parts::new(store.len() as u64, store))
+    }
+}
+
+/// wraps keys that already have versions without renumbering them. the
+/// counter is the highest version, version 0 fails with
+/// [`Error::InvalidVersion`].
+impl<KeyType> TryFrom<BTreeMap<u64, KeyType>> for Local<KeyType> {
+    type Error = Error;
+
+    fn try_from(store: BTreeMap<u64, KeyType>) -> Result<Self, Error> {
+        let count = store.keys().next_back().copied().unwrap_or(0);
+
+        Local::try_from(Snapshot { count, store })
+    }
+}
+
+/// the counter and keys of the snapshot as they are. fails with
+/// [`Error::InvalidVersion`] for version 0 and [`Error::CountTooLow`] for a
+/// counter below the highest version, the same as a load.
+impl<KeyType> TryFrom<Snapshot<KeyType>> for Local<KeyType> {
+    type Error = Error;
+
+    fn try_from(snapshot: Snapshot<KeyType>) -> Result<Self, Error> {
+        let Snapshot { count, store } = snapshot;
+
+        if store.contains_key(&0) {
+            return Err(Error::InvalidVersion(0));
+        }
+
+        if let Some(highest) = store.keys().next_back() {
+            if *highest > count {
+                return Err(Error::CountTooLow { count, highest: *highest });
+            }
+        }
+
+        Ok(Local::from_parts(Parts::new(count, store)))
+    }
+}
+
+/// adds the keys with [`update_many`](Local::update_many). panics if they
+/// cannot be added, e.g. when the store is poisoned.
+impl<KeyType> Extend<KeyType> for Local<KeyType> {
+    fn extend<I>(&mut self, iter: I)
+    where
+        I: IntoIterator<Item = KeyType>
+    {
+        if let Err(err) = self.update_many(iter) {
+            panic!("failed to extend store: {}", err);
+        }
+    }
+}
+
+impl<KeyType> fmt::Debug for Local<KeyType>
+where
+    KeyType: fmt::Debug
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Local")
+            .field("store", &self.store)
+            .field("count", &self.count)
+            .field("accessed", &self.accessed)
+            .field("pending", &self.pending)
+            .field("reserved", &self.reserved)
+            .field("tombstones", &self.tombstones)
+            .field("staged", &self.staged)
+            .field("disabled", &self.disabled)
+            .field("meta", &self.meta)
+            .field("frozen", &self.frozen)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+use serde::ser::{self, Serialize, Serializer, SerializeStruct, SerializeSeq, SerializeTuple, SerializeMap};
+use serde::de::{self, Deserialize, Deserializer, Visitor, MapAccess, SeqAccess};
+
+impl<KeyType> Serialize for Local<KeyType>
+where
+    KeyType: Serialize
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.serialize_with(SerializeOptions::default())
+            .serialize(serializer)
+    }
+}
+
+impl<KeyType> Serialize for Entries<'_, KeyType>
+where
+    KeyType: Serialize
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_map(Some(self.store.len()))?;
+
+        for (version, key) in self.store {
+            state.serialize_entry(version, &Entry {
+                version: *version,
+                key,
+                failed: self.failed,
+            })?;
+        }
+
+        state.end()
+    }
+}
+
+impl<KeyType> Serialize for Entry<'_, KeyType>
+where
+    KeyType: Serialize
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.key.serialize(serializer).inspect_err(|_| {
+            self.failed.set(Some(self.version));
+        })
+    }
+}
+
+/// the state of the store is read out and written as [`Fields`]
+impl<KeyType> Serialize for SerializeWith<'_, KeyType>
+where
+    KeyType: Serialize
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let accessed = if self.options.accessed {
+            Some(self.local.access_times().map_err(ser::Error::custom)?)
+        } else {
+            None
+        };
+        let pending = self.local.pending_drops().map_err(ser::Error::custom)?;
+        let reserved = self.local.reservations().map_err(ser::Error::custom)?;
+        let tombstones = self.local.tombstones().map_err(ser::Error::custom)?;
+        let staged = self.local.staged().map_err(ser::Error::custom)?;
+        let disabled = self.local.disabled().map_err(ser::Error::custom)?;
+        let meta = self.local.all_meta().map_err(ser::Error::custom)?;
+
+        let (count, store_reader) = self.local.counted_store()
+            .map_err(ser::Error::custom)?;
+
+        Fields {
+            count,
+            entries: Entries {
+                store: &store_reader,
+                failed: &self.failed,
+            },
+            accessed,
+            pending,
+            reserved,
+            tombstones,
+            staged,
+            disabled,
+            meta,
+        }.serialize(serializer)
+    }
+}
+
+/// human readable formats get a struct where optional fields are left out
+/// when they are not set. other formats get [`BINARY_TAG`] followed by a
+/// length prefixed sequence of the fields in a fixed order so that optional
+/// fields can be appended without requiring self describing input.
+///
+/// every map and set keyed by version is written in ascending numeric
+/// order of version, whatever the store keeps them in. this is part of the
+/// format so successive saves of the same store can be diffed, and is
+/// locked in by the order fixtures of the `compat` module.
+impl<KeyType> Serialize for Fields<'_, KeyType>
+where
+    KeyType: Serialize
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            let len = 2 + self.accessed.is_some() as usize
+                + !self.pending.is_empty() as usize
+                + !self.reserved.is_empty() as usize
+                + !self.tombstones.is_empty() as usize
+                + !self.staged.is_empty() as usize
+                + !self.disabled.is_empty() as usize
+                + !self.meta.is_empty() as usize;
+
+            let mut state = serializer.serialize_struct("Local", len)?;
+            state.serialize_field("count", &self.count)?;
+            state.serialize_field("store", &self.entries)?;
+
+            if let Some(accessed) = &self.accessed {
+                state.serialize_field("accessed", accessed)?;
+            }
+
+            if !self.pending.is_empty() {
+                state.serialize_field("pending", &self.pending)?;
+            }
+
+            if !self.reserved.is_empty() {
+                state.serialize_field("reserved", &self.reserved)?;
+            }
+
+            if !self.tombstones.is_empty() {
+                state.serialize_field("tombstones", &self.tombstones)?;
+            }
+
+            if !self.staged.is_empty() {
+                state.serialize_field("staged", &self.staged)?;
+            }
+
+            if !self.disabled.is_empty() {
+                state.serialize_field("disabled", &self.disabled)?;
+            }
+
+            if !self.meta.is_empty() {
+                state.serialize_field("meta", &self.meta)?;
+            }
+
+            state.end()
+        } else {
+            let mut state = serializer.serialize_tuple(2)?;
+            state.serialize_element(&BINARY_TAG)?;
+            state.serialize_element(&BinaryFields(self))?;
+            state.end()
+        }
+    }
+}
+
+impl<KeyType> Serialize for BinaryFields<'_, '_, KeyType>
+where
+    KeyType: Serialize
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let fields = self.0;
+
+        // reservations, tombstones, staged and disabled versions and
+        // metadata are only appended when there are any so stores without
+        // them keep the same bytes
+        let len = if !fields.meta.is_empty() {
+            9
+        } else if !fields.disabled.is_empty() {
+            8
+        } else if !fields.staged.is_empty() {
+            7
+        } else if !fields.tombstones.is_empty() {
+            6
+        } else if !fields.reserved.is_empty() {
+            5
+        } else {
+            4
+        };
+
+        let mut state = serializer.serialize_seq(Some(len))?;
+        state.serialize_element(&fields.count)?;
+        state.serialize_element(&fields.entries)?;
+
+        match &fields.accessed {
+            Some(accessed) => state.serialize_element(accessed)?,
+            None => state.serialize_element(&BTreeMap::<u64, AccessTimes>::new())?,
+        }
+
+        state.serialize_element(&fields.pending)?;
+
+        if len > 4 {
+            state.serialize_element(&fields.reserved)?;
+        }
+
+        if len > 5 {
+            state.serialize_element(&fields.tombstones)?;
+        }
+
+        if len > 6 {
+            state.serialize_element(&fields.staged)?;
+        }
+
+        if len > 7 {
+            state.serialize_element(&fields.disabled)?;
+        }
+
+        if len > 8 {
+            state.serialize_element(&fields.meta)?;
+        }
+
+        state.end()
+    }
+}
+
+impl<'de, KeyType> Deserialize<'de> for Parts<KeyType>
+where
+    KeyType: Deserialize<'de>
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>
+    {
+        const STRUCT_FIELDS: &[&str] = &["count", "store", "accessed", "pending", "reserved", "tombstones", "staged", "disabled", "meta"];
+
+        enum LocalField {
+            Count,
+            Store,
+            Accessed,
+            Pending,
+            Reserved,
+            Tombstones,
+            Staged,
+            Disabled,
+            Meta,
+        }
+
+        impl<'de> Deserialize<'de> for LocalField {
+            fn deserialize<D>(deserializer: D) -> Result<LocalField, D::Error>
+            where
+                D: Deserializer<'de>
+            {
+                struct LocalFieldVisitor;
+
+                impl<'de> Visitor<'de> for LocalFieldVisitor {
+                    type Value = LocalField;
+
+                    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                        formatter.write_str("'count', 'store', 'accessed', 'pending', 'reserved', 'tombstones', 'staged', 'disabled', or 'meta'")
+                    }
+
+                    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+                    where
+                        E: de::Error
+                    {
+                        match value {
+                            "count" => Ok(LocalField::Count),
+                            "store" => Ok(LocalField::Store),
+                            "accessed" => Ok(LocalField::Accessed),
+                            "pending" => Ok(LocalField::Pending),
+                            "reserved" => Ok(LocalField::Reserved),
+                            "tombstones" => Ok(LocalField::Tombstones),
+                            "staged" => Ok(LocalField::Staged),
+                            "disabled" => Ok(LocalField::Disabled),
+                            "meta" => Ok(LocalField::Meta),
+                            _ => Err(de::Error::unknown_field(value, STRUCT_FIELDS)),
+                        }
+                    }
+                }
+
+                deserializer.deserialize_identifier(LocalFieldVisitor)
+            }
+        }
+
+        struct LocalVisitor<KeyType> {
+            _key: PhantomData<KeyType>
+        }
+
+        impl<'de, KeyType> Visitor<'de> for LocalVisitor<KeyType>
+        where
+            KeyType: Deserialize<'de>
+        {
+            type Value = Parts<KeyType>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("struct Local")
+            }
+
+            fn visit_seq<V>(self, mut seq: V) -> Result<Self::Value, V::Error>
+            where
+                V: SeqAccess<'de>
+            {
+                let count = seq.next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let store = seq.next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                let mut parts = Parts::new(count, store);
+
+                if let Some(accessed) = seq.next_element()? {
+                    parts.accessed = accessed;
+                }
+
+                if let Some(pending) = seq.next_element()? {
+                    parts.pending = pending;
+                }
+
+                if let Some(reserved) = seq.next_element()? {
+                    parts.reserved = reserved;
+                }
+
+                if let Some(tombstones) = seq.next_element()? {
+                    parts.tombstones = tombstones;
+                }
+
+                if let Some(staged) = seq.next_element()? {
+                    parts.staged = staged;
+                }
+
+                if let Some(disabled) = seq.next_element()? {
+                    parts.disabled = disabled;
+                }
+
+                if let Some(meta) = seq.next_element()? {
+                    parts.meta = meta;
+                }
+
+                Ok(parts)
+            }
+
+            fn visit_map<V>(self, mut map: V) -> Result<Self::Value, V::Error>
+            where
+                V: MapAccess<'de>
+            {
+                let mut count = None;
+                let mut store = None;
+                let mut accessed = None;
+                let mut pending = None;
+                let mut reserved = None;
+                let mut tombstones = None;
+                let mut staged = None;
+                let mut disabled = None;
+                let mut meta = None;
+
+                while let Some(key) = map.next_key()? {
+                    match key {
+                        LocalField::Count => {
+                            if count.is_some() {
+                                return Err(de::Error::duplicate_field("count"));
+                            }
+
+                            count = Some(map.next_value()?);
+                        }
+                        LocalField::Store => {
+                            if store.is_some() {
+                                return Err(de::Error::duplicate_field("store"));
+                            }
+
+                            store = Some(map.next_value()?);
+                        }
+                        LocalField::Accessed => {
+                            if accessed.is_some() {
+                                return Err(de::Error::duplicate_field("accessed"));
+                            }
+
+                            accessed = Some(map.next_value()?);
+                        }
+                        LocalField::Pending => {
+                            if pending.is_some() {
+                                return Err(de::Error::duplicate_field("pending"));
+                            }
+
+                            pending = Some(map.next_value()?);
+                        }
+                        LocalField::Reserved => {
+                            if reserved.is_some() {
+                                return Err(de::Error::duplicate_field("reserved"));
+                            }
+
+                            reserved = Some(map.next_value()?);
+                        }
+                        LocalField::Tombstones => {
+                            if tombstones.is_some() {
+                                return Err(de::Error::duplicate_field("tombstones"));
+                            }
+
+                            tombstones = Some(map.next_value()?);
+                        }
+                        LocalField::Staged => {
+                            if staged.is_some() {
+                                return Err(de::Error::duplicate_field("staged"));
+                            }
+
+                            staged = Some(map.next_value()?);
+                        }
+                        LocalField::Disabled => {
+                            if disabled.is_some() {
+                                return Err(de::Error::duplicate_field("disabled"));
+                            }
+
+                            disabled = Some(map.next_value()?);
+                        }
+                        LocalField::Meta => {
+                            if meta.is_some() {
+                                return Err(de::Error::duplicate_field("meta"));
+                            }
+
+                            meta = Some(map.next_value()?);
+                        }
+                    }
+                }
+
+                let count = count.ok_or_else(|| de::Error::missing_field("count"))?;
+                let store = store.ok_or_else(|| de::Error::missing_field("store"))?;
+
+                let mut parts = Parts::new(count, store);
+                parts.accessed = accessed.unwrap_or_default();
+                parts.pending = pending.unwrap_or_default();
+                parts.reserved = reserved.unwrap_or_default();
+                parts.tombstones = tombstones.unwrap_or_default();
+                parts.staged = staged.unwrap_or_default();
+                parts.disabled = disabled.unwrap_or_default();
+                parts.meta = meta.unwrap_or_default();
+
+                Ok(parts)
+            }
+        }
+
+        /// the fields of the binary layout after the tag
+        struct TaggedFields<KeyType>(Parts<KeyType>);
+
+        impl<'de, KeyType> Deserialize<'de> for TaggedFields<KeyType>
+        where
+            KeyType: Deserialize<'de>
+        {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>
+            {
+                deserializer.deserialize_seq(LocalVisitor {
+                    _key: PhantomData
+                }).map(TaggedFields)
+            }
+        }
+
+        struct BinaryVisitor<KeyType> {
+            _key: PhantomData<KeyType>
+        }
+
+        impl<'de, KeyType> Visitor<'de> for BinaryVisitor<KeyType>
+        where
+            KeyType: Deserialize<'de>
+        {
+            type Value = Parts<KeyType>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("tagged or untagged Local")
+            }
+
+            fn visit_seq<V>(self, mut seq: V) -> Result<Self::Value, V::Error>
+            where
+                V: SeqAccess<'de>
+            {
+                let first: u64 = seq.next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+
+                if first == BINARY_TAG {
+                    let TaggedFields(parts) = seq.next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+
+                    Ok(parts)
+                } else {
+                    // saved before the tag, the counter and the store
+                    let store = seq.next_element()?
+                        .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+
+                    Ok(Parts::new(first, store))
+                }
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_struct("Local", STRUCT_FIELDS, LocalVisitor {
+                _key: PhantomData
+            })
+        } else {
+            deserializer.deserialize_tuple(2, BinaryVisitor {
+                _key: PhantomData
+            })
+        }
+    }
+}
+
+/// a counter below the highest version in the file fails with
+/// [`Error::CountTooLow`] as a serde error, since handing that version out
+/// again would replace a key. deserialize [`Healed`] to raise the counter
+/// instead.
+impl<'de, KeyType> Deserialize<'de> for Local<KeyType>
+where
+    KeyType: Deserialize<'de>
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>
+    {
+        Local::load_parts(Parts::deserialize(deserializer)?, false)
+            .map_err(de::Error::custom)
+    }
+}
+
+/// a [`Local`] deserialized with a counter below the highest version in the
+/// file raised to it, e.g. to read a file that was edited by hand. the
+/// state of versions that are not in the store is dropped the same as any
+/// other load.
+pub struct Healed<KeyType>(pub Local<KeyType>);
+
+impl<KeyType> Healed<KeyType> {
+    pub fn into_inner(self) -> Local<KeyType> {
+        self.0
+    }
+}
+
+impl<'de, KeyType> Deserialize<'de> for Healed<KeyType>
+where
+    KeyType: Deserialize<'de>
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>
+    {
+        Local::load_parts(Parts::deserialize(deserializer)?, true)
+            .map(Healed)
+            .map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    pub use crate::test_util::{assert_local_eq, sample_local};
+
+    pub type TestLocal = Local<u64>;
+
+    #[test]
+    fn serde() {
+        let local = sample_local();
+
+        let to_json = serde_json::to_string(&local)
+            .expect("failed to serialize Local to json string");
+
+        let and_back: TestLocal = serde_json::from_str(&to_json)
+            .expect("failed to deserialize Local from json string");
+
+        assert_local_eq(&local, &and_back)
+    }
+
+    #[test]
+    fn access_times() {
+        let local = sample_local();
+        let before = unix_now();
+
+        assert_eq!(local.last_accessed(&3).unwrap(), None);
+        assert_eq!(local.first_accessed(&3).unwrap(), None);
+
+        local.get(&3).unwrap().expect("missing version 3");
+
+        let first = local.first_accessed(&3).unwrap()
+            .expect("version 3 was not marked as accessed");
+        let last = local.last_accessed(&3).unwrap()
+            .expect("version 3 was not marked as accessed");
+
+        assert!(first >= before, "first access is before the get call");
+        assert!(last >= first, "last access is before first access");
+
+        local.get_version(&4).unwrap().expect("missing version 4");
+
+        assert!(local.last_accessed(&4).unwrap().is_some(), "version 4 was not marked as accessed");
+        assert_eq!(local.last_accessed(&5).unwrap(), None);
+
+        local.get(&100).unwrap();
+
+        assert_eq!(local.last_accessed(&100).unwrap(), None);
+
+        local.drop(&3).unwrap();
+
+        assert_eq!(local.last_accessed(&3).unwrap(), None);
+    }
+
+    #[test]
+    fn access_times_serde() {
+        let local = sample_local();
+
+        local.get(&2).unwrap();
+
+        let without = serde_json::to_value(&local).unwrap();
+
+        assert!(without.get("accessed").is_none(), "access times serialized by default");
+
+        let with = serde_json::to_string(&local.serialize_with(SerializeOptions {
+            accessed: true
+        })).unwrap();
+
+        let and_back: TestLocal = serde_json::from_str(&with)
+            .expect("failed to deserialize Local with access times");
+
+        assert_local_eq(&local, &and_back);
+        assert_eq!(and_back.access_times().unwrap(), local.access_times().unwrap());
+        assert_eq!(and_back.last_accessed(&1).unwrap(), None);
+    }
+
+    #[test]
+    fn scheduled_drop() {
+        let local = sample_local();
+        let now = 1_000;
+
+        local.schedule_drop_at(&12, now + 60).expect("failed to schedule drop");
+
+        assert!(local.is_pending_drop(&12).unwrap());
+        assert_eq!(local.get(&12).unwrap(), Some(26), "pending version is not readable");
+
+        let latest = local.latest_version().unwrap()
+            .expect("store has no latest version");
+
+        assert_eq!(*latest.version(), 11, "pending version returned as latest");
+
+        let removed = local.process_pending(now).unwrap();
+
+        assert!(removed.is_empty(), "removed versions before the deadline");
+        assert_eq!(local.get(&12).unwrap(), Some(26));
+
+        let removed = local.process_pending(now + 60).unwrap();
+
+        assert_eq!(removed, vec![(12, 26)]);
+        assert_eq!(local.get(&12).unwrap(), None);
+        assert!(!local.is_pending_drop(&12).unwrap());
+        assert_eq!(local.count().unwrap(), 12, "count changed after processing");
+    }
+
+    #[test]
+    fn cancel_scheduled_drop() {
+        let local = sample_local();
+
+        local.schedule_drop_at(&12, 10).expect("failed to schedule drop");
+
+        assert!(local.cancel_drop(&12).unwrap());
+        assert!(!local.cancel_drop(&12).unwrap());
+
+        assert!(local.process_pending(20).unwrap().is_empty());
+        assert_eq!(local.latest().unwrap(), Some(26));
+
+        assert!(matches!(
+            local.schedule_drop_at(&100, 10),
+            Err(Error::VersionNotFound(100))
+        ));
+    }
+
+    #[test]
+    fn scheduled_drop_serde() {
+        let local = sample_local();
+
+        local.schedule_drop_at(&3, 500).unwrap();
+        local.schedule_drop(&4, Duration::from_secs(60)).unwrap();
+
+        let to_json = serde_json::to_string(&local).unwrap();
+
+        let and_back: TestLocal = serde_json::from_str(&to_json)
+            .expect("failed to deserialize Local with pending drops");
+
+        assert_local_eq(&local, &and_back);
+        assert_eq!(and_back.pending_drops().unwrap(), local.pending_drops().unwrap());
+    }
+
+    #[test]
+    fn versions() {
+        let local: Local<u64> = Local::new();
+
+        assert!(local.versions().unwrap().is_empty());
+
+        for value in 0..6 {
+            local.update(value).unwrap();
+        }
+
+        local.drop(&2).unwrap();
+        local.drop(&5).unwrap();
+        local.stage(6).unwrap();
+
+        assert_eq!(local.versions().unwrap(), vec![1, 3, 4, 6, 7]);
+    }
+
+    #[test]
+    fn contains_version() {
+        // not Clone, so only methods without the bound are available
+        struct Opaque;
+
+        let local = Local::new();
+
+        assert!(!local.contains_version(&1).unwrap());
+
+        local.update(Opaque).unwrap();
+        local.update(Opaque).unwrap();
+        local.drop(&1).unwrap();
+
+        assert!(!local.contains_version(&1).unwrap());
+        assert!(local.contains_version(&2).unwrap());
+        assert!(!local.contains_version(&3).unwrap());
+    }
+
+    #[test]
+    fn len() {
+        let local: Local<u64> = Local::new();
+
+        assert_eq!(local.len().unwrap(), 0);
+        assert!(local.is_empty().unwrap());
+
+        for value in 0..4 {
+            local.update(value).unwrap();
+        }
+
+        local.drop(&1).unwrap();
+        local.drop(&3).unwrap();
+
+        assert_eq!(local.count().unwrap(), 4);
+        assert_eq!(local.len().unwrap(), 2);
+        assert!(!local.is_empty().unwrap());
+
+        local.drop(&2).unwrap();
+        local.drop(&4).unwrap();
+
+        assert_eq!(local.count().unwrap(), 4);
+        assert!(local.is_empty().unwrap());
+    }
+
+    #[test]
+    fn latest_n() {
+        let local: Local<u64> = Local::new();
+
+        assert!(local.latest_n(3).unwrap().is_empty());
+
+        for value in 1..=6 {
+            local.update(value * 10).unwrap();
+        }
+
+        local.drop(&5).unwrap();
+        local.drop(&3).unwrap();
+        local.stage(70).unwrap();
+
+        let found: Vec<(u64, u64)> = local.latest_n(3).unwrap()
+            .into_iter()
+            .map(|VersionedKey(version, key)| (version, key))
+            .collect();
+
+        assert_eq!(found, vec![(6, 60), (4, 40), (2, 20)]);
+        assert_eq!(local.latest_n(10).unwrap().len(), 4);
+        assert!(local.latest_n(0).unwrap().is_empty());
+        assert_eq!(local.latest_n(1).unwrap()[0].1, local.latest().unwrap().unwrap());
+    }
+
+    #[test]
+    fn oldest() {
+        let local: Local<u64> = Local::new();
+
+        assert_eq!(local.oldest().unwrap(), None);
+        assert!(local.oldest_version().unwrap().is_none());
+
+        for value in 1..=4 {
+            local.update(value * 10).unwrap();
+        }
+
+        assert_eq!(local.oldest().unwrap(), Some(10));
+
+        local.drop(&1).unwrap();
+        local.drop(&2).unwrap();
+
+        let VersionedKey(version, key) = local.oldest_version().unwrap().unwrap();
+
+        assert_eq!((version, key), (3, 30));
+
+        local.schedule_drop(&3, Duration::from_secs(60)).unwrap();
+
+        assert_eq!(local.oldest().unwrap(), Some(40));
+    }
+
+    #[test]
+    fn prune_before() {
+        let local = sample_local();
+
+        local.drop(&2).unwrap();
+        local.schedule_drop(&4, Duration::from_secs(60)).unwrap();
+
+        assert!(local.prune_before(&1).unwrap().is_empty());
+
+        let pruned: Vec<u64> = local.prune_before(&5).unwrap()
+            .into_iter()
+            .map(|VersionedKey(version, _)| version)
+            .collect();
+
+        assert_eq!(pruned, vec![1, 3, 4]);
+        assert_eq!(local.versions().unwrap(), (5..=12).collect::<Vec<u64>>());
+        assert!(local.pending_drops().unwrap().is_empty());
+        assert_eq!(local.tombstones().unwrap().keys().copied().collect::<Vec<u64>>(), vec![1, 2, 3, 4]);
+
+        // the counter is untouched
+        assert_eq!(local.count().unwrap(), 12);
+        assert_eq!(local.update(30).unwrap(), 13);
+    }
+
+    #[test]
+    fn retain() {
+        let local: Local<Key<Vec<u8>>> = Local::new();
+
+        for created in [100, 200, 300, 400, 500] {
+            let mut builder = Key::builder(vec![0; 4]);
+            builder.set_created(created);
+
+            local.update(builder.build().unwrap()).unwrap();
+        }
+
+        local.drop(&4).unwrap();
+
+        let cutoff = 250;
+        let removed = local.retain(|_, key| *key.created() >= cutoff).unwrap();
+
+        assert_eq!(removed, 2);
+        assert_eq!(local.versions().unwrap(), vec![3, 5]);
+        assert_eq!(local.tombstones().unwrap().keys().copied().collect::<Vec<u64>>(), vec![1, 2, 4]);
+        assert_eq!(local.retain(|_, _| true).unwrap(), 0);
+        assert_eq!(local.retain(|version, _| *version != 5).unwrap(), 1);
+        assert_eq!(local.count().unwrap(), 5);
+    }
+
+    #[test]
+    fn clear() {
+        let local = sample_local();
+        let old_count = local.count().unwrap();
+
+        local.drop(&3).unwrap();
+
+        let removed = local.clear().unwrap();
+
+        assert_eq!(removed.len(), 11);
+        assert_eq!(removed[0], 0);
+        assert!(local.is_empty().unwrap());
+        assert_eq!(local.latest().unwrap(), None);
+        assert_eq!(local.count().unwrap(), old_count);
+        assert_eq!(local.update(100).unwrap(), old_count + 1);
+        assert_eq!(local.clear().unwrap(), vec![100]);
+        assert!(local.clear().unwrap().is_empty());
+    }
+
+    #[test]
+    fn created_order() {
+        let local: Local<Key<u64>> = Local::new();
+        let created = [100, 50, 300, 50, 200];
+
+        for (i, ts) in created.iter().enumerate() {
+            let mut builder = Key::builder(i as u64);
+            builder.set_created(*ts);
+
+            local.update(builder.build().unwrap()).unwrap();
+        }
+
+        assert_eq!(
+            local.versions_by_created().unwrap(),
+            vec![(2, 50), (4, 50), (1, 100), (5, 200), (3, 300)]
+        );
+
+        let newest = local.latest_by_created().unwrap().unwrap();
+
+        assert_eq!(*newest.data(), 2);
+        assert_eq!(*local.latest().unwrap().unwrap().data(), 4);
+
+        local.schedule_drop_at(&3, u64::MAX).unwrap();
+
+        let newest = local.latest_by_created().unwrap().unwrap();
+
+        assert_eq!(*newest.data(), 4);
+    }
+
+    #[test]
+    fn compact() {
+        let local = sample_local();
+
+        local.get(&9).unwrap();
+        local.schedule_drop_at(&12, u64::MAX).unwrap();
+
+        assert!(matches!(local.compact(&[3, 40]), Err(Error::VersionNotFound(40))));
+        assert_eq!(local.count().unwrap(), 12, "failed compact changed the store");
+
+        let map = local.compact(&[12, 3, 9, 6]).unwrap();
+
+        assert_eq!(map.iter().collect::<Vec<_>>(), vec![(3, 1), (6, 2), (9, 3), (12, 4)]);
+        assert_eq!(map.get(&4), None);
+        assert_eq!(local.count().unwrap(), 4);
+
+        let store: Vec<(u64, u64)> = local.store_reader().unwrap()
+            .iter()
+            .map(|(v, k)| (*v, *k))
+            .collect();
+
+        assert_eq!(store, vec![(1, 2), (2, 9), (3, 16), (4, 26)]);
+        assert_eq!(local.get(&map.get(&9).unwrap()).unwrap(), Some(16));
+        assert!(local.last_accessed(&3).unwrap().is_some());
+        assert!(local.is_pending_drop(&4).unwrap());
+
+        local.update(30).unwrap();
+
+        assert_eq!(local.latest_version().unwrap().map(|k| k.0), Some(5));
+    }
+
+    #[test]
+    fn compact_notifies() {
+        let (sender, receiver) = mpsc::channel();
+        let local: TestLocal = Local::builder()
+            .on_change(move |change| sender.send(change).unwrap())
+            .build_from(sample_local())
+            .unwrap();
+
+        local.compact(&[3, 9]).unwrap();
+
+        assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![Change::Compacted(2)]);
+
+        assert!(local.compact(&[40]).is_err());
+        assert_eq!(receiver.try_iter().count(), 0, "failed compact notified");
+    }
+
+    #[test]
+    fn compact_recorded() {
+        let local: TestLocal = Local::builder()
+            .recent_ops(4)
+            .build_from(sample_local())
+            .unwrap();
+
+        local.compact(&[3, 9]).unwrap();
+        local.compact(&[40]).unwrap_err();
+
+        let recent: Vec<_> = local.recent_ops()
+            .into_iter()
+            .map(|record| (record.op, record.version, record.outcome))
+            .collect();
+
+        assert_eq!(recent, vec![
+            (Op::Compact, None, Outcome::Ok),
+            (Op::Compact, None, Outcome::Failed),
+        ]);
+    }
+
+    #[test]
+    fn concurrent_update_versions() {
+        let local: std::sync::Arc<TestLocal> = std::sync::Arc::new(Local::new());
+
+        let handles: Vec<_> = (0..8).map(|_| {
+            let local = local.clone();
+
+            std::thread::spawn(move || {
+                (0..50).map(|n| local.update(n).unwrap()).collect::<Vec<u64>>()
+            })
+        }).collect();
+
+        let mut all = Vec::new();
+
+        for handle in handles {
+            let versions = handle.join().unwrap();
+
+            assert!(versions.windows(2).all(|pair| pair[0] < pair[1]), "versions of a thread went backwards");
+
+            all.extend(versions);
+        }
+
+        all.sort_unstable();
+
+        assert_eq!(all, (1..=400).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn update_many() {
+        let build = || Local::builder().max_versions(5).with_initial_keys([1, 2]).build().unwrap();
+        let keys: Vec<u64> = (10..20).collect();
+
+        let one_by_one: TestLocal = build();
+        let expected: Vec<u64> = keys.iter().map(|key| one_by_one.update(*key).unwrap()).collect();
+
+        let batched: TestLocal = build();
+
+        assert_eq!(batched.update_many(keys).unwrap(), expected);
+        assert_local_eq(&one_by_one, &batched);
+        assert_eq!(
+            batched.tombstones().unwrap().into_keys().collect::<Vec<_>>(),
+            one_by_one.tombstones().unwrap().into_keys().collect::<Vec<_>>()
+        );
+
+        assert!(batched.update_many([]).unwrap().is_empty());
+        assert_eq!(batched.count().unwrap(), 12);
+
+        let _guard = batched.freeze();
+
+        assert!(matches!(batched.update_many([20, 21]), Err(Error::Frozen)));
+        assert_eq!(batched.count().unwrap(), 12);
+    }
+
+    #[test]
+    fn ascending_order() {
+        let local: TestLocal = Local::new();
+        let snapshot = Snapshot {
+            count: 0,
+            store: [(100, 1), (2, 2), (10, 3), (9, 4)].into(),
+        };
+
+        local.import(snapshot, ImportPolicy::Fail).unwrap();
+
+        assert_eq!(local.versions().unwrap(), vec![2, 9, 10, 100]);
+        assert_eq!(local.iter().unwrap().iter().map(|(v, _)| *v).collect::<Vec<_>>(), vec![2, 9, 10, 100]);
+
+        let json = serde_json::to_string(&local).unwrap();
+
+        assert!(json.contains(r#""store":{"2":2,"9":4,"10":3,"100":1}"#), "unexpected order: {}", json);
+    }
+
+    #[test]
+    fn update_if_latest() {
+        let local: TestLocal = Local::new();
+
+        assert!(matches!(
+            local.update_if_latest(Some(1), 10),
+            Err(Error::VersionConflict { expected: Some(1), actual: None })
+        ));
+        assert_eq!(local.update_if_latest(None, 10).unwrap(), 1);
+        assert!(matches!(
+            local.update_if_latest(None, 20),
+            Err(Error::VersionConflict { expected: None, actual: Some(1) })
+        ));
+        assert_eq!(local.update_if_latest(Some(1), 20).unwrap(), 2);
+        assert_eq!(local.count().unwrap(), 2);
+
+        local.drop(&2).unwrap();
+
+        assert_eq!(local.update_if_latest(Some(1), 30).unwrap(), 3);
+    }
+
+    #[test]
+    fn update_if_latest_race() {
+        let local: std::sync::Arc<TestLocal> = std::sync::Arc::new(Local::new());
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(8));
+
+        local.update(1).unwrap();
+
+        let handles: Vec<_> = (0..8).map(|n| {
+            let local = local.clone();
+            let barrier = barrier.clone();
+
+            std::thread::spawn(move || {
+                barrier.wait();
+
+                local.update_if_latest(Some(1), n)
+            })
+        }).collect();
+
+        let results: Vec<_> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        let rotated: Vec<_> = results.iter().filter_map(|result| result.as_ref().ok()).collect();
+
+        assert_eq!(rotated, vec![&2]);
+        assert!(results.iter().filter(|result| result.is_err()).all(|result| matches!(
+            result,
+            Err(Error::VersionConflict { expected: Some(1), actual: Some(2) })
+        )));
+        assert_eq!(local.versions().unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn rotate_with() {
+        let local: Local<Key<Vec<u8>>> = Local::new();
+
+        let generate = |previous: Option<&Key<Vec<u8>>>| {
+            let len = previous.map_or(16, |key| key.data().len());
+
+            Key::builder(vec![len as u8; len]).build().unwrap()
+        };
+
+        assert_eq!(local.rotate_with(generate).unwrap(), 1);
+
+        local.update(Key::builder(vec![0; 32]).build().unwrap()).unwrap();
+
+        assert_eq!(local.rotate_with(generate).unwrap(), 3);
+        assert_eq!(local.latest().unwrap().unwrap().data(), &vec![32; 32]);
+        assert_eq!(local.get(&1).unwrap().unwrap().data(), &vec![16; 16]);
+
+        let _guard = local.freeze();
+
+        assert!(local.rotate_with(|_| unreachable!("called while frozen")).is_err());
+    }
+
+    #[test]
+    fn merge() {
+        let local: TestLocal = Local::new();
+        let other: TestLocal = Local::new();
+
+        local.update(10).unwrap();
+        other.update(100).unwrap();
+        other.update(200).unwrap();
+        other.update(300).unwrap();
+        other.drop(&2).unwrap();
+
+        assert_eq!(local.merge(other).unwrap(), vec![(1, 2), (3, 3)]);
+        assert_eq!(local.get(&2).unwrap(), Some(100));
+        assert_eq!(local.latest().unwrap(), Some(300));
+
+        assert!(local.merge(Local::new()).unwrap().is_empty());
+        assert_eq!(local.count().unwrap(), 3);
+
+        let empty: TestLocal = Local::new();
+        let other: TestLocal = Local::new();
+
+        for key in [5, 6, 7] {
+            other.update(key).unwrap();
+        }
+
+        other.drop(&1).unwrap();
+
+        assert_eq!(empty.merge(other).unwrap(), vec![(2, 1), (3, 2)]);
+        assert_eq!(empty.versions().unwrap(), vec![1, 2]);
+        assert_eq!(empty.get(&1).unwrap(), Some(6));
+        assert_eq!(empty.latest().unwrap(), Some(7));
+    }
+
+    #[test]
+    fn drain_and_into_entries() {
+        let local: TestLocal = Local::new();
+
+        for key in [10, 20, 30] {
+            local.update(key).unwrap();
+        }
+
+        local.drop(&2).unwrap();
+
+        assert_eq!(local.drain().unwrap(), vec![(1, 10), (3, 30)]);
+        assert!(local.versions().unwrap().is_empty());
+        assert_eq!(local.count().unwrap(), 3);
+        assert_eq!(local.update(40).unwrap(), 4);
+        assert_eq!(local.drain().unwrap(), vec![(4, 40)]);
+        assert!(local.drain().unwrap().is_empty());
+
+        local.update(50).unwrap();
+        local.update(60).unwrap();
+
+        assert_eq!(local.into_entries(), vec![(5, 50), (6, 60)]);
+    }
+
+    #[test]
+    fn latest_or_insert_with() {
+        let local: std::sync::Arc<TestLocal> = std::sync::Arc::new(Local::new());
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(8));
+        let calls = std::sync::Arc::new(AtomicUsize::new(0));
+
+        let handles: Vec<_> = (0..8).map(|n| {
+            let local = local.clone();
+            let barrier = barrier.clone();
+            let calls = calls.clone();
+
+            std::thread::spawn(move || {
+                barrier.wait();
+
+                local.latest_or_insert_with(|| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+
+                    n
+                }).unwrap()
+            })
+        }).collect();
+
+        let found: Vec<_> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(local.versions().unwrap(), vec![1]);
+        assert!(found.iter().all(|other| other.0 == 1 && other.1 == found[0].1));
+
+        local.update(100).unwrap();
+
+        let found = local.latest_or_insert_with(|| unreachable!("store has a latest key")).unwrap();
+
+        assert_eq!((found.0, found.1), (2, 100));
+    }
+
+    #[test]
+    fn get_many() {
+        let local: TestLocal = Local::builder()
+            .track_usage(true)
+            .recent_ops(8)
+            .build()
+            .unwrap();
+
+        for key in [10, 20, 30] {
+            local.update(key).unwrap();
+        }
+
+        local.drop(&2).unwrap();
+
+        assert_eq!(
+            local.get_many(&[3, 2, 1, 3, 9]).unwrap(),
+            vec![Some(30), None, Some(10), Some(30), None]
+        );
+        assert!(local.get_many(&[]).unwrap().is_empty());
+        assert!(local.last_accessed(&3).unwrap().is_some());
+        assert!(local.last_accessed(&2).unwrap().is_none());
+
+        let outcomes: Vec<_> = local.recent_ops()
+            .into_iter()
+            .filter(|record| record.op == Op::Get)
+            .map(|record| (record.version, record.outcome))
+            .collect();
+
+        assert_eq!(outcomes, vec![
+            (Some(3), Outcome::Ok),
+            (Some(2), Outcome::NotFound),
+            (Some(1), Outcome::Ok),
+            (Some(3), Outcome::Ok),
+            (Some(9), Outcome::NotFound),
+        ]);
+    }
+
+    #[test]
+    fn with_key() {
+        struct Secret(Vec<u8>);
+
+        let local: Local<Secret> = Local::builder()
+            .track_usage(true)
+            .build()
+            .unwrap();
+
+        local.update(Secret(vec![1; 4])).unwrap();
+        local.update(Secret(vec![2; 8])).unwrap();
+
+        assert_eq!(local.with_key(&1, |key| key.0.len()).unwrap(), Some(4));
+        assert_eq!(local.with_key(&3, |key| key.0.len()).unwrap(), None);
+        assert!(local.last_accessed(&1).unwrap().is_some());
+        assert_eq!(local.with_latest(|version, key| (version, key.0[0])).unwrap(), Some((2, 2)));
+
+        local.drop(&2).unwrap();
+        local.drop(&1).unwrap();
+
+        assert_eq!(local.with_latest(|_, key| key.0.len()).unwrap(), None);
+    }
+
+    #[test]
+    fn from_iter_and_extend() {
+        let updated: TestLocal = Local::new();
+
+        for key in [10, 20, 30] {
+            updated.update(key).unwrap();
+        }
+
+        let mut collected: TestLocal = [10, 20].into_iter().collect();
+
+        collected.extend([30]);
+
+        assert_local_eq(&collected, &updated);
+        assert_eq!(collected.latest().unwrap(), Some(30));
+        assert_eq!(
+            serde_json::to_string(&collected).unwrap(),
+            serde_json::to_string(&updated).unwrap()
+        );
+
+        let and_back: TestLocal = serde_json::from_str(&serde_json::to_string(&collected).unwrap()).unwrap();
+
+        assert_local_eq(&collected, &and_back);
+
+        let empty: TestLocal = std::iter::empty().collect();
+
+        assert_local_eq(&empty, &TestLocal::default());
+    }
+
+    #[test]
+    fn try_from_map() {
+        let local = TestLocal::try_from(BTreeMap::from([(2, 20), (5, 50)])).unwrap();
+
+        assert_eq!(local.count().unwrap(), 5);
+        assert_eq!(local.versions().unwrap(), vec![2, 5]);
+        assert_eq!(local.update(60).unwrap(), 6);
+
+        let local = TestLocal::try_from(Snapshot {
+            count: 9,
+            store: BTreeMap::from([(2, 20), (5, 50)]),
+        }).unwrap();
+
+        assert_eq!(local.update(100).unwrap(), 10);
+        assert_eq!(TestLocal::try_from(BTreeMap::new()).unwrap().count().unwrap(), 0);
+
+        assert!(matches!(
+            TestLocal::try_from(BTreeMap::from([(0, 0), (1, 10)])),
+            Err(Error::InvalidVersion(0))
+        ));
+        assert!(matches!(
+            TestLocal::try_from(Snapshot { count: 4, store: BTreeMap::from([(2, 20), (5, 50)]) }),
+            Err(Error::CountTooLow { count: 4, highest: 5 })
+        ));
+    }
+
+    #[test]
+    fn stats() {
+        let local: TestLocal = Local::new();
+
+        assert_eq!(local.stats().unwrap(), StoreStats::default());
+
+        for key in [10, 20, 30, 40, 50] {
+            local.update(key).unwrap();
+        }
+
+        local.drop(&1).unwrap();
+        local.drop(&5).unwrap();
+        local.stage(60).unwrap();
+
+        assert_eq!(local.stats().unwrap(), StoreStats {
+            count: 6,
+            live_keys: 4,
+            oldest_version: Some(2),
+            latest_version: Some(4),
+            last_accessed: BTreeMap::new(),
+        });
+        assert_eq!(local.count().unwrap(), 6);
+        assert_eq!(local.len().unwrap(), 4);
+
+        local.get(&2).unwrap();
+        local.get(&4).unwrap();
+
+        let stats = local.stats().unwrap();
+
+        assert_eq!(stats.last_accessed.keys().copied().collect::<Vec<_>>(), vec![2, 4]);
+        assert_eq!(stats.last_accessed.get(&2).copied(), local.last_accessed(&2).unwrap());
+
+        local.compact(&[2, 4]).unwrap();
+
+        let stats = local.stats().unwrap();
+
+        assert_eq!(stats.count, 2);
+        assert_eq!(stats.live_keys, 2);
+        assert_eq!(stats.latest_version, Some(2));
+    }
+
+    #[test]
+    fn try_clone() {
+        let evicted = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = evicted.clone();
+        let local: TestLocal = Local::builder()
+            .max_versions(20)
+            .recent_ops(4)
+            .on_evict(move |version, key| recorded.lock().unwrap().push((version, key)))
+            .build_from(sample_local())
+            .unwrap();
+
+        local.get(&2).unwrap();
+        local.stage(100).unwrap();
+        local.schedule_drop_at(&3, u64::MAX).unwrap();
+        local.abandon(local.reserve().unwrap()).unwrap();
+        local.drop(&4).unwrap();
+        local.disable(&5).unwrap();
+        local.set_meta(&6, [("env".to_owned(), "prod".to_owned())].into()).unwrap();
+
+        let copy = local.try_clone().unwrap();
+
+        assert_local_eq(&copy, &local);
+        assert_eq!(copy.access_times().unwrap(), local.access_times().unwrap());
+        assert_eq!(copy.staged().unwrap(), local.staged().unwrap());
+        assert_eq!(copy.pending_drops().unwrap(), local.pending_drops().unwrap());
+        assert_eq!(copy.reservations().unwrap(), local.reservations().unwrap());
+        assert_eq!(copy.tombstones().unwrap(), local.tombstones().unwrap());
+        assert_eq!(copy.disabled().unwrap(), local.disabled().unwrap());
+        assert_eq!(copy.all_meta().unwrap(), local.all_meta().unwrap());
+        assert_eq!(copy.config().max_versions(), Some(20));
+        assert!(copy.recent_ops().is_empty(), "the copy has the recorded operations of the original");
+        assert_eq!(serde_json::to_string(&copy).unwrap(), serde_json::to_string(&local).unwrap());
+
+        // the options apply to the copy
+        let before = local.len().unwrap();
+
+        copy.update_many((0..20).map(|key| key + 300)).unwrap();
+
+        assert_eq!(copy.len().unwrap(), 20);
+        assert!(!evicted.lock().unwrap().is_empty(), "max_versions did not apply to the copy");
+        assert!(!copy.recent_ops().is_empty());
+        assert_eq!(local.len().unwrap(), before);
+
+        let local = sample_local();
+
+        local.stage(100).unwrap();
+
+        let copy = local.try_clone().unwrap();
+
+        // changes to the original do not reach the copy
+        local.update(200).unwrap();
+        local.drop(&1).unwrap();
+
+        assert_eq!(copy.count().unwrap(), 13);
+        assert_eq!(copy.get(&1).unwrap(), Some(0));
+        assert_eq!(copy.get(&14).unwrap(), None);
+        assert!(copy.tombstones().unwrap().is_empty());
+    }
+
+    #[test]
+    fn versioned_key() {
+        let found = sample_local().latest_version().unwrap().unwrap();
+
+        let json = serde_json::to_string(&found).unwrap();
+
+        assert_eq!(json, r#"{"version":12,"key":26}"#);
+
+        let and_back: VersionedKey<u64> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(and_back, found);
+
+        let mapped = found.clone().map(|key| key.to_string());
+
+        assert_eq!(mapped.version(), found.version());
+        assert_eq!(mapped.into_inner(), (12, "26".to_owned()));
+    }
+
+    #[test]
+    fn clone() {
+        let local = sample_local();
+        let copy = local.clone();
+
+        assert_local_eq(&copy, &local);
+
+        copy.update(100).unwrap();
+        copy.drop(&1).unwrap();
+
+        assert_eq!(local.count().unwrap(), 12);
+        assert_eq!(local.get(&1).unwrap(), Some(0));
+        assert_eq!(local.get(&13).unwrap(), None);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _store_writer = local.store.write().unwrap();
+
+            panic!("poison the store");
+        }));
+
+        assert!(result.is_err());
+
+        let copy = local.clone();
+
+        assert!(!copy.is_poisoned());
+        assert_local_eq(&copy, &sample_local());
+    }
+
+    #[test]
+    fn clone_keeps_max_versions() {
+        let local: TestLocal = Local::builder()
+            .max_versions(3)
+            .with_initial_keys([10, 20, 30])
+            .build()
+            .unwrap();
+
+        let copy = local.clone();
+
+        assert_eq!(copy.config().max_versions(), Some(3));
+
+        copy.update(40).unwrap();
+
+        assert_eq!(copy.versions().unwrap(), vec![2, 3, 4]);
+        assert_eq!(local.versions().unwrap(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn require() {
+        let local = sample_local();
+
+        assert_eq!(local.require(&1).unwrap(), 0);
+        assert!(matches!(local.require(&99), Err(Error::VersionNotFound(99))));
+        assert_eq!(local.require_latest().unwrap(), VersionedKey(12, 26));
+        assert!(matches!(TestLocal::new().require_latest(), Err(Error::Empty)));
+        assert_eq!(Error::VersionNotFound(99).to_string(), "VersionNotFound 99");
+    }
+
+    #[test]
+    fn counter_overflow() {
+        let local = TestLocal::try_from(Snapshot {
+            count: u64::MAX,
+            store: BTreeMap::from([(1, 10)]),
+        }).unwrap();
+
+        assert!(matches!(local.update(20), Err(Error::CounterOverflow)));
+        assert!(matches!(local.reserve(), Err(Error::CounterOverflow)));
+        assert_eq!(local.versions().unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn generation() {
+        let local = sample_local();
+
+        assert_eq!(local.generation(), 12);
+
+        local.get(&1).unwrap();
+        local.latest().unwrap();
+        local.drop(&99).unwrap();
+
+        assert_eq!(local.generation(), 12, "changed without a change");
+
+        local.update(100).unwrap();
+        local.drop(&1).unwrap();
+
+        assert_eq!(local.generation(), 14);
+
+        let frozen = local.freeze();
+
+        assert!(local.update(200).is_err());
+        assert_eq!(local.generation(), 14, "changed by a failed update");
+
+        drop(frozen);
+
+        let and_back: TestLocal = serde_json::from_str(&serde_json::to_string(&local).unwrap()).unwrap();
+
+        assert_eq!(and_back.generation(), 0);
+    }
+
+    #[test]
+    fn generation_compact() {
+        let local = sample_local();
+        let start = local.generation();
+
+        local.compact(&[3, 9]).unwrap();
+
+        assert_eq!(local.generation(), start + 1);
+
+        local.compact(&[40]).unwrap_err();
+
+        assert_eq!(local.generation(), start + 1, "changed by a failed compact");
+    }
+
+    #[test]
+    fn generation_scheduled_drops() {
+        let local = sample_local();
+        let start = local.generation();
+
+        local.schedule_drop_at(&3, u64::MAX).unwrap();
+
+        assert_eq!(local.generation(), start + 1);
+
+        assert!(local.cancel_drop(&3).unwrap());
+        assert!(!local.cancel_drop(&3).unwrap());
+
+        assert_eq!(local.generation(), start + 2, "changed by cancelling nothing");
+
+        local.schedule_drop_at(&99, u64::MAX).unwrap_err();
+        local.schedule_drop_at(&4, 0).unwrap();
+        local.process_pending(1).unwrap();
+
+        assert_eq!(local.generation(), start + 4);
+    }
+
+    #[test]
+    fn generation_meta() {
+        let local = sample_local();
+        let start = local.generation();
+        let labels: Meta = [("env".to_owned(), "prod".to_owned())].into();
+
+        local.set_meta(&3, labels.clone()).unwrap();
+
+        assert_eq!(local.generation(), start + 1);
+
+        local.set_meta(&3, labels).unwrap();
+        local.set_meta(&4, Meta::new()).unwrap();
+        local.set_meta(&99, Meta::new()).unwrap_err();
+
+        assert_eq!(local.generation(), start + 1, "changed without new metadata");
+
+        local.set_meta(&3, Meta::new()).unwrap();
+
+        assert_eq!(local.generation(), start + 2);
+    }
+
+    #[test]
+    fn generation_reservations() {
+        let local = sample_local();
+        let start = local.generation();
+
+        let abandoned = local.reserve().unwrap();
+        let fulfilled = local.reserve().unwrap();
+
+        assert_eq!(local.generation(), start + 2);
+
+        local.abandon(abandoned).unwrap();
+
+        assert_eq!(local.generation(), start + 3);
+
+        local.abandon(abandoned).unwrap_err();
+
+        assert_eq!(local.generation(), start + 3, "changed by a failed abandon");
+
+        local.fulfill(fulfilled, 100).unwrap();
+
+        assert_eq!(local.generation(), start + 4);
+    }
+
+    #[test]
+    fn generation_stage_and_disable() {
+        let local = sample_local();
+        let start = local.generation();
+
+        let staged = local.stage(100).unwrap();
+
+        local.promote(&staged).unwrap();
+        local.disable(&staged).unwrap();
+        local.disable(&staged).unwrap();
+
+        assert_eq!(local.generation(), start + 3);
+
+        local.enable(&staged).unwrap();
+
+        assert_eq!(local.generation(), start + 4);
+    }
+
+    #[test]
+    fn generation_reconcile_and_import() {
+        let local = sample_local();
+        let start = local.generation();
+
+        // only the counter is raised
+        local.reconcile(Snapshot { count: 20, store: BTreeMap::new() }, MergeStrategy::PreferLocal).unwrap();
+
+        assert_eq!(local.count().unwrap(), 20);
+        assert_eq!(local.generation(), start + 1);
+
+        local.reconcile(Snapshot { count: 20, store: BTreeMap::new() }, MergeStrategy::PreferLocal).unwrap();
+
+        assert_eq!(local.generation(), start + 1, "changed by an empty reconcile");
+
+        local.import(Snapshot { count: 0, store: BTreeMap::from([(30, 300), (31, 310)]) }, ImportPolicy::SkipExisting).unwrap();
+
+        assert_eq!(local.generation(), start + 3);
+    }
+
+    #[test]
+    fn generation_recover() {
+        let local = sample_local();
+        let start = local.generation();
+
+        local.recover().unwrap();
+
+        assert_eq!(local.generation(), start, "changed by recovering a healthy store");
+
+        // a key added by a change that panicked before it was notified
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _version_lock = local.count.lock().unwrap();
+            let mut store_writer = local.store.write().unwrap();
+
+            store_writer.insert(13, 100);
+
+            panic!("interrupted change");
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(local.generation(), start);
+
+        local.recover().unwrap();
+
+        assert_eq!(local.generation(), start + 1);
     }
 }
@@ -0,0 +1,365 @@
+use std::fmt;
+use std::sync::Arc;
+
+use std::time::Duration;
+
+use super::{Local, Error, RecentOps, DEFAULT_TOMBSTONE_RETENTION, DEFAULT_CONFIRM_WINDOW};
+use crate::hooks::Hooks;
+
+/// a change made to a [`Local`], given to the `on_change` callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// a key was added as this version
+    Updated(u64),
+    /// this version was removed by a drop, an eviction, or a scheduled drop
+    Dropped(u64),
+    /// this staged version can now be returned by `latest`
+    Promoted(u64),
+    /// this version is skipped by `latest` until it is enabled
+    Disabled(u64),
+    /// this disabled version can be returned by `latest` again
+    Enabled(u64),
+    /// the store was compacted with [`Local::compact`] and its counter
+    /// reset to this
+    Compacted(u64),
+}
+
+pub(crate) type ChangeHook = Arc<dyn Fn(Change) + Send + Sync>;
+
+pub(crate) type EvictHook<KeyType> = Arc<dyn Fn(u64, KeyType) + Send + Sync>;
+
+/// the options a [`Local`] was built with. they are fixed once the store is
+/// built and are not part of its serialized form, so a store that is
+/// deserialized or loaded always has the defaults until it is given to
+/// [`LocalBuilder::build_from`].
+#[derive(Clone)]
+pub struct Config {
+    pub(crate) max_versions: Option<usize>,
+    pub(crate) track_usage: bool,
+    pub(crate) tombstone_retention: usize,
+    pub(crate) on_change: Option<ChangeHook>,
+    pub(crate) hooks: Option<Arc<dyn Hooks>>,
+    pub(crate) require_confirmed_drop: bool,
+    pub(crate) confirm_window: Duration,
+    pub(crate) recent_ops: usize,
+}
+
+impl Config {
+    /// the most versions kept before the oldest are evicted
+    pub fn max_versions(&self) -> Option<usize> {
+        self.max_versions
+    }
+
+    /// if access times are recorded by `get` and `get_version`
+    pub fn track_usage(&self) -> bool {
+        self.track_usage
+    }
+
+    /// the most tombstones kept before the oldest are forgotten
+    pub fn tombstone_retention(&self) -> usize {
+        self.tombstone_retention
+    }
+
+    /// the hooks operations are timed with
+    pub fn hooks(&self) -> Option<&Arc<dyn Hooks>> {
+        self.hooks.as_ref()
+    }
+
+    /// if `drop` asks for confirmation instead of dropping
+    pub fn require_confirmed_drop(&self) -> bool {
+        self.require_confirmed_drop
+    }
+
+    /// how long a drop can be confirmed for
+    pub fn confirm_window(&self) -> Duration {
+        self.confirm_window
+    }
+
+    /// how many operations [`Local::recent_ops`] keeps, 0 when none are
+    pub fn recent_ops(&self) -> usize {
+        self.recent_ops
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_versions: None,
+            track_usage: true,
+            tombstone_retention: DEFAULT_TOMBSTONE_RETENTION,
+            on_change: None,
+            hooks: None,
+            require_confirmed_drop: false,
+            confirm_window: DEFAULT_CONFIRM_WINDOW,
+            recent_ops: 0,
+        }
+    }
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("max_versions", &self.max_versions)
+            .field("track_usage", &self.track_usage)
+            .field("tombstone_retention", &self.tombstone_retention)
+            .field("on_change", &self.on_change.is_some())
+            .field("hooks", &self.hooks.is_some())
+            .field("require_confirmed_drop", &self.require_confirmed_drop)
+            .field("confirm_window", &self.confirm_window)
+            .field("recent_ops", &self.recent_ops)
+            .finish()
+    }
+}
+
+pub struct LocalBuilder<KeyType> {
+    config: Config,
+    on_evict: Option<EvictHook<KeyType>>,
+    initial: Vec<KeyType>,
+}
+
+impl<KeyType> LocalBuilder<KeyType> {
+    pub(crate) fn new() -> Self {
+        LocalBuilder {
+            config: Config::default(),
+            on_evict: None,
+            initial: Vec::new(),
+        }
+    }
+
+    /// keeps at most `max` versions, evicting the oldest when a new key is
+    /// added. dropped versions do not count towards `max`. panics if `max`
+    /// is 0.
+    pub fn max_versions(mut self, max: usize) -> Self {
+        assert!(max > 0, "max versions must be at least 1");
+
+        self.config.max_versions = Some(max);
+        self
+    }
+
+    /// records access times on `get` and `get_version`. on by default.
+    pub fn track_usage(mut self, track: bool) -> Self {
+        self.config.track_usage = track;
+        self
+    }
+
+    /// keeps at most `max` tombstones, forgetting the lowest versions first.
+    /// 0 stops tombstones from being written. defaults to
+    /// [`DEFAULT_TOMBSTONE_RETENTION`].
+    pub fn tombstone_retention(mut self, max: usize) -> Self {
+        self.config.tombstone_retention = max;
+        self
+    }
+
+    /// keys added as versions 1 and up when the store is built. the other
+    /// options, including `max_versions` and `on_change`, apply to them.
+    pub fn with_initial_keys<I>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator<Item = KeyType>
+    {
+        self.initial.extend(keys);
+        self
+    }
+
+    /// called after every change to the store, once its locks are released
+    pub fn on_change<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(Change) + Send + Sync + 'static
+    {
+        self.config.on_change = Some(Arc::new(callback));
+        self
+    }
+
+    /// given the version and key of every version evicted to stay under
+    /// `max_versions`, once the locks of the store are released and before
+    /// `on_change` is told of the drop. lets evicted keys be archived or
+    /// zeroized instead of silently dropped.
+    pub fn on_evict<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(u64, KeyType) + Send + Sync + 'static
+    {
+        self.on_evict = Some(Arc::new(callback));
+        self
+    }
+
+    /// times `get`, `latest`, `update`, and `drop` with the given hooks
+    pub fn hooks(mut self, hooks: Arc<dyn Hooks>) -> Self {
+        self.config.hooks = Some(hooks);
+        self
+    }
+
+    /// makes `drop` and `drop_with_note` give back a
+    /// [`PendingDrop`](super::PendingDrop) in
+    /// [`Error::ConfirmDrop`] instead of dropping. the version is only
+    /// dropped once the token is given to
+    /// [`confirm_drop`](Local::confirm_drop). scheduled drops and evictions
+    /// are not affected. off by default.
+    pub fn require_confirmed_drop(mut self, require: bool) -> Self {
+        self.config.require_confirmed_drop = require;
+        self
+    }
+
+    /// how long a [`PendingDrop`](super::PendingDrop) can be confirmed
+    /// for. defaults to [`DEFAULT_CONFIRM_WINDOW`].
+    pub fn confirm_window(mut self, window: Duration) -> Self {
+        self.config.confirm_window = window;
+        self
+    }
+
+    /// keeps the last `capacity` operations in memory for
+    /// [`Local::recent_ops`]. 0, the default, keeps none.
+    pub fn recent_ops(mut self, capacity: usize) -> Self {
+        self.config.recent_ops = capacity;
+        self
+    }
+
+    pub fn build(self) -> Result<Local<KeyType>, Error> {
+        self.build_from(Local::new())
+    }
+
+    /// applies the options to an existing store, e.g. one that was
+    /// deserialized or loaded with the defaults. a store with more than
+    /// `max_versions` versions has its oldest evicted before the initial
+    /// keys are added.
+    pub fn build_from(self, mut local: Local<KeyType>) -> Result<Local<KeyType>, Error> {
+        let recent_ops = self.config.recent_ops;
+
+        local.config = self.config;
+        local.on_evict = self.on_evict;
+        local.recent = (recent_ops > 0).then(|| RecentOps::new(recent_ops));
+
+        local.enforce_max_versions()?;
+
+        for key in self.initial {
+            local.insert(key)?;
+        }
+
+        Ok(local)
+    }
+}
+
+impl<KeyType> Default for LocalBuilder<KeyType> {
+    fn default() -> Self {
+        LocalBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn configured() {
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let recorded = changes.clone();
+
+        let local: Local<u64> = Local::builder()
+            .max_versions(3)
+            .track_usage(false)
+            .with_initial_keys([10, 20])
+            .on_change(move |change| recorded.lock().unwrap().push(change))
+            .build()
+            .unwrap();
+
+        assert_eq!(local.config().max_versions(), Some(3));
+        assert!(!local.config().track_usage());
+
+        local.update(30).unwrap();
+        local.update(40).unwrap();
+
+        let versions: Vec<u64> = local.store_reader().unwrap().keys().copied().collect();
+
+        assert_eq!(versions, vec![2, 3, 4]);
+        assert_eq!(local.count().unwrap(), 4);
+
+        local.get(&3).unwrap();
+
+        assert!(local.access_times().unwrap().is_empty(), "access was tracked");
+
+        local.drop(&2).unwrap();
+
+        assert_eq!(*changes.lock().unwrap(), vec![
+            Change::Updated(1),
+            Change::Updated(2),
+            Change::Updated(3),
+            Change::Updated(4),
+            Change::Dropped(1),
+            Change::Dropped(2),
+        ]);
+    }
+
+    #[test]
+    fn serde_defaults() {
+        let local: Local<u64> = Local::builder()
+            .max_versions(2)
+            .track_usage(false)
+            .with_initial_keys([1, 2])
+            .build()
+            .unwrap();
+
+        let and_back: Local<u64> = serde_json::from_str(&serde_json::to_string(&local).unwrap())
+            .unwrap();
+
+        crate::local::test::assert_local_eq(&local, &and_back);
+        assert_eq!(and_back.config().max_versions(), None);
+        assert!(and_back.config().track_usage());
+    }
+
+    #[test]
+    fn on_evict() {
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let recorded = evicted.clone();
+
+        let local: Local<u64> = Local::builder()
+            .max_versions(2)
+            .on_evict(move |version, key| recorded.lock().unwrap().push((version, key)))
+            .with_initial_keys([10, 20])
+            .build()
+            .unwrap();
+
+        assert!(evicted.lock().unwrap().is_empty());
+
+        local.update(30).unwrap();
+
+        assert_eq!(*evicted.lock().unwrap(), vec![(1, 10)]);
+
+        // a dropped version frees its place so nothing is evicted
+        local.drop(&3).unwrap();
+        local.update(40).unwrap();
+
+        assert_eq!(*evicted.lock().unwrap(), vec![(1, 10)]);
+        assert_eq!(local.versions().unwrap(), vec![2, 4]);
+
+        local.update(50).unwrap();
+
+        assert_eq!(*evicted.lock().unwrap(), vec![(1, 10), (2, 20)]);
+        assert_eq!(local.drop(&2).unwrap(), None);
+    }
+
+    #[test]
+    fn build_from() {
+        let loaded: Local<u64> = Local::builder()
+            .with_initial_keys([10, 20, 30, 40])
+            .build()
+            .unwrap();
+
+        let and_back: Local<u64> = serde_json::from_str(&serde_json::to_string(&loaded).unwrap())
+            .unwrap();
+
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let recorded = evicted.clone();
+
+        let local = Local::builder()
+            .max_versions(2)
+            .on_evict(move |version, key| recorded.lock().unwrap().push((version, key)))
+            .with_initial_keys([50])
+            .build_from(and_back)
+            .unwrap();
+
+        assert_eq!(local.config().max_versions(), Some(2));
+        assert_eq!(*evicted.lock().unwrap(), vec![(1, 10), (2, 20), (3, 30)]);
+        assert_eq!(local.versions().unwrap(), vec![4, 5]);
+        assert_eq!(local.count().unwrap(), 5);
+        assert!(local.tombstones().unwrap().contains_key(&3));
+    }
+}
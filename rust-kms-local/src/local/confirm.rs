@@ -0,0 +1,132 @@
+use std::time::{Duration, Instant};
+
+use super::{Local, Error};
+
+/// how long a [`PendingDrop`] can be confirmed for unless the builder says
+/// otherwise
+pub const DEFAULT_CONFIRM_WINDOW: Duration = Duration::from_secs(60);
+
+/// a drop that has been asked for but not done. given back in
+/// [`Error::ConfirmDrop`] by [`Local::drop`] and
+/// [`Local::drop_with_note`] when the store requires confirmed drops.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[must_use = "nothing is dropped until the token is given to confirm_drop"]
+pub struct PendingDrop {
+    version: u64,
+    note: Option<String>,
+    issued: Instant,
+}
+
+impl PendingDrop {
+    pub fn version(&self) -> &u64 {
+        &self.version
+    }
+
+    pub fn note(&self) -> Option<&str> {
+        self.note.as_deref()
+    }
+}
+
+impl<KeyType> Local<KeyType> {
+    /// gives back a token instead of dropping if the store requires
+    /// confirmed drops and the version exists
+    pub(super) fn check_confirmed(&self, version: &u64, note: Option<String>) -> Result<Option<String>, Error> {
+        if !self.config.require_confirmed_drop || !self.store.read()?.contains_key(version) {
+            return Ok(note);
+        }
+
+        Err(Error::ConfirmDrop(PendingDrop {
+            version: *version,
+            note,
+            issued: Instant::now(),
+        }))
+    }
+
+    /// does a drop given back by [`drop`](Local::drop) or
+    /// [`drop_with_note`](Local::drop_with_note). `version` must be the
+    /// version of the token and the token must be confirmed within the
+    /// confirm window of the store.
+    pub fn confirm_drop(&self, version: &u64, token: PendingDrop) -> Result<Option<KeyType>, Error> {
+        if token.version != *version {
+            return Err(Error::DropTokenMismatch(*version));
+        }
+
+        if token.issued.elapsed() >= self.config.confirm_window {
+            return Err(Error::DropTokenExpired(*version));
+        }
+
+        self.remove(version, token.note)
+    }
+
+    /// drops the version even if the store requires confirmed drops. meant
+    /// for tooling that has already checked the key exists elsewhere.
+    pub fn drop_unchecked(&self, version: &u64) -> Result<Option<KeyType>, Error> {
+        self.remove(version, None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn confirmed(window: Duration) -> Local<u64> {
+        Local::builder()
+            .require_confirmed_drop(true)
+            .confirm_window(window)
+            .with_initial_keys([10, 20, 30])
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn confirm() {
+        let local = confirmed(DEFAULT_CONFIRM_WINDOW);
+
+        let Err(Error::ConfirmDrop(token)) = local.drop_with_note(&2, "rotated out") else {
+            panic!("drop did not ask for confirmation");
+        };
+
+        assert_eq!(*token.version(), 2);
+        assert_eq!(local.get(&2).unwrap(), Some(20));
+
+        // missing versions have nothing to confirm
+        assert_eq!(local.drop(&9).unwrap(), None);
+
+        assert!(matches!(local.confirm_drop(&3, token.clone()), Err(Error::DropTokenMismatch(3))));
+        assert_eq!(local.get(&3).unwrap(), Some(30));
+
+        assert_eq!(local.confirm_drop(&2, token).unwrap(), Some(20));
+        assert_eq!(local.get(&2).unwrap(), None);
+        assert_eq!(local.tombstones().unwrap()[&2].note.as_deref(), Some("rotated out"));
+    }
+
+    #[test]
+    fn expired() {
+        let local = confirmed(Duration::ZERO);
+
+        let Err(Error::ConfirmDrop(token)) = local.drop(&1) else {
+            panic!("drop did not ask for confirmation");
+        };
+
+        assert!(matches!(local.confirm_drop(&1, token), Err(Error::DropTokenExpired(1))));
+        assert_eq!(local.get(&1).unwrap(), Some(10));
+    }
+
+    #[test]
+    fn unchecked() {
+        let local = confirmed(DEFAULT_CONFIRM_WINDOW);
+
+        assert_eq!(local.drop_unchecked(&1).unwrap(), Some(10));
+        assert_eq!(local.get(&1).unwrap(), None);
+    }
+
+    #[test]
+    fn default_unchanged() {
+        let local = Local::new();
+
+        local.update(10).unwrap();
+
+        assert!(!local.config().require_confirmed_drop());
+        assert_eq!(local.drop(&1).unwrap(), Some(10));
+    }
+}
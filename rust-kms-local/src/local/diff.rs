@@ -0,0 +1,281 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use super::{Local, Error, Snapshot};
+
+/// how two stores differ, from [`Local::diff`]. the left side is the store
+/// the diff was called on.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Diff {
+    /// versions only in the left store
+    pub only_left: Vec<u64>,
+    /// versions only in the right store
+    pub only_right: Vec<u64>,
+    /// versions in both stores with different keys
+    pub changed: Vec<u64>,
+    pub counter_left: u64,
+    pub counter_right: u64,
+}
+
+impl Diff {
+    /// if the stores have the same keys and counter
+    pub fn is_empty(&self) -> bool {
+        self.only_left.is_empty() &&
+            self.only_right.is_empty() &&
+            self.changed.is_empty() &&
+            self.counter_left == self.counter_right
+    }
+}
+
+fn diff_maps<KeyType>(
+    left: (u64, &BTreeMap<u64, KeyType>),
+    right: (u64, &BTreeMap<u64, KeyType>),
+) -> Diff
+where
+    KeyType: PartialEq
+{
+    diff_maps_by(left, right, |left_key, right_key| left_key == right_key)
+}
+
+/// [`diff_maps`] with `same` deciding if the keys of a version match
+fn diff_maps_by<KeyType, F>(
+    (counter_left, left): (u64, &BTreeMap<u64, KeyType>),
+    (counter_right, right): (u64, &BTreeMap<u64, KeyType>),
+    same: F,
+) -> Diff
+where
+    F: Fn(&KeyType, &KeyType) -> bool
+{
+    let mut diff = Diff {
+        counter_left,
+        counter_right,
+        ..Diff::default()
+    };
+
+    for (version, key) in left {
+        match right.get(version) {
+            Some(other) if same(key, other) => {}
+            Some(_) => diff.changed.push(*version),
+            None => diff.only_left.push(*version),
+        }
+    }
+
+    diff.only_right.extend(right.keys().filter(|version| !left.contains_key(version)));
+
+    diff
+}
+
+impl<KeyType> Snapshot<KeyType>
+where
+    KeyType: PartialEq
+{
+    /// how this snapshot differs from `other`
+    pub fn diff(&self, other: &Snapshot<KeyType>) -> Diff {
+        diff_maps((self.count, &self.store), (other.count, &other.store))
+    }
+}
+
+impl<KeyType> Local<KeyType>
+where
+    KeyType: PartialEq
+{
+    /// how the store differs from a snapshot
+    pub fn diff_snapshot(&self, other: &Snapshot<KeyType>) -> Result<Diff, Error> {
+        let version_lock = self.count.lock()?;
+        let store_reader = self.store.read()?;
+
+        Ok(diff_maps((*version_lock, &store_reader), (other.count, &other.store)))
+    }
+}
+
+impl<KeyType> Local<KeyType>
+where
+    KeyType: PartialEq
+{
+    /// how the store differs from `other`. see
+    /// [`diff_versions`](Local::diff_versions) for how the stores are
+    /// locked.
+    pub fn diff(&self, other: &Local<KeyType>) -> Result<Diff, Error> {
+        self.with_both(other, |left, right| diff_maps(left, right))
+    }
+}
+
+impl<KeyType> Local<KeyType> {
+    /// the versions only in the store or only in `other`, for keys that
+    /// cannot be compared. `changed` is always empty.
+    ///
+    /// both stores are read locked for the whole diff. the store at the
+    /// lower address is always locked first so two threads diffing the
+    /// same stores the other way around cannot deadlock.
+    pub fn diff_versions(&self, other: &Local<KeyType>) -> Result<Diff, Error> {
+        self.with_both(other, |left, right| diff_maps_by(left, right, |_, _| true))
+    }
+
+    /// calls `f` with the counter and keys of the store and of `other`
+    /// while both are locked
+    fn with_both<F>(&self, other: &Local<KeyType>, f: F) -> Result<Diff, Error>
+    where
+        F: FnOnce((u64, &BTreeMap<u64, KeyType>), (u64, &BTreeMap<u64, KeyType>)) -> Diff
+    {
+        if std::ptr::eq(self, other) {
+            let version_lock = self.count.lock()?;
+            let store_reader = self.store.read()?;
+
+            return Ok(f((*version_lock, &store_reader), (*version_lock, &store_reader)));
+        }
+
+        let self_first = (self as *const Self) < (other as *const Self);
+        let (first, second) = if self_first { (self, other) } else { (other, self) };
+
+        let first_count = first.count.lock()?;
+        let first_store = first.store.read()?;
+        let second_count = second.count.lock()?;
+        let second_store = second.store.read()?;
+
+        let first = (*first_count, &*first_store);
+        let second = (*second_count, &*second_store);
+
+        if self_first {
+            Ok(f(first, second))
+        } else {
+            Ok(f(second, first))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn categories() {
+        let live = Local::new();
+        let backup = Local::new();
+
+        for value in [10, 20, 30, 40] {
+            live.update(value).unwrap();
+        }
+
+        for value in [10, 21, 30] {
+            backup.update(value).unwrap();
+        }
+
+        live.drop(&1).unwrap();
+        backup.update(50).unwrap();
+        backup.update(60).unwrap();
+        backup.drop(&4).unwrap();
+
+        let diff = live.diff(&backup).unwrap();
+
+        assert_eq!(diff, Diff {
+            only_left: vec![4],
+            only_right: vec![1, 5],
+            changed: vec![2],
+            counter_left: 4,
+            counter_right: 5,
+        });
+        assert!(!diff.is_empty());
+
+        // the other way around swaps the sides
+        let reverse = backup.diff(&live).unwrap();
+
+        assert_eq!(reverse.only_left, diff.only_right);
+        assert_eq!(reverse.only_right, diff.only_left);
+        assert_eq!(reverse.changed, diff.changed);
+
+        assert_eq!(live.snapshot().unwrap().diff(&backup.snapshot().unwrap()), diff);
+        assert_eq!(
+            serde_json::to_value(&diff).unwrap(),
+            serde_json::json!({
+                "only_left": [4],
+                "only_right": [1, 5],
+                "changed": [2],
+                "counter_left": 4,
+                "counter_right": 5,
+            })
+        );
+    }
+
+    #[test]
+    fn same_store() {
+        let local = Local::new();
+
+        local.update(1).unwrap();
+
+        assert!(local.diff(&local).unwrap().is_empty());
+        assert!(Local::<u64>::new().diff(&Local::new()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn disjoint_and_identical() {
+        let left = Local::new();
+        let right = Local::new();
+
+        left.update(1).unwrap();
+        right.update(2).unwrap();
+        right.update(3).unwrap();
+        right.drop(&1).unwrap();
+
+        assert_eq!(left.diff(&right).unwrap(), Diff {
+            only_left: vec![1],
+            only_right: vec![2],
+            changed: vec![],
+            counter_left: 1,
+            counter_right: 2,
+        });
+
+        let copy = Local::new();
+
+        copy.update(1).unwrap();
+
+        assert!(left.diff(&copy).unwrap().is_empty());
+    }
+
+    #[test]
+    fn versions_only() {
+        struct Opaque;
+
+        let left = Local::new();
+        let right = Local::new();
+
+        left.update(Opaque).unwrap();
+        left.update(Opaque).unwrap();
+        right.update(Opaque).unwrap();
+
+        let diff = left.diff_versions(&right).unwrap();
+
+        assert_eq!(diff.only_left, vec![2]);
+        assert!(diff.only_right.is_empty());
+        assert!(diff.changed.is_empty());
+    }
+
+    #[test]
+    fn both_directions_at_once() {
+        let left = std::sync::Arc::new(Local::new());
+        let right = std::sync::Arc::new(Local::new());
+
+        left.update(1).unwrap();
+        right.update(2).unwrap();
+
+        let handles: Vec<_> = (0..4).map(|n| {
+            let (left, right) = if n % 2 == 0 {
+                (left.clone(), right.clone())
+            } else {
+                (right.clone(), left.clone())
+            };
+
+            std::thread::spawn(move || {
+                for key in 0..200 {
+                    assert_eq!(left.diff(&right).unwrap().changed.first(), Some(&1));
+
+                    left.update(key).unwrap();
+                }
+            })
+        }).collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+}
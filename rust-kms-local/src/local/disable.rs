@@ -0,0 +1,117 @@
+use std::collections::BTreeSet;
+
+use super::{Local, Error, Change};
+
+impl<KeyType> Local<KeyType> {
+    /// keeps the version in the store, where it can still be fetched with
+    /// `get`, but has `latest` skip it, e.g. for a key that is suspected to
+    /// be leaked but is still needed to verify. returns false if it was
+    /// already disabled and fails with [`Error::VersionNotFound`] if it does
+    /// not exist.
+    pub fn disable(&self, version: &u64) -> Result<bool, Error> {
+        self.set_disabled(version, true)
+    }
+
+    /// makes a disabled version eligible for `latest` again. returns false
+    /// if it was not disabled and fails with [`Error::VersionNotFound`] if
+    /// it does not exist.
+    pub fn enable(&self, version: &u64) -> Result<bool, Error> {
+        self.set_disabled(version, false)
+    }
+
+    fn set_disabled(&self, version: &u64, disabled: bool) -> Result<bool, Error> {
+        let changed = {
+            let store_reader = self.store.read()?;
+
+            self.check_frozen()?;
+
+            if !store_reader.contains_key(version) {
+                return Err(Error::VersionNotFound(*version));
+            }
+
+            let mut disabled_writer = self.disabled.write()?;
+
+            if disabled {
+                disabled_writer.insert(*version)
+            } else {
+                disabled_writer.remove(version)
+            }
+        };
+
+        if changed {
+            self.notify(if disabled {
+                Change::Disabled(*version)
+            } else {
+                Change::Enabled(*version)
+            });
+        }
+
+        Ok(changed)
+    }
+
+    /// the versions that are disabled
+    pub fn disabled(&self) -> Result<BTreeSet<u64>, Error> {
+        Ok(self.disabled.read()?.clone())
+    }
+
+    pub fn is_disabled(&self, version: &u64) -> Result<bool, Error> {
+        Ok(self.disabled.read()?.contains(version))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::local::test::sample_local;
+
+    #[test]
+    fn latest_falls_back() {
+        let local = sample_local();
+        let newest = local.latest_version().unwrap().unwrap();
+        let previous = local.latest_n(2).unwrap().pop().unwrap();
+
+        assert!(local.disable(newest.version()).unwrap());
+        assert!(!local.disable(newest.version()).unwrap());
+        assert!(local.is_disabled(newest.version()).unwrap());
+        assert!(matches!(local.disable(&99), Err(Error::VersionNotFound(99))));
+
+        assert_eq!(local.latest_version().unwrap().unwrap().version(), previous.version());
+        assert_eq!(local.latest().unwrap(), Some(*previous));
+        assert_eq!(local.get(newest.version()).unwrap(), Some(*newest));
+
+        assert!(local.enable(newest.version()).unwrap());
+        assert!(!local.enable(newest.version()).unwrap());
+        assert_eq!(local.latest().unwrap(), Some(*newest));
+    }
+
+    #[test]
+    fn disabled_serde() {
+        let local = sample_local();
+        let newest = *local.latest_version().unwrap().unwrap().version();
+
+        local.disable(&newest).unwrap();
+
+        let json = serde_json::to_string(&local).unwrap();
+        let and_back: Local<u64> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(and_back.disabled().unwrap(), [newest].into());
+        assert_eq!(and_back.latest().unwrap(), local.latest().unwrap());
+
+        local.enable(&newest).unwrap();
+
+        let json = serde_json::to_string(&local).unwrap();
+
+        assert!(!json.contains("disabled"));
+    }
+
+    #[test]
+    fn dropped_disabled() {
+        let local = sample_local();
+        let newest = *local.latest_version().unwrap().unwrap().version();
+
+        local.disable(&newest).unwrap();
+        local.drop(&newest).unwrap();
+
+        assert!(local.disabled().unwrap().is_empty());
+    }
+}
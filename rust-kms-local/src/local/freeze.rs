@@ -0,0 +1,110 @@
+use std::sync::PoisonError;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::{Local, Error};
+
+/// keeps a [`Local`] frozen until it is dropped. returned by
+/// [`Local::freeze`].
+#[derive(Debug)]
+#[must_use = "the store is unfrozen as soon as the guard is dropped"]
+pub struct FreezeGuard<'a> {
+    frozen: &'a AtomicUsize,
+}
+
+impl Drop for FreezeGuard<'_> {
+    fn drop(&mut self) {
+        self.frozen.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl<KeyType> Local<KeyType> {
+    /// stops every method that changes the store from working until the
+    /// guard is dropped, they fail with [`Error::Frozen`] instead. reads and
+    /// saves by the fs wrappers keep working. freezes can be nested and the
+    /// store stays frozen until every guard is dropped.
+    ///
+    /// changes that were already running are finished before this returns.
+    pub fn freeze(&self) -> FreezeGuard<'_> {
+        // every change holds the counter or the store while it checks the
+        // flag so taking both waits for any change that is in progress
+        let _version_lock = self.count.lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let _store_writer = self.store.write()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        self.frozen.fetch_add(1, Ordering::AcqRel);
+
+        FreezeGuard { frozen: &self.frozen }
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.load(Ordering::Acquire) > 0
+    }
+
+    /// fails with [`Error::Frozen`] if there is a [`FreezeGuard`] alive
+    pub(super) fn check_frozen(&self) -> Result<(), Error> {
+        if self.is_frozen() {
+            Err(Error::Frozen)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::local::test::sample_local;
+
+    #[test]
+    fn freeze() {
+        let local = sample_local();
+        let count = local.count().unwrap();
+
+        let guard = local.freeze();
+
+        std::thread::scope(|scope| {
+            let rotate = scope.spawn(|| local.update(100));
+
+            assert!(matches!(rotate.join().unwrap(), Err(Error::Frozen)));
+        });
+
+        assert!(local.is_frozen());
+        assert!(matches!(local.drop(&1), Err(Error::Frozen)));
+        assert!(matches!(local.compact(&[1]), Err(Error::Frozen)));
+        assert!(matches!(local.reserve(), Err(Error::Frozen)));
+        assert_eq!(local.get(&1).unwrap(), Some(0));
+        assert_eq!(local.latest().unwrap(), Some(26));
+        assert_eq!(local.count().unwrap(), count);
+
+        drop(guard);
+
+        assert!(!local.is_frozen());
+
+        std::thread::scope(|scope| {
+            let rotate = scope.spawn(|| local.update(100));
+
+            assert!(rotate.join().unwrap().is_ok());
+        });
+
+        assert_eq!(local.latest().unwrap(), Some(100));
+    }
+
+    #[test]
+    fn nested() {
+        let local = sample_local();
+
+        let outer = local.freeze();
+        let inner = local.freeze();
+
+        drop(inner);
+
+        assert!(local.is_frozen());
+        assert!(matches!(local.update(100), Err(Error::Frozen)));
+
+        drop(outer);
+
+        assert!(!local.is_frozen());
+        assert!(local.update(100).is_ok());
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+
+use serde::{Serialize, Deserialize};
+
+use super::{Local, Error, Reserved};
+
+/// the number of tombstones kept by a store unless its builder says
+/// otherwise
+pub const DEFAULT_TOMBSTONE_RETENTION: usize = 1024;
+
+/// the record left behind when a version is removed from the store
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tombstone {
+    /// when the version was removed, in seconds since the unix epoch
+    pub at: u64,
+    /// the note given to [`Local::drop_with_note`]
+    pub note: Option<String>,
+    /// if the version was evicted to stay under `max_versions` rather than
+    /// dropped
+    pub evicted: bool,
+}
+
+impl Tombstone {
+    pub(super) fn dropped(at: u64, note: Option<String>) -> Self {
+        Tombstone { at, note, evicted: false }
+    }
+
+    pub(super) fn evicted(at: u64) -> Self {
+        Tombstone { at, note: None, evicted: true }
+    }
+}
+
+/// why a range of versions has no keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GapReason {
+    /// dropped, removed by a scheduled drop, or evicted
+    Dropped(Tombstone),
+    /// reserved and then abandoned
+    Abandoned,
+    /// reserved and not yet fulfilled
+    Outstanding,
+    /// removed before tombstones were kept or after its tombstone was
+    /// forgotten
+    Unknown,
+}
+
+/// consecutive versions without keys that share a reason
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gap {
+    pub range: RangeInclusive<u64>,
+    pub reason: GapReason,
+}
+
+fn push_gap(report: &mut Vec<Gap>, range: RangeInclusive<u64>, reason: GapReason) {
+    if let Some(last) = report.last_mut() {
+        if last.reason == reason && *last.range.end() + 1 == *range.start() {
+            last.range = *last.range.start()..=*range.end();
+            return;
+        }
+    }
+
+    report.push(Gap { range, reason });
+}
+
+impl<KeyType> Local<KeyType> {
+    /// records a tombstone, forgetting the lowest versions once there are
+    /// more than the configured retention
+    pub(super) fn bury(&self, tombstones: &mut BTreeMap<u64, Tombstone>, version: u64, tombstone: Tombstone) {
+        if self.config.tombstone_retention == 0 {
+            return;
+        }
+
+        tombstones.insert(version, tombstone);
+
+        while tombstones.len() > self.config.tombstone_retention {
+            tombstones.pop_first();
+        }
+    }
+
+    /// [`Local::drop`] that keeps a note in the tombstone of the version,
+    /// e.g. who dropped it and why
+    pub fn drop_with_note<N>(&self, version: &u64, note: N) -> Result<Option<KeyType>, Error>
+    where
+        N: Into<String>
+    {
+        let note = self.check_confirmed(version, Some(note.into()))?;
+
+        self.remove(version, note)
+    }
+
+    /// the tombstones of removed versions that are still kept
+    pub fn tombstones(&self) -> Result<BTreeMap<u64, Tombstone>, Error> {
+        Ok(self.tombstones.read()?.clone())
+    }
+
+    /// every range of versions up to the counter that has no key, with the
+    /// reason it is missing. adjacent versions with the same reason are
+    /// reported as one range.
+    pub fn gap_report(&self) -> Result<Vec<Gap>, Error> {
+        let version_lock = self.count.lock()?;
+        let store_reader = self.store.read()?;
+        let reserved_reader = self.reserved.read()?;
+        let tombstones_reader = self.tombstones.read()?;
+
+        let count = *version_lock;
+        let mut report = Vec::new();
+        let mut next = 1;
+
+        let ends = store_reader.keys()
+            .copied()
+            .filter(|version| *version <= count)
+            .chain(std::iter::once(count.saturating_add(1)));
+
+        for end in ends {
+            if end > next {
+                let range = next..=end - 1;
+                let mut cursor = next;
+
+                // tombstones win over reservations, a version should never
+                // have both
+                let known: BTreeMap<u64, GapReason> = reserved_reader.range(range.clone())
+                    .map(|(version, state)| (*version, match state {
+                        Reserved::Outstanding => GapReason::Outstanding,
+                        Reserved::Abandoned => GapReason::Abandoned,
+                    }))
+                    .chain(tombstones_reader.range(range.clone())
+                        .map(|(version, tombstone)| (*version, GapReason::Dropped(tombstone.clone()))))
+                    .collect();
+
+                for (version, reason) in known {
+                    if version > cursor {
+                        push_gap(&mut report, cursor..=version - 1, GapReason::Unknown);
+                    }
+
+                    push_gap(&mut report, version..=version, reason);
+                    cursor = version + 1;
+                }
+
+                if cursor <= *range.end() {
+                    push_gap(&mut report, cursor..=*range.end(), GapReason::Unknown);
+                }
+            }
+
+            next = end.saturating_add(1);
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::local::test::{sample_local, TestLocal};
+
+    #[test]
+    fn attributed() {
+        let local = sample_local();
+
+        local.drop_with_note(&7, "leaked in incident 42").unwrap();
+        local.drop(&8).unwrap();
+
+        let reservation = local.reserve().unwrap();
+        local.abandon(reservation).unwrap();
+        local.reserve().unwrap();
+
+        let report = local.gap_report().unwrap();
+
+        assert_eq!(report.len(), 4);
+        assert_eq!(report[0].range, 7..=7);
+        assert!(matches!(
+            &report[0].reason,
+            GapReason::Dropped(Tombstone { note: Some(note), evicted: false, .. }) if note == "leaked in incident 42"
+        ));
+        assert_eq!(report[1].range, 8..=8);
+        assert!(matches!(&report[1].reason, GapReason::Dropped(Tombstone { note: None, .. })));
+        assert_eq!(report[2], Gap { range: 13..=13, reason: GapReason::Abandoned });
+        assert_eq!(report[3], Gap { range: 14..=14, reason: GapReason::Outstanding });
+
+        // tombstones are kept across serialization
+        let json = serde_json::to_string(&local).unwrap();
+        let and_back: TestLocal = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(and_back.gap_report().unwrap(), report);
+    }
+
+    #[test]
+    fn unknown_and_evicted() {
+        let local = sample_local();
+
+        // a store written before tombstones has gaps with no record
+        local.tombstones.write().unwrap().clear();
+        local.store.write().unwrap().retain(|version, _| !(3..=5).contains(version));
+
+        let report = local.gap_report().unwrap();
+
+        assert_eq!(report, vec![Gap { range: 3..=5, reason: GapReason::Unknown }]);
+
+        let local = Local::builder()
+            .max_versions(2)
+            .tombstone_retention(2)
+            .build()
+            .unwrap();
+
+        for value in 0..5 {
+            local.update(value).unwrap();
+        }
+
+        let report = local.gap_report().unwrap();
+
+        // evictions in different seconds are separate gaps
+        assert_eq!(report[0], Gap { range: 1..=1, reason: GapReason::Unknown });
+        assert_eq!(*report[1].range.start(), 2);
+        assert_eq!(*report.last().unwrap().range.end(), 3);
+        assert!(report[1..].iter()
+            .all(|gap| matches!(&gap.reason, GapReason::Dropped(Tombstone { evicted: true, .. }))));
+        assert_eq!(local.tombstones().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+
+use super::{Local, Error, Change, Snapshot, VersionedKey};
+
+/// what [`Local::import`] does with a version that already has a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportPolicy {
+    /// keep the key in the store and skip the imported one
+    SkipExisting,
+    /// skip the imported key if it is the same as the one in the store,
+    /// otherwise keep the store key and report the version as conflicted
+    OverwriteIfIdentical,
+    /// fail with [`Error::Occupied`] without changing the store
+    Fail,
+}
+
+/// the versions of an import and what happened to them
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ImportReport {
+    /// versions that were free and now hold the imported key
+    pub inserted: Vec<u64>,
+    /// versions that already had a key that was kept
+    pub skipped: Vec<u64>,
+    /// versions that already had a different key that was kept
+    pub conflicted: Vec<u64>,
+}
+
+impl<KeyType> Local<KeyType>
+where
+    KeyType: PartialEq
+{
+    /// adds the keys of an exported snapshot at their original versions in
+    /// one step. keys are stored as they are in the snapshot so created
+    /// timestamps are never stamped again.
+    ///
+    /// the counter is raised to the highest inserted version if it is
+    /// lower, the counter of the snapshot itself is not used. versions that
+    /// already have a key are handled by the policy. reservations and
+    /// tombstones of inserted versions are cleared.
+    pub fn import(&self, export: Snapshot<KeyType>, policy: ImportPolicy) -> Result<ImportReport, Error> {
+        self.import_with(export.store, policy, |existing, key| existing == key)
+    }
+}
+
+impl<KeyType> Local<KeyType>
+where
+    KeyType: Clone
+{
+    /// every key with its version, oldest first, for moving keys to
+    /// something other than a [`Local`]
+    pub fn export(&self) -> Result<Vec<VersionedKey<KeyType>>, Error> {
+        Ok(self.store.read()?
+            .iter()
+            .map(|(version, key)| VersionedKey(*version, key.clone()))
+            .collect())
+    }
+}
+
+impl<KeyType> Local<KeyType> {
+    /// adds keys at the versions they come with, e.g. from
+    /// [`export`](Local::export). the same as [`import`](Local::import)
+    /// with [`ImportPolicy::Fail`], a version that is already in the store
+    /// or given twice fails with [`Error::Occupied`] and nothing is added.
+    pub fn import_entries<I>(&self, entries: I) -> Result<ImportReport, Error>
+    where
+        I: IntoIterator<Item = VersionedKey<KeyType>>
+    {
+        let mut store = BTreeMap::new();
+
+        for VersionedKey(version, key) in entries {
+            if store.insert(version, key).is_some() {
+                return Err(Error::Occupied(version));
+            }
+        }
+
+        self.import_with(store, ImportPolicy::Fail, |_, _| false)
+    }
+
+    /// [`Local::import`] with `same` deciding if an existing key is the
+    /// same as the imported one
+    fn import_with<F>(
+        &self,
+        import: BTreeMap<u64, KeyType>,
+        policy: ImportPolicy,
+        same: F
+    ) -> Result<ImportReport, Error>
+    where
+        F: Fn(&KeyType, &KeyType) -> bool
+    {
+        let mut report = ImportReport::default();
+
+        let evicted = {
+            let mut version_lock = self.count.lock()?;
+            let mut store_writer = self.store.write()?;
+
+            self.check_frozen()?;
+
+            if policy == ImportPolicy::Fail {
+                if let Some(version) = import.keys().find(|version| store_writer.contains_key(version)) {
+                    return Err(Error::Occupied(*version));
+                }
+            }
+
+            let mut accessed_writer = self.accessed.write()?;
+            let mut pending_writer = self.pending.write()?;
+
+            for (version, key) in import {
+                match store_writer.get(&version) {
+                    Some(existing) if policy == ImportPolicy::OverwriteIfIdentical && !same(existing, &key) => {
+                        report.conflicted.push(version);
+                    }
+                    Some(_) => {
+                        report.skipped.push(version);
+                    }
+                    None => {
+                        store_writer.insert(version, key);
+
+                        report.inserted.push(version);
+                    }
+                }
+            }
+
+            if let Some(highest) = report.inserted.last() {
+                *version_lock = (*version_lock).max(*highest);
+
+                let mut reserved_writer = self.reserved.write()?;
+                let mut tombstones_writer = self.tombstones.write()?;
+
+                for version in &report.inserted {
+                    reserved_writer.remove(version);
+                    tombstones_writer.remove(version);
+                }
+            }
+
+            self.evict_over_max(&mut store_writer, &mut accessed_writer, &mut pending_writer)?
+        };
+
+        for version in &report.inserted {
+            if !evicted.contains(version) {
+                self.notify(Change::Updated(*version));
+            }
+        }
+
+        self.notify_evicted(evicted);
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::key::Key;
+
+    fn key(data: u64, created: u64) -> Key<u64> {
+        let mut builder = Key::builder(data);
+        builder.set_created(created);
+
+        builder.build().unwrap()
+    }
+
+    /// versions 1 and 4 in the store, 2 reserved and 3 dropped
+    fn partial() -> Local<Key<u64>> {
+        let local = Local::new();
+
+        local.update(key(10, 100)).unwrap();
+
+        let reservation = local.reserve().unwrap();
+
+        local.update(key(30, 300)).unwrap();
+        local.drop(&3).unwrap();
+        local.update(key(40, 400)).unwrap();
+
+        assert_eq!(reservation.version(), 2);
+
+        local
+    }
+
+    /// the same key at 1, a different key at 4 and new keys at 2, 3 and 6
+    fn export() -> Snapshot<Key<u64>> {
+        Snapshot {
+            count: 9,
+            store: [
+                (1, key(10, 100)),
+                (2, key(20, 200)),
+                (3, key(30, 300)),
+                (4, key(44, 444)),
+                (6, key(60, 600)),
+            ].into(),
+        }
+    }
+
+    #[test]
+    fn skip_existing() {
+        let local = partial();
+
+        let report = local.import(export(), ImportPolicy::SkipExisting).unwrap();
+
+        assert_eq!(report.inserted, vec![2, 3, 6]);
+        assert_eq!(report.skipped, vec![1, 4]);
+        assert!(report.conflicted.is_empty());
+
+        assert_eq!(local.get(&4).unwrap(), Some(key(40, 400)));
+        assert_eq!(*local.get(&6).unwrap().unwrap().created(), 600);
+        assert_eq!(local.count().unwrap(), 6);
+        assert!(local.outstanding().unwrap().is_empty());
+        assert!(local.tombstones().unwrap().is_empty());
+        assert_eq!(local.update(key(70, 700)).unwrap(), 7);
+    }
+
+    #[test]
+    fn overwrite_if_identical() {
+        let local = partial();
+
+        let report = local.import(export(), ImportPolicy::OverwriteIfIdentical).unwrap();
+
+        assert_eq!(report.inserted, vec![2, 3, 6]);
+        assert_eq!(report.skipped, vec![1]);
+        assert_eq!(report.conflicted, vec![4]);
+        assert_eq!(local.get(&4).unwrap(), Some(key(40, 400)));
+    }
+
+    #[test]
+    fn fail() {
+        let local = partial();
+
+        assert!(matches!(local.import(export(), ImportPolicy::Fail), Err(Error::Occupied(1))));
+        assert_eq!(local.versions().unwrap(), vec![1, 4]);
+        assert_eq!(local.count().unwrap(), 4);
+
+        let fresh = Snapshot {
+            count: 0,
+            store: [(5, key(50, 500))].into(),
+        };
+        let report = local.import(fresh, ImportPolicy::Fail).unwrap();
+
+        assert_eq!(report.inserted, vec![5]);
+        assert_eq!(local.count().unwrap(), 5);
+    }
+
+    #[test]
+    fn export_and_import_entries() {
+        let local = partial();
+        let exported = local.export().unwrap();
+
+        assert_eq!(exported.iter().map(|found| found.0).collect::<Vec<_>>(), vec![1, 4]);
+
+        let empty = Local::new();
+        let report = empty.import_entries(exported).unwrap();
+
+        assert_eq!(report.inserted, vec![1, 4]);
+        assert_eq!(empty.count().unwrap(), 4);
+        crate::test_util::assert_local_eq(&empty, &local);
+
+        assert!(matches!(
+            empty.import_entries([VersionedKey(4, key(44, 444))]),
+            Err(Error::Occupied(4))
+        ));
+        assert!(matches!(
+            empty.import_entries([VersionedKey(7, key(70, 700)), VersionedKey(7, key(77, 777))]),
+            Err(Error::Occupied(7))
+        ));
+        assert_eq!(empty.versions().unwrap(), vec![1, 4]);
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::{btree_map, BTreeMap};
+use std::fmt;
+
+use super::{Local, Error};
+use super::sync::RwLockReadGuard;
+use crate::key_ref::KeyRef;
+
+/// the keys of a store in version order. holds the read lock of the store
+/// until it is dropped, so keys cannot be added or removed while it is
+/// alive.
+///
+/// iterate over a reference to it, `for (version, key) in &local.iter()?`.
+pub struct Iter<'a, KeyType> {
+    guard: RwLockReadGuard<'a, BTreeMap<u64, KeyType>>,
+}
+
+impl<'a, KeyType> Iter<'a, KeyType> {
+    pub fn iter(&self) -> btree_map::Iter<'_, u64, KeyType> {
+        self.guard.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.guard.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.guard.is_empty()
+    }
+}
+
+impl<'b, 'a, KeyType> IntoIterator for &'b Iter<'a, KeyType> {
+    type Item = (&'b u64, &'b KeyType);
+    type IntoIter = btree_map::Iter<'b, u64, KeyType>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// a borrowed [`VersionedKey`](super::VersionedKey)
+#[derive(Debug)]
+pub struct VersionedRef<'a, T>(
+    pub u64,
+    pub &'a T
+);
+
+impl<'a, T> VersionedRef<'a, T> {
+    pub fn version(&self) -> &u64 {
+        &self.0
+    }
+
+    /// the version as it is given to other systems
+    pub fn key_ref(&self) -> KeyRef {
+        KeyRef::from_version(self.0)
+    }
+}
+
+impl<'a, T> Clone for VersionedRef<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for VersionedRef<'a, T> {}
+
+impl<'a, T> std::ops::Deref for VersionedRef<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.1
+    }
+}
+
+/// [`Iter`] that gives a [`VersionedRef`] for each key
+pub struct IterVersioned<'a, KeyType> {
+    guard: RwLockReadGuard<'a, BTreeMap<u64, KeyType>>,
+}
+
+impl<'a, KeyType> IterVersioned<'a, KeyType> {
+    pub fn iter(&self) -> Versions<'_, KeyType> {
+        Versions {
+            inner: self.guard.iter(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.guard.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.guard.is_empty()
+    }
+}
+
+impl<'b, 'a, KeyType> IntoIterator for &'b IterVersioned<'a, KeyType> {
+    type Item = VersionedRef<'b, KeyType>;
+    type IntoIter = Versions<'b, KeyType>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// the iterator of [`IterVersioned`]
+pub struct Versions<'a, KeyType> {
+    inner: btree_map::Iter<'a, u64, KeyType>,
+}
+
+impl<'a, KeyType> Iterator for Versions<'a, KeyType> {
+    type Item = VersionedRef<'a, KeyType>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(version, key)| VersionedRef(*version, key))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<'a, KeyType> DoubleEndedIterator for Versions<'a, KeyType> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back().map(|(version, key)| VersionedRef(*version, key))
+    }
+}
+
+impl<'a, KeyType> ExactSizeIterator for Versions<'a, KeyType> {}
+
+/// a single key of a store, from [`Local::get_ref`] or
+/// [`Local::latest_ref`]. derefs to the key without copying it and holds
+/// the read lock of the store until it is dropped.
+///
+/// anything that changes the store, like `update` or `drop`, waits for the
+/// lock so calling one on the same thread while the guard is alive never
+/// returns. the guard cannot be sent to another thread.
+pub struct KeyGuard<'a, KeyType> {
+    guard: RwLockReadGuard<'a, BTreeMap<u64, KeyType>>,
+    version: u64,
+}
+
+impl<'a, KeyType> KeyGuard<'a, KeyType> {
+    pub fn version(&self) -> &u64 {
+        &self.version
+    }
+
+    /// the version as it is given to other systems
+    pub fn key_ref(&self) -> KeyRef {
+        KeyRef::from_version(self.version)
+    }
+}
+
+impl<'a, KeyType> std::ops::Deref for KeyGuard<'a, KeyType> {
+    type Target = KeyType;
+
+    fn deref(&self) -> &Self::Target {
+        // the lock is held since the version was found
+        &self.guard[&self.version]
+    }
+}
+
+impl<'a, KeyType> fmt::Debug for KeyGuard<'a, KeyType> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyGuard")
+            .field("version", &self.version)
+            .finish_non_exhaustive()
+    }
+}
+
+/// the whole store, from [`Local::store_reader`]. derefs to the map of
+/// versions to keys and holds the read lock of the store until it is
+/// dropped, the same as [`KeyGuard`]. it is the same type with or without
+/// the `parking_lot` feature.
+pub struct StoreReader<'a, KeyType> {
+    pub(super) guard: RwLockReadGuard<'a, BTreeMap<u64, KeyType>>,
+}
+
+impl<'a, KeyType> std::ops::Deref for StoreReader<'a, KeyType> {
+    type Target = BTreeMap<u64, KeyType>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<'a, KeyType> fmt::Debug for StoreReader<'a, KeyType> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StoreReader")
+            .field("len", &self.guard.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<KeyType> Local<KeyType> {
+    /// every key in the store in ascending version order, including staged
+    /// versions. access times are not updated.
+    pub fn iter(&self) -> Result<Iter<'_, KeyType>, Error> {
+        Ok(Iter {
+            guard: self.store.read()?,
+        })
+    }
+
+    /// the key of `version` without copying it, see [`KeyGuard`]. like
+    /// [`iter`](Local::iter) access times are not updated and the lookup is
+    /// not recorded.
+    pub fn get_ref(&self, version: &u64) -> Result<Option<KeyGuard<'_, KeyType>>, Error> {
+        let guard = self.store.read()?;
+
+        if !guard.contains_key(version) {
+            return Ok(None);
+        }
+
+        Ok(Some(KeyGuard {
+            guard,
+            version: *version,
+        }))
+    }
+
+    /// [`get_ref`](Local::get_ref) for the key `latest` would return
+    pub fn latest_ref(&self) -> Result<Option<KeyGuard<'_, KeyType>>, Error> {
+        let guard = self.store.read()?;
+
+        let Some(version) = self.latest_entry(&guard)?.map(|(version, _)| *version) else {
+            return Ok(None);
+        };
+
+        Ok(Some(KeyGuard {
+            guard,
+            version,
+        }))
+    }
+
+    /// [`iter`](Local::iter) that gives a [`VersionedRef`] for each key
+    pub fn iter_versioned(&self) -> Result<IterVersioned<'_, KeyType>, Error> {
+        Ok(IterVersioned {
+            guard: self.store.read()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn empty() {
+        let local: Local<u64> = Local::new();
+        let iter = local.iter().unwrap();
+
+        assert!(iter.is_empty());
+        assert_eq!(iter.iter().next(), None);
+        assert_eq!(local.iter_versioned().unwrap().iter().count(), 0);
+    }
+
+    #[test]
+    fn snapshot_of_guard() {
+        let local = Local::new();
+
+        for value in [10, 20, 30] {
+            local.update(value).unwrap();
+        }
+
+        local.drop(&2).unwrap();
+
+        {
+            let iter = local.iter().unwrap();
+            let mut seen = Vec::new();
+
+            for (version, key) in &iter {
+                seen.push((*version, *key));
+            }
+
+            assert_eq!(seen, [(1, 10), (3, 30)]);
+
+            // writers wait for the guard
+            assert!(local.store.try_write().is_err());
+        }
+
+        local.update(40).unwrap();
+
+        let versioned = local.iter_versioned().unwrap();
+        let seen: Vec<(u64, u64)> = versioned.iter()
+            .map(|key| (*key.version(), *key))
+            .collect();
+
+        assert_eq!(seen, [(1, 10), (3, 30), (4, 40)]);
+        assert_eq!(versioned.iter().next_back().unwrap().key_ref().to_string(), "00000000000000000004");
+        assert_eq!(versioned.len(), 3);
+    }
+
+    #[test]
+    fn key_guard() {
+        let local: Local<u64> = Local::new();
+
+        assert!(local.latest_ref().unwrap().is_none());
+
+        local.update(10).unwrap();
+        local.update(20).unwrap();
+
+        {
+            let latest = local.latest_ref().unwrap().unwrap();
+
+            assert_eq!((*latest.version(), *latest), (2, 20));
+            assert!(local.store.try_write().is_err());
+        }
+
+        // dropping the guard released the lock
+        assert!(local.store.try_write().is_ok());
+
+        local.stage(30).unwrap();
+
+        assert_eq!(*local.get_ref(&3).unwrap().unwrap(), 30);
+        assert_eq!(*local.latest_ref().unwrap().unwrap().version(), 2);
+        assert!(local.get_ref(&4).unwrap().is_none());
+        assert_eq!(local.update(40).unwrap(), 4);
+    }
+}
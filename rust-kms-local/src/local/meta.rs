@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+
+use super::{Local, Error, Added, Outcome};
+use crate::hooks::Op;
+
+/// labels kept with a version outside of its key, e.g. who added it or the
+/// environment it is for
+pub type Meta = BTreeMap<String, String>;
+
+impl<KeyType> Local<KeyType> {
+    /// [`update`](Local::update) that adds the key with the metadata
+    pub fn update_with_meta(&self, key: KeyType, meta: Meta) -> Result<u64, Error> {
+        let _timer = self.timer(Op::Update);
+
+        let result = self.insert_with(key, Added {
+            meta,
+            ..Added::default()
+        });
+
+        match &result {
+            Ok(version) => self.record(Op::Update, Some(*version), Outcome::Ok),
+            Err(_) => self.record(Op::Update, None, Outcome::Failed),
+        }
+
+        result
+    }
+
+    /// the metadata of the version, empty if it has none. `None` if the
+    /// version is not in the store.
+    pub fn meta(&self, version: &u64) -> Result<Option<Meta>, Error> {
+        let store_reader = self.store.read()?;
+
+        if !store_reader.contains_key(version) {
+            return Ok(None);
+        }
+
+        Ok(Some(self.meta.read()?.get(version).cloned().unwrap_or_default()))
+    }
+
+    /// replaces the metadata of the version and returns what it had. an
+    /// empty map removes it. fails with [`Error::VersionNotFound`] if the
+    /// version does not exist.
+    pub fn set_meta(&self, version: &u64, meta: Meta) -> Result<Meta, Error> {
+        let store_reader = self.store.read()?;
+
+        self.check_frozen()?;
+
+        if !store_reader.contains_key(version) {
+            return Err(Error::VersionNotFound(*version));
+        }
+
+        let mut meta_writer = self.meta.write()?;
+
+        let unchanged = meta_writer.get(version)
+            .map_or(meta.is_empty(), |current| *current == meta);
+
+        if !unchanged {
+            self.changed();
+        }
+
+        let previous = if meta.is_empty() {
+            meta_writer.remove(version)
+        } else {
+            meta_writer.insert(*version, meta)
+        };
+
+        Ok(previous.unwrap_or_default())
+    }
+
+    /// the metadata of every version that has any
+    pub fn all_meta(&self) -> Result<BTreeMap<u64, Meta>, Error> {
+        Ok(self.meta.read()?.clone())
+    }
+}
+
+impl<KeyType> Local<KeyType>
+where
+    KeyType: Clone
+{
+    /// [`get`](Local::get) with the metadata of the version, read under the
+    /// same lock
+    pub fn get_with_meta(&self, version: &u64) -> Result<Option<(KeyType, Meta)>, Error> {
+        let found = self.with_key(version, |key| -> Result<_, Error> {
+            let meta = self.meta.read()?.get(version).cloned().unwrap_or_default();
+
+            Ok((key.clone(), meta))
+        })?;
+
+        found.transpose()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::local::test::sample_local;
+
+    fn labels(pairs: &[(&str, &str)]) -> Meta {
+        pairs.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn meta() {
+        let local = sample_local();
+        let version = local.update_with_meta(100, labels(&[("by", "ops"), ("env", "prod")])).unwrap();
+
+        assert_eq!(local.meta(&version).unwrap(), Some(labels(&[("by", "ops"), ("env", "prod")])));
+        assert_eq!(local.meta(&1).unwrap(), Some(Meta::new()));
+        assert_eq!(local.meta(&99).unwrap(), None);
+        assert_eq!(
+            local.get_with_meta(&version).unwrap(),
+            Some((100, labels(&[("by", "ops"), ("env", "prod")])))
+        );
+        assert_eq!(local.get_with_meta(&99).unwrap(), None);
+
+        let previous = local.set_meta(&version, labels(&[("env", "staging")])).unwrap();
+
+        assert_eq!(previous, labels(&[("by", "ops"), ("env", "prod")]));
+        assert_eq!(local.meta(&version).unwrap(), Some(labels(&[("env", "staging")])));
+        assert!(matches!(local.set_meta(&99, Meta::new()), Err(Error::VersionNotFound(99))));
+
+        local.set_meta(&version, Meta::new()).unwrap();
+
+        assert!(local.all_meta().unwrap().is_empty());
+
+        local.set_meta(&1, labels(&[("env", "prod")])).unwrap();
+        local.drop(&1).unwrap();
+
+        assert!(local.all_meta().unwrap().is_empty());
+    }
+
+    #[test]
+    fn meta_serde() {
+        let local = sample_local();
+        let version = local.update_with_meta(100, labels(&[("env", "prod")])).unwrap();
+
+        let json = serde_json::to_string(&local).unwrap();
+        let and_back: Local<u64> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(and_back.all_meta().unwrap(), local.all_meta().unwrap());
+        assert_eq!(and_back.meta(&version).unwrap(), Some(labels(&[("env", "prod")])));
+
+        // entries without metadata leave the layout as it was
+        local.set_meta(&version, Meta::new()).unwrap();
+
+        let json = serde_json::to_string(&local).unwrap();
+
+        assert!(!json.contains("meta"));
+    }
+}
@@ -0,0 +1,263 @@
+//! the documented behavior of [`Local`] checked against a plain model of
+//! it. every transition is run from many states by a seeded sequence of
+//! operations and the edge cases get a test of their own.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::{Local, Healed, MergeStrategy, Snapshot};
+
+/// what the store is expected to hold
+#[derive(Debug, Default)]
+struct Model {
+    count: u64,
+    store: BTreeMap<u64, u64>,
+    staged: BTreeSet<u64>,
+    tombstoned: BTreeSet<u64>,
+}
+
+impl Model {
+    fn update(&mut self, key: u64) -> u64 {
+        self.count += 1;
+        self.store.insert(self.count, key);
+
+        self.count
+    }
+
+    fn drop(&mut self, version: u64) -> Option<u64> {
+        let removed = self.store.remove(&version);
+
+        if removed.is_some() {
+            self.staged.remove(&version);
+            self.tombstoned.insert(version);
+        }
+
+        removed
+    }
+
+    fn latest(&self) -> Option<(u64, u64)> {
+        self.store.iter()
+            .rev()
+            .find(|(version, _)| !self.staged.contains(version))
+            .map(|(version, key)| (*version, *key))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Transition {
+    Update(u64),
+    Stage(u64),
+    Promote(u64),
+    Drop(u64),
+    Reload,
+}
+
+/// a small linear congruential generator so runs are repeatable without
+/// depending on `rand`
+struct Steps(u64);
+
+impl Steps {
+    fn next(&mut self, below: u64) -> u64 {
+        self.0 = self.0.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+
+        (self.0 >> 33) % below
+    }
+
+    fn transition(&mut self, model: &Model) -> Transition {
+        // versions up to two past the counter so missing versions are hit
+        let version = self.next(model.count + 3);
+
+        match self.next(10) {
+            0..=3 => Transition::Update(self.next(1000)),
+            4 => Transition::Stage(self.next(1000)),
+            5 => Transition::Promote(version),
+            6..=8 => Transition::Drop(version),
+            _ => Transition::Reload,
+        }
+    }
+}
+
+fn reload(local: &Local<u64>) -> Local<u64> {
+    serde_json::from_str(&serde_json::to_string(local).unwrap()).unwrap()
+}
+
+fn check(local: &Local<u64>, model: &Model, step: usize, transition: Transition) {
+    let context = format!("after step {} {:?}", step, transition);
+
+    assert_eq!(local.count().unwrap(), model.count, "count {}", context);
+    assert_eq!(local.versions().unwrap(), model.store.keys().copied().collect::<Vec<_>>(), "versions {}", context);
+    assert_eq!(local.staged().unwrap(), model.staged, "staged {}", context);
+    assert_eq!(
+        local.latest_version().unwrap().map(|found| (found.0, found.1)),
+        model.latest(),
+        "latest {}",
+        context
+    );
+
+    for version in &model.tombstoned {
+        assert!(local.tombstones().unwrap().contains_key(version), "tombstone of {} {}", version, context);
+    }
+
+    assert!(
+        local.versions().unwrap().last().is_none_or(|highest| *highest <= model.count),
+        "count below the highest version {}",
+        context
+    );
+}
+
+#[test]
+fn transitions() {
+    for seed in 0..20 {
+        let mut steps = Steps(seed);
+        let mut local: Local<u64> = Local::new();
+        let mut model = Model::default();
+
+        for step in 0..300 {
+            let transition = steps.transition(&model);
+
+            match transition {
+                Transition::Update(key) => {
+                    assert_eq!(local.update(key).unwrap(), model.update(key));
+                }
+                Transition::Stage(key) => {
+                    let version = model.update(key);
+
+                    model.staged.insert(version);
+
+                    assert_eq!(local.stage(key).unwrap(), version);
+                }
+                Transition::Promote(version) => {
+                    let result = local.promote(&version);
+
+                    if model.store.contains_key(&version) {
+                        assert_eq!(result.unwrap(), model.staged.remove(&version));
+                    } else {
+                        assert!(result.is_err(), "promoted a missing version");
+                    }
+                }
+                Transition::Drop(version) => {
+                    assert_eq!(local.drop(&version).unwrap(), model.drop(version));
+                }
+                Transition::Reload => {
+                    local = reload(&local);
+                }
+            }
+
+            check(&local, &model, step, transition);
+        }
+    }
+}
+
+#[test]
+fn latest_falls_back_to_next_highest() {
+    let local: Local<u64> = Local::new();
+
+    local.update(10).unwrap();
+    local.update(20).unwrap();
+    local.update(30).unwrap();
+    local.drop(&3).unwrap();
+
+    assert_eq!(local.latest().unwrap(), Some(20));
+
+    local.drop(&1).unwrap();
+    local.drop(&2).unwrap();
+
+    assert_eq!(local.latest().unwrap(), None);
+    assert_eq!(local.update(40).unwrap(), 4, "dropping lowered the counter");
+}
+
+#[test]
+fn drop_missing_is_none() {
+    let local: Local<u64> = Local::new();
+
+    local.update(10).unwrap();
+
+    assert_eq!(local.drop(&5).unwrap(), None);
+    assert_eq!(local.drop(&0).unwrap(), None);
+    assert!(local.tombstones().unwrap().is_empty());
+    assert_eq!(local.count().unwrap(), 1);
+
+    local.drop(&1).unwrap();
+
+    assert_eq!(local.drop(&1).unwrap(), None, "dropped twice");
+}
+
+#[test]
+fn count_with_gaps_is_kept() {
+    let local: Local<u64> = serde_json::from_str(r#"{"count":5,"store":{"1":10,"3":30}}"#).unwrap();
+
+    assert_eq!(local.count().unwrap(), 5);
+    assert_eq!(local.update(60).unwrap(), 6);
+}
+
+#[test]
+fn count_too_low_on_load() {
+    let json = r#"{"count":1,"store":{"1":10,"4":40}}"#;
+    let error = serde_json::from_str::<Local<u64>>(json).unwrap_err();
+
+    assert!(error.to_string().contains("CountTooLow 1 below version 4"), "{}", error);
+
+    // non-contiguous stores load as long as the counter is past them
+    assert!(serde_json::from_str::<Local<u64>>(r#"{"count":4,"store":{"1":10,"4":40}}"#).is_ok());
+}
+
+#[test]
+fn count_healed_on_load() {
+    let Healed(local) = serde_json::from_str::<Healed<u64>>(r#"{"count":1,"store":{"1":10,"4":40}}"#).unwrap();
+
+    assert_eq!(local.count().unwrap(), 4);
+    assert_eq!(local.update(50).unwrap(), 5);
+    assert_eq!(local.get(&4).unwrap(), Some(40), "update replaced a loaded key");
+
+    let json = r#"{
+        "count": 2,
+        "store": {"1": 10},
+        "reserved": {"6": "Outstanding"},
+        "tombstones": {"9": {"at": 0, "note": null, "evicted": false}}
+    }"#;
+
+    assert!(serde_json::from_str::<Local<u64>>(json).is_err(), "reservations and tombstones are not checked");
+
+    let local = serde_json::from_str::<Healed<u64>>(json).unwrap().into_inner();
+
+    assert_eq!(local.count().unwrap(), 9);
+    assert_eq!(local.update(100).unwrap(), 10);
+}
+
+#[test]
+fn count_too_low_rejected_from_snapshot() {
+    let json = r#"{"count":3,"store":{"1":10,"10":100}}"#;
+
+    let Healed(healed) = serde_json::from_str::<Healed<u64>>(json).unwrap();
+
+    assert_eq!(healed.count().unwrap(), 10);
+
+    let snapshot: Snapshot<u64> = serde_json::from_str(json).unwrap();
+
+    assert!(matches!(
+        Local::try_from(snapshot),
+        Err(super::Error::CountTooLow { count: 3, highest: 10 })
+    ));
+
+    // gaps are fine as long as the counter is past them
+    let snapshot: Snapshot<u64> = serde_json::from_str(r#"{"count":12,"store":{"1":10,"10":100}}"#).unwrap();
+    let local = Local::try_from(snapshot).unwrap();
+
+    assert_eq!(local.update(130).unwrap(), 13);
+}
+
+#[test]
+fn count_healed_on_reconcile() {
+    let local: Local<u64> = Local::new();
+
+    local.update(10).unwrap();
+
+    let remote = Snapshot {
+        count: 0,
+        store: [(7, 70)].into(),
+    };
+
+    local.reconcile(remote, MergeStrategy::PreferLocal).unwrap();
+
+    assert_eq!(local.count().unwrap(), 7);
+    assert_eq!(local.update(80).unwrap(), 8);
+}
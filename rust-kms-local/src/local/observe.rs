@@ -0,0 +1,116 @@
+use std::sync::{mpsc, Arc};
+
+use super::{Local, Error, Change};
+
+impl<KeyType> Local<KeyType> {
+    /// adds a callback that is given every change to the store, after the
+    /// one set with [`LocalBuilder::on_change`](super::LocalBuilder::on_change)
+    /// and in the order they were added. like that one it is called once
+    /// the locks of the store are released so it can read the store, and a
+    /// panic in it reaches the caller of the change without poisoning the
+    /// store.
+    pub fn observe<F>(&self, callback: F) -> Result<(), Error>
+    where
+        F: Fn(Change) + Send + Sync + 'static
+    {
+        self.observers.write()?.push(Arc::new(callback));
+
+        Ok(())
+    }
+
+    /// a channel that is sent every change to the store once it is made,
+    /// after the callbacks. dropping the receiver unsubscribes it.
+    pub fn subscribe(&self) -> Result<mpsc::Receiver<Change>, Error> {
+        let (sender, receiver) = mpsc::channel();
+
+        self.subscribers.lock()?.push(sender);
+
+        Ok(receiver)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn observers() {
+        let local: Arc<Local<u64>> = Arc::new(Local::new());
+        let (sender, receiver) = mpsc::channel();
+
+        for observer in 0..2 {
+            let sender = sender.clone();
+            let reader = Arc::downgrade(&local);
+
+            local.observe(move |change| {
+                // the store can be read from inside the callback
+                let latest = reader.upgrade().unwrap().latest_version().unwrap().map(|found| found.0);
+
+                sender.send((observer, change, latest)).unwrap();
+            }).unwrap();
+        }
+
+        local.update(10).unwrap();
+        local.update(20).unwrap();
+        local.as_ref().drop(&2).unwrap();
+
+        let events: Vec<_> = receiver.try_iter().collect();
+
+        assert_eq!(events, vec![
+            (0, Change::Updated(1), Some(1)),
+            (1, Change::Updated(1), Some(1)),
+            (0, Change::Updated(2), Some(2)),
+            (1, Change::Updated(2), Some(2)),
+            (0, Change::Dropped(2), Some(1)),
+            (1, Change::Dropped(2), Some(1)),
+        ]);
+    }
+
+    #[test]
+    fn panicking_observer() {
+        let local: Local<u64> = Local::new();
+
+        local.observe(|change| {
+            if change == Change::Updated(2) {
+                panic!("observer failed");
+            }
+        }).unwrap();
+
+        local.update(10).unwrap();
+
+        let result = catch_unwind(AssertUnwindSafe(|| local.update(20)));
+
+        assert!(result.is_err());
+        assert!(!local.is_poisoned());
+        assert_eq!(local.latest().unwrap(), Some(20));
+        assert_eq!(local.update(30).unwrap(), 3);
+    }
+
+    #[test]
+    fn subscribe() {
+        let local: Local<u64> = Local::new();
+        let receiver = local.subscribe().unwrap();
+        let dropped = local.subscribe().unwrap();
+
+        drop(dropped);
+
+        let subscriber = thread::spawn(move || {
+            let first = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+            let second = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+
+            (first, second)
+        });
+
+        local.update(10).unwrap();
+        local.drop(&1).unwrap();
+
+        assert_eq!(subscriber.join().unwrap(), (Change::Updated(1), Change::Dropped(1)));
+
+        // the receivers are gone and changes carry on without them
+        assert_eq!(local.update(20).unwrap(), 2);
+        assert!(local.subscribers.lock().unwrap().is_empty());
+    }
+}
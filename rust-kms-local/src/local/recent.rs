@@ -0,0 +1,165 @@
+use std::collections::VecDeque;
+use std::sync::{Mutex, PoisonError};
+
+use super::{Local, Error, unix_now};
+use crate::hooks::Op;
+
+/// how an operation in [`Local::recent_ops`] ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    /// the version or a latest key was not in the store
+    NotFound,
+    /// the operation returned an error
+    Failed,
+}
+
+/// an operation kept by [`Local::recent_ops`]. never holds key data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpRecord {
+    pub op: Op,
+    /// the version the operation was for or ended up with, if known
+    pub version: Option<u64>,
+    /// seconds since the unix epoch when the operation finished
+    pub at: u64,
+    pub outcome: Outcome,
+}
+
+/// the last `capacity` operations, oldest first
+pub(super) struct RecentOps {
+    capacity: usize,
+    records: Mutex<VecDeque<OpRecord>>,
+}
+
+impl RecentOps {
+    pub(super) fn new(capacity: usize) -> Self {
+        RecentOps {
+            capacity,
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    fn push(&self, record: OpRecord) {
+        // a panic while pushing cannot leave the buffer half written
+        let mut records = self.records.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+
+        records.push_back(record);
+    }
+}
+
+impl<KeyType> Local<KeyType> {
+    /// the last operations on the store, oldest first, up to the capacity
+    /// given to [`recent_ops`](super::LocalBuilder::recent_ops). empty when
+    /// it was not set. `update`, `drop`, `get`, `latest` and their
+    /// `_version` variants are recorded, as is each version removed by
+    /// `prune_before`, `retain` and `clear`.
+    pub fn recent_ops(&self) -> Vec<OpRecord> {
+        let Some(recent) = &self.recent else {
+            return Vec::new();
+        };
+
+        let records = recent.records.lock().unwrap_or_else(PoisonError::into_inner);
+
+        records.iter().copied().collect()
+    }
+
+    /// records an operation when recent operations are kept. called once
+    /// the locks of the store are released.
+    pub(super) fn record(&self, op: Op, version: Option<u64>, outcome: Outcome) {
+        if let Some(recent) = &self.recent {
+            recent.push(OpRecord {
+                op,
+                version,
+                at: unix_now(),
+                outcome,
+            });
+        }
+    }
+
+    /// records an operation that looked up `version`
+    pub(super) fn record_lookup<T>(&self, op: Op, version: Option<u64>, result: &Result<Option<T>, Error>) {
+        let outcome = match result {
+            Ok(Some(_)) => Outcome::Ok,
+            Ok(None) => Outcome::NotFound,
+            Err(_) => Outcome::Failed,
+        };
+
+        self.record(op, version, outcome);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn tail_in_order() {
+        let local: Local<u64> = Local::builder()
+            .recent_ops(4)
+            .build()
+            .unwrap();
+
+        local.update(10).unwrap();
+        local.update(20).unwrap();
+        local.get(&1).unwrap();
+        local.drop(&2).unwrap();
+        local.get(&2).unwrap();
+        local.latest().unwrap();
+
+        let recent: Vec<_> = local.recent_ops()
+            .into_iter()
+            .map(|record| (record.op, record.version, record.outcome))
+            .collect();
+
+        assert_eq!(recent, vec![
+            (Op::Get, Some(1), Outcome::Ok),
+            (Op::Drop, Some(2), Outcome::Ok),
+            (Op::Get, Some(2), Outcome::NotFound),
+            (Op::Latest, Some(1), Outcome::Ok),
+        ]);
+
+        let _guard = local.freeze();
+
+        assert!(local.update(30).is_err());
+        assert_eq!(local.recent_ops().last().unwrap().outcome, Outcome::Failed);
+        assert!(Local::<u64>::new().recent_ops().is_empty());
+    }
+
+    #[test]
+    fn bounded_under_concurrent_writers() {
+        let local: Arc<Local<u64>> = Arc::new(Local::builder()
+            .recent_ops(16)
+            .build()
+            .unwrap());
+
+        let handles: Vec<_> = (0..4).map(|_| {
+            let local = local.clone();
+
+            std::thread::spawn(move || {
+                for n in 0..100 {
+                    local.update(n).unwrap();
+                }
+            })
+        }).collect();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let recent = local.recent_ops();
+
+        assert_eq!(recent.len(), 16);
+        assert!(recent.iter().all(|record| record.op == Op::Update && record.outcome == Outcome::Ok));
+
+        let versions: std::collections::BTreeSet<u64> = recent.iter()
+            .map(|record| record.version.unwrap())
+            .collect();
+
+        assert_eq!(versions.len(), 16);
+    }
+}
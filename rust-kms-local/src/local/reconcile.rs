@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+
+use super::{Local, Error, Change};
+
+/// a point in time copy of the keys in a [`Local`], used to exchange stores
+/// between sites. access times and pending drops are not included.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot<KeyType> {
+    pub count: u64,
+    pub store: BTreeMap<u64, KeyType>,
+}
+
+/// decides which key is kept when both stores have the same version with
+/// different keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// keep the local key
+    PreferLocal,
+    /// replace the local key with the remote key
+    PreferRemote,
+    /// fail with [`Error::Conflict`] without changing the store
+    Error,
+}
+
+/// the versions affected by [`Local::reconcile`]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ReconcileReport {
+    /// versions only present in the remote that were added
+    pub inserted: Vec<u64>,
+    /// versions present in both with different keys, resolved by the
+    /// strategy
+    pub conflicting: Vec<u64>,
+    /// versions present in both with equal keys
+    pub skipped: Vec<u64>,
+    /// versions only present in the remote that were dropped or evicted
+    /// locally. they are not added back and keep their tombstones.
+    pub tombstoned: Vec<u64>,
+}
+
+impl<KeyType> Local<KeyType>
+where
+    KeyType: Clone
+{
+    /// copies the counter and keys of the store
+    pub fn snapshot(&self) -> Result<Snapshot<KeyType>, Error> {
+        let version_lock = self.count.lock()?;
+        let store_reader = self.store.read()?;
+
+        Ok(Snapshot {
+            count: *version_lock,
+            store: store_reader.clone(),
+        })
+    }
+}
+
+impl<KeyType> Local<KeyType>
+where
+    KeyType: PartialEq
+{
+    /// merges the keys of a remote snapshot into the store without
+    /// renumbering anything.
+    ///
+    /// versions only in the remote are added at their original numbers and
+    /// the counter is moved up to the remote counter or the highest added
+    /// version if either is higher.
+    /// versions in both stores are expected to hold the same key, if they
+    /// do not the strategy decides which is kept.
+    ///
+    /// this assumes the sites share a version allocation scheme, e.g. each
+    /// site hands out versions from its own disjoint range, so that the same
+    /// version number always refers to the same key. a version that still
+    /// has a local tombstone is not added back so that drops propagate, it
+    /// is reported in [`tombstoned`](ReconcileReport::tombstoned). use
+    /// [`import`](Local::import) to bring it back on purpose.
+    pub fn reconcile(
+        &self,
+        remote: Snapshot<KeyType>,
+        strategy: MergeStrategy
+    ) -> Result<ReconcileReport, Error> {
+        let mut report = ReconcileReport::default();
+
+        let evicted = {
+            let mut version_lock = self.count.lock()?;
+            let mut store_writer = self.store.write()?;
+
+            self.check_frozen()?;
+
+            if strategy == MergeStrategy::Error {
+                let conflict = remote.store.iter()
+                    .find(|(version, key)| store_writer.get(version)
+                        .is_some_and(|local| local != *key));
+
+                if let Some((version, _)) = conflict {
+                    return Err(Error::Conflict(*version));
+                }
+            }
+
+            let mut accessed_writer = self.accessed.write()?;
+            let mut pending_writer = self.pending.write()?;
+            let tombstones_reader = self.tombstones.read()?;
+
+            for (version, key) in remote.store {
+                match store_writer.get_mut(&version) {
+                    Some(local) if *local == key => {
+                        report.skipped.push(version);
+                    }
+                    Some(local) => {
+                        report.conflicting.push(version);
+
+                        if strategy == MergeStrategy::PreferRemote {
+                            *local = key;
+
+                            accessed_writer.remove(&version);
+                        }
+                    }
+                    None if tombstones_reader.contains_key(&version) => {
+                        report.tombstoned.push(version);
+                    }
+                    None => {
+                        store_writer.insert(version, key);
+
+                        report.inserted.push(version);
+                    }
+                }
+            }
+
+            drop(tombstones_reader);
+
+            let before = *version_lock;
+
+            *version_lock = (*version_lock)
+                .max(remote.count)
+                .max(report.inserted.last().copied().unwrap_or(0));
+
+            // a counter raised without a key being added is not notified
+            if *version_lock > before && report.inserted.is_empty() {
+                self.changed();
+            }
+
+            self.evict_over_max(&mut store_writer, &mut accessed_writer, &mut pending_writer)?
+        };
+
+        for version in &report.inserted {
+            if !evicted.contains(version) {
+                self.notify(Change::Updated(*version));
+            }
+        }
+
+        if strategy == MergeStrategy::PreferRemote {
+            for version in &report.conflicting {
+                if !evicted.contains(version) {
+                    self.notify(Change::Updated(*version));
+                }
+            }
+        }
+
+        self.notify_evicted(evicted);
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn site(entries: &[(u64, u64)]) -> Local<u64> {
+        let local = Local::new();
+
+        {
+            let mut store = local.store.write().unwrap();
+            let mut count = local.count.lock().unwrap();
+
+            for (version, key) in entries {
+                store.insert(*version, *key);
+                *count = (*count).max(*version);
+            }
+        }
+
+        local
+    }
+
+    #[test]
+    fn disjoint() {
+        let local = site(&[(1, 10), (3, 30)]);
+        let remote = site(&[(2, 20), (4, 40)]);
+
+        let report = local.reconcile(remote.snapshot().unwrap(), MergeStrategy::Error)
+            .expect("failed to reconcile");
+
+        assert_eq!(report.inserted, vec![2, 4]);
+        assert!(report.conflicting.is_empty());
+        assert!(report.skipped.is_empty());
+        assert_eq!(local.count().unwrap(), 4);
+        assert_eq!(local.get(&2).unwrap(), Some(20));
+        assert_eq!(local.get(&4).unwrap(), Some(40));
+
+        local.update(50).unwrap();
+
+        assert_eq!(local.latest_version().unwrap().unwrap().version(), &5);
+    }
+
+    #[test]
+    fn overlapping_equal() {
+        let local = site(&[(1, 10), (2, 20)]);
+        let remote = site(&[(1, 10), (2, 20), (3, 30)]);
+
+        for strategy in [MergeStrategy::PreferLocal, MergeStrategy::PreferRemote, MergeStrategy::Error] {
+            let local = site(&[(1, 10), (2, 20)]);
+            let report = local.reconcile(remote.snapshot().unwrap(), strategy)
+                .expect("failed to reconcile");
+
+            assert_eq!(report.inserted, vec![3]);
+            assert_eq!(report.skipped, vec![1, 2]);
+            assert!(report.conflicting.is_empty());
+        }
+
+        local.reconcile(remote.snapshot().unwrap(), MergeStrategy::Error).unwrap();
+
+        assert_eq!(local.snapshot().unwrap(), remote.snapshot().unwrap());
+    }
+
+    #[test]
+    fn overlapping_conflicting() {
+        let remote = site(&[(1, 10), (2, 99), (3, 30)]);
+
+        let local = site(&[(1, 10), (2, 20)]);
+        let report = local.reconcile(remote.snapshot().unwrap(), MergeStrategy::PreferLocal)
+            .expect("failed to reconcile");
+
+        assert_eq!(report.inserted, vec![3]);
+        assert_eq!(report.conflicting, vec![2]);
+        assert_eq!(report.skipped, vec![1]);
+        assert_eq!(local.get(&2).unwrap(), Some(20));
+
+        let local = site(&[(1, 10), (2, 20)]);
+        let report = local.reconcile(remote.snapshot().unwrap(), MergeStrategy::PreferRemote)
+            .expect("failed to reconcile");
+
+        assert_eq!(report.conflicting, vec![2]);
+        assert_eq!(local.get(&2).unwrap(), Some(99));
+
+        let local = site(&[(1, 10), (2, 20)]);
+
+        assert!(matches!(
+            local.reconcile(remote.snapshot().unwrap(), MergeStrategy::Error),
+            Err(Error::Conflict(2))
+        ));
+        assert_eq!(local.count().unwrap(), 2);
+        assert_eq!(local.get(&2).unwrap(), Some(20));
+        assert_eq!(local.get(&3).unwrap(), None);
+    }
+
+    #[test]
+    fn dropped_locally() {
+        let local = site(&[(1, 10), (2, 20), (3, 30)]);
+        let remote = site(&[(1, 10), (2, 20), (3, 30)]);
+
+        local.drop(&2).unwrap();
+
+        let report = local.reconcile(remote.snapshot().unwrap(), MergeStrategy::Error)
+            .expect("failed to reconcile");
+
+        assert!(report.inserted.is_empty());
+        assert_eq!(report.skipped, vec![1, 3]);
+        assert_eq!(report.tombstoned, vec![2]);
+        assert_eq!(local.get(&2).unwrap(), None);
+        assert!(local.tombstones().unwrap().contains_key(&2));
+
+        local.drop(&1).unwrap();
+
+        let report = local.reconcile(remote.snapshot().unwrap(), MergeStrategy::Error)
+            .expect("failed to reconcile");
+
+        assert!(report.inserted.is_empty());
+        assert_eq!(report.skipped, vec![3]);
+        assert_eq!(report.tombstoned, vec![1, 2]);
+        assert_eq!(local.snapshot().unwrap().store, BTreeMap::from([(3, 30)]));
+
+        // the remote picks up the drops the same way once it drops them
+        remote.drop(&1).unwrap();
+        remote.drop(&2).unwrap();
+
+        assert_eq!(local.snapshot().unwrap(), remote.snapshot().unwrap());
+    }
+}
@@ -0,0 +1,129 @@
+use std::sync::PoisonError;
+
+use super::{Local, Error, healed_count};
+
+impl<KeyType> Local<KeyType> {
+    /// if a thread panicked while changing the store. every method that
+    /// locks the store fails with [`Error::Poisoned`] until
+    /// [`recover`](Local::recover) is called.
+    pub fn is_poisoned(&self) -> bool {
+        self.count.is_poisoned() ||
+            self.store.is_poisoned() ||
+            self.accessed.is_poisoned() ||
+            self.pending.is_poisoned() ||
+            self.reserved.is_poisoned() ||
+            self.tombstones.is_poisoned() ||
+            self.staged.is_poisoned() ||
+            self.disabled.is_poisoned() ||
+            self.meta.is_poisoned()
+    }
+
+    /// clears the poison left by a thread that panicked while changing the
+    /// store so it can be used again.
+    ///
+    /// a panic cannot leave a single map half written but it can stop a
+    /// change between two of them, e.g. after a key was added and before
+    /// the counter was raised. the store is repaired the same way as when
+    /// it is loaded as [`Healed`](super::Healed): the counter is raised to
+    /// the highest version handed out and state of versions that are no
+    /// longer in the store is removed. with the `parking_lot` feature the locks are never poisoned
+    /// but the repair is still done.
+    pub fn recover(&self) -> Result<(), Error> {
+        let mut version_lock = self.count.lock().unwrap_or_else(PoisonError::into_inner);
+        let store_reader = self.store.read().unwrap_or_else(PoisonError::into_inner);
+        let mut accessed_writer = self.accessed.write().unwrap_or_else(PoisonError::into_inner);
+        let mut pending_writer = self.pending.write().unwrap_or_else(PoisonError::into_inner);
+        let mut reserved_writer = self.reserved.write().unwrap_or_else(PoisonError::into_inner);
+        let mut tombstones_writer = self.tombstones.write().unwrap_or_else(PoisonError::into_inner);
+        let mut staged_writer = self.staged.write().unwrap_or_else(PoisonError::into_inner);
+        let mut disabled_writer = self.disabled.write().unwrap_or_else(PoisonError::into_inner);
+        let mut meta_writer = self.meta.write().unwrap_or_else(PoisonError::into_inner);
+
+        let count = healed_count(*version_lock, &store_reader, &reserved_writer, &tombstones_writer);
+        let before = pending_writer.len() + reserved_writer.len() + tombstones_writer.len() +
+            staged_writer.len() + disabled_writer.len() + meta_writer.len();
+
+        accessed_writer.retain(|version, _| store_reader.contains_key(version));
+        pending_writer.retain(|version, _| store_reader.contains_key(version));
+        reserved_writer.retain(|version, _| !store_reader.contains_key(version));
+        tombstones_writer.retain(|version, _| !store_reader.contains_key(version));
+        staged_writer.retain(|version| store_reader.contains_key(version));
+        disabled_writer.retain(|version| store_reader.contains_key(version));
+        meta_writer.retain(|version, _| store_reader.contains_key(version));
+
+        let after = pending_writer.len() + reserved_writer.len() + tombstones_writer.len() +
+            staged_writer.len() + disabled_writer.len() + meta_writer.len();
+
+        // a panic can stop a change before it was notified
+        if count != *version_lock || after != before {
+            self.changed();
+        }
+
+        *version_lock = count;
+
+        self.count.clear_poison();
+        self.store.clear_poison();
+        self.accessed.clear_poison();
+        self.pending.clear_poison();
+        self.reserved.clear_poison();
+        self.tombstones.clear_poison();
+        self.staged.clear_poison();
+        self.disabled.clear_poison();
+        self.meta.clear_poison();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    #[test]
+    fn recover() {
+        let local: Local<u64> = Local::new();
+
+        local.update(10).unwrap();
+
+        // a change that panics after the key was added but before the
+        // counter was raised
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let _version_lock = local.count.lock().unwrap();
+            let mut store_writer = local.store.write().unwrap();
+
+            store_writer.insert(2, 20);
+
+            panic!("interrupted change");
+        }));
+
+        assert!(result.is_err());
+
+        #[cfg(not(feature = "parking_lot"))]
+        {
+            assert!(local.is_poisoned());
+            assert!(matches!(local.get(&1), Err(Error::Poisoned)));
+            assert!(matches!(local.latest(), Err(Error::Poisoned)));
+        }
+
+        // the same store keeps working as if nothing happened
+        #[cfg(feature = "parking_lot")]
+        {
+            assert!(!local.is_poisoned());
+            assert_eq!(local.get(&1).unwrap(), Some(10));
+            assert_eq!(local.count().unwrap(), 1);
+        }
+
+        local.recover().unwrap();
+
+        assert!(!local.is_poisoned());
+        assert_eq!(local.get(&1).unwrap(), Some(10));
+        assert_eq!(local.latest().unwrap(), Some(20));
+        assert_eq!(local.count().unwrap(), 2);
+        assert_eq!(local.update(30).unwrap(), 3);
+
+        local.recover().unwrap();
+
+        assert_eq!(local.versions().unwrap(), vec![1, 2, 3]);
+    }
+}
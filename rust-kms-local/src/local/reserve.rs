@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+
+use super::{Local, Error, Change};
+
+/// the state of a reserved version that has no key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Reserved {
+    /// waiting for [`Local::fulfill`] or [`Local::abandon`]
+    Outstanding,
+    /// will never be given a key
+    Abandoned,
+}
+
+/// a version handed out by [`Local::reserve`] that has no key yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reservation {
+    version: u64,
+}
+
+impl Reservation {
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+impl<KeyType> Local<KeyType> {
+    /// allocates the next version without a key so it can be given to
+    /// another system before the key exists. the version is not used by
+    /// `update` and stays outstanding, including across saves, until it is
+    /// fulfilled or abandoned.
+    pub fn reserve(&self) -> Result<Reservation, Error> {
+        let mut version_lock = self.count.lock()?;
+
+        self.check_frozen()?;
+
+        let mut reserved_writer = self.reserved.write()?;
+
+        let version = super::next_version(*version_lock)?;
+
+        reserved_writer.insert(version, Reserved::Outstanding);
+        *version_lock = version;
+        self.changed();
+
+        Ok(Reservation { version })
+    }
+
+    /// adds the key at the reserved version. fails with
+    /// [`Error::NotReserved`] if the reservation was already fulfilled or
+    /// abandoned and [`Error::Occupied`] if the version has a key.
+    pub fn fulfill(&self, reservation: Reservation, key: KeyType) -> Result<(), Error> {
+        let version = reservation.version;
+
+        let evicted = {
+            let mut store_writer = self.store.write()?;
+
+            self.check_frozen()?;
+
+            let mut accessed_writer = self.accessed.write()?;
+            let mut pending_writer = self.pending.write()?;
+            let mut reserved_writer = self.reserved.write()?;
+
+            if reserved_writer.get(&version) != Some(&Reserved::Outstanding) {
+                return Err(Error::NotReserved(version));
+            }
+
+            if store_writer.contains_key(&version) {
+                return Err(Error::Occupied(version));
+            }
+
+            reserved_writer.remove(&version);
+            store_writer.insert(version, key);
+
+            self.evict_over_max(&mut store_writer, &mut accessed_writer, &mut pending_writer)?
+        };
+
+        if !evicted.contains(&version) {
+            self.notify(Change::Updated(version));
+        }
+
+        self.notify_evicted(evicted);
+
+        Ok(())
+    }
+
+    /// marks the reserved version as permanently skipped. it is kept in
+    /// [`Local::reservations`] so the gap can be explained later.
+    pub fn abandon(&self, reservation: Reservation) -> Result<(), Error> {
+        let _store_reader = self.store.read()?;
+
+        self.check_frozen()?;
+
+        let mut reserved_writer = self.reserved.write()?;
+
+        match reserved_writer.get_mut(&reservation.version) {
+            Some(state) if *state == Reserved::Outstanding => {
+                *state = Reserved::Abandoned;
+                self.changed();
+
+                Ok(())
+            }
+            _ => Err(Error::NotReserved(reservation.version))
+        }
+    }
+
+    /// the reservations that are still outstanding, e.g. after a crash
+    /// between reserving and fulfilling
+    pub fn outstanding(&self) -> Result<Vec<Reservation>, Error> {
+        let reserved_reader = self.reserved.read()?;
+
+        Ok(reserved_reader.iter()
+            .filter(|(_, state)| **state == Reserved::Outstanding)
+            .map(|(version, _)| Reservation { version: *version })
+            .collect())
+    }
+
+    /// every reserved version without a key and its state
+    pub fn reservations(&self) -> Result<BTreeMap<u64, Reserved>, Error> {
+        Ok(self.reserved.read()?.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::local::test::{sample_local, TestLocal};
+
+    #[test]
+    fn fulfill() {
+        let local = sample_local();
+        let reservation = local.reserve().unwrap();
+
+        assert_eq!(reservation.version(), 13);
+
+        local.update(100).unwrap();
+
+        assert_eq!(local.latest_version().unwrap().unwrap().version(), &14);
+        assert_eq!(local.get(&13).unwrap(), None);
+
+        local.fulfill(reservation, 50).unwrap();
+
+        assert_eq!(local.get(&13).unwrap(), Some(50));
+        assert!(local.reservations().unwrap().is_empty());
+        assert!(matches!(local.fulfill(reservation, 51), Err(Error::NotReserved(13))));
+        assert!(matches!(local.abandon(reservation), Err(Error::NotReserved(13))));
+        assert_eq!(local.get(&13).unwrap(), Some(50));
+    }
+
+    #[test]
+    fn abandon() {
+        let local = sample_local();
+        let reservation = local.reserve().unwrap();
+
+        local.abandon(reservation).unwrap();
+
+        assert_eq!(local.reservations().unwrap(), BTreeMap::from([(13, Reserved::Abandoned)]));
+        assert!(local.outstanding().unwrap().is_empty());
+        assert!(matches!(local.fulfill(reservation, 1), Err(Error::NotReserved(13))));
+
+        local.update(100).unwrap();
+
+        assert_eq!(local.latest_version().unwrap().unwrap().version(), &14);
+    }
+
+    #[test]
+    fn persisted() {
+        let local = sample_local();
+        let outstanding = local.reserve().unwrap();
+        let abandoned = local.reserve().unwrap();
+
+        local.abandon(abandoned).unwrap();
+
+        let json = serde_json::to_string(&local).unwrap();
+        let and_back: TestLocal = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(and_back.count().unwrap(), 14);
+        assert_eq!(and_back.outstanding().unwrap(), vec![outstanding]);
+        assert_eq!(and_back.reservations().unwrap(), local.reservations().unwrap());
+
+        and_back.fulfill(outstanding, 7).unwrap();
+
+        assert_eq!(and_back.get(&13).unwrap(), Some(7));
+        assert_eq!(and_back.reservations().unwrap(), BTreeMap::from([(14, Reserved::Abandoned)]));
+
+        // stores without reservations do not write the field
+        let json = serde_json::to_value(sample_local()).unwrap();
+
+        assert!(json.get("reserved").is_none());
+    }
+
+    #[test]
+    fn compact() {
+        let local = sample_local();
+        let reservation = local.reserve().unwrap();
+
+        assert!(matches!(local.compact(&[1, 2]), Err(Error::Conflict(13))));
+
+        local.abandon(reservation).unwrap();
+        local.compact(&[1, 2]).unwrap();
+
+        assert!(local.reservations().unwrap().is_empty());
+        assert_eq!(local.count().unwrap(), 2);
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::BTreeSet;
+
+use super::{Local, Error, Change, Added};
+
+impl<KeyType> Local<KeyType> {
+    /// adds the key as a new version that can be fetched with `get` but is
+    /// skipped by `latest` until it is promoted, so it can reach every
+    /// verifier before any signer starts using it. returns the version it
+    /// was given.
+    pub fn stage(&self, key: KeyType) -> Result<u64, Error> {
+        self.insert_with(key, Added {
+            staged: true,
+            ..Added::default()
+        })
+    }
+
+    /// makes a staged version eligible for `latest`. returns false if the
+    /// version was not staged and fails with [`Error::VersionNotFound`] if it
+    /// does not exist.
+    pub fn promote(&self, version: &u64) -> Result<bool, Error> {
+        let promoted = {
+            let store_reader = self.store.read()?;
+
+            self.check_frozen()?;
+
+            if !store_reader.contains_key(version) {
+                return Err(Error::VersionNotFound(*version));
+            }
+
+            self.staged.write()?.remove(version)
+        };
+
+        if promoted {
+            self.notify(Change::Promoted(*version));
+        }
+
+        Ok(promoted)
+    }
+
+    /// the versions that are staged and not yet promoted
+    pub fn staged(&self) -> Result<BTreeSet<u64>, Error> {
+        Ok(self.staged.read()?.clone())
+    }
+
+    pub fn is_staged(&self, version: &u64) -> Result<bool, Error> {
+        Ok(self.staged.read()?.contains(version))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::local::test::sample_local;
+
+    #[test]
+    fn stage_and_promote() {
+        let local = sample_local();
+        let latest = local.latest_version().unwrap().unwrap();
+
+        let version = local.stage(100).unwrap();
+
+        assert_eq!(version, latest.version() + 1);
+        assert!(local.is_staged(&version).unwrap());
+        assert_eq!(local.get(&version).unwrap(), Some(100));
+        assert_eq!(local.latest_version().unwrap().unwrap().version(), latest.version());
+        assert_eq!(local.latest().unwrap(), Some(*latest));
+
+        assert!(local.promote(&version).unwrap());
+        assert!(!local.promote(&version).unwrap());
+        assert!(matches!(local.promote(&99), Err(Error::VersionNotFound(99))));
+
+        assert_eq!(local.latest_version().unwrap().unwrap().version(), &version);
+        assert_eq!(local.latest().unwrap(), Some(100));
+    }
+
+    #[test]
+    fn staged_serde() {
+        let local = sample_local();
+        let version = local.stage(100).unwrap();
+
+        let json = serde_json::to_string(&local).unwrap();
+        let and_back: Local<u64> = serde_json::from_str(&json).unwrap();
+
+        assert!(and_back.is_staged(&version).unwrap());
+        assert_eq!(and_back.latest().unwrap(), local.latest().unwrap());
+
+        // stores written before staging was added are fully promoted
+        local.promote(&version).unwrap();
+
+        let json = serde_json::to_string(&local).unwrap();
+
+        assert!(!json.contains("staged"));
+
+        let and_back: Local<u64> = serde_json::from_str(&json).unwrap();
+
+        assert!(and_back.staged().unwrap().is_empty());
+        assert_eq!(and_back.latest().unwrap(), Some(100));
+    }
+
+    #[test]
+    fn dropped_staged() {
+        let local = sample_local();
+        let version = local.stage(100).unwrap();
+
+        local.drop(&version).unwrap();
+
+        assert!(local.staged().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,91 @@
+//! the locks used by [`Local`](super::Local).
+//!
+//! with the `parking_lot` feature the locks of parking_lot are used behind
+//! the same api as the std locks. they are never poisoned so every result
+//! is `Ok`.
+
+#[cfg(not(feature = "parking_lot"))]
+pub(crate) use std::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+#[cfg(feature = "parking_lot")]
+pub(crate) use parking_lot_locks::*;
+
+#[cfg(feature = "parking_lot")]
+mod parking_lot_locks {
+    use std::fmt;
+    use std::sync::{LockResult, TryLockError, TryLockResult};
+
+    pub use parking_lot::{MutexGuard, RwLockReadGuard, RwLockWriteGuard};
+
+    fn would_block<T>(guard: Option<T>) -> TryLockResult<T> {
+        guard.ok_or(TryLockError::WouldBlock)
+    }
+
+    pub struct Mutex<T>(parking_lot::Mutex<T>);
+
+    impl<T> Mutex<T> {
+        pub fn new(value: T) -> Self {
+            Mutex(parking_lot::Mutex::new(value))
+        }
+
+        pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+            Ok(self.0.lock())
+        }
+
+        pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+            would_block(self.0.try_lock())
+        }
+
+        pub fn is_poisoned(&self) -> bool {
+            false
+        }
+
+        pub fn clear_poison(&self) {}
+    }
+
+    impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+
+    pub struct RwLock<T>(parking_lot::RwLock<T>);
+
+    impl<T> RwLock<T> {
+        pub fn new(value: T) -> Self {
+            RwLock(parking_lot::RwLock::new(value))
+        }
+
+        pub fn read(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+            Ok(self.0.read())
+        }
+
+        pub fn write(&self) -> LockResult<RwLockWriteGuard<'_, T>> {
+            Ok(self.0.write())
+        }
+
+        pub fn try_read(&self) -> TryLockResult<RwLockReadGuard<'_, T>> {
+            would_block(self.0.try_read())
+        }
+
+        pub fn try_write(&self) -> TryLockResult<RwLockWriteGuard<'_, T>> {
+            would_block(self.0.try_write())
+        }
+
+        pub fn into_inner(self) -> LockResult<T> {
+            Ok(self.0.into_inner())
+        }
+
+        pub fn is_poisoned(&self) -> bool {
+            false
+        }
+
+        pub fn clear_poison(&self) {}
+    }
+
+    impl<T: fmt::Debug> fmt::Debug for RwLock<T> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+}
@@ -0,0 +1,393 @@
+use std::sync::{TryLockError, TryLockResult};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use super::{Local, Error, Added, Access, AccessTimes, Outcome, Skipped, VersionedKey, unix_now};
+use crate::hooks::Op;
+
+/// the longest sleep between attempts while waiting for a lock
+const MAX_BACKOFF: Duration = Duration::from_millis(10);
+
+/// calls `attempt` until it gets the lock. with no timeout it is only tried
+/// once and fails with [`Error::WouldBlock`], otherwise it is retried with
+/// a growing sleep until the timeout passes and fails with
+/// [`Error::LockTimeout`].
+fn acquire<T, F>(timeout: Option<Duration>, mut attempt: F) -> Result<T, Error>
+where
+    F: FnMut() -> TryLockResult<T>
+{
+    let start = Instant::now();
+    let mut backoff = Duration::from_micros(50);
+
+    loop {
+        match attempt() {
+            Ok(guard) => return Ok(guard),
+            Err(TryLockError::Poisoned(_)) => return Err(Error::Poisoned),
+            Err(TryLockError::WouldBlock) => {}
+        }
+
+        let Some(timeout) = timeout else {
+            return Err(Error::WouldBlock);
+        };
+
+        let waited = start.elapsed();
+
+        if waited >= timeout {
+            return Err(Error::LockTimeout { waited });
+        }
+
+        std::thread::sleep(backoff.min(timeout - waited));
+
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// [`acquire`] for a lock taken after others. it is bounded by what is left
+/// of `timeout` since `start` and a timeout reports the whole wait.
+fn acquire_rest<T, F>(timeout: Option<Duration>, start: Instant, attempt: F) -> Result<T, Error>
+where
+    F: FnMut() -> TryLockResult<T>
+{
+    acquire(timeout.map(|timeout| timeout.saturating_sub(start.elapsed())), attempt)
+        .map_err(|e| match e {
+            Error::LockTimeout { .. } => Error::LockTimeout { waited: start.elapsed() },
+            e => e
+        })
+}
+
+impl<KeyType> Local<KeyType> {
+    /// [`touch`](Local::touch) that takes the access times with
+    /// [`acquire`]. access times only help to find unused keys so if the
+    /// lock is busy the update is skipped instead of failing the read.
+    fn touch_within(&self, version: u64, timeout: Option<Duration>, start: Instant) -> Result<(), Error> {
+        if !self.config.track_usage {
+            return Ok(());
+        }
+
+        let now = unix_now();
+
+        {
+            let accessed_reader = match acquire_rest(timeout, start, || self.accessed.try_read()) {
+                Ok(reader) => reader,
+                Err(Error::WouldBlock | Error::LockTimeout { .. }) => return Ok(()),
+                Err(e) => return Err(e),
+            };
+
+            if let Some(access) = accessed_reader.get(&version) {
+                access.last.fetch_max(now, Ordering::Relaxed);
+
+                return Ok(());
+            }
+        }
+
+        let mut accessed_writer = match acquire_rest(timeout, start, || self.accessed.try_write()) {
+            Ok(writer) => writer,
+            Err(Error::WouldBlock | Error::LockTimeout { .. }) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        accessed_writer.entry(version)
+            .or_insert_with(|| Access::new(AccessTimes { first: now, last: now }))
+            .last
+            .fetch_max(now, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// [`skipped`](Local::skipped) that takes each lock with
+    /// [`acquire_rest`]
+    fn skipped_within(&self, timeout: Option<Duration>, start: Instant) -> Result<Skipped<'_>, Error> {
+        Ok(Skipped {
+            pending: acquire_rest(timeout, start, || self.pending.try_read())?,
+            staged: acquire_rest(timeout, start, || self.staged.try_read())?,
+            disabled: acquire_rest(timeout, start, || self.disabled.try_read())?,
+        })
+    }
+}
+
+impl<KeyType> Local<KeyType>
+where
+    KeyType: Clone
+{
+    /// [`get`](Local::get) that fails with [`Error::WouldBlock`] instead of
+    /// waiting for the store.
+    ///
+    /// the access time of the version is not waited on either, it is left
+    /// as it was if its lock is busy.
+    pub fn try_get(&self, version: &u64) -> Result<Option<KeyType>, Error> {
+        self.get_within(version, None)
+    }
+
+    /// [`get`](Local::get) that fails with [`Error::LockTimeout`] if the
+    /// store cannot be read within `timeout`.
+    ///
+    /// the access time of the version is left as it was if its lock cannot
+    /// be taken in what is left of `timeout`.
+    pub fn get_timeout(&self, version: &u64, timeout: Duration) -> Result<Option<KeyType>, Error> {
+        self.get_within(version, Some(timeout))
+    }
+
+    fn get_within(&self, version: &u64, timeout: Option<Duration>) -> Result<Option<KeyType>, Error> {
+        let _timer = self.timer(Op::Get);
+
+        let result = self.find_within(version, timeout);
+
+        self.record_lookup(Op::Get, Some(*version), &result);
+
+        result
+    }
+
+    fn find_within(&self, version: &u64, timeout: Option<Duration>) -> Result<Option<KeyType>, Error> {
+        let start = Instant::now();
+
+        let found = {
+            let store_reader = acquire(timeout, || self.store.try_read())?;
+
+            store_reader.get(version).cloned()
+        };
+
+        if found.is_some() {
+            self.touch_within(*version, timeout, start)?;
+        }
+
+        Ok(found)
+    }
+
+    /// [`latest`](Local::latest) that fails with [`Error::WouldBlock`]
+    /// instead of waiting for the store or the versions it skips
+    pub fn try_latest(&self) -> Result<Option<KeyType>, Error> {
+        self.latest_within(None)
+    }
+
+    /// [`latest`](Local::latest) that fails with [`Error::LockTimeout`] if
+    /// the store and the versions it skips cannot be read within `timeout`
+    pub fn latest_timeout(&self, timeout: Duration) -> Result<Option<KeyType>, Error> {
+        self.latest_within(Some(timeout))
+    }
+
+    fn latest_within(&self, timeout: Option<Duration>) -> Result<Option<KeyType>, Error> {
+        let _timer = self.timer(Op::Latest);
+
+        let result = self.find_latest_within(timeout);
+        let version = result.as_ref().ok().and_then(|found| found.as_ref().map(|found| found.0));
+
+        self.record_lookup(Op::Latest, version, &result);
+
+        result.map(|found| found.map(|found| found.1))
+    }
+
+    fn find_latest_within(&self, timeout: Option<Duration>) -> Result<Option<VersionedKey<KeyType>>, Error> {
+        let start = Instant::now();
+
+        let store_reader = acquire(timeout, || self.store.try_read())?;
+        let skipped = self.skipped_within(timeout, start)?;
+
+        let found = store_reader.iter()
+            .rev()
+            .find(|(version, _)| !skipped.contains(version));
+
+        Ok(found.map(|(version, key)| VersionedKey(*version, key.clone())))
+    }
+}
+
+impl<KeyType> Local<KeyType> {
+    /// [`update`](Local::update) that fails with [`Error::WouldBlock`]
+    /// instead of waiting for the counter or the store
+    pub fn try_update(&self, key: KeyType) -> Result<u64, Error> {
+        self.update_within(key, None)
+    }
+
+    /// [`update`](Local::update) that fails with [`Error::LockTimeout`] if
+    /// the counter and the store cannot both be locked within `timeout`.
+    ///
+    /// only those two locks are bounded. the rest are only held for short
+    /// changes and are waited on as usual.
+    pub fn update_timeout(&self, key: KeyType, timeout: Duration) -> Result<u64, Error> {
+        self.update_within(key, Some(timeout))
+    }
+
+    fn update_within(&self, key: KeyType, timeout: Option<Duration>) -> Result<u64, Error> {
+        let _timer = self.timer(Op::Update);
+        let start = Instant::now();
+
+        let result = acquire(timeout, || self.count.try_lock())
+            .and_then(|version_lock| {
+                let store_writer = acquire_rest(timeout, start, || self.store.try_write())?;
+
+                self.insert_locked(version_lock, store_writer, key, Added::default())
+            });
+
+        match &result {
+            Ok(version) => self.record(Op::Update, Some(*version), Outcome::Ok),
+            Err(_) => self.record(Op::Update, None, Outcome::Failed),
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn held_reader() {
+        let local = Local::new();
+
+        local.update(1).unwrap();
+
+        let (held, wait_held) = mpsc::channel();
+        let (release, wait_release) = mpsc::channel::<()>();
+
+        std::thread::scope(|s| {
+            let local = &local;
+
+            s.spawn(move || {
+                let _reader = local.store_reader().unwrap();
+
+                held.send(()).unwrap();
+                wait_release.recv().unwrap();
+            });
+
+            wait_held.recv().unwrap();
+
+            let start = Instant::now();
+
+            assert!(matches!(local.try_update(2), Err(Error::WouldBlock)));
+            assert!(start.elapsed() < Duration::from_millis(100), "try_update waited");
+
+            let result = local.update_timeout(2, Duration::from_millis(20));
+
+            match result {
+                Err(Error::LockTimeout { waited }) => assert!(waited >= Duration::from_millis(20)),
+                _ => panic!("unexpected result: {:?}", result),
+            }
+
+            // readers are not blocked by other readers
+            assert_eq!(local.try_latest().unwrap(), Some(1));
+
+            release.send(()).unwrap();
+        });
+
+        assert_eq!(local.try_update(2).unwrap(), 2);
+        assert_eq!(local.latest_timeout(Duration::from_secs(1)).unwrap(), Some(2));
+    }
+
+    #[test]
+    fn held_writer() {
+        let local = Local::new();
+
+        local.update(1).unwrap();
+
+        let _writer = local.store.write().unwrap();
+
+        assert!(matches!(local.try_latest(), Err(Error::WouldBlock)));
+        assert!(matches!(
+            local.latest_timeout(Duration::from_millis(5)),
+            Err(Error::LockTimeout { .. })
+        ));
+    }
+
+    #[test]
+    fn writer_on_other_thread() {
+        let local = Local::new();
+
+        local.update(1).unwrap();
+
+        let (held, wait_held) = mpsc::channel();
+        let (release, wait_release) = mpsc::channel::<()>();
+
+        std::thread::scope(|s| {
+            let local = &local;
+
+            s.spawn(move || {
+                let _writer = local.store.write().unwrap();
+
+                held.send(()).unwrap();
+                wait_release.recv().unwrap();
+            });
+
+            wait_held.recv().unwrap();
+
+            assert!(matches!(local.try_latest(), Err(Error::WouldBlock)));
+            assert!(matches!(local.try_get(&1), Err(Error::WouldBlock)));
+            assert!(matches!(
+                local.get_timeout(&1, Duration::from_millis(5)),
+                Err(Error::LockTimeout { .. })
+            ));
+
+            release.send(()).unwrap();
+        });
+
+        assert_eq!(local.try_get(&1).unwrap(), Some(1));
+        assert_eq!(local.try_get(&2).unwrap(), None);
+        assert_eq!(local.get_timeout(&1, Duration::from_secs(1)).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn held_skipped() {
+        let local = Local::new();
+
+        local.update(1).unwrap();
+
+        let _writer = local.staged.write().unwrap();
+
+        assert!(matches!(local.try_latest(), Err(Error::WouldBlock)));
+        assert!(matches!(
+            local.latest_timeout(Duration::from_millis(5)),
+            Err(Error::LockTimeout { .. })
+        ));
+    }
+
+    #[test]
+    fn recorded() {
+        let local = Local::builder()
+            .recent_ops(8)
+            .build()
+            .unwrap();
+
+        local.try_update(10).unwrap();
+        local.try_get(&1).unwrap();
+        local.get_timeout(&2, Duration::from_secs(1)).unwrap();
+        local.try_latest().unwrap();
+
+        {
+            let _writer = local.store.write().unwrap();
+
+            local.try_latest().unwrap_err();
+        }
+
+        let recent: Vec<_> = local.recent_ops()
+            .into_iter()
+            .map(|record| (record.op, record.version, record.outcome))
+            .collect();
+
+        assert_eq!(recent, vec![
+            (Op::Update, Some(1), Outcome::Ok),
+            (Op::Get, Some(1), Outcome::Ok),
+            (Op::Get, Some(2), Outcome::NotFound),
+            (Op::Latest, Some(1), Outcome::Ok),
+            (Op::Latest, None, Outcome::Failed),
+        ]);
+    }
+
+    #[test]
+    fn busy_access_times() {
+        let local = Local::new();
+
+        local.update(1).unwrap();
+
+        {
+            let _writer = local.accessed.write().unwrap();
+
+            assert_eq!(local.try_get(&1).unwrap(), Some(1));
+            assert_eq!(local.get_timeout(&1, Duration::from_millis(5)).unwrap(), Some(1));
+        }
+
+        assert_eq!(local.last_accessed(&1).unwrap(), None, "busy access times were waited on");
+
+        assert_eq!(local.try_get(&1).unwrap(), Some(1));
+        assert!(local.last_accessed(&1).unwrap().is_some());
+    }
+}
@@ -0,0 +1,173 @@
+use std::fmt;
+
+use rust_kms_core::traits::Manager;
+
+use super::{Local, Error, VersionedKey};
+use crate::hooks::Op;
+
+/// a read only view of a [`Local`] as if `max_version` was the newest
+/// version it was given. returned by [`Local::view_at`].
+///
+/// nothing is copied, every read goes to the store. changes made to the
+/// store after the view was taken are seen by the view if they are at or
+/// below the pin, e.g. dropping version 2 hides it from a view at 3 while
+/// a new version 31 never shows up in it.
+pub struct View<'a, KeyType> {
+    local: &'a Local<KeyType>,
+    max_version: u64,
+}
+
+impl<'a, KeyType> Clone for View<'a, KeyType> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, KeyType> Copy for View<'a, KeyType> {}
+
+impl<'a, KeyType> fmt::Debug for View<'a, KeyType> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("View")
+            .field("max_version", &self.max_version)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a, KeyType> View<'a, KeyType> {
+    /// the newest version the view can see
+    pub fn max_version(&self) -> u64 {
+        self.max_version
+    }
+
+    /// the store being viewed
+    pub fn local(&self) -> &'a Local<KeyType> {
+        self.local
+    }
+
+    /// the versions at or below the pin in ascending order
+    pub fn versions(&self) -> Result<Vec<u64>, Error> {
+        let store_reader = self.local.store.read()?;
+
+        Ok(store_reader.range(..=self.max_version)
+            .map(|(version, _)| *version)
+            .collect())
+    }
+}
+
+impl<'a, KeyType> View<'a, KeyType>
+where
+    KeyType: Clone
+{
+    /// [`Local::get`] that finds nothing above the pin
+    pub fn get(&self, version: &u64) -> Result<Option<KeyType>, Error> {
+        if *version > self.max_version {
+            return Ok(None);
+        }
+
+        self.local.get(version)
+    }
+
+    /// [`Local::get_version`] that finds nothing above the pin
+    pub fn get_version(&self, version: &u64) -> Result<Option<VersionedKey<KeyType>>, Error> {
+        if *version > self.max_version {
+            return Ok(None);
+        }
+
+        self.local.get_version(version)
+    }
+
+    /// [`Local::latest`] of the versions at or below the pin
+    pub fn latest(&self) -> Result<Option<KeyType>, Error> {
+        Ok(self.latest_version()?.map(|found| found.1))
+    }
+
+    /// [`Local::latest_version`] of the versions at or below the pin
+    pub fn latest_version(&self) -> Result<Option<VersionedKey<KeyType>>, Error> {
+        let _timer = self.local.timer(Op::Latest);
+
+        let store_reader = self.local.store.read()?;
+
+        let Some((version, key)) = self.local.latest_entry_until(&store_reader, self.max_version)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(VersionedKey(*version, key.clone())))
+    }
+}
+
+impl<'a, KeyType> Manager for View<'a, KeyType>
+where
+    KeyType: Clone
+{
+    type Key = KeyType;
+    type Version = u64;
+    type Error = Error;
+
+    fn get(&self, version: u64) -> Result<Self::Key, Self::Error> {
+        View::get(self, &version)?
+            .ok_or(Error::VersionNotFound(version))
+    }
+
+    fn latest(&self) -> Result<Self::Key, Self::Error> {
+        View::latest(self)?
+            .ok_or(Error::Empty)
+    }
+}
+
+impl<KeyType> Local<KeyType> {
+    /// a view of the store that hides every version above `max_version`
+    pub fn view_at(&self, max_version: u64) -> View<'_, KeyType> {
+        View {
+            local: self,
+            max_version,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pinned() {
+        let local = Local::new();
+
+        for value in 1..=5 {
+            local.update(value * 10).unwrap();
+        }
+
+        let view = local.view_at(3);
+
+        assert_eq!(view.latest().unwrap(), Some(30));
+        assert_eq!(*view.latest_version().unwrap().unwrap().version(), 3);
+        assert_eq!(view.get(&5).unwrap(), None);
+        assert_eq!(view.get(&2).unwrap(), Some(20));
+        assert_eq!(view.versions().unwrap(), vec![1, 2, 3]);
+        assert_eq!(local.get(&5).unwrap(), Some(50));
+        assert_eq!(local.latest().unwrap(), Some(50));
+
+        // changes at or below the pin show through
+        local.drop(&3).unwrap();
+        local.update(60).unwrap();
+
+        assert_eq!(view.latest().unwrap(), Some(20));
+        assert_eq!(view.versions().unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn manager() {
+        fn newest<M: Manager<Version = u64>>(manager: &M) -> Result<M::Key, M::Error> {
+            manager.latest()
+        }
+
+        let local = Local::new();
+
+        for value in 1..=5 {
+            local.update(value).unwrap();
+        }
+
+        assert_eq!(newest(&local.view_at(4)).unwrap(), 4);
+        assert!(matches!(Manager::get(&local.view_at(4), 5), Err(Error::VersionNotFound(5))));
+        assert!(matches!(newest(&local.view_at(0)), Err(Error::Empty)));
+    }
+}
@@ -0,0 +1,580 @@
+use std::fmt;
+use std::io;
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::{self, Visitor, SeqAccess};
+
+use crate::zero::zero;
+
+#[derive(Debug)]
+pub enum Error {
+    /// the memory could not be locked and strict locking was requested
+    Lock(io::Error),
+    /// more bytes were written than the buffer was created with
+    Capacity,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Lock(e) => write!(f, "Lock {}", e),
+            Error::Capacity => f.write_str("Capacity"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Lock(e) => Some(e),
+            Error::Capacity => None,
+        }
+    }
+}
+
+/// how a [`LockedBuffer`] reacts to memory that cannot be locked
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// fall back to unlocked memory and keep the reason
+    #[default]
+    BestEffort,
+    /// fail with [`Error::Lock`]
+    Strict,
+}
+
+/// if this platform has a way to lock memory. when it does not every
+/// buffer is unlocked and strict buffers fail to create.
+pub const fn supported() -> bool {
+    cfg!(any(unix, windows))
+}
+
+#[cfg(unix)]
+fn page_size() -> usize {
+    // SAFETY: sysconf has no preconditions
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+
+    usize::try_from(size).unwrap_or(4096)
+}
+
+#[cfg(unix)]
+fn lock_pages(start: usize, len: usize) -> io::Result<()> {
+    // SAFETY: mlock only reads the address range, which holds a live
+    // allocation
+    let result = unsafe { libc::mlock(start as *const libc::c_void, len) };
+
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(unix)]
+fn unlock_pages(start: usize, len: usize) {
+    // SAFETY: munlock only reads the address range
+    unsafe {
+        libc::munlock(start as *const libc::c_void, len);
+    }
+}
+
+#[cfg(windows)]
+fn page_size() -> usize {
+    use windows_sys::Win32::System::SystemInformation::{GetSystemInfo, SYSTEM_INFO};
+
+    // SAFETY: the pointer is to a SYSTEM_INFO that GetSystemInfo fills in
+    let info = unsafe {
+        let mut info: SYSTEM_INFO = std::mem::zeroed();
+        GetSystemInfo(&mut info);
+        info
+    };
+
+    info.dwPageSize as usize
+}
+
+#[cfg(windows)]
+fn lock_pages(start: usize, len: usize) -> io::Result<()> {
+    use windows_sys::Win32::System::Memory::VirtualLock;
+
+    // SAFETY: the address range holds a live allocation
+    let result = unsafe { VirtualLock(start as *const _, len) };
+
+    if result != 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(windows)]
+fn unlock_pages(start: usize, len: usize) {
+    use windows_sys::Win32::System::Memory::VirtualUnlock;
+
+    // SAFETY: VirtualUnlock only reads the address range
+    unsafe {
+        VirtualUnlock(start as *const _, len);
+    }
+}
+
+/// the number of buffers on each locked page, by page address.
+///
+/// the kernel locks whole pages and does not count how often a page was
+/// locked, so a page shared by two buffers is only unlocked once neither
+/// of them is left.
+#[cfg(any(unix, windows))]
+static LOCKED_PAGES: std::sync::Mutex<std::collections::BTreeMap<usize, usize>> =
+    std::sync::Mutex::new(std::collections::BTreeMap::new());
+
+/// the address of the first page and of the page after the last one that
+/// `bytes` is on, along with the page size
+#[cfg(any(unix, windows))]
+fn pages(bytes: &[u8]) -> (usize, usize, usize) {
+    static PAGE_SIZE: std::sync::OnceLock<usize> = std::sync::OnceLock::new();
+
+    let page_size = *PAGE_SIZE.get_or_init(page_size);
+    let start = bytes.as_ptr() as usize;
+    let end = start + bytes.len();
+
+    (start - start % page_size, end.div_ceil(page_size) * page_size, page_size)
+}
+
+#[cfg(any(unix, windows))]
+fn lock(bytes: &[u8]) -> io::Result<()> {
+    let (first, end, page_size) = pages(bytes);
+    let mut locked = LOCKED_PAGES.lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+    // locking a page again does nothing so the whole range is locked
+    lock_pages(first, end - first)?;
+
+    for page in (first..end).step_by(page_size) {
+        *locked.entry(page).or_insert(0) += 1;
+    }
+
+    Ok(())
+}
+
+#[cfg(any(unix, windows))]
+fn unlock(bytes: &[u8]) {
+    let (first, end, page_size) = pages(bytes);
+    let mut locked = LOCKED_PAGES.lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+    for page in (first..end).step_by(page_size) {
+        let Some(count) = locked.get_mut(&page) else {
+            continue;
+        };
+
+        *count -= 1;
+
+        if *count == 0 {
+            locked.remove(&page);
+            unlock_pages(page, page_size);
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn lock(_bytes: &[u8]) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "memory locking is not supported"))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn unlock(_bytes: &[u8]) {}
+
+/// a fixed capacity heap buffer that is kept out of swap when possible and
+/// zeroed when dropped.
+///
+/// locking is attempted once when the buffer is created. if it fails, e.g.
+/// because `RLIMIT_MEMLOCK` is used up, the buffer is still usable but
+/// unlocked and the reason is kept in [`lock_error`] unless the buffer was
+/// created with [`strict`]. memory is locked by the page, a page shared by
+/// several buffers stays locked until the last of them is dropped.
+///
+/// [`lock_error`]: LockedBuffer::lock_error
+/// [`strict`]: LockedBuffer::strict
+pub struct LockedBuffer {
+    bytes: Box<[u8]>,
+    len: usize,
+    locked: bool,
+    lock_error: Option<io::Error>,
+}
+
+/// key data held in a [`LockedBuffer`], for use as `Key<LockedBytes>`
+pub type LockedBytes = LockedBuffer;
+
+impl LockedBuffer {
+    /// an empty buffer that can hold up to `capacity` bytes, falling back to
+    /// unlocked memory if it cannot be locked
+    pub fn with_capacity(capacity: usize) -> Self {
+        let bytes = vec![0u8; capacity].into_boxed_slice();
+
+        let (locked, lock_error) = if capacity == 0 {
+            (true, None)
+        } else {
+            match lock(&bytes) {
+                Ok(()) => (true, None),
+                Err(e) => (false, Some(e)),
+            }
+        };
+
+        LockedBuffer {
+            bytes,
+            len: 0,
+            locked,
+            lock_error,
+        }
+    }
+
+    /// [`with_capacity`](LockedBuffer::with_capacity) that fails if the
+    /// memory cannot be locked
+    pub fn strict(capacity: usize) -> Result<Self, Error> {
+        let mut rtn = Self::with_capacity(capacity);
+
+        match rtn.lock_error.take() {
+            Some(e) => Err(Error::Lock(e)),
+            None => Ok(rtn),
+        }
+    }
+
+    /// [`with_capacity`](LockedBuffer::with_capacity) or
+    /// [`strict`](LockedBuffer::strict) depending on the mode
+    pub fn with_mode(capacity: usize, mode: LockMode) -> Result<Self, Error> {
+        match mode {
+            LockMode::BestEffort => Ok(Self::with_capacity(capacity)),
+            LockMode::Strict => Self::strict(capacity),
+        }
+    }
+
+    /// copies the slice into a new buffer of the same length
+    pub fn from_slice(slice: &[u8]) -> Self {
+        let mut rtn = Self::with_capacity(slice.len());
+        rtn.bytes.copy_from_slice(slice);
+        rtn.len = slice.len();
+        rtn
+    }
+
+    /// if the memory is locked
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// why the memory could not be locked
+    pub fn lock_error(&self) -> Option<&io::Error> {
+        self.lock_error.as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.bytes[..self.len]
+    }
+
+    /// appends the slice, failing if it does not fit in the capacity
+    pub fn extend_from_slice(&mut self, slice: &[u8]) -> Result<(), Error> {
+        let end = self.len.checked_add(slice.len())
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(Error::Capacity)?;
+
+        self.bytes[self.len..end].copy_from_slice(slice);
+        self.len = end;
+
+        Ok(())
+    }
+
+    /// shortens the buffer, zeroing the removed bytes
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len {
+            zero(&mut self.bytes[len..self.len]);
+            self.len = len;
+        }
+    }
+}
+
+impl Drop for LockedBuffer {
+    fn drop(&mut self) {
+        zero(&mut self.bytes);
+
+        if self.locked && !self.bytes.is_empty() {
+            unlock(&self.bytes);
+        }
+    }
+}
+
+impl std::ops::Deref for LockedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl AsRef<[u8]> for LockedBuffer {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl AsMut<[u8]> for LockedBuffer {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self.as_mut_slice()
+    }
+}
+
+impl Clone for LockedBuffer {
+    fn clone(&self) -> Self {
+        LockedBuffer::from_slice(self.as_slice())
+    }
+}
+
+impl PartialEq for LockedBuffer {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for LockedBuffer {}
+
+impl fmt::Debug for LockedBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LockedBuffer")
+            .field("len", &self.len)
+            .field("locked", &self.locked)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "crypto")]
+impl chacha20poly1305::aead::Buffer for LockedBuffer {
+    fn extend_from_slice(&mut self, other: &[u8]) -> chacha20poly1305::aead::Result<()> {
+        LockedBuffer::extend_from_slice(self, other)
+            .map_err(|_| chacha20poly1305::aead::Error)
+    }
+
+    fn truncate(&mut self, len: usize) {
+        LockedBuffer::truncate(self, len)
+    }
+}
+
+impl Serialize for LockedBuffer {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer
+    {
+        serializer.serialize_bytes(self.as_slice())
+    }
+}
+
+/// the capacity a buffer read from a sequence starts with, enough for a
+/// 256 bit key
+const MIN_SEQ_CAPACITY: usize = 32;
+
+/// the most a length given by a sequence is trusted for the first
+/// allocation
+const MAX_SEQ_CAPACITY: usize = 4096;
+
+impl<'de> Deserialize<'de> for LockedBuffer {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>
+    {
+        struct LockedVisitor;
+
+        impl<'de> Visitor<'de> for LockedVisitor {
+            type Value = LockedBuffer;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("bytes")
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+            where
+                E: de::Error
+            {
+                Ok(LockedBuffer::from_slice(v))
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>
+            {
+                // the length is only trusted up to a cap. the bytes go
+                // straight into locked buffers that are doubled when full,
+                // each one is zeroed as it is replaced.
+                let capacity = seq.size_hint()
+                    .unwrap_or(0)
+                    .clamp(MIN_SEQ_CAPACITY, MAX_SEQ_CAPACITY);
+                let mut rtn = LockedBuffer::with_capacity(capacity);
+
+                while let Some(b) = seq.next_element::<u8>()? {
+                    if rtn.len() == rtn.capacity() {
+                        let mut grown = LockedBuffer::with_capacity(rtn.capacity() * 2);
+
+                        grown.extend_from_slice(rtn.as_slice())
+                            .map_err(de::Error::custom)?;
+
+                        rtn = grown;
+                    }
+
+                    rtn.extend_from_slice(&[b])
+                        .map_err(de::Error::custom)?;
+                }
+
+                Ok(rtn)
+            }
+        }
+
+        deserializer.deserialize_bytes(LockedVisitor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lifecycle() {
+        let mut buffer = LockedBuffer::with_capacity(64);
+
+        assert_eq!(buffer.capacity(), 64);
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.is_locked(), buffer.lock_error().is_none());
+
+        buffer.extend_from_slice(b"secret").unwrap();
+        buffer.extend_from_slice(&[1; 58]).unwrap();
+
+        assert_eq!(&buffer[..6], b"secret");
+        assert!(matches!(buffer.extend_from_slice(b"x"), Err(Error::Capacity)));
+
+        buffer.truncate(6);
+
+        assert_eq!(buffer.as_slice(), b"secret");
+        assert_eq!(buffer.clone(), buffer);
+
+        let empty = LockedBuffer::from_slice(&[]);
+
+        assert!(empty.is_locked());
+    }
+
+    #[test]
+    fn serde() {
+        let buffer = LockedBuffer::from_slice(b"locked key data");
+
+        #[cfg(feature = "binary")]
+        {
+            let bytes = bincode::serialize(&buffer).unwrap();
+            let and_back: LockedBuffer = bincode::deserialize(&bytes).unwrap();
+
+            assert_eq!(and_back, buffer);
+        }
+
+        let json = serde_json::to_string(&buffer).unwrap();
+        let and_back: LockedBuffer = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(and_back, buffer);
+    }
+
+    #[test]
+    fn serde_seq() {
+        let data: Vec<u8> = (0..100).collect();
+        let json = serde_json::to_string(&data).unwrap();
+        let and_back: LockedBuffer = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(and_back.as_slice(), data.as_slice());
+
+        let and_back: LockedBuffer = serde_json::from_str("[]").unwrap();
+
+        assert!(and_back.is_empty());
+    }
+
+    #[cfg(any(unix, windows))]
+    #[test]
+    fn shared_pages() {
+        let mut buffers: Vec<LockedBuffer> = (0..64u8)
+            .map(|b| LockedBuffer::from_slice(&[b; 16]))
+            .collect();
+
+        if buffers.iter().any(|b| !b.is_locked()) {
+            eprintln!("skipping shared_pages, memory cannot be locked");
+            return;
+        }
+
+        // small buffers share pages, dropping one must not unlock the
+        // pages of the others
+        let kept: Vec<LockedBuffer> = buffers.drain(..)
+            .enumerate()
+            .filter_map(|(index, b)| (index % 2 == 0).then_some(b))
+            .collect();
+
+        let locked = LOCKED_PAGES.lock().unwrap();
+
+        for buffer in &kept {
+            let (first, end, page_size) = pages(&buffer.bytes);
+
+            for page in (first..end).step_by(page_size) {
+                assert!(locked.get(&page).is_some_and(|count| *count > 0), "page of a live buffer was unlocked");
+            }
+        }
+    }
+
+    #[test]
+    fn key_data() {
+        use crate::key::Key;
+        use crate::local::Local;
+
+        let local = Local::new();
+
+        local.update(Key::builder(LockedBytes::from_slice(&[7; 32])).build().unwrap())
+            .unwrap();
+
+        let key = local.latest().unwrap().unwrap();
+
+        assert_eq!(key.data().as_slice(), &[7; 32]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn strict_limit() {
+        let mut original = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+
+        // SAFETY: the pointer is to a valid rlimit
+        assert_eq!(unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut original) }, 0);
+
+        let limited = libc::rlimit { rlim_cur: 0, rlim_max: original.rlim_max };
+
+        // SAFETY: the pointer is to a valid rlimit
+        if unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &limited) } != 0 {
+            eprintln!("skipping strict_limit, RLIMIT_MEMLOCK cannot be lowered");
+            return;
+        }
+
+        let strict = LockedBuffer::strict(1 << 20);
+        let fallback = LockedBuffer::with_capacity(1 << 20);
+
+        // SAFETY: the pointer is to a valid rlimit
+        unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &original) };
+
+        match strict {
+            Err(Error::Lock(_)) => {
+                assert!(!fallback.is_locked());
+                assert!(fallback.lock_error().is_some());
+            }
+            Err(e) => panic!("unexpected error: {:?}", e),
+            // CAP_IPC_LOCK ignores the limit
+            Ok(_) => eprintln!("skipping strict_limit, the limit is not enforced for this process"),
+        }
+    }
+}
@@ -0,0 +1,461 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use serde::{Serialize, Deserialize};
+
+use rust_kms_core::traits::Manager;
+
+use crate::key::Key;
+use crate::local::{self, Local, CompactionMap, unix_now, unix_secs};
+
+/// the rules that can be configured in a [`PolicySet`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    /// the key is older than [`PolicySet::max_age`]
+    Expired,
+    /// the version is scheduled to be dropped
+    PendingDrop,
+    /// the version is pinned and no [`PinnedAck`] was given
+    Pinned,
+    /// the version is disabled with [`Local::disable`] and is not fetched
+    /// for decryption only
+    Disabled,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::Expired => f.write_str("Expired"),
+            Rule::PendingDrop => f.write_str("PendingDrop"),
+            Rule::Pinned => f.write_str("Pinned"),
+            Rule::Disabled => f.write_str("Disabled"),
+        }
+    }
+}
+
+/// a refused access naming the rule that refused it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PolicyViolation {
+    pub rule: Rule,
+    pub version: u64,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Local(local::Error),
+    Empty,
+    PolicyViolation(PolicyViolation),
+}
+
+impl From<local::Error> for Error {
+    fn from(e: local::Error) -> Self {
+        Error::Local(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Local(_) => f.write_str("Local"),
+            Error::Empty => f.write_str("Empty"),
+            Error::PolicyViolation(v) => write!(
+                f, "PolicyViolation {} for version {}", v.rule, v.version
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Local(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// the rules enforced by [`Enforced`]. the default allows everything.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PolicySet {
+    /// seconds after its created timestamp that a key may still be returned
+    /// by [`Enforced::latest`] for encryption. older versions can still be
+    /// fetched by version to decrypt what they encrypted.
+    pub max_age: Option<u64>,
+    /// refuse versions pending a drop unless fetched for decryption only
+    pub block_pending: bool,
+    /// versions that require a [`PinnedAck`] to access
+    pub pinned: BTreeSet<u64>,
+}
+
+impl PolicySet {
+    /// the time left before a key created at `latest_created` is older than
+    /// `max_age`. `None` if the key is overdue or no `max_age` is set.
+    pub fn time_until_rotation(&self, latest_created: u64, now: SystemTime) -> Option<Duration> {
+        let deadline = latest_created.saturating_add(self.max_age?);
+
+        deadline.checked_sub(unix_secs(now))
+            .map(Duration::from_secs)
+    }
+}
+
+/// what a key is being fetched for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Usage {
+    Encrypt,
+    DecryptOnly,
+}
+
+/// acknowledgment that a pinned version is being accessed on purpose
+#[derive(Debug)]
+pub struct PinnedAck(());
+
+impl PinnedAck {
+    pub fn acknowledge() -> Self {
+        PinnedAck(())
+    }
+}
+
+/// a [`Local`] that refuses access that breaks its [`PolicySet`].
+///
+/// age is only checked by `latest`, since fetching an old version by number
+/// is how existing ciphertext is decrypted. pending drops and pinned
+/// versions are checked as the policy says. disabled versions are always
+/// refused unless they are fetched for decryption only, `latest` already
+/// passes over them.
+pub struct Enforced<Data> {
+    local: Local<Key<Data>>,
+    policy: PolicySet,
+}
+
+impl<Data> Enforced<Data> {
+    pub fn new(local: Local<Key<Data>>, policy: PolicySet) -> Self {
+        Enforced { local, policy }
+    }
+
+    pub fn policy(&self) -> &PolicySet {
+        &self.policy
+    }
+
+    /// the wrapped store. access through it is not checked.
+    pub fn local(&self) -> &Local<Key<Data>> {
+        &self.local
+    }
+
+    pub fn into_inner(self) -> Local<Key<Data>> {
+        self.local
+    }
+
+    pub fn update(&self, key: Key<Data>) -> Result<u64, Error> {
+        Ok(self.local.update(key)?)
+    }
+
+    /// [`Local::compact`] that refuses to drop pinned versions. the pinned
+    /// versions of the policy are not renumbered and need to be updated from
+    /// the returned map.
+    pub fn compact(&self, keep: &[u64]) -> Result<CompactionMap, Error> {
+        if let Some(version) = self.policy.pinned.iter().find(|v| !keep.contains(v)) {
+            return Err(Error::PolicyViolation(PolicyViolation {
+                rule: Rule::Pinned,
+                version: *version,
+            }));
+        }
+
+        Ok(self.local.compact(keep)?)
+    }
+
+    fn check(
+        &self,
+        version: u64,
+        usage: Usage,
+        ack: Option<&PinnedAck>
+    ) -> Result<(), Error> {
+        let violation = |rule| Err(Error::PolicyViolation(PolicyViolation { rule, version }));
+
+        if self.policy.pinned.contains(&version) && ack.is_none() {
+            return violation(Rule::Pinned);
+        }
+
+        if self.policy.block_pending && usage != Usage::DecryptOnly && self.local.is_pending_drop(&version)? {
+            return violation(Rule::PendingDrop);
+        }
+
+        if usage != Usage::DecryptOnly && self.local.is_disabled(&version)? {
+            return violation(Rule::Disabled);
+        }
+
+        Ok(())
+    }
+}
+
+impl<Data> Enforced<Data>
+where
+    Data: Clone
+{
+    /// fetches a version for the given usage
+    pub fn get(&self, version: &u64, usage: Usage) -> Result<Option<Key<Data>>, Error> {
+        self.get_inner(version, usage, None)
+    }
+
+    /// fetches a version that may be pinned
+    pub fn get_pinned(&self, version: &u64, usage: Usage, ack: &PinnedAck) -> Result<Option<Key<Data>>, Error> {
+        self.get_inner(version, usage, Some(ack))
+    }
+
+    fn get_inner(&self, version: &u64, usage: Usage, ack: Option<&PinnedAck>) -> Result<Option<Key<Data>>, Error> {
+        let Some(key) = self.local.get(version)? else {
+            return Ok(None);
+        };
+
+        self.check(*version, usage, ack)?;
+
+        Ok(Some(key))
+    }
+
+    /// the latest key for encryption, refused once it is older than
+    /// [`PolicySet::max_age`]
+    pub fn latest(&self) -> Result<Option<Key<Data>>, Error> {
+        let Some(found) = self.local.latest_version()? else {
+            return Ok(None);
+        };
+
+        let version = *found.version();
+
+        self.check(version, Usage::Encrypt, None)?;
+
+        if let Some(max_age) = self.policy.max_age {
+            if unix_now().saturating_sub(*found.created()) > max_age {
+                return Err(Error::PolicyViolation(PolicyViolation {
+                    rule: Rule::Expired,
+                    version,
+                }));
+            }
+        }
+
+        Ok(Some(found.1))
+    }
+}
+
+impl<Data> Manager for Enforced<Data>
+where
+    Data: Clone
+{
+    type Key = Key<Data>;
+    type Version = u64;
+    type Error = Error;
+
+    /// fetches the version without checking its age so generic code can
+    /// decrypt with old versions. pending drops and disabled versions are
+    /// still refused as for [`Usage::Encrypt`].
+    fn get(&self, version: u64) -> Result<Self::Key, Self::Error> {
+        Enforced::get(self, &version, Usage::Encrypt)?
+            .ok_or(Error::Local(local::Error::VersionNotFound(version)))
+    }
+
+    fn latest(&self) -> Result<Self::Key, Self::Error> {
+        Enforced::latest(self)?
+            .ok_or(Error::Empty)
+    }
+}
+
+impl<Data> fmt::Debug for Enforced<Data>
+where
+    Data: fmt::Debug
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Enforced")
+            .field("local", &self.local)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn create_store() -> Local<Key<u64>> {
+        let local = Local::new();
+        let now = unix_now();
+
+        for (data, age) in [(1, 500), (2, 300), (3, 100)] {
+            let mut builder = Key::builder(data);
+            builder.set_created(now - age);
+
+            local.update(builder.build().unwrap()).unwrap();
+        }
+
+        local
+    }
+
+    fn assert_violation<T: fmt::Debug>(result: Result<T, Error>, rule: Rule, version: u64) {
+        match result {
+            Err(Error::PolicyViolation(v)) => assert_eq!(v, PolicyViolation { rule, version }),
+            _ => panic!("expected {} violation for {}: {:?}", rule, version, result),
+        }
+    }
+
+    #[test]
+    fn permissive() {
+        let enforced = Enforced::new(create_store(), PolicySet::default());
+        let local = create_store();
+
+        for version in 1..=3 {
+            assert_eq!(
+                Manager::get(&enforced, version).unwrap(),
+                local.get(&version).unwrap().unwrap()
+            );
+        }
+
+        assert_eq!(Manager::latest(&enforced).unwrap(), local.latest().unwrap().unwrap());
+        assert!(matches!(
+            Manager::get(&enforced, 4),
+            Err(Error::Local(local::Error::VersionNotFound(4)))
+        ));
+    }
+
+    #[test]
+    fn expired() {
+        let policy = PolicySet {
+            max_age: Some(200),
+            ..Default::default()
+        };
+        let enforced = Enforced::new(create_store(), policy);
+
+        assert!(enforced.latest().unwrap().is_some());
+        assert!(enforced.get(&1, Usage::Encrypt).unwrap().is_some());
+        assert!(enforced.get(&1, Usage::DecryptOnly).unwrap().is_some());
+
+        enforced.local().drop(&3).unwrap();
+
+        assert_violation(Manager::latest(&enforced), Rule::Expired, 2);
+    }
+
+    #[test]
+    fn expired_through_manager() {
+        let permissive = Enforced::new(create_store(), PolicySet::default());
+        let policy = PolicySet {
+            max_age: Some(200),
+            ..Default::default()
+        };
+        let enforced = Enforced::new(create_store(), policy);
+
+        // old versions are still there to decrypt with
+        for version in 1..=3 {
+            assert_eq!(
+                Manager::get(&enforced, version).unwrap(),
+                Manager::get(&permissive, version).unwrap()
+            );
+        }
+
+        enforced.local().drop(&3).unwrap();
+
+        assert_violation(Manager::latest(&enforced), Rule::Expired, 2);
+    }
+
+    #[test]
+    fn time_until_rotation() {
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let policy = PolicySet {
+            max_age: Some(1_000),
+            ..Default::default()
+        };
+
+        // fresh
+        assert_eq!(policy.time_until_rotation(5_000, at(5_100)), Some(Duration::from_secs(900)));
+        // imminent
+        assert_eq!(policy.time_until_rotation(5_000, at(5_999)), Some(Duration::from_secs(1)));
+        assert_eq!(policy.time_until_rotation(5_000, at(6_000)), Some(Duration::ZERO));
+        // overdue
+        assert_eq!(policy.time_until_rotation(5_000, at(6_001)), None);
+
+        assert_eq!(PolicySet::default().time_until_rotation(5_000, at(5_100)), None);
+
+        let local = Local::new();
+
+        assert_eq!(local.latest_age(at(5_100)).unwrap(), None);
+
+        for created in [4_000, 5_000] {
+            let mut builder = Key::builder(created);
+            builder.set_created(created);
+
+            local.update(builder.build().unwrap()).unwrap();
+        }
+
+        assert_eq!(local.latest_age(at(5_100)).unwrap(), Some(Duration::from_secs(100)));
+
+        local.schedule_drop_at(&2, u64::MAX).unwrap();
+
+        assert_eq!(local.latest_age(at(5_100)).unwrap(), Some(Duration::from_secs(1_100)));
+    }
+
+    #[test]
+    fn pending_drop() {
+        let policy = PolicySet {
+            block_pending: true,
+            ..Default::default()
+        };
+        let enforced = Enforced::new(create_store(), policy);
+
+        enforced.local().schedule_drop_at(&2, u64::MAX).unwrap();
+
+        assert_violation(enforced.get(&2, Usage::Encrypt), Rule::PendingDrop, 2);
+        assert!(enforced.get(&2, Usage::DecryptOnly).unwrap().is_some());
+    }
+
+    #[test]
+    fn disabled() {
+        let enforced = Enforced::new(create_store(), PolicySet::default());
+
+        enforced.local().disable(&3).unwrap();
+
+        assert_violation(enforced.get(&3, Usage::Encrypt), Rule::Disabled, 3);
+        assert_violation(Manager::get(&enforced, 3), Rule::Disabled, 3);
+        assert_eq!(*enforced.get(&3, Usage::DecryptOnly).unwrap().unwrap().data(), 3);
+        assert_eq!(*enforced.latest().unwrap().unwrap().data(), 2);
+
+        enforced.local().enable(&3).unwrap();
+
+        assert!(enforced.get(&3, Usage::Encrypt).unwrap().is_some());
+    }
+
+    #[test]
+    fn pinned() {
+        let policy = PolicySet {
+            pinned: BTreeSet::from([3]),
+            ..Default::default()
+        };
+        let enforced = Enforced::new(create_store(), policy);
+
+        assert_violation(enforced.get(&3, Usage::DecryptOnly), Rule::Pinned, 3);
+        assert_violation(enforced.latest(), Rule::Pinned, 3);
+        assert!(enforced.get(&2, Usage::Encrypt).unwrap().is_some());
+
+        let key = enforced.get_pinned(&3, Usage::Encrypt, &PinnedAck::acknowledge())
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(*key.data(), 3);
+
+        assert_violation(enforced.compact(&[1, 2]), Rule::Pinned, 3);
+        assert_eq!(enforced.compact(&[1, 3]).unwrap().get(&3), Some(2));
+    }
+
+    #[test]
+    fn policy_serde() {
+        let policy: PolicySet = serde_json::from_str(r#"{"max_age":3600,"pinned":[1,2]}"#)
+            .expect("failed to parse policy");
+
+        assert_eq!(policy, PolicySet {
+            max_age: Some(3600),
+            block_pending: false,
+            pinned: BTreeSet::from([1, 2]),
+        });
+
+        let and_back: PolicySet = serde_json::from_str(&serde_json::to_string(&policy).unwrap())
+            .unwrap();
+
+        assert_eq!(policy, and_back);
+    }
+}
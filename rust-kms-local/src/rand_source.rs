@@ -0,0 +1,222 @@
+//! the entropy source used when keys and nonces are generated without an
+//! explicit rng.
+//!
+//! [`crypto::make_nonce`](crate::crypto::make_nonce), everything that
+//! encrypts through it, `MasterKey::generate` and
+//! [`Key::builder_default_rng`](crate::Key::builder_default_rng) all read
+//! from [`fill_bytes`]. it uses the rng given to [`set_default_rng`] and
+//! falls back to [`OsRng`] until one is set. the `*_os_rng` and
+//! `*_thread_rng` builders always use the rng they are named after.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+use rand::{CryptoRng, RngCore};
+use rand::rngs::OsRng;
+
+/// an rng that can be used as the crate wide entropy source
+pub trait EntropySource: RngCore + CryptoRng + Send + Sync {}
+
+impl<T> EntropySource for T
+where
+    T: RngCore + CryptoRng + Send + Sync
+{}
+
+#[derive(Debug)]
+pub enum Error {
+    /// [`set_default_rng`] was already called
+    AlreadySet,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::AlreadySet => f.write_str("AlreadySet"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+static DEFAULT: OnceLock<Mutex<Box<dyn EntropySource>>> = OnceLock::new();
+
+thread_local! {
+    static OVERRIDE: RefCell<Option<Box<dyn EntropySource>>> = const { RefCell::new(None) };
+}
+
+/// sets the rng used for the rest of the process. it can only be set once
+/// so that keys made before and after are never from different sources
+/// without the caller knowing.
+pub fn set_default_rng(rng: Box<dyn EntropySource>) -> Result<(), Error> {
+    DEFAULT.set(Mutex::new(rng))
+        .map_err(|_| Error::AlreadySet)
+}
+
+/// if [`set_default_rng`] has been called
+pub fn is_default_set() -> bool {
+    DEFAULT.get().is_some()
+}
+
+/// fills `dest` from the override of this thread, the default rng, or
+/// [`OsRng`], in that order
+pub fn fill_bytes(dest: &mut [u8]) -> Result<(), rand::Error> {
+    let overridden = OVERRIDE.with(|cell| {
+        cell.borrow_mut()
+            .as_mut()
+            .map(|rng| rng.try_fill_bytes(dest))
+    });
+
+    if let Some(result) = overridden {
+        return result;
+    }
+
+    match DEFAULT.get() {
+        Some(rng) => rng.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .try_fill_bytes(dest),
+        None => OsRng.try_fill_bytes(dest),
+    }
+}
+
+/// puts back the previous override when the scope ends, including by panic
+struct Restore(Option<Box<dyn EntropySource>>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+
+        OVERRIDE.with(|cell| *cell.borrow_mut() = previous);
+    }
+}
+
+/// uses `rng` instead of the default for everything `f` generates on this
+/// thread. meant for tests that need deterministic keys or nonces, other
+/// threads are not affected. overrides can be nested.
+pub fn with_rng_for_test<R, F, T>(rng: R, f: F) -> T
+where
+    R: EntropySource + 'static,
+    F: FnOnce() -> T,
+{
+    let previous = OVERRIDE.with(|cell| cell.borrow_mut().replace(Box::new(rng)));
+    let _restore = Restore(previous);
+
+    f()
+}
+
+#[cfg(all(test, feature = "crypto"))]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    use crate::crypto;
+    use crate::key::Key;
+
+    /// a seeded rng that counts the bytes taken from it
+    struct Counting {
+        rng: StdRng,
+        taken: Arc<AtomicUsize>,
+    }
+
+    impl Counting {
+        fn new(seed: u64) -> (Self, Arc<AtomicUsize>) {
+            let taken = Arc::new(AtomicUsize::new(0));
+
+            (Counting { rng: StdRng::seed_from_u64(seed), taken: taken.clone() }, taken)
+        }
+    }
+
+    impl RngCore for Counting {
+        fn next_u32(&mut self) -> u32 {
+            self.taken.fetch_add(4, Ordering::Relaxed);
+            self.rng.next_u32()
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.taken.fetch_add(8, Ordering::Relaxed);
+            self.rng.next_u64()
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            self.taken.fetch_add(dest.len(), Ordering::Relaxed);
+            self.rng.fill_bytes(dest)
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            self.taken.fetch_add(dest.len(), Ordering::Relaxed);
+            self.rng.try_fill_bytes(dest)
+        }
+    }
+
+    impl CryptoRng for Counting {}
+
+    #[test]
+    fn override_is_consulted() {
+        let (rng, taken) = Counting::new(7);
+
+        let (nonce, key) = with_rng_for_test(rng, || {
+            let nonce = crypto::make_nonce().unwrap();
+            let key = Key::<crypto::Key>::builder_default_rng().unwrap().build().unwrap();
+
+            crypto::encrypt_data(key.data(), b"data".to_vec()).unwrap();
+
+            (nonce, key)
+        });
+
+        assert_eq!(taken.load(Ordering::Relaxed), crypto::NONCE_LEN * 2 + crypto::KEY_LEN);
+
+        // the same seed gives the same values
+        let (rng, _) = Counting::new(7);
+
+        with_rng_for_test(rng, || {
+            assert_eq!(crypto::make_nonce().unwrap(), nonce);
+            assert_eq!(Key::<crypto::Key>::builder_default_rng().unwrap().build().unwrap().data(), key.data());
+        });
+
+        // the override is gone once the scope ends
+        crypto::make_nonce().unwrap();
+
+        assert_eq!(taken.load(Ordering::Relaxed), crypto::NONCE_LEN * 2 + crypto::KEY_LEN);
+    }
+
+    #[test]
+    fn nested_override() {
+        let (outer, outer_taken) = Counting::new(1);
+        let (inner, inner_taken) = Counting::new(2);
+
+        with_rng_for_test(outer, || {
+            with_rng_for_test(inner, || crypto::make_nonce().unwrap());
+
+            crypto::make_nonce().unwrap();
+        });
+
+        assert_eq!(inner_taken.load(Ordering::Relaxed), crypto::NONCE_LEN);
+        assert_eq!(outer_taken.load(Ordering::Relaxed), crypto::NONCE_LEN);
+    }
+
+    #[test]
+    fn default_set_once() {
+        let (rng, taken) = Counting::new(3);
+
+        set_default_rng(Box::new(rng)).unwrap();
+
+        assert!(is_default_set());
+
+        // other tests may also be drawing from the default at the same time
+        let before = taken.load(Ordering::Relaxed);
+
+        std::thread::spawn(|| crypto::make_nonce().unwrap())
+            .join()
+            .unwrap();
+
+        assert!(taken.load(Ordering::Relaxed) >= before + crypto::NONCE_LEN);
+
+        let (again, _) = Counting::new(4);
+
+        assert!(matches!(set_default_rng(Box::new(again)), Err(Error::AlreadySet)));
+    }
+}
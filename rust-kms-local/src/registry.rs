@@ -0,0 +1,209 @@
+use std::any::{Any, type_name};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, OnceLock, RwLock, PoisonError};
+
+use crate::local::Local;
+
+/// a store that can be kept in the registry
+pub trait AnyStore: Any + Send + Sync {
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync>;
+
+    /// the name of the key type held by the store
+    fn key_type(&self) -> &'static str;
+}
+
+impl<KeyType> AnyStore for Local<KeyType>
+where
+    KeyType: Send + Sync + 'static
+{
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+
+    fn key_type(&self) -> &'static str {
+        type_name::<KeyType>()
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Poisoned,
+    AlreadyRegistered(&'static str),
+    NotFound(String),
+    TypeMismatch {
+        name: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+}
+
+impl<T> From<PoisonError<T>> for Error {
+    fn from(_e: PoisonError<T>) -> Self {
+        Error::Poisoned
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Poisoned => f.write_str("RegistryPoisoned"),
+            Error::AlreadyRegistered(name) => write!(f, "AlreadyRegistered {}", name),
+            Error::NotFound(name) => write!(f, "NotFound {}", name),
+            Error::TypeMismatch { name, expected, found } => write!(
+                f, "TypeMismatch {} expected {} found {}", name, expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+type Registry = RwLock<HashMap<&'static str, Arc<dyn AnyStore>>>;
+
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+fn registry() -> &'static Registry {
+    REGISTRY.get_or_init(Default::default)
+}
+
+/// registers a store under a name for the life of the process. a name can
+/// only be registered once.
+///
+/// the registry is a convenience for applications with many stores and is
+/// not meant to replace passing stores to where they are needed.
+pub fn insert(name: &'static str, store: Arc<dyn AnyStore>) -> Result<(), Error> {
+    let mut writer = registry().write()?;
+
+    if writer.contains_key(name) {
+        return Err(Error::AlreadyRegistered(name));
+    }
+
+    writer.insert(name, store);
+
+    Ok(())
+}
+
+/// removes a store from the registry, returning it if it was registered
+pub fn remove(name: &str) -> Result<Option<Arc<dyn AnyStore>>, Error> {
+    Ok(registry().write()?.remove(name))
+}
+
+/// the store registered under the name if it holds `KeyType`
+pub fn get<KeyType>(name: &str) -> Option<Arc<Local<KeyType>>>
+where
+    KeyType: Send + Sync + 'static
+{
+    try_get(name).ok()
+}
+
+/// the store registered under the name, failing if it is missing or holds
+/// a different key type
+pub fn try_get<KeyType>(name: &str) -> Result<Arc<Local<KeyType>>, Error>
+where
+    KeyType: Send + Sync + 'static
+{
+    let store = {
+        let reader = registry().read()?;
+
+        let Some(store) = reader.get(name) else {
+            return Err(Error::NotFound(name.to_owned()));
+        };
+
+        store.clone()
+    };
+
+    let found = store.key_type();
+
+    store.into_any()
+        .downcast::<Local<KeyType>>()
+        .map_err(|_| Error::TypeMismatch {
+            name: name.to_owned(),
+            expected: type_name::<KeyType>(),
+            found,
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::key::Key;
+
+    // the registry is shared by every test in the process so each test uses
+    // its own names
+
+    #[test]
+    fn typed() {
+        let store: Local<Key<Vec<u8>>> = Local::new();
+        store.update(Key::builder(vec![1, 2, 3]).build().unwrap()).unwrap();
+
+        insert("typed.sessions", Arc::new(store)).unwrap();
+
+        let found = get::<Key<Vec<u8>>>("typed.sessions")
+            .expect("failed to get store");
+
+        assert_eq!(*found.latest().unwrap().unwrap().data(), vec![1, 2, 3]);
+        assert!(get::<Key<Vec<u8>>>("typed.missing").is_none());
+        assert!(matches!(
+            try_get::<Key<Vec<u8>>>("typed.missing"),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn wrong_type() {
+        insert("wrong_type.signing", Arc::new(Local::<u64>::new())).unwrap();
+
+        assert!(get::<Key<Vec<u8>>>("wrong_type.signing").is_none());
+
+        match try_get::<Key<Vec<u8>>>("wrong_type.signing") {
+            Err(Error::TypeMismatch { expected, found, .. }) => {
+                assert_eq!(expected, type_name::<Key<Vec<u8>>>());
+                assert_eq!(found, type_name::<u64>());
+            }
+            result => panic!("unexpected result: {:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn double_registration() {
+        insert("double.webhooks", Arc::new(Local::<u64>::new())).unwrap();
+
+        assert!(matches!(
+            insert("double.webhooks", Arc::new(Local::<u64>::new())),
+            Err(Error::AlreadyRegistered("double.webhooks"))
+        ));
+
+        assert!(remove("double.webhooks").unwrap().is_some());
+
+        insert("double.webhooks", Arc::new(Local::<u64>::new())).unwrap();
+    }
+
+    #[test]
+    fn concurrent() {
+        let names = ["concurrent.0", "concurrent.1", "concurrent.2", "concurrent.3"];
+
+        std::thread::scope(|s| {
+            for (i, name) in names.iter().enumerate() {
+                s.spawn(move || {
+                    let store = Local::<u64>::new();
+                    store.update(i as u64).unwrap();
+
+                    insert(name, Arc::new(store)).unwrap();
+                });
+            }
+        });
+
+        std::thread::scope(|s| {
+            for (i, name) in names.iter().enumerate() {
+                s.spawn(move || {
+                    for _ in 0..100 {
+                        let store = get::<u64>(name).expect("failed to get store");
+
+                        assert_eq!(store.latest().unwrap(), Some(i as u64));
+                    }
+                });
+            }
+        });
+    }
+}
@@ -0,0 +1,246 @@
+//! conversion between seconds since the unix epoch and RFC 3339 UTC
+//! timestamps, e.g. `2023-11-14T22:13:20Z`.
+//!
+//! with the `rfc3339-timestamps` feature the `created` field of a
+//! [`Key`](crate::Key) is written in this form to human readable formats.
+//! either form is always accepted when reading.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// not in the form `YYYY-MM-DDTHH:MM:SS` followed by an optional
+    /// fraction and `Z` or an offset
+    Malformed(String),
+    /// a field is out of range, such as month 13 or February 30
+    OutOfRange(String),
+    /// before the unix epoch
+    BeforeEpoch(String),
+}
+
+impl ParseError {
+    /// the input that failed to parse
+    pub fn input(&self) -> &str {
+        match self {
+            ParseError::Malformed(input) |
+            ParseError::OutOfRange(input) |
+            ParseError::BeforeEpoch(input) => input,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Malformed(input) => write!(f, "Malformed {:?}", input),
+            ParseError::OutOfRange(input) => write!(f, "OutOfRange {:?}", input),
+            ParseError::BeforeEpoch(input) => write!(f, "BeforeEpoch {:?}", input),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+const SECS_PER_DAY: i64 = 86_400;
+
+fn is_leap(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// days since the epoch of a date in the proleptic gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146_097 + doe - 719_468
+}
+
+/// the date of a number of days since the epoch
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+/// formats seconds since the epoch as `YYYY-MM-DDTHH:MM:SSZ`
+pub fn format(secs: u64) -> String {
+    let secs = secs as i128;
+    let days = (secs / SECS_PER_DAY as i128) as i64;
+    let rem = (secs % SECS_PER_DAY as i128) as i64;
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+/// reads `len` ascii digits from the front of `s`
+fn digits(s: &mut &[u8], len: usize) -> Option<i64> {
+    if s.len() < len || !s[..len].iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+
+    let value = s[..len].iter().fold(0, |acc, b| acc * 10 + (b - b'0') as i64);
+    *s = &s[len..];
+
+    Some(value)
+}
+
+/// takes `expected` off the front of `s`
+fn literal(s: &mut &[u8], expected: &[u8]) -> Option<()> {
+    let (first, rest) = s.split_first()?;
+
+    if !expected.contains(first) {
+        return None;
+    }
+
+    *s = rest;
+
+    Some(())
+}
+
+/// the fields of a timestamp and its offset from UTC in seconds
+fn fields(input: &str) -> Option<([i64; 6], i64)> {
+    let mut s = input.as_bytes();
+
+    let year = digits(&mut s, 4)?;
+    literal(&mut s, b"-")?;
+    let month = digits(&mut s, 2)?;
+    literal(&mut s, b"-")?;
+    let day = digits(&mut s, 2)?;
+    literal(&mut s, b"Tt ")?;
+    let hour = digits(&mut s, 2)?;
+    literal(&mut s, b":")?;
+    let minute = digits(&mut s, 2)?;
+    literal(&mut s, b":")?;
+    let second = digits(&mut s, 2)?;
+
+    // fractions of a second are dropped, created only keeps whole seconds
+    if literal(&mut s, b".").is_some() {
+        let len = s.iter().take_while(|b| b.is_ascii_digit()).count();
+
+        if len == 0 {
+            return None;
+        }
+
+        s = &s[len..];
+    }
+
+    let offset = match s.split_first()? {
+        (b'Z' | b'z', []) => 0,
+        (sign @ (b'+' | b'-'), rest) => {
+            s = rest;
+
+            let hours = digits(&mut s, 2)?;
+            literal(&mut s, b":")?;
+            let minutes = digits(&mut s, 2)?;
+
+            if !s.is_empty() || hours > 23 || minutes > 59 {
+                return None;
+            }
+
+            let offset = hours * 3600 + minutes * 60;
+
+            if *sign == b'-' { -offset } else { offset }
+        }
+        _ => return None,
+    };
+
+    Some(([year, month, day, hour, minute, second], offset))
+}
+
+/// parses an RFC 3339 timestamp into seconds since the epoch. fractions of
+/// a second are dropped.
+pub fn parse(input: &str) -> Result<u64, ParseError> {
+    let Some(([year, month, day, hour, minute, second], offset)) = fields(input) else {
+        return Err(ParseError::Malformed(input.to_owned()));
+    };
+
+    if !(1..=12).contains(&month) ||
+        day < 1 || day > days_in_month(year, month) ||
+        hour > 23 || minute > 59 || second > 59
+    {
+        return Err(ParseError::OutOfRange(input.to_owned()));
+    }
+
+    let secs = days_from_civil(year, month, day) * SECS_PER_DAY +
+        hour * 3600 + minute * 60 + second - offset;
+
+    u64::try_from(secs).map_err(|_| ParseError::BeforeEpoch(input.to_owned()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let cases = [
+            (0, "1970-01-01T00:00:00Z"),
+            (2, "1970-01-01T00:00:02Z"),
+            (951_782_400, "2000-02-29T00:00:00Z"),
+            (1_700_000_000, "2023-11-14T22:13:20Z"),
+            (4_102_444_799, "2099-12-31T23:59:59Z"),
+        ];
+
+        for (secs, text) in cases {
+            assert_eq!(format(secs), text);
+            assert_eq!(parse(text).unwrap(), secs);
+        }
+    }
+
+    #[test]
+    fn accepted() {
+        assert_eq!(parse("2023-11-14t22:13:20z").unwrap(), 1_700_000_000);
+        assert_eq!(parse("2023-11-14 22:13:20Z").unwrap(), 1_700_000_000);
+        assert_eq!(parse("2023-11-14T22:13:20.999Z").unwrap(), 1_700_000_000);
+        assert_eq!(parse("2023-11-15T00:13:20+02:00").unwrap(), 1_700_000_000);
+        assert_eq!(parse("2023-11-14T17:13:20-05:00").unwrap(), 1_700_000_000);
+    }
+
+    #[test]
+    fn rejected() {
+        for input in ["", "1700000000", "2023-11-14", "2023-11-14T22:13:20", "2023-11-14T22:13Z", "2023-11-14T22:13:20.Z", "2023-11-14T22:13:20Z ", "+2023-11-14T22:13:20Z"] {
+            assert_eq!(parse(input), Err(ParseError::Malformed(input.to_owned())));
+        }
+
+        for input in ["2023-13-01T00:00:00Z", "2023-02-29T00:00:00Z", "2023-11-14T24:00:00Z", "2023-11-14T22:60:00Z"] {
+            assert_eq!(parse(input), Err(ParseError::OutOfRange(input.to_owned())));
+        }
+
+        assert_eq!(
+            parse("1969-12-31T23:59:59Z"),
+            Err(ParseError::BeforeEpoch("1969-12-31T23:59:59Z".to_owned()))
+        );
+        assert_eq!(
+            parse("1970-01-01T00:00:00+00:01"),
+            Err(ParseError::BeforeEpoch("1970-01-01T00:00:00+00:01".to_owned()))
+        );
+    }
+}
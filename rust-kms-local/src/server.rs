@@ -0,0 +1,346 @@
+//! a small http server for handing keys to other processes on the same
+//! host.
+//!
+//! this is a localhost / sidecar convenience and not a hardened network
+//! kms. there is no tls, no rate limiting and a single shared bearer token,
+//! so it should only be bound to a loopback address or a socket that is not
+//! reachable from outside the host.
+//!
+//! | method | path | |
+//! |---|---|---|
+//! | `GET` | `/v1/healthz` | always `200`, no token required |
+//! | `GET` | `/v1/keys/latest` | the latest key |
+//! | `GET` | `/v1/keys/{version}` | the key at a version or `404` |
+//! | `POST` | `/v1/keys:rotate` | adds a random key the size of the latest key |
+//!
+//! keys are returned as `{"version": 1, "created": 0, "data": "<base64>"}`.
+//! requests without `Authorization: Bearer <token>` get `401`, an empty
+//! token is refused when the routes are built. a rotation that keeps losing
+//! to other keys being added gets `409`. nothing is logged, key data is only
+//! ever written to response bodies.
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::Router;
+use axum::extract::{Path, State, Request};
+use axum::http::{StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Json;
+use serde::{Serialize, Deserialize};
+
+use crate::key::Key;
+use crate::local::{self, Local};
+
+pub use crate::wire::KeyBody;
+
+/// the size of keys created by rotating an empty store
+pub const DEFAULT_KEY_LEN: usize = 32;
+
+/// how many times a rotation is tried when another key is added between
+/// reading the latest key and adding the new one
+const ROTATE_ATTEMPTS: usize = 3;
+
+#[derive(Debug)]
+pub enum Error {
+    /// the auth token is empty, which would let any request through that
+    /// sends `Bearer ` with nothing after it
+    EmptyToken,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::EmptyToken => f.write_str("EmptyToken"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// the body of every response that is not a key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatusBody {
+    pub status: String,
+}
+
+fn status(code: StatusCode, msg: &str) -> Response {
+    (code, Json(StatusBody { status: msg.to_owned() })).into_response()
+}
+
+struct ServerState {
+    store: Arc<Local<Key<Vec<u8>>>>,
+    token: String,
+}
+
+type Shared = Arc<ServerState>;
+
+/// the routes of [`serve`] for use with another listener or in tests.
+/// fails with [`Error::EmptyToken`] for an empty `auth_token`.
+pub fn router<T>(store: Arc<Local<Key<Vec<u8>>>>, auth_token: T) -> Result<Router, Error>
+where
+    T: Into<String>
+{
+    let token = auth_token.into();
+
+    if token.is_empty() {
+        return Err(Error::EmptyToken);
+    }
+
+    let state = Arc::new(ServerState {
+        store,
+        token,
+    });
+
+    let keys = Router::new()
+        .route("/v1/keys/latest", get(latest))
+        .route("/v1/keys/:version", get(version))
+        .route("/v1/keys:rotate", post(rotate))
+        .route_layer(middleware::from_fn_with_state(state.clone(), authorize));
+
+    Ok(Router::new()
+        .route("/v1/healthz", get(healthz))
+        .merge(keys)
+        .with_state(state))
+}
+
+/// binds to the address and serves the store until the returned future is
+/// dropped or fails. fails with [`Error::EmptyToken`] before binding for an
+/// empty `auth_token`.
+pub fn serve<T>(
+    store: Arc<Local<Key<Vec<u8>>>>,
+    addr: SocketAddr,
+    auth_token: T
+) -> Result<impl Future<Output = io::Result<()>>, Error>
+where
+    T: Into<String>
+{
+    let app = router(store, auth_token)?;
+
+    Ok(async move {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+
+        axum::serve(listener, app).await
+    })
+}
+
+/// compares without stopping at the first difference so the time taken
+/// does not depend on how much of the token was right
+fn token_eq(given: &[u8], expected: &[u8]) -> bool {
+    if given.len() != expected.len() {
+        return false;
+    }
+
+    given.iter()
+        .zip(expected)
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+async fn authorize(State(state): State<Shared>, request: Request, next: Next) -> Response {
+    let given = request.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match given {
+        Some(given) if token_eq(given.as_bytes(), state.token.as_bytes()) => next.run(request).await,
+        _ => status(StatusCode::UNAUTHORIZED, "unauthorized"),
+    }
+}
+
+async fn healthz() -> Response {
+    status(StatusCode::OK, "ok")
+}
+
+async fn latest(State(state): State<Shared>) -> Response {
+    match state.store.latest_version() {
+        Ok(Some(found)) => Json(KeyBody::new(found.0, &found.1)).into_response(),
+        Ok(None) => status(StatusCode::NOT_FOUND, "empty"),
+        Err(_) => status(StatusCode::INTERNAL_SERVER_ERROR, "store error"),
+    }
+}
+
+async fn version(State(state): State<Shared>, Path(version): Path<u64>) -> Response {
+    match state.store.get(&version) {
+        Ok(Some(key)) => Json(KeyBody::new(version, &key)).into_response(),
+        Ok(None) => status(StatusCode::NOT_FOUND, "unknown version"),
+        Err(_) => status(StatusCode::INTERNAL_SERVER_ERROR, "store error"),
+    }
+}
+
+/// the key is only added if the latest key it was sized from is still the
+/// latest, so a concurrent rotation or update cannot slip in between
+async fn rotate(State(state): State<Shared>) -> Response {
+    for _ in 0..ROTATE_ATTEMPTS {
+        let (expected, size) = match state.store.latest_version() {
+            Ok(Some(found)) => (Some(found.0), found.1.data().len()),
+            Ok(None) => (None, DEFAULT_KEY_LEN),
+            Err(_) => return status(StatusCode::INTERNAL_SERVER_ERROR, "store error"),
+        };
+
+        let key = match Key::<Vec<u8>>::builder_default_rng(size) {
+            Ok(builder) => builder.build(),
+            Err(_) => return status(StatusCode::INTERNAL_SERVER_ERROR, "rng error"),
+        };
+        let Ok(key) = key else {
+            return status(StatusCode::INTERNAL_SERVER_ERROR, "key error");
+        };
+        let body = KeyBody::new(0, &key);
+
+        match state.store.update_if_latest(expected, key) {
+            Ok(version) => return (StatusCode::CREATED, Json(KeyBody { version, ..body })).into_response(),
+            Err(local::Error::VersionConflict { .. }) => continue,
+            Err(_) => return status(StatusCode::INTERNAL_SERVER_ERROR, "store error"),
+        }
+    }
+
+    status(StatusCode::CONFLICT, "conflict")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Method;
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use tower::ServiceExt;
+    use crate::hooks::Op;
+    use crate::local::Outcome;
+
+    const TOKEN: &str = "sidecar-token";
+
+    fn create_store() -> Arc<Local<Key<Vec<u8>>>> {
+        let local = Local::new();
+
+        for byte in 1..=3u8 {
+            let mut builder = Key::builder(vec![byte; 16]);
+            builder.set_created(byte as u64 * 10);
+
+            local.update(builder.build().unwrap()).unwrap();
+        }
+
+        Arc::new(local)
+    }
+
+    async fn send(app: &Router, method: Method, uri: &str, token: Option<&str>) -> (StatusCode, Vec<u8>) {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri);
+
+        if let Some(token) = token {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+
+        let response = app.clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (status, body.to_vec())
+    }
+
+    fn key_body(bytes: &[u8]) -> KeyBody {
+        serde_json::from_slice(bytes).expect("body is not a key")
+    }
+
+    #[tokio::test]
+    async fn endpoints() {
+        let store = create_store();
+        let app = router(store.clone(), TOKEN).unwrap();
+
+        let (code, _) = send(&app, Method::GET, "/v1/healthz", None).await;
+
+        assert_eq!(code, StatusCode::OK);
+
+        let (code, body) = send(&app, Method::GET, "/v1/keys/latest", Some(TOKEN)).await;
+        let latest = key_body(&body);
+
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(latest.version, 3);
+        assert_eq!(latest.created, 30);
+        assert_eq!(BASE64.decode(&latest.data).unwrap(), vec![3; 16]);
+
+        let (code, body) = send(&app, Method::GET, "/v1/keys/2", Some(TOKEN)).await;
+
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(key_body(&body).version, 2);
+
+        let (code, _) = send(&app, Method::GET, "/v1/keys/99", Some(TOKEN)).await;
+
+        assert_eq!(code, StatusCode::NOT_FOUND);
+
+        let (code, body) = send(&app, Method::POST, "/v1/keys:rotate", Some(TOKEN)).await;
+        let rotated = key_body(&body);
+
+        assert_eq!(code, StatusCode::CREATED);
+        assert_eq!(rotated.version, 4);
+        assert_eq!(BASE64.decode(&rotated.data).unwrap().len(), 16);
+        assert_eq!(store.latest_version().unwrap().unwrap().version(), &4);
+    }
+
+    #[tokio::test]
+    async fn unauthorized() {
+        let store = create_store();
+        let app = router(store.clone(), TOKEN).unwrap();
+
+        for (method, uri) in [
+            (Method::GET, "/v1/keys/latest"),
+            (Method::GET, "/v1/keys/1"),
+            (Method::POST, "/v1/keys:rotate"),
+        ] {
+            let (code, body) = send(&app, method.clone(), uri, None).await;
+
+            assert_eq!(code, StatusCode::UNAUTHORIZED, "{} {}", method, uri);
+            assert!(serde_json::from_slice::<StatusBody>(&body).is_ok());
+
+            let (code, _) = send(&app, method.clone(), uri, Some("wrong-token")).await;
+
+            assert_eq!(code, StatusCode::UNAUTHORIZED, "{} {}", method, uri);
+        }
+
+        assert_eq!(store.count().unwrap(), 3);
+    }
+
+    #[test]
+    fn empty_token() {
+        assert!(matches!(router(create_store(), ""), Err(Error::EmptyToken)));
+        assert!(matches!(
+            serve(create_store(), SocketAddr::from(([127, 0, 0, 1], 0)), String::new()),
+            Err(Error::EmptyToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn rotate_recorded() {
+        let store = Arc::new(Local::builder()
+            .recent_ops(4)
+            .build()
+            .unwrap());
+        let changes = store.subscribe().unwrap();
+        let app = router(store.clone(), TOKEN).unwrap();
+
+        let (code, body) = send(&app, Method::POST, "/v1/keys:rotate", Some(TOKEN)).await;
+
+        assert_eq!(code, StatusCode::CREATED);
+        assert_eq!(key_body(&body).version, 1);
+        assert_eq!(BASE64.decode(&key_body(&body).data).unwrap().len(), DEFAULT_KEY_LEN);
+        assert_eq!(changes.try_recv().unwrap(), local::Change::Updated(1));
+
+        let recent = store.recent_ops();
+        let last = recent.last().unwrap();
+
+        assert_eq!(last.op, Op::Update);
+        assert_eq!(last.version, Some(1));
+        assert_eq!(last.outcome, Outcome::Ok);
+    }
+}
@@ -0,0 +1,152 @@
+//! helpers for tests of code that uses this crate.
+//!
+//! [`TempStore`] gives every test its own file in the temp directory so
+//! tests can run in parallel without sharing or leaving files behind.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::key::Key;
+use crate::local::Local;
+#[cfg(feature = "binary")]
+use crate::fs::Binary;
+#[cfg(feature = "json")]
+use crate::fs::Json;
+#[cfg(feature = "encrypted")]
+use crate::{crypto, fs::Encrypted};
+
+static NEXT: AtomicU64 = AtomicU64::new(0);
+
+/// a file in the temp directory that nothing else uses. the file, its
+/// siblings and their temporary files from saves are removed when dropped.
+///
+/// the file is not created, wrappers create it on their first save.
+#[derive(Debug)]
+pub struct TempStore {
+    path: PathBuf,
+    siblings: Vec<PathBuf>,
+}
+
+impl TempStore {
+    /// a new path with `name` in the file name, e.g. the format of the
+    /// store, to tell files apart when a test fails
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "rust-kms-{}-{}.{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed),
+            name
+        ));
+
+        TempStore {
+            path,
+            siblings: Vec::new(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// a path next to the store with `suffix` added to its name, such as a
+    /// detached header. it is removed along with the store.
+    pub fn sibling(&mut self, suffix: &str) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(suffix);
+
+        let path = PathBuf::from(name);
+        self.siblings.push(path.clone());
+
+        path
+    }
+
+    /// a json wrapper for `local` saved to this path
+    #[cfg(feature = "json")]
+    pub fn json<KeyType>(&self, local: Local<KeyType>) -> Json<KeyType> {
+        Json::new(local, self.path())
+    }
+
+    /// a binary wrapper for `local` saved to this path
+    #[cfg(feature = "binary")]
+    pub fn binary<KeyType>(&self, local: Local<KeyType>) -> Binary<KeyType> {
+        Binary::new(local, self.path())
+    }
+
+    /// an encrypted wrapper for `local` saved to this path
+    #[cfg(feature = "encrypted")]
+    pub fn encrypted<KeyType>(&self, local: Local<KeyType>, key: crypto::Key) -> Encrypted<KeyType> {
+        Encrypted::new(local, self.path(), key)
+    }
+}
+
+impl Drop for TempStore {
+    fn drop(&mut self) {
+        for path in std::iter::once(&self.path).chain(&self.siblings) {
+            let mut temp = path.as_os_str().to_owned();
+            temp.push(".tmp");
+
+            let _ = std::fs::remove_file(path);
+            let _ = std::fs::remove_file(temp);
+        }
+    }
+}
+
+/// a store of `u64` keys with gaps in the values but none in the versions
+pub fn sample_local() -> Local<u64> {
+    let local = Local::new();
+    let values = [0, 1, 2, 4, 5, 9, 11, 12, 16, 17, 22, 26];
+
+    for v in &values {
+        local.update(*v).expect("failed to add value");
+    }
+
+    local
+}
+
+/// a store of four byte keys created at 10, 20, 30 and 40
+pub fn sample_key_store() -> Local<Key<Vec<u8>>> {
+    let local = Local::new();
+
+    for created in [10, 20, 30, 40] {
+        let mut builder = Key::builder(b"super secret key data".to_vec());
+        builder.set_created(created);
+
+        local.update(builder.build().unwrap())
+            .expect("failed to add value");
+    }
+
+    local
+}
+
+/// asserts that both stores have the same counter and keys
+pub fn assert_local_eq<K>(a: &Local<K>, b: &Local<K>)
+where
+    K: PartialEq + std::fmt::Debug
+{
+    assert_eq!(a.count().unwrap(), b.count().unwrap(), "counts are not equal");
+    assert_eq!(*a.store_reader().unwrap(), *b.store_reader().unwrap(), "stores are not equal");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unique_and_removed() {
+        let mut a = TempStore::new("unique");
+        let b = TempStore::new("unique");
+
+        assert_ne!(a.path(), b.path());
+
+        let header = a.sibling(".header");
+        let path = a.path().to_owned();
+
+        std::fs::write(&path, b"store").unwrap();
+        std::fs::write(&header, b"header").unwrap();
+
+        drop(a);
+
+        assert!(!path.exists());
+        assert!(!header.exists());
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::crypto;
+use crate::key::Key;
+use crate::local::{self, Local, Meta};
+
+/// the plaintext encrypted for every canary
+pub const CANARY_PLAINTEXT: &[u8] = b"rust-kms canary";
+
+#[derive(Debug)]
+pub enum Error {
+    Local(local::Error),
+    Crypto(crypto::Error),
+}
+
+impl From<local::Error> for Error {
+    fn from(e: local::Error) -> Self {
+        Error::Local(e)
+    }
+}
+
+impl From<crypto::Error> for Error {
+    fn from(e: crypto::Error) -> Self {
+        Error::Crypto(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Local(_) => f.write_str("Local"),
+            Error::Crypto(_) => f.write_str("Crypto"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Local(e) => Some(e),
+            Error::Crypto(e) => Some(e),
+        }
+    }
+}
+
+/// the metadata name the canary of a version is kept under, as hex
+pub const CANARY_META: &str = "canary";
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut rtn = String::with_capacity(bytes.len() * 2);
+
+    for b in bytes {
+        rtn.push_str(&format!("{:02x}", b));
+    }
+
+    rtn
+}
+
+fn from_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// adds the key to the store with a ciphertext of [`CANARY_PLAINTEXT`] made
+/// with the key, kept in the metadata of its version under
+/// [`CANARY_META`].
+///
+/// the canary goes wherever the version goes, it is renumbered by
+/// `compact` and removed with the key. it only opens with the key that
+/// made it so it cannot be moved to another version.
+pub fn update_with_canary(
+    store: &Local<Key<crypto::Key>>,
+    key: Key<crypto::Key>
+) -> Result<u64, Error> {
+    let canary = crypto::encrypt_data(key.data(), CANARY_PLAINTEXT.to_vec())?;
+    let meta = Meta::from([(CANARY_META.to_owned(), to_hex(&canary))]);
+
+    Ok(store.update_with_meta(key, meta)?)
+}
+
+/// how a version was checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    /// a fresh canary was encrypted and decrypted. this only proves the key
+    /// can be used, not that its bytes are the ones it was created with.
+    RoundTrip,
+    /// the canary kept in the metadata of the version since it was added
+    /// was decrypted
+    Canary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionReport {
+    pub check: Check,
+    pub passed: bool,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub versions: BTreeMap<u64, VersionReport>,
+}
+
+impl ValidationReport {
+    pub fn is_ok(&self) -> bool {
+        self.versions.values().all(|r| r.passed)
+    }
+
+    /// the versions that did not pass
+    pub fn failed(&self) -> Vec<u64> {
+        self.versions.iter()
+            .filter(|(_, r)| !r.passed)
+            .map(|(v, _)| *v)
+            .collect()
+    }
+}
+
+/// checks every key in the store, decrypting the canary recorded by
+/// [`update_with_canary`] for versions that have one and doing a round
+/// trip for the rest.
+pub fn prove_keys(store: &Local<Key<crypto::Key>>) -> Result<ValidationReport, Error> {
+    let meta = store.all_meta()?;
+    let reader = store.store_reader()?;
+    let mut report = ValidationReport::default();
+
+    for (version, key) in reader.iter() {
+        let start = Instant::now();
+        let canary = meta.get(version)
+            .and_then(|meta| meta.get(CANARY_META));
+
+        let (check, passed) = if let Some(canary) = canary {
+            let result = from_hex(canary)
+                .map(|canary| crypto::decrypt_data(key.data(), canary));
+
+            (Check::Canary, matches!(result, Some(Ok(plain)) if plain == CANARY_PLAINTEXT))
+        } else {
+            let encrypted = crypto::encrypt_data(key.data(), CANARY_PLAINTEXT.to_vec())?;
+            let result = crypto::decrypt_data(key.data(), encrypted);
+
+            (Check::RoundTrip, matches!(result, Ok(plain) if plain == CANARY_PLAINTEXT))
+        };
+
+        report.versions.insert(*version, VersionReport {
+            check,
+            passed,
+            elapsed: start.elapsed(),
+        });
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn create_store(canaries: bool) -> Local<Key<crypto::Key>> {
+        let store = Local::new();
+
+        for _ in 0..4 {
+            let key = Key::<crypto::Key>::builder_os_rng()
+                .unwrap()
+                .build()
+                .unwrap();
+
+            if canaries {
+                update_with_canary(&store, key).unwrap();
+            } else {
+                store.update(key).unwrap();
+            }
+        }
+
+        store
+    }
+
+    #[test]
+    fn round_trip() {
+        let store = create_store(false);
+
+        let report = prove_keys(&store).unwrap();
+
+        assert_eq!(report.versions.len(), 4);
+        assert!(report.is_ok());
+        assert!(report.versions.values().all(|r| r.check == Check::RoundTrip));
+    }
+
+    #[test]
+    fn corrupted_key() {
+        let store = create_store(true);
+
+        let report = prove_keys(&store).unwrap();
+
+        assert!(report.is_ok());
+        assert!(report.versions.values().all(|r| r.check == Check::Canary));
+
+        {
+            let mut writer = store.store.write().unwrap();
+            let key = writer.get_mut(&3).unwrap();
+            let mut data = *key.data();
+            data[0] ^= 1;
+
+            *key = Key::from_parts(data, *key.created());
+        }
+
+        let report = prove_keys(&store).unwrap();
+
+        assert_eq!(report.failed(), vec![3]);
+    }
+
+    #[test]
+    fn dropped_and_compacted() {
+        let store = create_store(true);
+
+        store.drop(&1).unwrap();
+
+        assert!(!store.all_meta().unwrap().contains_key(&1), "canary kept for a dropped version");
+
+        store.compact(&[2, 3, 4]).unwrap();
+
+        let report = prove_keys(&store).unwrap();
+
+        assert_eq!(report.versions.keys().copied().collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert!(report.is_ok(), "failed after compact: {:?}", report.failed());
+        assert!(report.versions.values().all(|r| r.check == Check::Canary));
+    }
+}
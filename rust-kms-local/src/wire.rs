@@ -0,0 +1,34 @@
+//! the json representation of keys shared by the sidecar server and
+//! client
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::{Serialize, Deserialize};
+
+use crate::key::Key;
+
+/// a key as sent over http
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyBody {
+    pub version: u64,
+    pub created: u64,
+    /// the key data in standard base64
+    pub data: String,
+}
+
+impl KeyBody {
+    pub fn new(version: u64, key: &Key<Vec<u8>>) -> Self {
+        KeyBody {
+            version,
+            created: *key.created(),
+            data: BASE64.encode(key.data()),
+        }
+    }
+
+    /// decodes the data into a key with the same created timestamp
+    pub fn to_key(&self) -> Result<Key<Vec<u8>>, base64::DecodeError> {
+        let data = BASE64.decode(&self.data)?;
+
+        Ok(Key::from_parts(data, self.created))
+    }
+}
@@ -0,0 +1,10 @@
+/// overwrites the bytes with zeros in a way the compiler cannot skip, for
+/// key material that is about to be dropped or reused
+pub(crate) fn zero(bytes: &mut [u8]) {
+    for b in bytes.iter_mut() {
+        // SAFETY: the pointer comes from a valid mutable reference
+        unsafe { std::ptr::write_volatile(b, 0) };
+    }
+
+    std::sync::atomic::compiler_fence(std::sync::atomic::Ordering::SeqCst);
+}
@@ -0,0 +1,133 @@
+//! runs the logic of the programs in `examples/` against real files
+
+#![cfg(all(feature = "encrypted", feature = "json"))]
+
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, SystemTime};
+
+use rust_kms_local::{Key, Local};
+use rust_kms_local::fs::{Encrypted, Json, Wrapper};
+use rust_kms_local::fs::encrypted::Options;
+use rust_kms_local::policy::PolicySet;
+
+#[path = "../examples/rotate_daemon.rs"]
+#[allow(dead_code)]
+mod rotate_daemon;
+
+#[path = "../examples/envelope.rs"]
+#[allow(dead_code)]
+mod envelope;
+
+#[path = "../examples/migrate_format.rs"]
+#[allow(dead_code)]
+mod migrate_format;
+
+/// a path in the crate directory that is removed when dropped
+struct TestFile(PathBuf);
+
+impl TestFile {
+    fn new(name: &str) -> Self {
+        let path = PathBuf::from(format!("test.example.{}", name));
+
+        let _ = std::fs::remove_file(&path);
+
+        TestFile(path)
+    }
+}
+
+impl Drop for TestFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn rotate_daemon() {
+    let store_file = TestFile::new("rotate.enc");
+    let key_file = TestFile::new("rotate.key");
+
+    let master_key = rotate_daemon::master_key(&key_file.0).unwrap();
+
+    assert_eq!(rotate_daemon::master_key(&key_file.0).unwrap(), master_key);
+
+    let store = rotate_daemon::open_store(&store_file.0, master_key).unwrap();
+    let policy = PolicySet {
+        max_age: Some(60),
+        ..PolicySet::default()
+    };
+    let now = SystemTime::now();
+
+    assert_eq!(store.count().unwrap(), 0);
+    assert_eq!(rotate_daemon::rotate_if_due(&store, &policy, now).unwrap(), Some(1));
+    assert_eq!(
+        rotate_daemon::rotate_if_due(&store, &policy, now + Duration::from_secs(30)).unwrap(),
+        None
+    );
+    assert_eq!(
+        rotate_daemon::rotate_if_due(&store, &policy, now + Duration::from_secs(61)).unwrap(),
+        Some(2)
+    );
+
+    // a stop that was already requested still saves before returning
+    rotate_daemon::run(&store, &policy, Duration::from_secs(1), &AtomicBool::new(true)).unwrap();
+
+    let reopened = rotate_daemon::open_store(&store_file.0, master_key).unwrap();
+
+    assert_eq!(reopened.count().unwrap(), 2);
+    assert_eq!(*reopened.store_reader().unwrap(), *store.store_reader().unwrap());
+}
+
+#[test]
+fn envelope() {
+    let input = TestFile::new("envelope.txt");
+    let sealed = TestFile::new("envelope.sealed");
+
+    std::fs::write(&input.0, b"attack at dawn").unwrap();
+
+    let keys = Local::new();
+    let first = envelope::new_data_key(&keys).unwrap();
+
+    assert_eq!(envelope::encrypt_file(&keys, &input.0, &sealed.0).unwrap(), first);
+    assert_ne!(std::fs::read(&sealed.0).unwrap(), b"attack at dawn");
+
+    let rotated = envelope::new_data_key(&keys).unwrap();
+
+    assert_eq!(rotated, first + 1);
+    assert_eq!(envelope::decrypt_file(&keys, &sealed.0).unwrap(), b"attack at dawn");
+
+    keys.drop(&first).unwrap();
+
+    assert!(envelope::decrypt_file(&keys, &sealed.0).is_err());
+}
+
+#[test]
+fn migrate_format() {
+    let json_file = TestFile::new("migrate.json");
+    let encrypted_file = TestFile::new("migrate.enc");
+    let key_file = TestFile::new("migrate.key");
+
+    let local = Local::new();
+
+    for byte in 1..=3u8 {
+        local.update(Key::<Vec<u8>>::builder_os_rng(16 + byte as usize).unwrap().build().unwrap()).unwrap();
+    }
+
+    local.drop(&2).unwrap();
+
+    Json::new(local, &json_file.0).save().unwrap();
+    std::fs::write(&key_file.0, [9; 32]).unwrap();
+
+    assert!(migrate_format::read_master_key(&json_file.0).is_err());
+
+    let master_key = migrate_format::read_master_key(&key_file.0).unwrap();
+
+    assert_eq!(migrate_format::migrate(&json_file.0, &encrypted_file.0, master_key).unwrap(), 2);
+
+    let encrypted: Encrypted<Key<Vec<u8>>> = Encrypted::load(Options::new(&encrypted_file.0, master_key))
+        .unwrap();
+
+    assert_eq!(encrypted.count().unwrap(), 3);
+    assert_eq!(encrypted.store_reader().unwrap().keys().copied().collect::<Vec<_>>(), vec![1, 3]);
+    assert!(Encrypted::<Key<Vec<u8>>>::load(Options::new(&encrypted_file.0, [8; 32])).is_err());
+}
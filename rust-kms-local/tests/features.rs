@@ -0,0 +1,91 @@
+//! checks that each feature exposes the items it is documented to. every
+//! test only names items so a feature that loses one fails to compile.
+//!
+//! run against each combination with `features.sh` in the workspace root.
+
+use rust_kms_local::{Key, Local};
+
+#[test]
+fn always() {
+    let local: Local<Key<Vec<u8>>> = Local::new();
+    let key = Key::builder(vec![1, 2, 3]).build().unwrap();
+
+    assert_eq!(local.update(key).unwrap(), 1);
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn fs() {
+    use rust_kms_local::fs::{self, RetryPolicy};
+
+    fn _wrapper<W: fs::Wrapper>() {}
+
+    let _ = RetryPolicy::new(1, std::time::Duration::ZERO);
+    let _ = fs::Error::Cancelled;
+}
+
+#[cfg(feature = "binary")]
+#[test]
+fn binary() {
+    use rust_kms_local::fs::{Binary, KeyCodec, SerdeCodec, Wrapper};
+
+    fn _wrapper<W: Wrapper>() {}
+    fn _codec<C: KeyCodec<u64>>() {}
+
+    _wrapper::<Binary<u64>>();
+    _codec::<SerdeCodec>();
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json() {
+    use rust_kms_local::fs::{Json, Wrapper};
+
+    fn _wrapper<W: Wrapper>() {}
+
+    _wrapper::<Json<u64>>();
+}
+
+#[cfg(feature = "rand")]
+#[test]
+fn rand() {
+    let mut bytes = [0; 8];
+
+    rust_kms_local::rand_source::fill_bytes(&mut bytes).unwrap();
+}
+
+#[cfg(feature = "crypto")]
+#[test]
+fn crypto() {
+    use rust_kms_local::{crypto, envelope};
+
+    let local = Local::new();
+    local.update(Key::<crypto::Key>::builder_os_rng().unwrap().build().unwrap()).unwrap();
+
+    let blob = envelope::seal(&local, b"data".to_vec()).unwrap();
+
+    assert_eq!(envelope::open(&local, &blob).unwrap(), b"data");
+}
+
+#[cfg(feature = "encrypted")]
+#[test]
+fn encrypted() {
+    use rust_kms_local::fs::{self, Encrypted, Wrapper};
+
+    fn _wrapper<W: Wrapper>() {}
+
+    _wrapper::<Encrypted<u64>>();
+
+    let _ = fs::Error::MissingHeader;
+}
+
+#[cfg(feature = "sealed")]
+#[test]
+fn sealed() {
+    use rust_kms_local::fs::{EncryptedLazy, SealedValues, Wrapper};
+
+    fn _wrapper<W: Wrapper>() {}
+
+    _wrapper::<SealedValues<Vec<u8>>>();
+    _wrapper::<EncryptedLazy<Vec<u8>>>();
+}